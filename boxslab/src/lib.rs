@@ -18,7 +18,7 @@
 use std::{
     mem::MaybeUninit,
     ops::{Deref, DerefMut},
-    sync::Mutex,
+    sync::{Arc, Mutex},
 };
 
 // TODO: why is max bitmap size 64 a correct max size? Could we match
@@ -67,39 +67,24 @@ impl Bitmap {
 #[macro_export]
 macro_rules! box_slab {
     ($name:ident,$t:ty,$v:expr) => {
-        use std::mem::MaybeUninit;
-        use std::sync::Once;
         use $crate::{BoxSlab, Slab, SlabPool};
 
         pub struct $name;
 
         impl SlabPool for $name {
             type SlabType = $t;
-            fn get_slab() -> &'static Slab<Self> {
-                const MAYBE_INIT: MaybeUninit<$t> = MaybeUninit::uninit();
-                static mut SLAB_POOL: [MaybeUninit<$t>; $v] = [MAYBE_INIT; $v];
-                static mut SLAB_SPACE: Option<Slab<$name>> = None;
-                static mut INIT: Once = Once::new();
-                unsafe {
-                    INIT.call_once(|| {
-                        SLAB_SPACE = Some(Slab::<$name>::init(&mut SLAB_POOL, $v));
-                    });
-                    SLAB_SPACE.as_ref().unwrap()
-                }
-            }
+            const SIZE: usize = $v;
         }
     };
 }
 
 pub trait SlabPool {
     type SlabType: 'static;
-    fn get_slab() -> &'static Slab<Self>
-    where
-        Self: Sized;
+    const SIZE: usize;
 }
 
 pub struct Inner<T: 'static + SlabPool> {
-    pool: &'static mut [MaybeUninit<T::SlabType>],
+    pool: Box<[MaybeUninit<T::SlabType>]>,
     map: Bitmap,
 }
 
@@ -107,30 +92,37 @@ pub struct Inner<T: 'static + SlabPool> {
 pub struct Slab<T: 'static + SlabPool>(Mutex<Inner<T>>);
 
 impl<T: SlabPool> Slab<T> {
-    pub fn init(pool: &'static mut [MaybeUninit<T::SlabType>], size: usize) -> Self {
-        Self(Mutex::new(Inner {
+    /// Create a new slab, with space for T::SIZE objects
+    ///
+    /// Every slab owns its own storage, so objects allocated from one slab don't count
+    /// against the capacity of another
+    pub fn new() -> Arc<Self> {
+        let pool = (0..T::SIZE).map(|_| MaybeUninit::uninit()).collect();
+        Arc::new(Self(Mutex::new(Inner {
             pool,
-            map: Bitmap::new(size),
-        }))
+            map: Bitmap::new(T::SIZE),
+        })))
     }
 
-    pub fn try_new(new_object: T::SlabType) -> Option<BoxSlab<T>> {
-        let slab = T::get_slab();
-        let mut inner = slab.0.lock().unwrap();
+    pub fn try_new(self: &Arc<Self>, new_object: T::SlabType) -> Option<BoxSlab<T>> {
+        let mut inner = self.0.lock().unwrap();
         if let Some(index) = inner.map.first_false_index() {
             inner.map.set(index);
             inner.pool[index].write(new_object);
-            let cell_ptr = unsafe { &mut *inner.pool[index].as_mut_ptr() };
+            // The storage is boxed, so this pointer stays valid until the slot is freed,
+            // which only happens when the BoxSlab is dropped
+            let data = inner.pool[index].as_mut_ptr();
             Some(BoxSlab {
-                data: cell_ptr,
+                slab: self.clone(),
                 index,
+                data,
             })
         } else {
             None
         }
     }
 
-    pub fn free(&self, index: usize) {
+    fn free(&self, index: usize) {
         let mut inner = self.0.lock().unwrap();
         inner.map.reset(index);
         let old_value = std::mem::replace(&mut inner.pool[index], MaybeUninit::uninit());
@@ -140,29 +132,35 @@ impl<T: SlabPool> Slab<T> {
 }
 
 pub struct BoxSlab<T: 'static + SlabPool> {
-    // Because the data is a reference within the MaybeUninit, we don't have a mechanism
-    // to go out to the MaybeUninit from this reference. Hence this index
+    // The slab this object was allocated from, this also keeps the storage alive
+    slab: Arc<Slab<T>>,
+    // Because the data is a pointer within the MaybeUninit, we don't have a mechanism
+    // to go out to the MaybeUninit from this pointer. Hence this index
     index: usize,
     // TODO: We should figure out a way to get rid of the index too
-    data: &'static mut T::SlabType,
+    data: *mut T::SlabType,
 }
 
+// The BoxSlab has exclusive access to its object, just like a Box would
+unsafe impl<T: SlabPool> Send for BoxSlab<T> where T::SlabType: Send {}
+unsafe impl<T: SlabPool> Sync for BoxSlab<T> where T::SlabType: Sync {}
+
 impl<T: 'static + SlabPool> Drop for BoxSlab<T> {
     fn drop(&mut self) {
-        T::get_slab().free(self.index);
+        self.slab.free(self.index);
     }
 }
 
 impl<T: SlabPool> Deref for BoxSlab<T> {
     type Target = T::SlabType;
     fn deref(&self) -> &Self::Target {
-        self.data
+        unsafe { &*self.data }
     }
 }
 
 impl<T: SlabPool> DerefMut for BoxSlab<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.data
+        unsafe { &mut *self.data }
     }
 }
 
@@ -176,40 +174,44 @@ mod tests {
 
     box_slab!(TestSlab, Test, 3);
 
+    fn is_empty(slab: &Slab<TestSlab>) -> bool {
+        slab.0.lock().unwrap().map.is_empty()
+    }
+
     #[test]
     fn simple_alloc_free() {
+        let slab = Slab::<TestSlab>::new();
         {
-            let a = Slab::<TestSlab>::try_new(Test { val: Arc::new(10) }).unwrap();
+            let a = slab.try_new(Test { val: Arc::new(10) }).unwrap();
             assert_eq!(*a.val.deref(), 10);
-            let inner = TestSlab::get_slab().0.lock().unwrap();
-            assert!(!inner.map.is_empty());
+            assert!(!is_empty(&slab));
         }
         // Validates that the 'Drop' got executed
-        let inner = TestSlab::get_slab().0.lock().unwrap();
-        assert!(inner.map.is_empty());
+        assert!(is_empty(&slab));
         println!("Box Size {}", std::mem::size_of::<Box<Test>>());
         println!("BoxSlab Size {}", std::mem::size_of::<BoxSlab<TestSlab>>());
     }
 
     #[test]
     fn alloc_full_block() {
+        let slab = Slab::<TestSlab>::new();
         {
-            let a = Slab::<TestSlab>::try_new(Test { val: Arc::new(10) }).unwrap();
-            let b = Slab::<TestSlab>::try_new(Test { val: Arc::new(11) }).unwrap();
-            let c = Slab::<TestSlab>::try_new(Test { val: Arc::new(12) }).unwrap();
+            let a = slab.try_new(Test { val: Arc::new(10) }).unwrap();
+            let b = slab.try_new(Test { val: Arc::new(11) }).unwrap();
+            let c = slab.try_new(Test { val: Arc::new(12) }).unwrap();
             // Test that at overflow, we return None
-            assert!(Slab::<TestSlab>::try_new(Test { val: Arc::new(13) }).is_none(),);
+            assert!(slab.try_new(Test { val: Arc::new(13) }).is_none(),);
             assert_eq!(*b.val.deref(), 11);
 
             {
-                let inner = TestSlab::get_slab().0.lock().unwrap();
+                let inner = slab.0.lock().unwrap();
                 // Test that the bitmap is marked as full
                 assert!(inner.map.is_full());
             }
 
             // Purposefully drop, to test that new allocation is possible
             std::mem::drop(b);
-            let d = Slab::<TestSlab>::try_new(Test { val: Arc::new(21) }).unwrap();
+            let d = slab.try_new(Test { val: Arc::new(21) }).unwrap();
             assert_eq!(*d.val.deref(), 21);
 
             // Ensure older allocations are still valid
@@ -218,20 +220,34 @@ mod tests {
         }
 
         // Validates that the 'Drop' got executed - test that the bitmap is empty
-        let inner = TestSlab::get_slab().0.lock().unwrap();
-        assert!(inner.map.is_empty());
+        assert!(is_empty(&slab));
     }
 
     #[test]
     fn test_drop_logic() {
+        let slab = Slab::<TestSlab>::new();
         let root = Arc::new(10);
         {
-            let _a = Slab::<TestSlab>::try_new(Test { val: root.clone() }).unwrap();
-            let _b = Slab::<TestSlab>::try_new(Test { val: root.clone() }).unwrap();
-            let _c = Slab::<TestSlab>::try_new(Test { val: root.clone() }).unwrap();
+            let _a = slab.try_new(Test { val: root.clone() }).unwrap();
+            let _b = slab.try_new(Test { val: root.clone() }).unwrap();
+            let _c = slab.try_new(Test { val: root.clone() }).unwrap();
             assert_eq!(Arc::strong_count(&root), 4);
         }
         // Test that Drop was correctly called on all the members of the pool
         assert_eq!(Arc::strong_count(&root), 1);
     }
+
+    #[test]
+    fn independent_slabs() {
+        let first = Slab::<TestSlab>::new();
+        let second = Slab::<TestSlab>::new();
+        let _a = first.try_new(Test { val: Arc::new(10) }).unwrap();
+        let _b = first.try_new(Test { val: Arc::new(11) }).unwrap();
+        let _c = first.try_new(Test { val: Arc::new(12) }).unwrap();
+        assert!(first.try_new(Test { val: Arc::new(13) }).is_none());
+
+        // An exhausted slab doesn't affect the allocations from another one
+        let d = second.try_new(Test { val: Arc::new(20) }).unwrap();
+        assert_eq!(*d.val.deref(), 20);
+    }
 }
@@ -19,6 +19,7 @@ mod dev_att;
 use matter::core::{self, CommissioningData};
 use matter::data_model::cluster_basic_information::BasicInfoConfig;
 use matter::data_model::device_types::device_type_add_on_off_light;
+use matter::persist::FileKvStore;
 use matter::secure_channel::spake2p::VerifierData;
 
 fn main() {
@@ -41,11 +42,16 @@ fn main() {
     };
     let dev_att = Box::new(dev_att::HardCodedDevAtt::new());
 
-    let mut matter = core::Matter::new(dev_info, dev_att, comm_data).unwrap();
+    // The fabrics and ACLs are kept across restarts
+    let storage_dir = FileKvStore::default_dir().expect("HOME is not set");
+    println!("Storing the state in {}", storage_dir.display());
+
+    let mut matter = core::Matter::new(dev_info, dev_att, comm_data, storage_dir).unwrap();
     let dm = matter.get_data_model();
+    let group_keys = matter.get_group_keys();
     {
         let mut node = dm.node.write().unwrap();
-        let endpoint = device_type_add_on_off_light(&mut node).unwrap();
+        let endpoint = device_type_add_on_off_light(&mut node, group_keys).unwrap();
         println!("Added OnOff Light Device type at endpoint id: {}", endpoint);
         println!("Data Model now is: {}", node);
     }
@@ -20,6 +20,7 @@ use matter::core::{self, CommissioningData};
 use matter::data_model::cluster_basic_information::BasicInfoConfig;
 use matter::data_model::cluster_media_playback::{Commands, MediaPlaybackCluster};
 use matter::data_model::device_types::DEV_TYPE_ON_SMART_SPEAKER;
+use matter::persist::FileKvStore;
 use matter::secure_channel::spake2p::VerifierData;
 
 fn main() {
@@ -42,7 +43,11 @@ fn main() {
     };
     let dev_att = Box::new(dev_att::HardCodedDevAtt::new());
 
-    let mut matter = core::Matter::new(dev_info, dev_att, comm_data).unwrap();
+    // The fabrics and ACLs are kept across restarts
+    let storage_dir = FileKvStore::default_dir().expect("HOME is not set");
+    println!("Storing the state in {}", storage_dir.display());
+
+    let mut matter = core::Matter::new(dev_info, dev_att, comm_data, storage_dir).unwrap();
     let dm = matter.get_data_model();
     {
         let mut node = dm.node.write().unwrap();
@@ -17,7 +17,7 @@
 
 use std::{
     fmt::Display,
-    sync::{Arc, Mutex, MutexGuard, RwLock},
+    sync::{Arc, RwLock},
 };
 
 use crate::{
@@ -25,7 +25,7 @@ use crate::{
     error::Error,
     fabric,
     interaction_model::messages::GenericPath,
-    sys::Psm,
+    persist::{self, KvStore, SharedKvStore},
     tlv::{FromTLV, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
     transport::session::MAX_CAT_IDS_PER_NOC,
     utils::writebuf::WriteBuf,
@@ -374,24 +374,43 @@ struct AclMgrInner {
     entries: AclEntries,
 }
 
-const ACL_KV_ENTRY: &str = "acl";
+const ACL_KV_ENTRY: &str = "acls";
+// The key that the releases before the ACL record stored the unversioned entries in
+const ACL_KV_LEGACY_ENTRY: &str = "acl";
+// The schema version of the ACL record, to be bumped on any change to the AclEntry layout
+const ACL_RECORD_VERSION: u8 = 1;
 const ACL_KV_MAX_SIZE: usize = 300;
 impl AclMgrInner {
-    pub fn store(&self, psm: &MutexGuard<Psm>) -> Result<(), Error> {
+    pub fn store(&self, storage: &mut dyn KvStore) -> Result<(), Error> {
         let mut acl_tlvs = [0u8; ACL_KV_MAX_SIZE];
         let mut wb = WriteBuf::new(&mut acl_tlvs, ACL_KV_MAX_SIZE);
         let mut tw = TLVWriter::new(&mut wb);
         self.entries.to_tlv(&mut tw, TagType::Anonymous)?;
-        psm.set_kv_slice(ACL_KV_ENTRY, wb.as_slice())
+        persist::set_record(storage, ACL_KV_ENTRY, ACL_RECORD_VERSION, wb.as_slice())
+    }
+
+    pub fn load(storage: &mut dyn KvStore) -> Result<Self, Error> {
+        match persist::get_record(
+            storage,
+            ACL_KV_ENTRY,
+            ACL_RECORD_VERSION,
+            persist::no_migration,
+        ) {
+            Ok(acl_tlvs) => Self::from_tlvs(&acl_tlvs),
+            Err(Error::NotFound) => {
+                let mut acl_tlvs = Vec::new();
+                storage.get(ACL_KV_LEGACY_ENTRY, &mut acl_tlvs)?;
+                let inner = Self::from_tlvs(&acl_tlvs)?;
+                inner.store(storage)?;
+                storage.remove(ACL_KV_LEGACY_ENTRY)?;
+                Ok(inner)
+            }
+            Err(e) => Err(e),
+        }
     }
 
-    pub fn load(psm: &MutexGuard<Psm>) -> Result<Self, Error> {
-        let mut acl_tlvs = Vec::new();
-        psm.get_kv_slice(ACL_KV_ENTRY, &mut acl_tlvs)?;
-        let root = TLVList::new(&acl_tlvs)
-            .iter()
-            .next()
-            .ok_or(Error::Invalid)?;
+    fn from_tlvs(acl_tlvs: &[u8]) -> Result<Self, Error> {
+        let root = TLVList::new(acl_tlvs).iter().next().ok_or(Error::Invalid)?;
 
         Ok(Self {
             entries: AclEntries::from_tlv(&root)?,
@@ -426,40 +445,37 @@ pub struct AclMgr {
     inner: RwLock<AclMgrInner>,
     // The Option<> is solely because test execution is faster
     // Doing this here adds the least overhead during ACL verification
-    psm: Option<Arc<Mutex<Psm>>>,
+    storage: Option<SharedKvStore>,
 }
 
 impl AclMgr {
-    pub fn new() -> Result<Self, Error> {
-        AclMgr::new_with(true)
+    pub fn new(storage: SharedKvStore) -> Result<Self, Error> {
+        AclMgr::new_with(Some(storage))
     }
 
-    pub fn new_with(psm_support: bool) -> Result<Self, Error> {
+    pub fn new_with(storage: Option<SharedKvStore>) -> Result<Self, Error> {
         const INIT: Option<AclEntry> = None;
-        let mut psm = None;
 
-        let inner = if !psm_support {
-            AclMgrInner {
-                entries: [INIT; MAX_ACL_ENTRIES],
-            }
-        } else {
-            let psm_handle = Psm::get()?;
+        let inner = if let Some(storage_handle) = storage.as_ref() {
             let inner = {
-                let psm_lock = psm_handle.lock().unwrap();
-                AclMgrInner::load(&psm_lock)
+                let mut storage_lock = storage_handle.lock().unwrap();
+                AclMgrInner::load(&mut *storage_lock)
             };
 
-            psm = Some(psm_handle);
             inner.unwrap_or({
-                // Error loading from PSM
+                // Error loading from the storage
                 AclMgrInner {
                     entries: [INIT; MAX_ACL_ENTRIES],
                 }
             })
+        } else {
+            AclMgrInner {
+                entries: [INIT; MAX_ACL_ENTRIES],
+            }
         };
         Ok(Self {
             inner: RwLock::new(inner),
-            psm,
+            storage,
         })
     }
 
@@ -468,9 +484,9 @@ impl AclMgr {
         for i in 0..MAX_ACL_ENTRIES {
             inner.entries[i] = None;
         }
-        if let Some(psm) = self.psm.as_ref() {
-            let psm = psm.lock().unwrap();
-            let _ = inner.store(&psm).map_err(|e| {
+        if let Some(storage) = self.storage.as_ref() {
+            let mut storage = storage.lock().unwrap();
+            let _ = inner.store(&mut *storage).map_err(|e| {
                 error!("Error in storing ACLs {}", e);
             });
         }
@@ -494,9 +510,9 @@ impl AclMgr {
             .ok_or(Error::NoSpace)?;
         inner.entries[index] = Some(entry);
 
-        if let Some(psm) = self.psm.as_ref() {
-            let psm = psm.lock().unwrap();
-            inner.store(&psm)
+        if let Some(storage) = self.storage.as_ref() {
+            let mut storage = storage.lock().unwrap();
+            inner.store(&mut *storage)
         } else {
             Ok(())
         }
@@ -508,9 +524,9 @@ impl AclMgr {
         let old = inner.for_index_in_fabric(index, fab_idx)?;
         *old = Some(new);
 
-        if let Some(psm) = self.psm.as_ref() {
-            let psm = psm.lock().unwrap();
-            inner.store(&psm)
+        if let Some(storage) = self.storage.as_ref() {
+            let mut storage = storage.lock().unwrap();
+            inner.store(&mut *storage)
         } else {
             Ok(())
         }
@@ -521,9 +537,9 @@ impl AclMgr {
         let old = inner.for_index_in_fabric(index, fab_idx)?;
         *old = None;
 
-        if let Some(psm) = self.psm.as_ref() {
-            let psm = psm.lock().unwrap();
-            inner.store(&psm)
+        if let Some(storage) = self.storage.as_ref() {
+            let mut storage = storage.lock().unwrap();
+            inner.store(&mut *storage)
         } else {
             Ok(())
         }
@@ -541,9 +557,9 @@ impl AclMgr {
             }
         }
 
-        if let Some(psm) = self.psm.as_ref() {
-            let psm = psm.lock().unwrap();
-            inner.store(&psm)
+        if let Some(storage) = self.storage.as_ref() {
+            let mut storage = storage.lock().unwrap();
+            inner.store(&mut *storage)
         } else {
             Ok(())
         }
@@ -598,14 +614,17 @@ mod tests {
         acl::{gen_noc_cat, AccessorSubjects},
         data_model::objects::{Access, Privilege},
         interaction_model::messages::GenericPath,
+        persist::{MemKvStore, SharedKvStore},
     };
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
 
-    use super::{AccessReq, Accessor, AclEntry, AclMgr, AuthMode, Target};
+    use super::{
+        AccessReq, Accessor, AclEntry, AclMgr, AuthMode, Target, ACL_KV_ENTRY, ACL_KV_LEGACY_ENTRY,
+    };
 
     #[test]
     fn test_basic_empty_subject_target() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
         let path = GenericPath::new(Some(1), Some(1234), None);
@@ -633,7 +652,7 @@ mod tests {
 
     #[test]
     fn test_subject() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
         let path = GenericPath::new(Some(1), Some(1234), None);
@@ -655,7 +674,7 @@ mod tests {
 
     #[test]
     fn test_cat() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
 
         let allow_cat = 0xABCD;
@@ -693,7 +712,7 @@ mod tests {
 
     #[test]
     fn test_cat_version() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
 
         let allow_cat = 0xABCD;
@@ -725,7 +744,7 @@ mod tests {
 
     #[test]
     fn test_target() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
         let path = GenericPath::new(Some(1), Some(1234), None);
@@ -786,7 +805,7 @@ mod tests {
 
     #[test]
     fn test_privilege() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
 
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
@@ -827,7 +846,7 @@ mod tests {
 
     #[test]
     fn test_delete_for_fabric() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new_with(None).unwrap());
         am.erase_all();
         let path = GenericPath::new(Some(1), Some(1234), None);
         let accessor2 = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
@@ -854,4 +873,47 @@ mod tests {
         assert_eq!(req2.allow(), false);
         assert_eq!(req3.allow(), true);
     }
+
+    fn fabrics_with_acls(am: &AclMgr) -> Vec<Option<u8>> {
+        let mut fabrics = Vec::new();
+        am.for_each_acl(|e| fabrics.push(e.fab_idx)).unwrap();
+        fabrics
+    }
+
+    #[test]
+    fn test_persist() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let am = AclMgr::new(storage.clone()).unwrap();
+        let mut new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
+        new.add_subject(112233).unwrap();
+        am.add(new).unwrap();
+
+        // Another AclMgr on the same storage starts off with the same entries
+        let am = AclMgr::new(storage).unwrap();
+        assert_eq!(fabrics_with_acls(&am), [Some(2)]);
+    }
+
+    #[test]
+    fn test_legacy_migration() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let am = AclMgr::new(storage.clone()).unwrap();
+        am.add(AclEntry::new(2, Privilege::VIEW, AuthMode::Case))
+            .unwrap();
+
+        // Turn the record into the legacy layout, which is the same without the version
+        {
+            let mut s = storage.lock().unwrap();
+            let mut record = Vec::new();
+            s.get(ACL_KV_ENTRY, &mut record).unwrap();
+            s.set(ACL_KV_LEGACY_ENTRY, &record[1..]).unwrap();
+            s.remove(ACL_KV_ENTRY).unwrap();
+        }
+
+        let am = AclMgr::new(storage.clone()).unwrap();
+        assert_eq!(fabrics_with_acls(&am), [Some(2)]);
+        let s = storage.lock().unwrap();
+        let mut val = Vec::new();
+        assert!(s.get(ACL_KV_ENTRY, &mut val).is_ok());
+        assert!(s.get(ACL_KV_LEGACY_ENTRY, &mut val).is_err());
+    }
 }
@@ -0,0 +1,235 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The X.509 certificates of the device attestation chain
+//!
+//! Unlike the operational certificates, the DAC, PAI and PAA need not have a Matter TLV form,
+//! so they are checked in DER as is.
+
+use crate::{
+    cert::{
+        asn1_reader::{
+            ASN1Reader, TAG_BOOLEAN, TAG_INTEGER, TAG_OCTET_STRING, TAG_OID, TAG_SEQUENCE, TAG_SET,
+            TAG_UTF8_STRING,
+        },
+        x509::{
+            parse_basic_constraints, parse_der_signature, parse_key_usage, parse_pubkey, parse_time,
+        },
+        CertTime, KEY_USAGE_DIGITAL_SIGN, KEY_USAGE_KEY_CERT_SIGN, OID_AUTH_KEY_ID,
+        OID_BASIC_CONSTRAINTS, OID_ECDSA_WITH_SHA256, OID_KEY_USAGE, OID_SUBJ_KEY_IDENTIFIER,
+    },
+    crypto::{CryptoKeyPair, KeyPair},
+    error::Error,
+};
+
+const OID_MATTER_VID: [u8; 10] = [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x02, 0x01];
+const OID_MATTER_PID: [u8; 10] = [0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x02, 0x02];
+
+/// A parsed attestation certificate, borrowing from its DER
+pub struct AttCert<'a> {
+    tbs: &'a [u8],
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    not_before: u32,
+    not_after: u32,
+    pubkey: &'a [u8],
+    is_ca: bool,
+    path_len: Option<u8>,
+    key_usage: Option<u16>,
+    subj_key_id: Option<&'a [u8]>,
+    auth_key_id: Option<&'a [u8]>,
+    vid: Option<u16>,
+    pid: Option<u16>,
+    signature: Vec<u8>,
+}
+
+impl<'a> AttCert<'a> {
+    pub fn new(der: &'a [u8]) -> Result<Self, Error> {
+        let mut r = ASN1Reader::new(der);
+        let mut c = r.enter(TAG_SEQUENCE)?;
+        if !r.is_empty() {
+            return Err(Error::Invalid);
+        }
+        let tbs = c.read_raw(TAG_SEQUENCE)?;
+        c.enter(TAG_SEQUENCE)?.expect_oid(&OID_ECDSA_WITH_SHA256)?;
+        let signature = parse_der_signature(c.bitstr()?)?;
+
+        let mut t = ASN1Reader::new(tbs).enter(TAG_SEQUENCE)?;
+        // Only v3 certificates
+        if t.enter(0xA0)?.read(TAG_INTEGER)? != [2] {
+            return Err(Error::InvalidData);
+        }
+        let _serial_no = t.read(TAG_INTEGER)?;
+        t.enter(TAG_SEQUENCE)?.expect_oid(&OID_ECDSA_WITH_SHA256)?;
+        let issuer = t.read_raw(TAG_SEQUENCE)?;
+        let mut validity = t.enter(TAG_SEQUENCE)?;
+        let not_before = parse_time(&mut validity)?.ok_or(Error::InvalidData)?;
+        let not_after = parse_time(&mut validity)?.unwrap_or(0);
+        let subject = t.read_raw(TAG_SEQUENCE)?;
+        let pubkey = parse_pubkey(t.enter(TAG_SEQUENCE)?)?;
+        let (vid, pid) = parse_vid_pid(subject)?;
+
+        let mut cert = Self {
+            tbs,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            pubkey,
+            is_ca: false,
+            path_len: None,
+            key_usage: None,
+            subj_key_id: None,
+            auth_key_id: None,
+            vid,
+            pid,
+            signature,
+        };
+
+        let mut exts = t.enter(0xA3)?.enter(TAG_SEQUENCE)?;
+        while !exts.is_empty() {
+            let mut e = exts.enter(TAG_SEQUENCE)?;
+            let oid = e.read(TAG_OID)?;
+            let critical = e.read_optional(TAG_BOOLEAN)? == Some(&[0xFF]);
+            let mut value = e.enter(TAG_OCTET_STRING)?;
+
+            if oid == OID_BASIC_CONSTRAINTS {
+                let (is_ca, path_len) = parse_basic_constraints(&mut value)?;
+                cert.is_ca = is_ca;
+                cert.path_len = path_len;
+            } else if oid == OID_KEY_USAGE {
+                cert.key_usage = Some(parse_key_usage(&mut value)?);
+            } else if oid == OID_SUBJ_KEY_IDENTIFIER {
+                cert.subj_key_id = Some(value.read(TAG_OCTET_STRING)?);
+            } else if oid == OID_AUTH_KEY_ID {
+                cert.auth_key_id = Some(value.enter(TAG_SEQUENCE)?.read(0x80)?);
+            } else if critical {
+                // Anything else is fine, as long as it can be ignored
+                return Err(Error::InvalidData);
+            }
+        }
+        Ok(cert)
+    }
+
+    pub fn get_pubkey(&self) -> &'a [u8] {
+        self.pubkey
+    }
+
+    pub fn get_subject_key_id(&self) -> Option<&'a [u8]> {
+        self.subj_key_id
+    }
+
+    pub fn get_auth_key_id(&self) -> Option<&'a [u8]> {
+        self.auth_key_id
+    }
+
+    pub fn get_vid(&self) -> Option<u16> {
+        self.vid
+    }
+
+    pub fn get_pid(&self) -> Option<u16> {
+        self.pid
+    }
+
+    pub fn is_valid_at(&self, time: CertTime) -> Result<(), Error> {
+        time.check_validity(self.not_before, self.not_after)
+    }
+
+    /// Check the extensions for the position of the certificate in the chain, a depth of 0
+    /// is the DAC, 1 the PAI and 2 the PAA
+    pub fn is_valid_usage(&self, depth: usize) -> Result<(), Error> {
+        let key_usage = self.key_usage.ok_or(Error::InvalidKeyUsage)?;
+        if depth == 0 {
+            if self.is_ca {
+                return Err(Error::InvalidBasicConstraints);
+            }
+            if key_usage & KEY_USAGE_DIGITAL_SIGN == 0 {
+                return Err(Error::InvalidKeyUsage);
+            }
+        } else {
+            if !self.is_ca {
+                return Err(Error::InvalidBasicConstraints);
+            }
+            if key_usage & KEY_USAGE_KEY_CERT_SIGN == 0 {
+                return Err(Error::InvalidKeyUsage);
+            }
+            if self
+                .path_len
+                .is_some_and(|path| usize::from(path) < depth - 1)
+            {
+                return Err(Error::PathLenExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that this certificate was issued by the given one
+    pub fn verify_issued_by(&self, issuer: &AttCert) -> Result<(), Error> {
+        if self.issuer != issuer.subject {
+            return Err(Error::InvalidIssuer);
+        }
+        match (self.auth_key_id, issuer.subj_key_id) {
+            (Some(akid), Some(skid)) if akid == skid => (),
+            _ => return Err(Error::InvalidAuthKey),
+        }
+        self.verify_signature(issuer.pubkey)
+    }
+
+    /// Verify that this is a self-signed certificate, the AKID is optional here
+    pub fn verify_self_signed(&self) -> Result<(), Error> {
+        if self.issuer != self.subject {
+            return Err(Error::InvalidIssuer);
+        }
+        if self.auth_key_id.is_some() && self.auth_key_id != self.subj_key_id {
+            return Err(Error::InvalidAuthKey);
+        }
+        self.verify_signature(self.pubkey)
+    }
+
+    fn verify_signature(&self, pubkey: &[u8]) -> Result<(), Error> {
+        KeyPair::new_from_public(pubkey)?.verify_msg(self.tbs, &self.signature)
+    }
+}
+
+// The VID and PID attributes of the subject, if present
+fn parse_vid_pid(subject: &[u8]) -> Result<(Option<u16>, Option<u16>), Error> {
+    let (mut vid, mut pid) = (None, None);
+    let mut names = ASN1Reader::new(subject).enter(TAG_SEQUENCE)?;
+    while !names.is_empty() {
+        let mut rdn = names.enter(TAG_SET)?;
+        while !rdn.is_empty() {
+            let mut attr = rdn.enter(TAG_SEQUENCE)?;
+            let oid = attr.read(TAG_OID)?;
+            if oid == OID_MATTER_VID {
+                vid = Some(parse_hex_u16(attr.read(TAG_UTF8_STRING)?)?);
+            } else if oid == OID_MATTER_PID {
+                pid = Some(parse_hex_u16(attr.read(TAG_UTF8_STRING)?)?);
+            }
+        }
+    }
+    Ok((vid, pid))
+}
+
+// Exactly four uppercase hex digits
+fn parse_hex_u16(value: &[u8]) -> Result<u16, Error> {
+    let valid = |b: &u8| b.is_ascii_digit() || (b'A'..=b'F').contains(b);
+    if value.len() != 4 || !value.iter().all(valid) {
+        return Err(Error::InvalidData);
+    }
+    let value = std::str::from_utf8(value)?;
+    u16::from_str_radix(value, 16).map_err(|_| Error::InvalidData)
+}
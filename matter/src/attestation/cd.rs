@@ -0,0 +1,83 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use super::cms;
+use crate::{
+    crypto::KeyPair,
+    error::Error,
+    tlv::{FromTLV, TLVArrayOwned, TLVElement, TLVWriter, TagType, ToTLV},
+    utils::writebuf::WriteBuf,
+};
+
+/// The maximum size of the signed Certification Declaration
+pub const MAX_CERT_DECLARATION_LEN: usize = 600;
+
+/// The Certification Declaration
+///
+/// This is the TLV content of the CMS envelope that the CSA signs for a certified product.
+#[derive(FromTLV, ToTLV)]
+pub struct CertDeclaration {
+    pub format_version: u16,
+    pub vendor_id: u16,
+    pub product_ids: TLVArrayOwned<u16>,
+    pub device_type_id: u32,
+    pub certificate_id: String,
+    pub security_level: u8,
+    pub security_info: u16,
+    pub version_number: u16,
+    pub certification_type: u8,
+    /// The VID and PID in the DAC, when these differ from the ones above
+    pub dac_origin_vid: Option<u16>,
+    pub dac_origin_pid: Option<u16>,
+    /// The Subject Key Identifiers of the PAAs that may be at the root of the DAC chain
+    pub authorized_paa_list: Option<TLVArrayOwned<Vec<u8>>>,
+}
+
+impl CertDeclaration {
+    pub fn as_tlv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut wb = WriteBuf::new(buf, buf.len());
+        let mut tw = TLVWriter::new(&mut wb);
+        self.to_tlv(&mut tw, TagType::Anonymous)?;
+        Ok(wb.as_slice().len())
+    }
+
+    /// Sign the TLV encoding, and wrap it in a CMS SignedData in DER
+    ///
+    /// The signer is identified by the Subject Key Identifier of its certificate, which is
+    /// what the commissioner looks it up by.
+    pub fn sign(
+        &self,
+        signer_key_id: &[u8],
+        signer: &KeyPair,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let mut content = [0u8; MAX_CERT_DECLARATION_LEN];
+        let len = self.as_tlv(&mut content)?;
+        cms::sign(&content[..len], signer_key_id, signer, buf)
+    }
+
+    pub fn has_product_id(&self, pid: u16) -> bool {
+        self.product_ids.iter().any(|p| *p == pid)
+    }
+
+    pub fn is_paa_authorized(&self, paa_skid: &[u8]) -> bool {
+        match &self.authorized_paa_list {
+            Some(list) => list.iter().any(|skid| skid == paa_skid),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The CMS SignedData envelope of the Certification Declaration
+//!
+//! Only the profile that Matter uses is supported: a single signer identified by its Subject
+//! Key Identifier, SHA-256 with ECDSA, and no certificates or signed attributes.
+
+use crate::{
+    cert::{
+        asn1_reader::{ASN1Reader, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE, TAG_SET},
+        encode_der_signature,
+        x509::parse_der_signature,
+        ASN1Writer, CertConsumer, MAX_DER_SIGNATURE_LEN, OID_ECDSA_WITH_SHA256,
+    },
+    crypto::{self, CryptoKeyPair, KeyPair},
+    error::Error,
+};
+
+const OID_SIGNED_DATA: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x02];
+const OID_DATA: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x01];
+const OID_SHA256: [u8; 9] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+// Version 3, since the signer is identified by its Subject Key Identifier
+const CMS_VERSION: u8 = 3;
+
+pub struct SignedData<'a> {
+    pub content: &'a [u8],
+    pub signer_key_id: &'a [u8],
+    signature: Vec<u8>,
+}
+
+impl<'a> SignedData<'a> {
+    pub fn new(der: &'a [u8]) -> Result<Self, Error> {
+        let mut r = ASN1Reader::new(der);
+        let mut content_info = r.enter(TAG_SEQUENCE)?;
+        if !r.is_empty() {
+            return Err(Error::Invalid);
+        }
+        content_info.expect_oid(&OID_SIGNED_DATA)?;
+
+        let mut sd = content_info.enter(0xA0)?.enter(TAG_SEQUENCE)?;
+        if sd.read(TAG_INTEGER)? != [CMS_VERSION] {
+            return Err(Error::InvalidData);
+        }
+        sd.enter(TAG_SET)?
+            .enter(TAG_SEQUENCE)?
+            .expect_oid(&OID_SHA256)?;
+        let mut encap = sd.enter(TAG_SEQUENCE)?;
+        encap.expect_oid(&OID_DATA)?;
+        let content = encap.enter(0xA0)?.read(TAG_OCTET_STRING)?;
+        // Any certificates or CRLs are of no use, the signer has to be known already
+        sd.read_optional(0xA0)?;
+        sd.read_optional(0xA1)?;
+
+        let mut signer_infos = sd.enter(TAG_SET)?;
+        let mut si = signer_infos.enter(TAG_SEQUENCE)?;
+        if !signer_infos.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        if si.read(TAG_INTEGER)? != [CMS_VERSION] {
+            return Err(Error::InvalidData);
+        }
+        let signer_key_id = si.read(0x80)?;
+        si.enter(TAG_SEQUENCE)?.expect_oid(&OID_SHA256)?;
+        // Without signed attributes, the signature is over the content itself
+        if si.peek_tag() == Some(0xA0) {
+            return Err(Error::InvalidData);
+        }
+        si.enter(TAG_SEQUENCE)?.expect_oid(&OID_ECDSA_WITH_SHA256)?;
+        let signature = parse_der_signature(si.read(TAG_OCTET_STRING)?)?;
+
+        Ok(Self {
+            content,
+            signer_key_id,
+            signature,
+        })
+    }
+
+    pub fn verify(&self, signer_pubkey: &[u8]) -> Result<(), Error> {
+        KeyPair::new_from_public(signer_pubkey)?.verify_msg(self.content, &self.signature)
+    }
+}
+
+/// Sign the content, and encode it along with the signature as a SignedData in DER
+pub fn sign(
+    content: &[u8],
+    signer_key_id: &[u8],
+    signer: &KeyPair,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+    signer.sign_msg(content, &mut signature)?;
+    let mut der_signature = [0u8; MAX_DER_SIGNATURE_LEN];
+    let der_signature_len = encode_der_signature(&signature, &mut der_signature)?;
+
+    let mut w = ASN1Writer::new(buf);
+    w.start_seq("")?;
+    w.oid("Content Type", &OID_SIGNED_DATA)?;
+    w.start_ctx("Content", 0)?;
+    w.start_seq("")?;
+    w.integer("Version", &[CMS_VERSION])?;
+
+    w.start_set("Digest Algorithms")?;
+    w.start_seq("")?;
+    w.oid("", &OID_SHA256)?;
+    w.end_seq()?;
+    w.end_set()?;
+
+    w.start_seq("Encapsulated Content Info")?;
+    w.oid("Content Type", &OID_DATA)?;
+    w.start_ctx("Content", 0)?;
+    w.ostr("", content)?;
+    w.end_ctx()?;
+    w.end_seq()?;
+
+    w.start_set("Signer Infos")?;
+    w.start_seq("")?;
+    w.integer("Version", &[CMS_VERSION])?;
+    w.ctx("Subject Key Identifier", 0, signer_key_id)?;
+    w.start_seq("Digest Algorithm")?;
+    w.oid("", &OID_SHA256)?;
+    w.end_seq()?;
+    w.start_seq("Signature Algorithm")?;
+    w.oid("", &OID_ECDSA_WITH_SHA256)?;
+    w.end_seq()?;
+    w.ostr("Signature", &der_signature[..der_signature_len])?;
+    w.end_seq()?;
+    w.end_set()?;
+
+    w.end_seq()?;
+    w.end_ctx()?;
+    w.end_seq()?;
+    Ok(w.as_slice().len())
+}
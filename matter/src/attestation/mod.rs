@@ -0,0 +1,578 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! Verification of the device attestation, on the commissioner side
+//!
+//! The commissioner sends an Attestation Request with a random nonce, and the device responds
+//! with the attestation elements, signed by its DAC over the elements followed by the
+//! attestation challenge of the secure session. The DAC and the PAI are fetched with
+//! Certificate Chain Requests. [AttestationVerifier] checks all of these against a set of
+//! trusted PAAs and the known signers of the Certification Declaration.
+
+mod att_cert;
+mod cd;
+mod cms;
+mod paa_store;
+#[cfg(test)]
+pub(crate) mod test_vectors;
+
+pub use self::att_cert::AttCert;
+pub use self::cd::{CertDeclaration, MAX_CERT_DECLARATION_LEN};
+pub use self::paa_store::PaaStore;
+
+use std::fmt;
+
+use log::error;
+
+use self::cms::SignedData;
+use crate::{
+    cert::CertTime,
+    crypto::{self, CryptoKeyPair, KeyPair},
+    error::Error,
+    tlv::{get_root_node_struct, FromTLV},
+};
+
+/// The reasons that the device attestation fails for
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AttestationError {
+    /// The attestation elements couldn't be parsed
+    InvalidElements,
+    /// The attestation nonce isn't the one that was sent in the request
+    NonceMismatch,
+    /// The signature over the attestation elements and the challenge isn't by the DAC
+    InvalidAttestationSignature,
+    /// The DAC couldn't be parsed, or its extensions or VID/PID aren't fit for a DAC
+    InvalidDac,
+    /// The PAI couldn't be parsed, or its extensions or VID/PID aren't fit for a PAI
+    InvalidPai,
+    /// The PAA couldn't be parsed, or its extensions aren't fit for a PAA
+    InvalidPaa,
+    /// The PAA that the PAI refers to isn't in the trust store
+    PaaNotFound,
+    /// The DAC is outside of its validity period
+    DacExpired,
+    /// The PAI is outside of its validity period
+    PaiExpired,
+    /// The PAA is outside of its validity period
+    PaaExpired,
+    /// The DAC isn't issued by the PAI
+    DacSignatureInvalid,
+    /// The PAI isn't issued by the PAA
+    PaiSignatureInvalid,
+    /// The VID of the DAC and the PAI differ
+    DacVendorIdMismatch,
+    /// The VID of the PAI and the PAA differ
+    PaiVendorIdMismatch,
+    /// The PAI is for a single product, which isn't the one in the DAC
+    DacProductIdMismatch,
+    /// The Certification Declaration couldn't be parsed
+    InvalidCertDeclaration,
+    /// The signer of the Certification Declaration isn't a known one
+    CdSignerNotFound,
+    /// The signature of the Certification Declaration is invalid
+    CdSignatureInvalid,
+    /// The VID of the DAC isn't the one in the Certification Declaration
+    CdVendorIdMismatch,
+    /// The PID of the DAC isn't one of those in the Certification Declaration
+    CdProductIdMismatch,
+    /// The PAA isn't in the authorized PAA list of the Certification Declaration
+    PaaNotAuthorized,
+}
+
+impl fmt::Display for AttestationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for AttestationError {}
+
+/// What the device provides for its attestation
+pub struct AttestationData<'a> {
+    /// The attestation elements of the Attestation Response
+    pub elements: &'a [u8],
+    /// The signature of the Attestation Response
+    pub signature: &'a [u8],
+    /// The DAC in DER, from the Certificate Chain Response
+    pub dac: &'a [u8],
+    /// The PAI in DER, from the Certificate Chain Response
+    pub pai: &'a [u8],
+}
+
+/// The attested identity of the device
+pub struct AttestationInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub cert_dec: CertDeclaration,
+}
+
+pub struct AttestationVerifier {
+    paa_store: PaaStore,
+    // The Subject Key Identifier and public key of each signer
+    cd_signers: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl AttestationVerifier {
+    pub fn new(paa_store: PaaStore) -> Self {
+        Self {
+            paa_store,
+            cd_signers: Vec::new(),
+        }
+    }
+
+    /// Add a certificate in DER, whose key signs Certification Declarations
+    pub fn add_cd_signer(&mut self, der: &[u8]) -> Result<(), Error> {
+        let cert = AttCert::new(der)?;
+        let skid = cert.get_subject_key_id().ok_or(Error::InvalidAuthKey)?;
+        self.cd_signers
+            .push((skid.to_vec(), cert.get_pubkey().to_vec()));
+        Ok(())
+    }
+
+    /// Verify the attestation of a device
+    ///
+    /// The nonce is the one sent in the Attestation Request, and the challenge is that of the
+    /// secure session over which it was sent.
+    pub fn verify(
+        &self,
+        data: &AttestationData,
+        challenge: &[u8],
+        nonce: &[u8],
+        time: CertTime,
+    ) -> Result<AttestationInfo, AttestationError> {
+        // The signature comes from the device, don't let a malformed one reach the crypto
+        if data.signature.len() != crypto::EC_SIGNATURE_LEN_BYTES {
+            return Err(AttestationError::InvalidAttestationSignature);
+        }
+        let dac = AttCert::new(data.dac).map_err(|_| AttestationError::InvalidDac)?;
+        let pai = AttCert::new(data.pai).map_err(|_| AttestationError::InvalidPai)?;
+
+        let mut msg = data.elements.to_vec();
+        msg.extend_from_slice(challenge);
+        KeyPair::new_from_public(dac.get_pubkey())
+            .and_then(|key| key.verify_msg(&msg, data.signature))
+            .map_err(|_| AttestationError::InvalidAttestationSignature)?;
+
+        let paa_skid = pai.get_auth_key_id().ok_or(AttestationError::InvalidPai)?;
+        let paa = self
+            .paa_store
+            .find(paa_skid)
+            .ok_or(AttestationError::PaaNotFound)?;
+        let paa = AttCert::new(paa).map_err(|_| AttestationError::InvalidPaa)?;
+        let (vid, pid) = verify_chain(&dac, &pai, &paa, time)?;
+
+        let (cert_dec, elements_nonce) = get_root_node_struct(data.elements)
+            .and_then(|root| {
+                let cert_dec = root.find_tag(1)?.slice()?;
+                let nonce = root.find_tag(2)?.slice()?;
+                Ok((cert_dec, nonce))
+            })
+            .map_err(|_| AttestationError::InvalidElements)?;
+        if elements_nonce != nonce {
+            return Err(AttestationError::NonceMismatch);
+        }
+
+        let cert_dec = self.verify_cert_dec(cert_dec)?;
+        // The DAC origin is for products whose DAC is from another vendor's PAI
+        let (cd_vid, cd_pid_ok) = match (cert_dec.dac_origin_vid, cert_dec.dac_origin_pid) {
+            (Some(origin_vid), Some(origin_pid)) => (origin_vid, origin_pid == pid),
+            (None, None) => (cert_dec.vendor_id, cert_dec.has_product_id(pid)),
+            _ => return Err(AttestationError::InvalidCertDeclaration),
+        };
+        if cd_vid != vid {
+            return Err(AttestationError::CdVendorIdMismatch);
+        }
+        if !cd_pid_ok {
+            return Err(AttestationError::CdProductIdMismatch);
+        }
+        if !cert_dec.is_paa_authorized(paa_skid) {
+            return Err(AttestationError::PaaNotAuthorized);
+        }
+
+        Ok(AttestationInfo { vid, pid, cert_dec })
+    }
+
+    fn verify_cert_dec(&self, der: &[u8]) -> Result<CertDeclaration, AttestationError> {
+        let signed = SignedData::new(der).map_err(|_| AttestationError::InvalidCertDeclaration)?;
+        let (_, signer_pubkey) = self
+            .cd_signers
+            .iter()
+            .find(|(skid, _)| skid == signed.signer_key_id)
+            .ok_or(AttestationError::CdSignerNotFound)?;
+        signed
+            .verify(signer_pubkey)
+            .map_err(|_| AttestationError::CdSignatureInvalid)?;
+        get_root_node_struct(signed.content)
+            .and_then(|root| CertDeclaration::from_tlv(&root))
+            .map_err(|e| {
+                error!("Error parsing the Certification Declaration: {}", e);
+                AttestationError::InvalidCertDeclaration
+            })
+    }
+}
+
+// Returns the VID and PID that the chain attests to
+fn verify_chain(
+    dac: &AttCert,
+    pai: &AttCert,
+    paa: &AttCert,
+    time: CertTime,
+) -> Result<(u16, u16), AttestationError> {
+    dac.is_valid_usage(0)
+        .map_err(|_| AttestationError::InvalidDac)?;
+    pai.is_valid_usage(1)
+        .map_err(|_| AttestationError::InvalidPai)?;
+    paa.is_valid_usage(2)
+        .map_err(|_| AttestationError::InvalidPaa)?;
+
+    dac.is_valid_at(time)
+        .map_err(|_| AttestationError::DacExpired)?;
+    pai.is_valid_at(time)
+        .map_err(|_| AttestationError::PaiExpired)?;
+    paa.is_valid_at(time)
+        .map_err(|_| AttestationError::PaaExpired)?;
+
+    dac.verify_issued_by(pai)
+        .map_err(|_| AttestationError::DacSignatureInvalid)?;
+    pai.verify_issued_by(paa)
+        .map_err(|_| AttestationError::PaiSignatureInvalid)?;
+
+    // The DAC has both the VID and the PID, the PAI at least the VID, and the PAA may have it
+    let vid = dac.get_vid().ok_or(AttestationError::InvalidDac)?;
+    let pid = dac.get_pid().ok_or(AttestationError::InvalidDac)?;
+    let pai_vid = pai.get_vid().ok_or(AttestationError::InvalidPai)?;
+    if pai_vid != vid {
+        return Err(AttestationError::DacVendorIdMismatch);
+    }
+    if paa.get_vid().is_some_and(|paa_vid| paa_vid != pai_vid) {
+        return Err(AttestationError::PaiVendorIdMismatch);
+    }
+    if pai.get_pid().is_some_and(|pai_pid| pai_pid != pid) {
+        return Err(AttestationError::DacProductIdMismatch);
+    }
+    Ok((vid, pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{
+        test_vectors, AttestationData, AttestationError, AttestationVerifier, CertDeclaration,
+        PaaStore, MAX_CERT_DECLARATION_LEN,
+    };
+    use crate::{
+        cert::{pem, CertTime},
+        crypto::{self, CryptoKeyPair, KeyPair},
+        tlv::{TLVWriter, TagType},
+        utils::writebuf::WriteBuf,
+    };
+
+    // 2023-01-02, the test certificates are valid from 2023-01-01
+    const TEST_TIME: CertTime = CertTime::Trusted(725_932_800);
+    const NONCE: [u8; 32] = [0x5A; 32];
+    const CHALLENGE: [u8; 16] = [0xC4; 16];
+
+    fn verifier() -> AttestationVerifier {
+        let mut paa_store = PaaStore::new();
+        paa_store.add(&test_vectors::PAA).unwrap();
+        let mut verifier = AttestationVerifier::new(paa_store);
+        verifier.add_cd_signer(&test_vectors::CD_SIGNER).unwrap();
+        verifier
+    }
+
+    // The attestation elements for the CD and nonce, and their signature by the DAC
+    fn attest(cd: &[u8], nonce: &[u8], challenge: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut buf = [0u8; 800];
+        let len = buf.len();
+        let mut wb = WriteBuf::new(&mut buf, len);
+        let mut tw = TLVWriter::new(&mut wb);
+        tw.start_struct(TagType::Anonymous).unwrap();
+        tw.str16(TagType::Context(1), cd).unwrap();
+        tw.str8(TagType::Context(2), nonce).unwrap();
+        tw.u32(TagType::Context(3), 0).unwrap();
+        tw.end_container().unwrap();
+        let elements = wb.as_borrow_slice().to_vec();
+        let signature = sign(&elements, challenge);
+        (elements, signature)
+    }
+
+    fn sign(elements: &[u8], challenge: &[u8]) -> Vec<u8> {
+        let dac_key =
+            KeyPair::new_from_components(&test_vectors::DAC_PUBKEY, &test_vectors::DAC_PRIVKEY)
+                .unwrap();
+        let mut msg = elements.to_vec();
+        msg.extend_from_slice(challenge);
+        let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+        dac_key.sign_msg(&msg, &mut signature).unwrap();
+        signature.to_vec()
+    }
+
+    fn verify(
+        verifier: &AttestationVerifier,
+        cd: &[u8],
+        time: CertTime,
+    ) -> Result<(u16, u16), AttestationError> {
+        let (elements, signature) = attest(cd, &NONCE, &CHALLENGE);
+        let data = AttestationData {
+            elements: &elements,
+            signature: &signature,
+            dac: &test_vectors::DAC,
+            pai: &test_vectors::PAI,
+        };
+        verifier
+            .verify(&data, &CHALLENGE, &NONCE, time)
+            .map(|info| (info.vid, info.pid))
+    }
+
+    #[test]
+    fn test_verify_success() {
+        let verifier = verifier();
+        let (elements, signature) = attest(&test_vectors::CD, &NONCE, &CHALLENGE);
+        let data = AttestationData {
+            elements: &elements,
+            signature: &signature,
+            dac: &test_vectors::DAC,
+            pai: &test_vectors::PAI,
+        };
+        let info = verifier
+            .verify(&data, &CHALLENGE, &NONCE, TEST_TIME)
+            .unwrap();
+        assert_eq!((info.vid, info.pid), (0xFFF1, 0x8000));
+        assert_eq!(info.cert_dec.vendor_id, 0xFFF1);
+        assert!(info.cert_dec.has_product_id(0x8001));
+        assert_eq!(info.cert_dec.certificate_id, "ZIG20142ZB330003-24");
+
+        // Without a time, the validity period isn't checked
+        assert_eq!(
+            verify(&verifier, &test_vectors::CD, CertTime::Unknown),
+            Ok((0xFFF1, 0x8000))
+        );
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let verifier = verifier();
+        let (elements, signature) = attest(&test_vectors::CD, &NONCE, &CHALLENGE);
+        let mut data = AttestationData {
+            elements: &elements,
+            signature: &signature,
+            dac: &test_vectors::DAC,
+            pai: &test_vectors::PAI,
+        };
+
+        // The device signed for another session
+        assert_eq!(
+            verifier.verify(&data, &[0; 16], &NONCE, TEST_TIME).err(),
+            Some(AttestationError::InvalidAttestationSignature)
+        );
+        // The device responded to another request
+        assert_eq!(
+            verifier
+                .verify(&data, &CHALLENGE, &[0; 32], TEST_TIME)
+                .err(),
+            Some(AttestationError::NonceMismatch)
+        );
+        // The signature is truncated
+        data.signature = &signature[..10];
+        assert_eq!(
+            verifier.verify(&data, &CHALLENGE, &NONCE, TEST_TIME).err(),
+            Some(AttestationError::InvalidAttestationSignature)
+        );
+        // The elements are signed, but not a TLV structure
+        let elements = [0x15, 0x30, 0x01];
+        let signature = sign(&elements, &CHALLENGE);
+        data.elements = &elements;
+        data.signature = &signature;
+        assert_eq!(
+            verifier.verify(&data, &CHALLENGE, &NONCE, TEST_TIME).err(),
+            Some(AttestationError::InvalidElements)
+        );
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let verifier = verifier();
+        let (elements, signature) = attest(&test_vectors::CD, &NONCE, &CHALLENGE);
+        let data = AttestationData {
+            elements: &elements,
+            signature: &signature,
+            dac: &test_vectors::DAC,
+            pai: &test_vectors::PAI,
+        };
+
+        // An unknown PAA
+        let unknown_paa = AttestationVerifier::new(PaaStore::new());
+        assert_eq!(
+            unknown_paa
+                .verify(&data, &CHALLENGE, &NONCE, TEST_TIME)
+                .err(),
+            Some(AttestationError::PaaNotFound)
+        );
+        // Before the validity period
+        assert_eq!(
+            verify(&verifier, &test_vectors::CD, CertTime::Trusted(1_000)),
+            Err(AttestationError::DacExpired)
+        );
+        // The PAI in place of the DAC, which doesn't have the DAC key either
+        let swapped = AttestationData {
+            dac: &test_vectors::PAI,
+            ..data
+        };
+        assert_eq!(
+            verifier
+                .verify(&swapped, &CHALLENGE, &NONCE, TEST_TIME)
+                .err(),
+            Some(AttestationError::InvalidAttestationSignature)
+        );
+        // The PAA in place of the PAI, which is a CA but not the issuer of the DAC
+        let wrong_pai = AttestationData {
+            pai: &test_vectors::PAA,
+            ..data
+        };
+        assert_eq!(
+            verifier
+                .verify(&wrong_pai, &CHALLENGE, &NONCE, TEST_TIME)
+                .err(),
+            Some(AttestationError::DacSignatureInvalid)
+        );
+        // A corrupted DAC
+        let mut dac = test_vectors::DAC;
+        dac[20] ^= 0xFF;
+        let corrupt = AttestationData { dac: &dac, ..data };
+        assert_eq!(
+            verifier
+                .verify(&corrupt, &CHALLENGE, &NONCE, TEST_TIME)
+                .err(),
+            Some(AttestationError::InvalidDac)
+        );
+    }
+
+    #[test]
+    fn test_verify_cert_dec() {
+        let verifier = verifier();
+        assert_eq!(
+            verify(&verifier, &test_vectors::CD_OTHER_PID, TEST_TIME),
+            Err(AttestationError::CdProductIdMismatch)
+        );
+        assert_eq!(
+            verify(&verifier, &test_vectors::CD_OTHER_PAA, TEST_TIME),
+            Err(AttestationError::PaaNotAuthorized)
+        );
+        assert_eq!(
+            verify(&verifier, &test_vectors::CD[..100], TEST_TIME),
+            Err(AttestationError::InvalidCertDeclaration)
+        );
+
+        // The last byte is part of the signature
+        let mut cd = test_vectors::CD;
+        let len = cd.len();
+        cd[len - 1] ^= 0x01;
+        assert_eq!(
+            verify(&verifier, &cd, TEST_TIME),
+            Err(AttestationError::CdSignatureInvalid)
+        );
+
+        let no_signers = {
+            let mut paa_store = PaaStore::new();
+            paa_store.add(&test_vectors::PAA).unwrap();
+            AttestationVerifier::new(paa_store)
+        };
+        assert_eq!(
+            verify(&no_signers, &test_vectors::CD, TEST_TIME),
+            Err(AttestationError::CdSignerNotFound)
+        );
+    }
+
+    #[test]
+    fn test_sign_cert_dec() {
+        let verifier = verifier();
+        let signer = KeyPair::new_from_components(
+            &test_vectors::CD_SIGNER_PUBKEY,
+            &test_vectors::CD_SIGNER_PRIVKEY,
+        )
+        .unwrap();
+        let sign = |cert_dec: &CertDeclaration, signer_key_id: &[u8]| {
+            let mut buf = [0u8; MAX_CERT_DECLARATION_LEN];
+            let len = cert_dec.sign(signer_key_id, &signer, &mut buf).unwrap();
+            buf[..len].to_vec()
+        };
+
+        let mut cert_dec = CertDeclaration {
+            format_version: 1,
+            vendor_id: 0xFFF1,
+            product_ids: vec![0x8000, 0x8001].into(),
+            device_type_id: 0x0100,
+            certificate_id: "ZIG20142ZB330003-24".to_string(),
+            security_level: 0,
+            security_info: 0,
+            version_number: 0x2694,
+            certification_type: 0,
+            dac_origin_vid: None,
+            dac_origin_pid: None,
+            authorized_paa_list: Some(vec![test_vectors::PAA_SKID.to_vec()].into()),
+        };
+        let cd = sign(&cert_dec, &test_vectors::CD_SIGNER_SKID);
+        assert_eq!(verify(&verifier, &cd, TEST_TIME), Ok((0xFFF1, 0x8000)));
+
+        // A product with the DAC of another vendor
+        cert_dec.vendor_id = 0xFFF2;
+        cert_dec.product_ids = vec![0x1234].into();
+        cert_dec.dac_origin_vid = Some(0xFFF1);
+        cert_dec.dac_origin_pid = Some(0x8000);
+        let cd = sign(&cert_dec, &test_vectors::CD_SIGNER_SKID);
+        assert_eq!(verify(&verifier, &cd, TEST_TIME), Ok((0xFFF1, 0x8000)));
+
+        cert_dec.dac_origin_pid = Some(0x8001);
+        let cd = sign(&cert_dec, &test_vectors::CD_SIGNER_SKID);
+        assert_eq!(
+            verify(&verifier, &cd, TEST_TIME),
+            Err(AttestationError::CdProductIdMismatch)
+        );
+
+        let cd = sign(&cert_dec, &[0; 20]);
+        assert_eq!(
+            verify(&verifier, &cd, TEST_TIME),
+            Err(AttestationError::CdSignerNotFound)
+        );
+    }
+
+    #[test]
+    fn test_paa_store() {
+        let dir = std::env::temp_dir().join(format!("matter_paa_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("paa.pem"),
+            pem::encode(&test_vectors::PAA, pem::LABEL_CERTIFICATE),
+        )
+        .unwrap();
+        // Not a PAA, nor a certificate, nor a certificate file
+        fs::write(dir.join("pai.der"), test_vectors::PAI).unwrap();
+        fs::write(dir.join("junk.pem"), "junk").unwrap();
+        fs::write(dir.join("README"), "PAAs").unwrap();
+
+        let store = PaaStore::from_dir(&dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store.find(&test_vectors::PAA_SKID),
+            Some(&test_vectors::PAA[..])
+        );
+        assert_eq!(store.find(&[0; 20]), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
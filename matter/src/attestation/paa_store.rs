@@ -0,0 +1,94 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{fs, path::Path};
+
+use log::warn;
+
+use super::AttCert;
+use crate::{cert::pem, error::Error};
+
+/// The trusted Product Attestation Authorities
+///
+/// The PAAs are looked up by their Subject Key Identifier, which the PAI refers to through
+/// its Authority Key Identifier.
+#[derive(Default)]
+pub struct PaaStore {
+    paas: Vec<Vec<u8>>,
+}
+
+impl PaaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load all the certificates in a directory, either as .der or .pem files
+    ///
+    /// Files that aren't valid PAA certificates are skipped.
+    pub fn from_dir(dir: &Path) -> Result<Self, Error> {
+        let mut store = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let der = match path.extension().and_then(|e| e.to_str()) {
+                Some("der") => fs::read(&path)?,
+                Some("pem") => {
+                    match pem::decode(&fs::read_to_string(&path)?, pem::LABEL_CERTIFICATE) {
+                        Ok(der) => der,
+                        Err(e) => {
+                            warn!("Skipping {}: {}", path.display(), e);
+                            continue;
+                        }
+                    }
+                }
+                _ => continue,
+            };
+            if let Err(e) = store.add(&der) {
+                warn!("Skipping {}: {}", path.display(), e);
+            }
+        }
+        Ok(store)
+    }
+
+    /// Add a PAA certificate in DER
+    pub fn add(&mut self, der: &[u8]) -> Result<(), Error> {
+        let paa = AttCert::new(der)?;
+        paa.is_valid_usage(2)?;
+        paa.verify_self_signed()?;
+        let skid = paa.get_subject_key_id().ok_or(Error::InvalidAuthKey)?;
+        if self.find(skid).is_some() {
+            return Err(Error::Duplicate);
+        }
+        self.paas.push(der.to_vec());
+        Ok(())
+    }
+
+    /// The PAA certificate with the given Subject Key Identifier
+    pub fn find(&self, skid: &[u8]) -> Option<&[u8]> {
+        self.paas
+            .iter()
+            .find(|der| AttCert::new(der).is_ok_and(|paa| paa.get_subject_key_id() == Some(skid)))
+            .map(|der| der.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.paas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paas.is_empty()
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The test PKI for device attestation: a PAA, a PAI and a DAC for VID 0xFFF1 and PID
+//! 0x8000, and the Certification Declarations signed by a test CD signer
+
+pub const PAA: [u8; 430] = [
+    0x30, 0x82, 0x01, 0xaa, 0x30, 0x82, 0x01, 0x4f, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01,
+    0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x29, 0x31, 0x11,
+    0x30, 0x0f, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x08, 0x54, 0x65, 0x73, 0x74, 0x20, 0x50, 0x41,
+    0x41, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02,
+    0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x33, 0x30, 0x31, 0x30,
+    0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39, 0x39, 0x39, 0x39, 0x31, 0x32,
+    0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30, 0x29, 0x31, 0x11, 0x30, 0x0f, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x08, 0x54, 0x65, 0x73, 0x74, 0x20, 0x50, 0x41, 0x41, 0x31, 0x14,
+    0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04,
+    0x46, 0x46, 0x46, 0x31, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,
+    0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0xb8,
+    0x75, 0xaf, 0xd6, 0xbf, 0xc7, 0xd9, 0xd1, 0xfb, 0x48, 0x11, 0x79, 0xc2, 0xa0, 0xe3, 0x93, 0xd6,
+    0x05, 0x97, 0x8e, 0x0b, 0x2c, 0x46, 0x34, 0x01, 0x9e, 0xa1, 0x9a, 0xd7, 0xf3, 0x66, 0xfe, 0x62,
+    0x00, 0x73, 0x68, 0xdd, 0x68, 0x54, 0x39, 0x66, 0x75, 0x31, 0x25, 0x4d, 0x17, 0xe4, 0x88, 0xee,
+    0x71, 0x01, 0x49, 0xb9, 0xe9, 0xab, 0x9b, 0x8a, 0x55, 0xec, 0x25, 0x3a, 0xa6, 0xe9, 0xec, 0xa3,
+    0x66, 0x30, 0x64, 0x30, 0x12, 0x06, 0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff, 0x04, 0x08, 0x30,
+    0x06, 0x01, 0x01, 0xff, 0x02, 0x01, 0x01, 0x30, 0x0e, 0x06, 0x03, 0x55, 0x1d, 0x0f, 0x01, 0x01,
+    0xff, 0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16,
+    0x04, 0x14, 0x2f, 0x8e, 0x41, 0xb8, 0xeb, 0x3a, 0x90, 0x34, 0xe2, 0x86, 0xfa, 0xc5, 0x06, 0xb9,
+    0x86, 0x3d, 0xb3, 0xef, 0x10, 0x1f, 0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30,
+    0x16, 0x80, 0x14, 0x2f, 0x8e, 0x41, 0xb8, 0xeb, 0x3a, 0x90, 0x34, 0xe2, 0x86, 0xfa, 0xc5, 0x06,
+    0xb9, 0x86, 0x3d, 0xb3, 0xef, 0x10, 0x1f, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+    0x04, 0x03, 0x02, 0x03, 0x49, 0x00, 0x30, 0x46, 0x02, 0x21, 0x00, 0xe6, 0x0f, 0xcd, 0xc9, 0x0e,
+    0x13, 0x9c, 0xf1, 0x41, 0x27, 0x5b, 0x00, 0x56, 0xa8, 0xcb, 0x5c, 0x6d, 0xb5, 0x73, 0x80, 0x34,
+    0x15, 0x67, 0xef, 0x15, 0xd6, 0xcb, 0xbb, 0xc8, 0x04, 0x26, 0x48, 0x02, 0x21, 0x00, 0xc2, 0xc4,
+    0x86, 0x8e, 0x67, 0xf1, 0x41, 0xda, 0x46, 0xe9, 0x1b, 0x3e, 0x29, 0x5c, 0xff, 0x38, 0xb6, 0x58,
+    0x98, 0xff, 0xae, 0xfb, 0x57, 0xb5, 0x85, 0xb2, 0xc8, 0x04, 0x9c, 0x84, 0xb0, 0x21,
+];
+pub const PAI: [u8; 451] = [
+    0x30, 0x82, 0x01, 0xbf, 0x30, 0x82, 0x01, 0x65, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x02,
+    0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x29, 0x31, 0x11,
+    0x30, 0x0f, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x08, 0x54, 0x65, 0x73, 0x74, 0x20, 0x50, 0x41,
+    0x41, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02,
+    0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x33, 0x30, 0x31, 0x30,
+    0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39, 0x39, 0x39, 0x39, 0x31, 0x32,
+    0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30, 0x3f, 0x31, 0x11, 0x30, 0x0f, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x08, 0x54, 0x65, 0x73, 0x74, 0x20, 0x50, 0x41, 0x49, 0x31, 0x14,
+    0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04,
+    0x46, 0x46, 0x46, 0x31, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82,
+    0xa2, 0x7c, 0x02, 0x02, 0x0c, 0x04, 0x38, 0x30, 0x30, 0x30, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07,
+    0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01,
+    0x07, 0x03, 0x42, 0x00, 0x04, 0xaf, 0xca, 0x11, 0x1c, 0x8b, 0x33, 0x48, 0x4f, 0x15, 0x60, 0x6a,
+    0x46, 0x39, 0xf6, 0x41, 0x1d, 0x2d, 0x4b, 0x5c, 0xcd, 0x80, 0x85, 0xfe, 0x53, 0x2d, 0x3e, 0x7d,
+    0x24, 0x2e, 0x30, 0xca, 0x51, 0xe6, 0xaf, 0xbc, 0x2f, 0x07, 0x67, 0x87, 0x3c, 0x81, 0xf6, 0x21,
+    0x82, 0x8e, 0x70, 0xa2, 0x89, 0x3d, 0x1e, 0xaa, 0xff, 0x3c, 0x5d, 0xec, 0x7d, 0xd4, 0xeb, 0x7c,
+    0x96, 0xef, 0xf1, 0x83, 0x54, 0xa3, 0x66, 0x30, 0x64, 0x30, 0x12, 0x06, 0x03, 0x55, 0x1d, 0x13,
+    0x01, 0x01, 0xff, 0x04, 0x08, 0x30, 0x06, 0x01, 0x01, 0xff, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x06,
+    0x03, 0x55, 0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30, 0x1d, 0x06,
+    0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0xd8, 0x46, 0x01, 0xce, 0x79, 0x62, 0xd7, 0x16,
+    0xa1, 0xfd, 0xff, 0xa1, 0xb9, 0x7a, 0x2e, 0x8a, 0xb9, 0xdc, 0x00, 0xc9, 0x30, 0x1f, 0x06, 0x03,
+    0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x2f, 0x8e, 0x41, 0xb8, 0xeb, 0x3a, 0x90,
+    0x34, 0xe2, 0x86, 0xfa, 0xc5, 0x06, 0xb9, 0x86, 0x3d, 0xb3, 0xef, 0x10, 0x1f, 0x30, 0x0a, 0x06,
+    0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48, 0x00, 0x30, 0x45, 0x02, 0x20,
+    0x1b, 0x14, 0x4a, 0x8d, 0x25, 0x4a, 0xd7, 0x68, 0x14, 0x5b, 0x30, 0x96, 0x3d, 0xd4, 0x66, 0xdb,
+    0xf7, 0x63, 0xb4, 0x6e, 0xd3, 0xf8, 0x1c, 0xc0, 0x32, 0x3a, 0x68, 0x8e, 0x5c, 0xae, 0x40, 0x52,
+    0x02, 0x21, 0x00, 0xf4, 0x62, 0xac, 0x60, 0x76, 0x21, 0x75, 0x1f, 0x82, 0xdd, 0x5c, 0x1d, 0x1b,
+    0x8f, 0xbd, 0xb7, 0x10, 0x42, 0xb4, 0xad, 0x86, 0x5d, 0x97, 0x1f, 0xd1, 0xdd, 0x3b, 0x18, 0x50,
+    0x47, 0x8d, 0xc9,
+];
+pub const DAC: [u8; 467] = [
+    0x30, 0x82, 0x01, 0xcf, 0x30, 0x82, 0x01, 0x75, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x03,
+    0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x3f, 0x31, 0x11,
+    0x30, 0x0f, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x08, 0x54, 0x65, 0x73, 0x74, 0x20, 0x50, 0x41,
+    0x49, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02,
+    0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01,
+    0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x02, 0x0c, 0x04, 0x38, 0x30, 0x30, 0x30, 0x30, 0x20, 0x17,
+    0x0d, 0x32, 0x33, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f,
+    0x39, 0x39, 0x39, 0x39, 0x31, 0x32, 0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30,
+    0x3f, 0x31, 0x11, 0x30, 0x0f, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x08, 0x54, 0x65, 0x73, 0x74,
+    0x20, 0x44, 0x41, 0x43, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82,
+    0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a,
+    0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x02, 0x0c, 0x04, 0x38, 0x30, 0x30, 0x30,
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0xa4, 0x12, 0x6a, 0xb3, 0x8a,
+    0x1d, 0xd6, 0xed, 0x86, 0x30, 0x55, 0x03, 0x61, 0xb2, 0x71, 0x26, 0x14, 0xf5, 0x6e, 0xe3, 0xd6,
+    0x09, 0x4c, 0x2e, 0xbe, 0xf2, 0x98, 0x86, 0x28, 0xf2, 0x05, 0xba, 0x43, 0x8c, 0xcc, 0xb2, 0x9c,
+    0x0b, 0xb5, 0x7c, 0x5e, 0x20, 0xdd, 0xd6, 0xe3, 0x90, 0xc2, 0xa5, 0xa9, 0x68, 0xe4, 0x88, 0x08,
+    0x8a, 0xf9, 0x07, 0xa8, 0x1b, 0xcf, 0x6f, 0x20, 0x98, 0x83, 0xa4, 0xa3, 0x60, 0x30, 0x5e, 0x30,
+    0x0c, 0x06, 0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff, 0x04, 0x02, 0x30, 0x00, 0x30, 0x0e, 0x06,
+    0x03, 0x55, 0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30, 0x1d, 0x06,
+    0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0x10, 0x2b, 0x9c, 0x86, 0x8a, 0xf9, 0xba, 0xb0,
+    0xd4, 0x83, 0x80, 0x38, 0x09, 0x89, 0xce, 0x6c, 0x57, 0xfc, 0x68, 0x56, 0x30, 0x1f, 0x06, 0x03,
+    0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0xd8, 0x46, 0x01, 0xce, 0x79, 0x62, 0xd7,
+    0x16, 0xa1, 0xfd, 0xff, 0xa1, 0xb9, 0x7a, 0x2e, 0x8a, 0xb9, 0xdc, 0x00, 0xc9, 0x30, 0x0a, 0x06,
+    0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48, 0x00, 0x30, 0x45, 0x02, 0x21,
+    0x00, 0x83, 0xdd, 0x87, 0x49, 0x5c, 0x25, 0xf2, 0x4f, 0x92, 0x3d, 0x92, 0xfa, 0x9d, 0x5f, 0xeb,
+    0x5a, 0xa9, 0x8c, 0x20, 0xc8, 0x93, 0x74, 0x5e, 0x6c, 0x20, 0x1e, 0xf3, 0x25, 0x53, 0x76, 0x1a,
+    0x85, 0x02, 0x20, 0x0a, 0x43, 0xee, 0x37, 0x43, 0x3b, 0xd3, 0x82, 0xc7, 0x2b, 0xf9, 0x00, 0xd0,
+    0xc1, 0x04, 0x04, 0x6d, 0xc0, 0x97, 0x0a, 0xd4, 0x45, 0xba, 0x0a, 0x16, 0xe3, 0xb5, 0xb7, 0x45,
+    0x72, 0x3d, 0xa5,
+];
+pub const CD_SIGNER: [u8; 401] = [
+    0x30, 0x82, 0x01, 0x8d, 0x30, 0x82, 0x01, 0x33, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x04,
+    0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x1e, 0x31, 0x1c,
+    0x30, 0x1a, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x13, 0x54, 0x65, 0x73, 0x74, 0x20, 0x43, 0x44,
+    0x20, 0x53, 0x69, 0x67, 0x6e, 0x69, 0x6e, 0x67, 0x20, 0x4b, 0x65, 0x79, 0x30, 0x20, 0x17, 0x0d,
+    0x32, 0x33, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39,
+    0x39, 0x39, 0x39, 0x31, 0x32, 0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30, 0x1e,
+    0x31, 0x1c, 0x30, 0x1a, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x13, 0x54, 0x65, 0x73, 0x74, 0x20,
+    0x43, 0x44, 0x20, 0x53, 0x69, 0x67, 0x6e, 0x69, 0x6e, 0x67, 0x20, 0x4b, 0x65, 0x79, 0x30, 0x59,
+    0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48,
+    0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0x65, 0x55, 0x24, 0x00, 0xa5, 0x1d, 0x68,
+    0x19, 0xe7, 0x96, 0x52, 0x35, 0xfe, 0x9e, 0x3e, 0x3d, 0x66, 0x75, 0x73, 0x86, 0x8b, 0x4b, 0xf8,
+    0xb2, 0x03, 0x55, 0x47, 0xb2, 0xff, 0x46, 0xd5, 0x86, 0x63, 0x2a, 0xc7, 0x91, 0xc7, 0x46, 0x86,
+    0x5f, 0x4d, 0x96, 0x88, 0x81, 0x71, 0x6f, 0x61, 0xb1, 0xbc, 0x3f, 0x13, 0x1a, 0xc9, 0x1f, 0x30,
+    0xfd, 0x3b, 0x1e, 0xb2, 0x7b, 0x5e, 0x0c, 0x0d, 0x8c, 0xa3, 0x60, 0x30, 0x5e, 0x30, 0x0c, 0x06,
+    0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff, 0x04, 0x02, 0x30, 0x00, 0x30, 0x0e, 0x06, 0x03, 0x55,
+    0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30, 0x1d, 0x06, 0x03, 0x55,
+    0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0xf8, 0xc9, 0x54, 0x66, 0x1e, 0x25, 0xb5, 0x23, 0x25, 0x80,
+    0xe7, 0xb3, 0x92, 0xb0, 0x50, 0x32, 0x41, 0xe7, 0x81, 0x9c, 0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d,
+    0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0xf8, 0xc9, 0x54, 0x66, 0x1e, 0x25, 0xb5, 0x23, 0x25,
+    0x80, 0xe7, 0xb3, 0x92, 0xb0, 0x50, 0x32, 0x41, 0xe7, 0x81, 0x9c, 0x30, 0x0a, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48, 0x00, 0x30, 0x45, 0x02, 0x20, 0x4e, 0xf4,
+    0x6a, 0x8a, 0xdb, 0x41, 0x0a, 0xd4, 0x1a, 0x2a, 0xc6, 0xb9, 0xfe, 0xf1, 0xd4, 0x25, 0xc5, 0x45,
+    0xc3, 0x22, 0x78, 0x93, 0xf2, 0x32, 0x43, 0x8b, 0x6a, 0x3c, 0x6b, 0xe2, 0x6e, 0xd0, 0x02, 0x21,
+    0x00, 0xf2, 0x57, 0xb5, 0x83, 0x56, 0x22, 0x0b, 0x43, 0xbb, 0x85, 0xd0, 0x3c, 0x6e, 0x03, 0xf2,
+    0x8a, 0xba, 0xcb, 0xa2, 0x35, 0xe6, 0xfd, 0x5f, 0x71, 0xcb, 0x75, 0xbc, 0x22, 0x71, 0x4f, 0xb1,
+    0x98,
+];
+pub const CD: [u8; 267] = [
+    0x30, 0x82, 0x01, 0x07, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02, 0xa0,
+    0x81, 0xf9, 0x30, 0x81, 0xf6, 0x02, 0x01, 0x03, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x09, 0x60, 0x86,
+    0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x30, 0x63, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7,
+    0x0d, 0x01, 0x07, 0x01, 0xa0, 0x56, 0x04, 0x54, 0x15, 0x24, 0x00, 0x01, 0x25, 0x01, 0xf1, 0xff,
+    0x36, 0x02, 0x05, 0x00, 0x80, 0x05, 0x01, 0x80, 0x18, 0x26, 0x03, 0x00, 0x01, 0x00, 0x00, 0x2c,
+    0x04, 0x13, 0x5a, 0x49, 0x47, 0x32, 0x30, 0x31, 0x34, 0x32, 0x5a, 0x42, 0x33, 0x33, 0x30, 0x30,
+    0x30, 0x33, 0x2d, 0x32, 0x34, 0x24, 0x05, 0x00, 0x24, 0x06, 0x00, 0x25, 0x07, 0x94, 0x26, 0x24,
+    0x08, 0x00, 0x36, 0x0b, 0x10, 0x14, 0x2f, 0x8e, 0x41, 0xb8, 0xeb, 0x3a, 0x90, 0x34, 0xe2, 0x86,
+    0xfa, 0xc5, 0x06, 0xb9, 0x86, 0x3d, 0xb3, 0xef, 0x10, 0x1f, 0x18, 0x18, 0x31, 0x7d, 0x30, 0x7b,
+    0x02, 0x01, 0x03, 0x80, 0x14, 0xf8, 0xc9, 0x54, 0x66, 0x1e, 0x25, 0xb5, 0x23, 0x25, 0x80, 0xe7,
+    0xb3, 0x92, 0xb0, 0x50, 0x32, 0x41, 0xe7, 0x81, 0x9c, 0x30, 0x0b, 0x06, 0x09, 0x60, 0x86, 0x48,
+    0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04,
+    0x03, 0x02, 0x04, 0x47, 0x30, 0x45, 0x02, 0x20, 0x42, 0xb4, 0x99, 0x6a, 0x7f, 0x05, 0xae, 0xa2,
+    0xa0, 0x84, 0x8d, 0xc2, 0x62, 0xc2, 0xf7, 0xcd, 0x17, 0x43, 0x7d, 0x1b, 0xb6, 0x4b, 0xec, 0xa5,
+    0xa5, 0xf6, 0x39, 0xf2, 0x55, 0x25, 0x8e, 0x3b, 0x02, 0x21, 0x00, 0x91, 0xf3, 0xb0, 0xc9, 0xb2,
+    0x3c, 0xb9, 0x21, 0xd9, 0x95, 0x38, 0x6a, 0x31, 0xa9, 0x05, 0x78, 0x2d, 0x6d, 0x03, 0xfd, 0x07,
+    0x13, 0xd6, 0x1c, 0xcf, 0xda, 0x28, 0xc6, 0xbe, 0x46, 0xf3, 0x25,
+];
+pub const CD_OTHER_PID: [u8; 238] = [
+    0x30, 0x81, 0xeb, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02, 0xa0, 0x81,
+    0xdd, 0x30, 0x81, 0xda, 0x02, 0x01, 0x03, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x09, 0x60, 0x86, 0x48,
+    0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x30, 0x47, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d,
+    0x01, 0x07, 0x01, 0xa0, 0x3a, 0x04, 0x38, 0x15, 0x24, 0x00, 0x01, 0x25, 0x01, 0xf1, 0xff, 0x36,
+    0x02, 0x05, 0x01, 0x80, 0x18, 0x26, 0x03, 0x00, 0x01, 0x00, 0x00, 0x2c, 0x04, 0x13, 0x5a, 0x49,
+    0x47, 0x32, 0x30, 0x31, 0x34, 0x32, 0x5a, 0x42, 0x33, 0x33, 0x30, 0x30, 0x30, 0x33, 0x2d, 0x32,
+    0x34, 0x24, 0x05, 0x00, 0x24, 0x06, 0x00, 0x25, 0x07, 0x94, 0x26, 0x24, 0x08, 0x00, 0x18, 0x31,
+    0x7d, 0x30, 0x7b, 0x02, 0x01, 0x03, 0x80, 0x14, 0xf8, 0xc9, 0x54, 0x66, 0x1e, 0x25, 0xb5, 0x23,
+    0x25, 0x80, 0xe7, 0xb3, 0x92, 0xb0, 0x50, 0x32, 0x41, 0xe7, 0x81, 0x9c, 0x30, 0x0b, 0x06, 0x09,
+    0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48,
+    0xce, 0x3d, 0x04, 0x03, 0x02, 0x04, 0x47, 0x30, 0x45, 0x02, 0x20, 0x67, 0x09, 0x92, 0xb6, 0x47,
+    0xa1, 0xb7, 0x45, 0x50, 0x3d, 0xb3, 0xc9, 0x32, 0x7b, 0xb0, 0xc0, 0x8e, 0xf7, 0xcf, 0x1a, 0x64,
+    0x63, 0xb2, 0xee, 0x83, 0x83, 0x50, 0x6b, 0xae, 0xf3, 0x21, 0xa6, 0x02, 0x21, 0x00, 0xbf, 0x16,
+    0x4d, 0x71, 0x1e, 0x39, 0xe7, 0x71, 0x66, 0x9a, 0x92, 0xc8, 0x43, 0xfa, 0x88, 0x5f, 0x01, 0x70,
+    0xa2, 0x48, 0x3d, 0x1d, 0x02, 0x38, 0x84, 0x7b, 0xe6, 0xe7, 0x14, 0x6d, 0xdf, 0xc2,
+];
+pub const CD_OTHER_PAA: [u8; 268] = [
+    0x30, 0x82, 0x01, 0x08, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02, 0xa0,
+    0x81, 0xfa, 0x30, 0x81, 0xf7, 0x02, 0x01, 0x03, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x09, 0x60, 0x86,
+    0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x30, 0x63, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7,
+    0x0d, 0x01, 0x07, 0x01, 0xa0, 0x56, 0x04, 0x54, 0x15, 0x24, 0x00, 0x01, 0x25, 0x01, 0xf1, 0xff,
+    0x36, 0x02, 0x05, 0x00, 0x80, 0x05, 0x01, 0x80, 0x18, 0x26, 0x03, 0x00, 0x01, 0x00, 0x00, 0x2c,
+    0x04, 0x13, 0x5a, 0x49, 0x47, 0x32, 0x30, 0x31, 0x34, 0x32, 0x5a, 0x42, 0x33, 0x33, 0x30, 0x30,
+    0x30, 0x33, 0x2d, 0x32, 0x34, 0x24, 0x05, 0x00, 0x24, 0x06, 0x00, 0x25, 0x07, 0x94, 0x26, 0x24,
+    0x08, 0x00, 0x36, 0x0b, 0x10, 0x14, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a,
+    0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x18, 0x18, 0x31, 0x7e, 0x30, 0x7c,
+    0x02, 0x01, 0x03, 0x80, 0x14, 0xf8, 0xc9, 0x54, 0x66, 0x1e, 0x25, 0xb5, 0x23, 0x25, 0x80, 0xe7,
+    0xb3, 0x92, 0xb0, 0x50, 0x32, 0x41, 0xe7, 0x81, 0x9c, 0x30, 0x0b, 0x06, 0x09, 0x60, 0x86, 0x48,
+    0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04,
+    0x03, 0x02, 0x04, 0x48, 0x30, 0x46, 0x02, 0x21, 0x00, 0xfc, 0x5d, 0xb2, 0xe4, 0x99, 0x49, 0xc1,
+    0xc8, 0x37, 0xa1, 0xa6, 0x74, 0xbd, 0x38, 0x60, 0x3a, 0x53, 0xc8, 0xa1, 0x69, 0x8c, 0x0d, 0x23,
+    0x0c, 0x1b, 0xdc, 0x47, 0xbd, 0xde, 0x71, 0x2a, 0x1a, 0x02, 0x21, 0x00, 0x9a, 0xaf, 0x69, 0x72,
+    0xc1, 0xb6, 0x50, 0x1a, 0x40, 0x63, 0x30, 0xee, 0x5e, 0x94, 0x46, 0x26, 0x78, 0x92, 0x0f, 0x3e,
+    0x40, 0x68, 0x40, 0xd3, 0xbc, 0x9c, 0x75, 0x1d, 0xcc, 0x52, 0xa9, 0x29,
+];
+pub const DAC_PUBKEY: [u8; 65] = [
+    0x04, 0xa4, 0x12, 0x6a, 0xb3, 0x8a, 0x1d, 0xd6, 0xed, 0x86, 0x30, 0x55, 0x03, 0x61, 0xb2, 0x71,
+    0x26, 0x14, 0xf5, 0x6e, 0xe3, 0xd6, 0x09, 0x4c, 0x2e, 0xbe, 0xf2, 0x98, 0x86, 0x28, 0xf2, 0x05,
+    0xba, 0x43, 0x8c, 0xcc, 0xb2, 0x9c, 0x0b, 0xb5, 0x7c, 0x5e, 0x20, 0xdd, 0xd6, 0xe3, 0x90, 0xc2,
+    0xa5, 0xa9, 0x68, 0xe4, 0x88, 0x08, 0x8a, 0xf9, 0x07, 0xa8, 0x1b, 0xcf, 0x6f, 0x20, 0x98, 0x83,
+    0xa4,
+];
+pub const DAC_PRIVKEY: [u8; 32] = [
+    0x94, 0x28, 0x4d, 0xad, 0x64, 0x96, 0xb5, 0x05, 0x3c, 0xed, 0x71, 0x24, 0xc4, 0xc9, 0x6f, 0x1f,
+    0x44, 0x8c, 0x19, 0x2d, 0xb7, 0x67, 0x88, 0x31, 0xfb, 0x75, 0x09, 0x82, 0x1e, 0xa6, 0xcb, 0x88,
+];
+pub const CD_SIGNER_SKID: [u8; 20] = [
+    0xf8, 0xc9, 0x54, 0x66, 0x1e, 0x25, 0xb5, 0x23, 0x25, 0x80, 0xe7, 0xb3, 0x92, 0xb0, 0x50, 0x32,
+    0x41, 0xe7, 0x81, 0x9c,
+];
+pub const CD_SIGNER_PUBKEY: [u8; 65] = [
+    0x04, 0x65, 0x55, 0x24, 0x00, 0xa5, 0x1d, 0x68, 0x19, 0xe7, 0x96, 0x52, 0x35, 0xfe, 0x9e, 0x3e,
+    0x3d, 0x66, 0x75, 0x73, 0x86, 0x8b, 0x4b, 0xf8, 0xb2, 0x03, 0x55, 0x47, 0xb2, 0xff, 0x46, 0xd5,
+    0x86, 0x63, 0x2a, 0xc7, 0x91, 0xc7, 0x46, 0x86, 0x5f, 0x4d, 0x96, 0x88, 0x81, 0x71, 0x6f, 0x61,
+    0xb1, 0xbc, 0x3f, 0x13, 0x1a, 0xc9, 0x1f, 0x30, 0xfd, 0x3b, 0x1e, 0xb2, 0x7b, 0x5e, 0x0c, 0x0d,
+    0x8c,
+];
+pub const CD_SIGNER_PRIVKEY: [u8; 32] = [
+    0xe9, 0x46, 0xce, 0x62, 0x87, 0xa3, 0x5a, 0xdd, 0x78, 0x5a, 0xec, 0x42, 0x30, 0xd9, 0x24, 0xd1,
+    0xcf, 0x9a, 0xfb, 0x73, 0xb9, 0x8b, 0xfc, 0x4b, 0xee, 0xc9, 0xf8, 0x8b, 0x5e, 0x2a, 0xb6, 0x02,
+];
+pub const PAA_SKID: [u8; 20] = [
+    0x2f, 0x8e, 0x41, 0xb8, 0xeb, 0x3a, 0x90, 0x34, 0xe2, 0x86, 0xfa, 0xc5, 0x06, 0xb9, 0x86, 0x3d,
+    0xb3, 0xef, 0x10, 0x1f,
+];
+pub const DAC_KEY_SEC1: [u8; 121] = [
+    0x30, 0x77, 0x02, 0x01, 0x01, 0x04, 0x20, 0x94, 0x28, 0x4d, 0xad, 0x64, 0x96, 0xb5, 0x05, 0x3c,
+    0xed, 0x71, 0x24, 0xc4, 0xc9, 0x6f, 0x1f, 0x44, 0x8c, 0x19, 0x2d, 0xb7, 0x67, 0x88, 0x31, 0xfb,
+    0x75, 0x09, 0x82, 0x1e, 0xa6, 0xcb, 0x88, 0xa0, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+    0x03, 0x01, 0x07, 0xa1, 0x44, 0x03, 0x42, 0x00, 0x04, 0xa4, 0x12, 0x6a, 0xb3, 0x8a, 0x1d, 0xd6,
+    0xed, 0x86, 0x30, 0x55, 0x03, 0x61, 0xb2, 0x71, 0x26, 0x14, 0xf5, 0x6e, 0xe3, 0xd6, 0x09, 0x4c,
+    0x2e, 0xbe, 0xf2, 0x98, 0x86, 0x28, 0xf2, 0x05, 0xba, 0x43, 0x8c, 0xcc, 0xb2, 0x9c, 0x0b, 0xb5,
+    0x7c, 0x5e, 0x20, 0xdd, 0xd6, 0xe3, 0x90, 0xc2, 0xa5, 0xa9, 0x68, 0xe4, 0x88, 0x08, 0x8a, 0xf9,
+    0x07, 0xa8, 0x1b, 0xcf, 0x6f, 0x20, 0x98, 0x83, 0xa4,
+];
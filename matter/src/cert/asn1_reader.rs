@@ -0,0 +1,155 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::error::Error;
+
+pub const TAG_BOOLEAN: u8 = 0x01;
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTF8_STRING: u8 = 0x0C;
+pub const TAG_PRINTABLE_STRING: u8 = 0x13;
+pub const TAG_UTC_TIME: u8 = 0x17;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+
+/// A reader for DER encoded data, which returns the elements in the order in which they appear
+#[derive(Debug)]
+pub struct ASN1Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ASN1Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.buf.first().copied()
+    }
+
+    /// Read the next element, returning its tag, the element as a whole, and its contents
+    fn next(&mut self) -> Result<(u8, &'a [u8], &'a [u8]), Error> {
+        let tag = self.peek_tag().ok_or(Error::TruncatedPacket)?;
+        let first = *self.buf.get(1).ok_or(Error::TruncatedPacket)?;
+        let (len, offset) = if first < 0x80 {
+            (first as usize, 2)
+        } else {
+            // DER only has the definite form, and we don't have anything larger than 64K
+            let bytes = (first & 0x7F) as usize;
+            if bytes == 0 || bytes > 2 {
+                return Err(Error::Invalid);
+            }
+            let len_bytes = self.buf.get(2..2 + bytes).ok_or(Error::TruncatedPacket)?;
+            let len = len_bytes.iter().fold(0, |len, b| (len << 8) | *b as usize);
+            (len, 2 + bytes)
+        };
+        let element = self.buf.get(..offset + len).ok_or(Error::TruncatedPacket)?;
+        self.buf = &self.buf[offset + len..];
+        Ok((tag, element, &element[offset..]))
+    }
+
+    fn next_of(&mut self, tag: u8) -> Result<(&'a [u8], &'a [u8]), Error> {
+        if self.peek_tag() != Some(tag) {
+            return Err(Error::Invalid);
+        }
+        self.next()
+            .map(|(_, element, contents)| (element, contents))
+    }
+
+    /// Read the next element, whatever its tag, and return the tag and its contents
+    pub fn read_any(&mut self) -> Result<(u8, &'a [u8]), Error> {
+        self.next().map(|(tag, _, contents)| (tag, contents))
+    }
+
+    /// Read the next element, which has to be of the given tag, and return its contents
+    pub fn read(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        self.next_of(tag).map(|(_, contents)| contents)
+    }
+
+    /// Read the next element, which has to be of the given tag, and return it as a whole
+    pub fn read_raw(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        self.next_of(tag).map(|(element, _)| element)
+    }
+
+    pub fn read_optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>, Error> {
+        if self.peek_tag() == Some(tag) {
+            self.read(tag).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the next element, which has to be of the given tag, and return a reader for
+    /// its contents
+    pub fn enter(&mut self, tag: u8) -> Result<ASN1Reader<'a>, Error> {
+        self.read(tag).map(ASN1Reader::new)
+    }
+
+    pub fn expect_oid(&mut self, oid: &[u8]) -> Result<(), Error> {
+        if self.read(TAG_OID)? == oid {
+            Ok(())
+        } else {
+            Err(Error::InvalidData)
+        }
+    }
+
+    /// The contents of a BIT STRING, which has to be a whole number of bytes
+    pub fn bitstr(&mut self) -> Result<&'a [u8], Error> {
+        match self.read(TAG_BIT_STRING)?.split_first() {
+            Some((0, bits)) => Ok(bits),
+            _ => Err(Error::InvalidData),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ASN1Reader, TAG_BOOLEAN, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE};
+    use crate::error::Error;
+
+    #[test]
+    fn test_read() {
+        let mut long = vec![TAG_SEQUENCE, 0x81, 0x80, TAG_OCTET_STRING, 0x7E];
+        long.extend_from_slice(&[0xAB; 0x7E]);
+        let mut r = ASN1Reader::new(&long);
+        let mut seq = r.enter(TAG_SEQUENCE).unwrap();
+        assert!(r.is_empty());
+        assert_eq!(seq.read_optional(TAG_BOOLEAN), Ok(None));
+        assert_eq!(seq.read(TAG_OCTET_STRING), Ok(&[0xAB; 0x7E][..]));
+        assert!(seq.is_empty());
+
+        let data = [TAG_INTEGER, 0x01, 0x05, TAG_BOOLEAN, 0x01, 0xFF];
+        let mut r = ASN1Reader::new(&data);
+        assert_eq!(r.read(TAG_BOOLEAN), Err(Error::Invalid));
+        assert_eq!(r.read_raw(TAG_INTEGER), Ok(&data[..3]));
+        assert_eq!(r.read_any(), Ok((TAG_BOOLEAN, &data[5..])));
+        assert_eq!(r.read_any(), Err(Error::TruncatedPacket));
+
+        // Truncated, and indefinite length
+        let mut r = ASN1Reader::new(&[TAG_SEQUENCE, 0x03, TAG_INTEGER, 0x01]);
+        assert_eq!(r.read(TAG_SEQUENCE), Err(Error::TruncatedPacket));
+        let mut r = ASN1Reader::new(&[TAG_SEQUENCE, 0x80, 0x00, 0x00]);
+        assert_eq!(r.read(TAG_SEQUENCE), Err(Error::Invalid));
+    }
+}
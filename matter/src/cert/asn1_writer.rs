@@ -15,7 +15,7 @@
  *    limitations under the License.
  */
 
-use super::{CertConsumer, MAX_DEPTH};
+use super::{CertConsumer, MAX_DEPTH, NO_EXPIRY_TIME};
 use crate::error::Error;
 use chrono::{Datelike, TimeZone, Utc};
 use log::warn;
@@ -286,4 +286,8 @@ impl<'a> CertConsumer for ASN1Writer<'a> {
             self.write_str(0x17, time_str.as_bytes())
         }
     }
+
+    fn no_expiry_time(&mut self, _tag: &str) -> Result<(), Error> {
+        self.write_str(0x18, NO_EXPIRY_TIME.as_bytes())
+    }
 }
@@ -0,0 +1,308 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use rand::RngCore;
+
+use super::{
+    BasicConstraints, Cert, DistNameValue, DnTags, EcCurveIdValue, PubKeyAlgoValue, SignAlgoValue,
+    EXT_KEY_USAGE_CLIENT_AUTH, EXT_KEY_USAGE_SERVER_AUTH, KEY_USAGE_CRL_SIGN,
+    KEY_USAGE_DIGITAL_SIGN, KEY_USAGE_KEY_CERT_SIGN, MAX_ASN1_CERT_SIZE,
+};
+use crate::{
+    crypto::{self, CryptoKeyPair, KeyPair, Sha256},
+    error::Error,
+};
+
+const SERIAL_NO_LEN: usize = 8;
+const SUBJECT_KEY_ID_LEN: usize = 20;
+
+/// The Matter specific attributes of a certificate's Distinguished Name
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MatterDn {
+    NodeId(u64),
+    FabricId(u64),
+    NocCat(u32),
+    IcaId(u64),
+    RootCaId(u64),
+}
+
+impl MatterDn {
+    fn to_dn(self) -> (u8, DistNameValue) {
+        let (tag, value) = match self {
+            MatterDn::NodeId(v) => (DnTags::NodeId, v),
+            MatterDn::FabricId(v) => (DnTags::FabricId, v),
+            MatterDn::NocCat(v) => (DnTags::NocCat, v as u64),
+            MatterDn::IcaId(v) => (DnTags::IcaId, v),
+            MatterDn::RootCaId(v) => (DnTags::RootCaId, v),
+        };
+        (tag as u8, DistNameValue::Uint(value))
+    }
+}
+
+/// The kinds of Matter operational certificates, which differ in their extensions
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CertProfile {
+    Rcac,
+    Icac,
+    Noc,
+}
+
+/// Builds and signs Matter certificates
+///
+/// Without a call to set_issuer(), the certificate is self-signed and so has to be
+/// signed with the key pair of its own public key.
+pub struct CertBuilder {
+    cert: Cert,
+    self_signed: bool,
+}
+
+impl Default for CertBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CertBuilder {
+    pub fn new() -> Self {
+        let mut serial_no = vec![0; SERIAL_NO_LEN];
+        rand::thread_rng().fill_bytes(&mut serial_no);
+        // Keep the serial number positive, and its DER encoding at the full length
+        serial_no[0] = (serial_no[0] & 0x7F) | 0x40;
+
+        Self {
+            cert: Cert {
+                serial_no,
+                sign_algo: SignAlgoValue::ECDSAWithSHA256 as u8,
+                pubkey_algo: PubKeyAlgoValue::EcPubKey as u8,
+                ec_curve_id: EcCurveIdValue::Prime256V1 as u8,
+                ..Default::default()
+            },
+            self_signed: true,
+        }
+    }
+
+    /// Create a builder with the extensions that the Matter specification mandates for the
+    /// given kind of certificate
+    pub fn new_with(profile: CertProfile) -> Self {
+        let b = Self::new();
+        match profile {
+            CertProfile::Rcac | CertProfile::Icac => b
+                .set_basic_constraints(true, None)
+                .set_key_usage(KEY_USAGE_KEY_CERT_SIGN | KEY_USAGE_CRL_SIGN),
+            CertProfile::Noc => b
+                .set_basic_constraints(false, None)
+                .set_key_usage(KEY_USAGE_DIGITAL_SIGN)
+                .set_ext_key_usage(&[EXT_KEY_USAGE_CLIENT_AUTH, EXT_KEY_USAGE_SERVER_AUTH]),
+        }
+    }
+
+    pub fn set_serial_no(mut self, serial_no: &[u8]) -> Self {
+        self.cert.serial_no = serial_no.to_vec();
+        self
+    }
+
+    /// The validity period in seconds since the Matter epoch, a not_after of 0 means that
+    /// the certificate doesn't expire
+    pub fn set_validity(mut self, not_before: u32, not_after: u32) -> Self {
+        self.cert.not_before = not_before;
+        self.cert.not_after = not_after;
+        self
+    }
+
+    pub fn add_subject(mut self, dn: MatterDn) -> Self {
+        self.cert.subject.dn.push(dn.to_dn());
+        self
+    }
+
+    /// The certificate is issued by, and has to be signed with the key pair of, the
+    /// given certificate
+    pub fn set_issuer(mut self, issuer: &Cert) -> Result<Self, Error> {
+        self.cert.issuer = issuer.subject.clone();
+        self.cert.extensions.auth_key_id = Some(issuer.get_subject_key_id()?.to_vec());
+        self.self_signed = false;
+        Ok(self)
+    }
+
+    pub fn set_pubkey(mut self, pubkey: &[u8]) -> Result<Self, Error> {
+        if pubkey.len() != crypto::EC_POINT_LEN_BYTES {
+            return Err(Error::InvalidKeyLength);
+        }
+        self.cert.pubkey = pubkey.to_vec();
+        Ok(self)
+    }
+
+    pub fn set_basic_constraints(mut self, is_ca: bool, path: Option<u8>) -> Self {
+        self.cert.extensions.basic_const = Some(BasicConstraints { is_ca, path });
+        self
+    }
+
+    pub fn set_key_usage(mut self, key_usage: u16) -> Self {
+        self.cert.extensions.key_usage = Some(key_usage);
+        self
+    }
+
+    pub fn set_ext_key_usage(mut self, ext_key_usage: &[u8]) -> Self {
+        self.cert.extensions.ext_key_usage = Some(ext_key_usage.to_vec().into());
+        self
+    }
+
+    /// If not set, the subject key identifier is derived from the public key
+    pub fn set_subject_key_id(mut self, key_id: &[u8]) -> Self {
+        self.cert.extensions.subj_key_id = Some(key_id.to_vec());
+        self
+    }
+
+    pub fn sign(mut self, key_pair: &KeyPair) -> Result<Cert, Error> {
+        if self.cert.pubkey.is_empty() || self.cert.subject.dn.is_empty() {
+            return Err(Error::Invalid);
+        }
+        if self.cert.extensions.subj_key_id.is_none() {
+            // As per RFC 7093, the leftmost 160 bits of the SHA-256 hash of the public key
+            let mut hash = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+            let mut sha = Sha256::new()?;
+            sha.update(&self.cert.pubkey)?;
+            sha.finish(&mut hash)?;
+            self.cert.extensions.subj_key_id = Some(hash[..SUBJECT_KEY_ID_LEN].to_vec());
+        }
+        if self.self_signed {
+            let mut pubkey = [0u8; crypto::EC_POINT_LEN_BYTES];
+            let len = key_pair.get_public_key(&mut pubkey)?;
+            if pubkey[..len] != self.cert.pubkey[..] {
+                return Err(Error::InvalidAuthKey);
+            }
+            self.cert.issuer = self.cert.subject.clone();
+            self.cert.extensions.auth_key_id = self.cert.extensions.subj_key_id.clone();
+        }
+
+        let mut tbs = [0u8; MAX_ASN1_CERT_SIZE];
+        let len = self.cert.as_asn1(&mut tbs)?;
+        let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+        let len = key_pair.sign_msg(&tbs[..len], &mut signature)?;
+        self.cert.signature = signature[..len].to_vec();
+        Ok(self.cert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cert::{Cert, CertTime},
+        crypto::{self, CryptoKeyPair, KeyPair},
+        error::Error,
+    };
+
+    use super::{CertBuilder, CertProfile, MatterDn};
+
+    // 2023-01-01 until 2032-12-31
+    const NOT_BEFORE: u32 = 725_846_400;
+    const NOT_AFTER: u32 = 1_041_379_200;
+
+    fn pubkey(key_pair: &KeyPair) -> Vec<u8> {
+        let mut pubkey = [0u8; crypto::EC_POINT_LEN_BYTES];
+        let len = key_pair.get_public_key(&mut pubkey).unwrap();
+        pubkey[..len].to_vec()
+    }
+
+    #[test]
+    fn test_build_chain() {
+        let rcac_key = KeyPair::new().unwrap();
+        let rcac = CertBuilder::new_with(CertProfile::Rcac)
+            .set_validity(NOT_BEFORE, NOT_AFTER)
+            .add_subject(MatterDn::RootCaId(1))
+            .set_pubkey(&pubkey(&rcac_key))
+            .unwrap()
+            .sign(&rcac_key)
+            .unwrap();
+
+        let icac_key = KeyPair::new().unwrap();
+        let icac = CertBuilder::new_with(CertProfile::Icac)
+            .set_validity(NOT_BEFORE, NOT_AFTER)
+            .add_subject(MatterDn::IcaId(2))
+            .add_subject(MatterDn::FabricId(0xABCD))
+            .set_pubkey(&pubkey(&icac_key))
+            .unwrap()
+            .set_issuer(&rcac)
+            .unwrap()
+            .sign(&rcac_key)
+            .unwrap();
+
+        let noc_key = KeyPair::new().unwrap();
+        let noc = CertBuilder::new_with(CertProfile::Noc)
+            .set_validity(NOT_BEFORE, NOT_AFTER)
+            .add_subject(MatterDn::NodeId(0x1234))
+            .add_subject(MatterDn::FabricId(0xABCD))
+            .add_subject(MatterDn::NocCat(0x0001_0001))
+            .set_pubkey(&pubkey(&noc_key))
+            .unwrap()
+            .set_issuer(&icac)
+            .unwrap()
+            .sign(&icac_key)
+            .unwrap();
+
+        assert_eq!(noc.get_node_id(), Ok(0x1234));
+        assert_eq!(noc.get_fabric_id(), Ok(0xABCD));
+        let mut cats = [0u32; 2];
+        noc.get_cat_ids(&mut cats);
+        assert_eq!(cats, [0x0001_0001, 0]);
+
+        noc.verify_chain_start(CertTime::Trusted(NOT_BEFORE))
+            .add_cert(&icac)
+            .unwrap()
+            .add_cert(&rcac)
+            .unwrap()
+            .finalise()
+            .unwrap();
+
+        // The TLV form parses back to the same certificate
+        let mut tlv = [0u8; 400];
+        let len = noc.as_tlv(&mut tlv).unwrap();
+        let parsed = Cert::new(&tlv[..len]).unwrap();
+        let mut tlv_again = [0u8; 400];
+        assert_eq!(parsed.as_tlv(&mut tlv_again), Ok(len));
+        assert_eq!(tlv[..len], tlv_again[..len]);
+
+        // The DER form wraps the to-be-signed part
+        let mut tbs = [0u8; 1000];
+        let tbs_len = noc.as_asn1(&mut tbs).unwrap();
+        let mut der = [0u8; 1000];
+        let der_len = noc.as_der(&mut der).unwrap();
+        assert_eq!(der[0], 0x30);
+        assert_eq!(der[4..4 + tbs_len], tbs[..tbs_len]);
+        assert!(der_len > tbs_len + crypto::EC_SIGNATURE_LEN_BYTES);
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        let key_pair = KeyPair::new().unwrap();
+        let other = KeyPair::new().unwrap();
+
+        // A self-signed certificate has to be signed with its own key
+        let b = CertBuilder::new_with(CertProfile::Rcac)
+            .add_subject(MatterDn::RootCaId(1))
+            .set_pubkey(&pubkey(&key_pair))
+            .unwrap();
+        assert_eq!(b.sign(&other).map(|_| ()), Err(Error::InvalidAuthKey));
+
+        // The subject is mandatory
+        let b = CertBuilder::new_with(CertProfile::Rcac)
+            .set_pubkey(&pubkey(&key_pair))
+            .unwrap();
+        assert_eq!(b.sign(&key_pair).map(|_| ()), Err(Error::Invalid));
+
+        assert!(CertBuilder::new().set_pubkey(&[0x04; 10]).is_err());
+    }
+}
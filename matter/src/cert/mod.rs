@@ -15,25 +15,27 @@
  *    limitations under the License.
  */
 
-use std::fmt;
+use std::{convert::TryFrom, fmt};
 
 use crate::{
-    crypto::{CryptoKeyPair, KeyPair},
+    crypto::{self, CryptoKeyPair, KeyPair},
     error::Error,
     tlv::{self, FromTLV, TLVArrayOwned, TLVElement, TLVWriter, TagType, ToTLV},
-    utils::writebuf::WriteBuf,
+    utils::{clock::Clock, writebuf::WriteBuf},
 };
 use log::error;
 use num_derive::FromPrimitive;
 
+pub use self::asn1_reader::ASN1Reader;
 pub use self::asn1_writer::ASN1Writer;
+pub use self::builder::{CertBuilder, CertProfile, MatterDn};
 use self::printer::CertPrinter;
 
 // As per https://datatracker.ietf.org/doc/html/rfc5280
 
-const OID_PUB_KEY_ECPUBKEY: [u8; 7] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
-const OID_EC_TYPE_PRIME256V1: [u8; 8] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
-const OID_ECDSA_WITH_SHA256: [u8; 8] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+pub(crate) const OID_PUB_KEY_ECPUBKEY: [u8; 7] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+pub(crate) const OID_EC_TYPE_PRIME256V1: [u8; 8] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+pub(crate) const OID_ECDSA_WITH_SHA256: [u8; 8] = [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
 
 #[derive(FromPrimitive)]
 pub enum CertTags {
@@ -77,15 +79,18 @@ pub fn get_sign_algo(algo: u8) -> Option<SignAlgoValue> {
     num::FromPrimitive::from_u8(algo)
 }
 
-const KEY_USAGE_DIGITAL_SIGN: u16 = 0x0001;
-const KEY_USAGE_NON_REPUDIATION: u16 = 0x0002;
-const KEY_USAGE_KEY_ENCIPHERMENT: u16 = 0x0004;
-const KEY_USAGE_DATA_ENCIPHERMENT: u16 = 0x0008;
-const KEY_USAGE_KEY_AGREEMENT: u16 = 0x0010;
-const KEY_USAGE_KEY_CERT_SIGN: u16 = 0x0020;
-const KEY_USAGE_CRL_SIGN: u16 = 0x0040;
-const KEY_USAGE_ENCIPHER_ONLY: u16 = 0x0080;
-const KEY_USAGE_DECIPHER_ONLY: u16 = 0x0100;
+pub const KEY_USAGE_DIGITAL_SIGN: u16 = 0x0001;
+pub const KEY_USAGE_NON_REPUDIATION: u16 = 0x0002;
+pub const KEY_USAGE_KEY_ENCIPHERMENT: u16 = 0x0004;
+pub const KEY_USAGE_DATA_ENCIPHERMENT: u16 = 0x0008;
+pub const KEY_USAGE_KEY_AGREEMENT: u16 = 0x0010;
+pub const KEY_USAGE_KEY_CERT_SIGN: u16 = 0x0020;
+pub const KEY_USAGE_CRL_SIGN: u16 = 0x0040;
+pub const KEY_USAGE_ENCIPHER_ONLY: u16 = 0x0080;
+pub const KEY_USAGE_DECIPHER_ONLY: u16 = 0x0100;
+
+pub const EXT_KEY_USAGE_SERVER_AUTH: u8 = 1;
+pub const EXT_KEY_USAGE_CLIENT_AUTH: u8 = 2;
 
 fn reverse_byte(byte: u8) -> u8 {
     const LOOKUP: [u8; 16] = [
@@ -136,30 +141,34 @@ fn encode_key_usage(key_usage: u16, w: &mut dyn CertConsumer) -> Result<(), Erro
     Ok(())
 }
 
+const OID_SERVER_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+const OID_CLIENT_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+const OID_CODE_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+const OID_EMAIL_PROT: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04];
+const OID_TIMESTAMP: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x08];
+const OID_OCSP_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+// Indexed by the Matter key purpose id
+const EXT_KEY_USAGE_ENCODING: [(&str, &[u8]); 7] = [
+    ("", &[0; 8]),
+    ("ServerAuth", &OID_SERVER_AUTH),
+    ("ClientAuth", &OID_CLIENT_AUTH),
+    ("CodeSign", &OID_CODE_SIGN),
+    ("EmailProtection", &OID_EMAIL_PROT),
+    ("Timestamp", &OID_TIMESTAMP),
+    ("OCSPSign", &OID_OCSP_SIGN),
+];
+
 fn encode_extended_key_usage(
     list: &TLVArrayOwned<u8>,
     w: &mut dyn CertConsumer,
 ) -> Result<(), Error> {
-    const OID_SERVER_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
-    const OID_CLIENT_AUTH: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
-    const OID_CODE_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
-    const OID_EMAIL_PROT: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04];
-    const OID_TIMESTAMP: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x08];
-    const OID_OCSP_SIGN: [u8; 8] = [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
-    let encoding = [
-        ("", &[0; 8]),
-        ("ServerAuth", &OID_SERVER_AUTH),
-        ("ClientAuth", &OID_CLIENT_AUTH),
-        ("CodeSign", &OID_CODE_SIGN),
-        ("EmailProtection", &OID_EMAIL_PROT),
-        ("Timestamp", &OID_TIMESTAMP),
-        ("OCSPSign", &OID_OCSP_SIGN),
-    ];
+    let encoding = EXT_KEY_USAGE_ENCODING;
 
     w.start_seq("")?;
     for t in list.iter() {
         let t = *t as usize;
-        if t > 0 && t <= encoding.len() {
+        if t > 0 && t < encoding.len() {
             w.oid(encoding[t].0, encoding[t].1)?;
         } else {
             error!("Skipping encoding key usage out of bounds");
@@ -220,14 +229,14 @@ struct Extensions {
     future_extensions: Option<Vec<u8>>,
 }
 
+pub(crate) const OID_BASIC_CONSTRAINTS: [u8; 3] = [0x55, 0x1D, 0x13];
+pub(crate) const OID_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x0F];
+pub(crate) const OID_EXT_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x25];
+pub(crate) const OID_SUBJ_KEY_IDENTIFIER: [u8; 3] = [0x55, 0x1D, 0x0E];
+pub(crate) const OID_AUTH_KEY_ID: [u8; 3] = [0x55, 0x1D, 0x23];
+
 impl Extensions {
     fn encode(&self, w: &mut dyn CertConsumer) -> Result<(), Error> {
-        const OID_BASIC_CONSTRAINTS: [u8; 3] = [0x55, 0x1D, 0x13];
-        const OID_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x0F];
-        const OID_EXT_KEY_USAGE: [u8; 3] = [0x55, 0x1D, 0x25];
-        const OID_SUBJ_KEY_IDENTIFIER: [u8; 3] = [0x55, 0x1D, 0x0E];
-        const OID_AUTH_KEY_ID: [u8; 3] = [0x55, 0x1D, 0x23];
-
         w.start_ctx("X509v3 extensions:", 3)?;
         w.start_seq("")?;
         if let Some(t) = &self.basic_const {
@@ -293,13 +302,14 @@ enum DnTags {
     NocCat = 22,
 }
 
+#[derive(PartialEq, Clone)]
 enum DistNameValue {
     Uint(u64),
     Utf8Str(Vec<u8>),
     PrintableStr(Vec<u8>),
 }
 
-#[derive(Default)]
+#[derive(Default, PartialEq, Clone)]
 struct DistNames {
     // The order in which the DNs arrive is important, as the signing
     // requires that the ASN1 notation retains the same order
@@ -379,100 +389,101 @@ impl ToTLV for DistNames {
     }
 }
 
+const OID_COMMON_NAME: [u8; 3] = [0x55_u8, 0x04, 0x03];
+const OID_SURNAME: [u8; 3] = [0x55_u8, 0x04, 0x04];
+const OID_SERIAL_NUMBER: [u8; 3] = [0x55_u8, 0x04, 0x05];
+const OID_COUNTRY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x06];
+const OID_LOCALITY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x07];
+const OID_STATE_NAME: [u8; 3] = [0x55_u8, 0x04, 0x08];
+const OID_ORGANIZATION_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0A];
+const OID_ORGANIZATIONAL_UNIT_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0B];
+const OID_TITLE: [u8; 3] = [0x55_u8, 0x04, 0x0C];
+const OID_NAME: [u8; 3] = [0x55_u8, 0x04, 0x29];
+const OID_GIVEN_NAME: [u8; 3] = [0x55_u8, 0x04, 0x2A];
+const OID_INITIALS: [u8; 3] = [0x55_u8, 0x04, 0x2B];
+const OID_GENERATION_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2C];
+const OID_DN_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2E];
+const OID_PSEUDONYM: [u8; 3] = [0x55_u8, 0x04, 0x41];
+const OID_DOMAIN_COMPONENT: [u8; 10] = [
+    0x09_u8, 0x92, 0x26, 0x89, 0x93, 0xF2, 0x2C, 0x64, 0x01, 0x19,
+];
+const OID_MATTER_NODE_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x01,
+];
+const OID_MATTER_FW_SIGNING_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x02,
+];
+const OID_MATTER_ICAC_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x03,
+];
+const OID_MATTER_RCAC_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x04,
+];
+const OID_MATTER_FABRIC_ID: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x05,
+];
+const OID_MATTER_CASE_AUTH_TAG: [u8; 10] = [
+    0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x06,
+];
+
+// Indexed by the DN tag - 1
+const DN_ENCODING: [(&str, &[u8], Option<IntToStringLen>); 22] = [
+    ("Common Name:", &OID_COMMON_NAME, None),
+    ("Surname:", &OID_SURNAME, None),
+    ("Serial Number", &OID_SERIAL_NUMBER, None),
+    ("Country Name", &OID_COUNTRY_NAME, None),
+    ("Locality name", &OID_LOCALITY_NAME, None),
+    ("State Name", &OID_STATE_NAME, None),
+    ("Org Name", &OID_ORGANIZATION_NAME, None),
+    ("OU Name", &OID_ORGANIZATIONAL_UNIT_NAME, None),
+    ("Title", &OID_TITLE, None),
+    ("Name", &OID_NAME, None),
+    ("Given Name", &OID_GIVEN_NAME, None),
+    ("Initials", &OID_INITIALS, None),
+    ("Gen Qualifier", &OID_GENERATION_QUALIFIER, None),
+    ("DN Qualifier", &OID_DN_QUALIFIER, None),
+    ("Pseudonym", &OID_PSEUDONYM, None),
+    ("Domain Component", &OID_DOMAIN_COMPONENT, None),
+    (
+        "Chip Node Id:",
+        &OID_MATTER_NODE_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip Firmware Signing Id:",
+        &OID_MATTER_FW_SIGNING_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip ICA Id:",
+        &OID_MATTER_ICAC_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip Root CA Id:",
+        &OID_MATTER_RCAC_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip Fabric Id:",
+        &OID_MATTER_FABRIC_ID,
+        Some(IntToStringLen::Len16),
+    ),
+    (
+        "Chip NOC CAT Id:",
+        &OID_MATTER_CASE_AUTH_TAG,
+        Some(IntToStringLen::Len8),
+    ),
+];
+
 impl DistNames {
     fn encode(&self, tag: &str, w: &mut dyn CertConsumer) -> Result<(), Error> {
-        const OID_COMMON_NAME: [u8; 3] = [0x55_u8, 0x04, 0x03];
-        const OID_SURNAME: [u8; 3] = [0x55_u8, 0x04, 0x04];
-        const OID_SERIAL_NUMBER: [u8; 3] = [0x55_u8, 0x04, 0x05];
-        const OID_COUNTRY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x06];
-        const OID_LOCALITY_NAME: [u8; 3] = [0x55_u8, 0x04, 0x07];
-        const OID_STATE_NAME: [u8; 3] = [0x55_u8, 0x04, 0x08];
-        const OID_ORGANIZATION_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0A];
-        const OID_ORGANIZATIONAL_UNIT_NAME: [u8; 3] = [0x55_u8, 0x04, 0x0B];
-        const OID_TITLE: [u8; 3] = [0x55_u8, 0x04, 0x0C];
-        const OID_NAME: [u8; 3] = [0x55_u8, 0x04, 0x29];
-        const OID_GIVEN_NAME: [u8; 3] = [0x55_u8, 0x04, 0x2A];
-        const OID_INITIALS: [u8; 3] = [0x55_u8, 0x04, 0x2B];
-        const OID_GENERATION_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2C];
-        const OID_DN_QUALIFIER: [u8; 3] = [0x55_u8, 0x04, 0x2E];
-        const OID_PSEUDONYM: [u8; 3] = [0x55_u8, 0x04, 0x41];
-        const OID_DOMAIN_COMPONENT: [u8; 10] = [
-            0x09_u8, 0x92, 0x26, 0x89, 0x93, 0xF2, 0x2C, 0x64, 0x01, 0x19,
-        ];
-        const OID_MATTER_NODE_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x01,
-        ];
-        const OID_MATTER_FW_SIGNING_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x02,
-        ];
-        const OID_MATTER_ICAC_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x03,
-        ];
-        const OID_MATTER_RCAC_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x04,
-        ];
-        const OID_MATTER_FABRIC_ID: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x05,
-        ];
-        const OID_MATTER_CASE_AUTH_TAG: [u8; 10] = [
-            0x2B_u8, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x06,
-        ];
-
-        const DN_ENCODING: [(&str, &[u8], Option<IntToStringLen>); 22] = [
-            ("Common Name:", &OID_COMMON_NAME, None),
-            ("Surname:", &OID_SURNAME, None),
-            ("Serial Number", &OID_SERIAL_NUMBER, None),
-            ("Country Name", &OID_COUNTRY_NAME, None),
-            ("Locality name", &OID_LOCALITY_NAME, None),
-            ("State Name", &OID_STATE_NAME, None),
-            ("Org Name", &OID_ORGANIZATION_NAME, None),
-            ("OU Name", &OID_ORGANIZATIONAL_UNIT_NAME, None),
-            ("Title", &OID_TITLE, None),
-            ("Name", &OID_NAME, None),
-            ("Given Name", &OID_GIVEN_NAME, None),
-            ("Initials", &OID_INITIALS, None),
-            ("Gen Qualifier", &OID_GENERATION_QUALIFIER, None),
-            ("DN Qualifier", &OID_DN_QUALIFIER, None),
-            ("Pseudonym", &OID_PSEUDONYM, None),
-            ("Domain Component", &OID_DOMAIN_COMPONENT, None),
-            (
-                "Chip Node Id:",
-                &OID_MATTER_NODE_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip Firmware Signing Id:",
-                &OID_MATTER_FW_SIGNING_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip ICA Id:",
-                &OID_MATTER_ICAC_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip Root CA Id:",
-                &OID_MATTER_RCAC_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip Fabric Id:",
-                &OID_MATTER_FABRIC_ID,
-                Some(IntToStringLen::Len16),
-            ),
-            (
-                "Chip NOC CAT Id:",
-                &OID_MATTER_CASE_AUTH_TAG,
-                Some(IntToStringLen::Len8),
-            ),
-        ];
-
         w.start_seq(tag)?;
         for (id, value) in &self.dn {
             let tag: Option<DnTags> = num::FromPrimitive::from_u8(*id);
             if tag.is_some() {
                 let index = (id - 1) as usize;
-                if index <= DN_ENCODING.len() {
+                if index < DN_ENCODING.len() {
                     let this = &DN_ENCODING[index];
                     encode_dn_value(value, this.0, this.1, w, this.2)?;
                 } else {
@@ -603,8 +614,74 @@ impl Cert {
         Ok(w.as_slice().len())
     }
 
-    pub fn verify_chain_start(&self) -> CertVerifier {
-        CertVerifier::new(self)
+    /// The complete X.509 certificate in DER, as opposed to as_asn1() which only has the
+    /// to-be-signed part
+    pub fn as_der(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut sig = [0u8; MAX_DER_SIGNATURE_LEN];
+        let sig_len = encode_der_signature(self.get_signature(), &mut sig)?;
+
+        let mut w = ASN1Writer::new(buf);
+        w.start_seq("")?;
+        self.encode(&mut w)?;
+        w.start_seq("")?;
+        w.oid("", &OID_ECDSA_WITH_SHA256)?;
+        w.end_seq()?;
+        w.bitstr("", false, &sig[..sig_len])?;
+        w.end_seq()?;
+        Ok(w.as_slice().len())
+    }
+
+    pub fn get_not_before(&self) -> u32 {
+        self.not_before
+    }
+
+    pub fn get_not_after(&self) -> u32 {
+        self.not_after
+    }
+
+    pub fn verify_chain_start(&self, time: CertTime) -> CertVerifier {
+        CertVerifier::new(self, time)
+    }
+
+    fn is_valid_at(&self, time: CertTime) -> Result<(), Error> {
+        time.check_validity(self.not_before, self.not_after)
+    }
+
+    // The checks depend on the position of the certificate in the chain, a depth of 0 is the
+    // leaf certificate, everything above it has to be a CA
+    fn is_valid_usage(&self, depth: usize) -> Result<(), Error> {
+        let ext = &self.extensions;
+        let key_usage = ext.key_usage.ok_or(Error::InvalidKeyUsage)?;
+        if depth == 0 {
+            if ext.basic_const.as_ref().is_some_and(|b| b.is_ca) {
+                return Err(Error::InvalidBasicConstraints);
+            }
+            if key_usage & KEY_USAGE_DIGITAL_SIGN == 0 {
+                return Err(Error::InvalidKeyUsage);
+            }
+            if let Some(ext_key_usage) = &ext.ext_key_usage {
+                let has = |purpose| ext_key_usage.iter().any(|p| *p == purpose);
+                if !has(EXT_KEY_USAGE_SERVER_AUTH) || !has(EXT_KEY_USAGE_CLIENT_AUTH) {
+                    return Err(Error::InvalidKeyUsage);
+                }
+            }
+        } else {
+            let basic_const = ext
+                .basic_const
+                .as_ref()
+                .filter(|b| b.is_ca)
+                .ok_or(Error::InvalidBasicConstraints)?;
+            if key_usage & KEY_USAGE_KEY_CERT_SIGN == 0 || ext.ext_key_usage.is_some() {
+                return Err(Error::InvalidKeyUsage);
+            }
+            // The path length counts the intermediate CAs below this one
+            if let Some(path) = basic_const.path {
+                if depth - 1 > path as usize {
+                    return Err(Error::PathLenExceeded);
+                }
+            }
+        }
+        Ok(())
     }
 
     fn encode(&self, w: &mut dyn CertConsumer) -> Result<(), Error> {
@@ -627,7 +704,11 @@ impl Cert {
 
         w.start_seq("Validity:")?;
         w.utctime("Not Before:", self.not_before)?;
-        w.utctime("Not After:", self.not_after)?;
+        if self.not_after == 0 {
+            w.no_expiry_time("Not After:")?;
+        } else {
+            w.utctime("Not After:", self.not_after)?;
+        }
         w.end_seq()?;
 
         self.subject.encode("Subject:", w)?;
@@ -666,19 +747,91 @@ impl fmt::Display for Cert {
     }
 }
 
+/// The time that the validity period of the certificates is checked against
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CertTime {
+    /// No trusted time is available yet, the validity period is not checked
+    Unknown,
+    /// The last known good time, in seconds since the Matter epoch. The actual time can only be
+    /// later than this, so only expired certificates are rejected
+    LastKnownGood(u32),
+    /// The current time, in seconds since the Matter epoch
+    Trusted(u32),
+}
+
+impl CertTime {
+    /// The current time as per the clock, a clock that is still before the Matter epoch
+    /// hasn't been set yet
+    pub fn from_clock(clock: &dyn Clock) -> Self {
+        match clock.matter_epoch_us() / 1_000_000 {
+            0 => CertTime::Unknown,
+            secs => CertTime::Trusted(u32::try_from(secs).unwrap_or(u32::MAX)),
+        }
+    }
+
+    /// Check a validity period against this time, a NotAfter of 0 means that there is no
+    /// well-defined expiration date
+    pub fn check_validity(&self, not_before: u32, not_after: u32) -> Result<(), Error> {
+        let expired = |now: u32| not_after != 0 && now > not_after;
+        match *self {
+            CertTime::Unknown => Ok(()),
+            CertTime::LastKnownGood(lkg) if expired(lkg) => Err(Error::CertExpired),
+            CertTime::LastKnownGood(_) => Ok(()),
+            CertTime::Trusted(now) if now < not_before => Err(Error::CertNotYetValid),
+            CertTime::Trusted(now) if expired(now) => Err(Error::CertExpired),
+            CertTime::Trusted(_) => Ok(()),
+        }
+    }
+}
+
+// The Matter signature is r and s back to back, X.509 has them as a sequence of DER integers
+pub(crate) fn encode_der_signature(signature: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+    if signature.len() != crypto::EC_SIGNATURE_LEN_BYTES {
+        return Err(Error::InvalidSignature);
+    }
+    let mut w = ASN1Writer::new(buf);
+    w.start_seq("")?;
+    for int in signature.chunks(crypto::BIGNUM_LEN_BYTES) {
+        // DER integers are signed and take the fewest bytes possible
+        let start = int.iter().position(|b| *b != 0).unwrap_or(int.len() - 1);
+        let int = &int[start..];
+        if int[0] & 0x80 != 0 {
+            let mut padded = [0u8; crypto::BIGNUM_LEN_BYTES + 1];
+            padded[1..=int.len()].copy_from_slice(int);
+            w.integer("", &padded[..=int.len()])?;
+        } else {
+            w.integer("", int)?;
+        }
+    }
+    w.end_seq()?;
+    Ok(w.as_slice().len())
+}
+
 pub struct CertVerifier<'a> {
     cert: &'a Cert,
+    time: CertTime,
+    // The position of cert in the chain, the leaf is at 0
+    depth: usize,
 }
 
 impl<'a> CertVerifier<'a> {
-    pub fn new(cert: &'a Cert) -> Self {
-        Self { cert }
+    pub fn new(cert: &'a Cert, time: CertTime) -> Self {
+        Self {
+            cert,
+            time,
+            depth: 0,
+        }
     }
 
     pub fn add_cert(self, parent: &'a Cert) -> Result<CertVerifier<'a>, Error> {
+        self.cert.is_valid_at(self.time)?;
+        self.cert.is_valid_usage(self.depth)?;
         if !self.cert.is_authority(parent)? {
             return Err(Error::InvalidAuthKey);
         }
+        if self.cert.issuer != parent.subject {
+            return Err(Error::InvalidIssuer);
+        }
         let mut asn1 = [0u8; MAX_ASN1_CERT_SIZE];
         let len = self.cert.as_asn1(&mut asn1)?;
         let asn1 = &asn1[..len];
@@ -692,8 +845,11 @@ impl<'a> CertVerifier<'a> {
             e
         })?;
 
-        // TODO: other validation checks
-        Ok(CertVerifier::new(parent))
+        Ok(CertVerifier {
+            cert: parent,
+            time: self.time,
+            depth: self.depth + 1,
+        })
     }
 
     pub fn finalise(self) -> Result<(), Error> {
@@ -721,17 +877,29 @@ pub trait CertConsumer {
     fn end_ctx(&mut self) -> Result<(), Error>;
     fn oid(&mut self, tag: &str, oid: &[u8]) -> Result<(), Error>;
     fn utctime(&mut self, tag: &str, epoch: u32) -> Result<(), Error>;
+    // The time that stands for no well-defined expiration date, as per RFC 5280 4.1.2.5
+    fn no_expiry_time(&mut self, tag: &str) -> Result<(), Error>;
 }
 
 const MAX_DEPTH: usize = 10;
+// The GeneralizedTime of a certificate without a well-defined expiration date
+const NO_EXPIRY_TIME: &str = "99991231235959Z";
 const MAX_ASN1_CERT_SIZE: usize = 1000;
+// A sequence of two integers, each of which might need a leading 0. The ASN1Writer reserves
+// 3 bytes for the length of the sequence, while writing it
+pub(crate) const MAX_DER_SIGNATURE_LEN: usize = 4 + 2 * (2 + crypto::BIGNUM_LEN_BYTES + 1);
 
+pub mod asn1_reader;
 mod asn1_writer;
+mod builder;
+pub mod pem;
 mod printer;
+pub(crate) mod x509;
 
 #[cfg(test)]
 mod tests {
-    use crate::cert::Cert;
+    use crate::cert::{Cert, CertBuilder, CertProfile, CertTime, MatterDn};
+    use crate::crypto::{self, CryptoKeyPair, KeyPair};
     use crate::error::Error;
     use crate::tlv::{self, FromTLV, TLVWriter, TagType, ToTLV};
     use crate::utils::writebuf::WriteBuf;
@@ -765,7 +933,7 @@ mod tests {
         let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
         let rca = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(TEST_TIME);
         a.add_cert(&icac)
             .unwrap()
             .add_cert(&rca)
@@ -779,7 +947,7 @@ mod tests {
         // The chain doesn't lead up to a self-signed certificate
         let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(TEST_TIME);
         assert_eq!(
             Err(Error::InvalidAuthKey),
             a.add_cert(&icac).unwrap().finalise()
@@ -790,7 +958,7 @@ mod tests {
     fn test_auth_key_chain_incorrect() {
         let noc = Cert::new(&test_vectors::NOC1_AUTH_KEY_FAIL).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(TEST_TIME);
         assert_eq!(Err(Error::InvalidAuthKey), a.add_cert(&icac).map(|_| ()));
     }
 
@@ -798,10 +966,83 @@ mod tests {
     fn test_cert_corrupted() {
         let noc = Cert::new(&test_vectors::NOC1_CORRUPT_CERT).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(TEST_TIME);
         assert_eq!(Err(Error::InvalidSignature), a.add_cert(&icac).map(|_| ()));
     }
 
+    #[test]
+    fn test_verify_chain_validity() {
+        // All the certificates are valid from 2021 until 2030
+        let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+        let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        let rca = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
+        let verify = |time| {
+            noc.verify_chain_start(time)
+                .add_cert(&icac)?
+                .add_cert(&rca)?
+                .finalise()
+        };
+        let before = noc.get_not_before() - 1;
+        let after = noc.get_not_after() + 1;
+
+        assert_eq!(
+            Err(Error::CertNotYetValid),
+            verify(CertTime::Trusted(before))
+        );
+        assert_eq!(Err(Error::CertExpired), verify(CertTime::Trusted(after)));
+        // Without a trusted time, only what was known to be past can be rejected
+        assert_eq!(Ok(()), verify(CertTime::LastKnownGood(before)));
+        assert_eq!(
+            Err(Error::CertExpired),
+            verify(CertTime::LastKnownGood(after))
+        );
+        assert_eq!(Ok(()), verify(CertTime::Unknown));
+    }
+
+    #[test]
+    fn test_verify_chain_usage() {
+        let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+        let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        let rca = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
+
+        // The leaf is not allowed to act as a CA
+        let mut leaf_ca = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+        leaf_ca.extensions.basic_const.as_mut().unwrap().is_ca = true;
+        assert_eq!(
+            Err(Error::InvalidBasicConstraints),
+            leaf_ca
+                .verify_chain_start(TEST_TIME)
+                .add_cert(&icac)
+                .map(|_| ())
+        );
+
+        // A CA has to be able to sign certificates
+        let mut icac_no_sign = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        icac_no_sign.extensions.key_usage = Some(super::KEY_USAGE_DIGITAL_SIGN);
+        let v = noc.verify_chain_start(TEST_TIME).add_cert(&icac_no_sign);
+        assert_eq!(
+            Err(Error::InvalidKeyUsage),
+            v.unwrap().add_cert(&rca).map(|_| ())
+        );
+
+        // The ICAC is the only intermediate below the root, which a path length of 0 forbids
+        let mut rca_no_path = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
+        rca_no_path.extensions.basic_const.as_mut().unwrap().path = Some(0);
+        let v = noc.verify_chain_start(TEST_TIME).add_cert(&icac).unwrap();
+        assert_eq!(
+            Err(Error::PathLenExceeded),
+            v.add_cert(&rca_no_path).unwrap().finalise()
+        );
+
+        // The issuer of the NOC is not the subject of the RCA
+        let v = noc.verify_chain_start(TEST_TIME);
+        assert_eq!(Err(Error::InvalidAuthKey), v.add_cert(&rca).map(|_| ()));
+        let mut noc_issuer = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+        noc_issuer.issuer.dn.pop();
+        let v = noc_issuer.verify_chain_start(TEST_TIME);
+        assert_eq!(Err(Error::InvalidIssuer), v.add_cert(&icac).map(|_| ()));
+    }
+
     #[test]
     fn test_tlv_conversions() {
         let test_input: [&[u8]; 3] = [
@@ -823,6 +1064,99 @@ mod tests {
         }
     }
 
+    // 2023-01-01, within the validity period of all the test certificates
+    const TEST_TIME: CertTime = CertTime::Trusted(725_846_400);
+
+    #[test]
+    fn test_der_conversions() {
+        let test_input: [&[u8]; 4] = [
+            &test_vectors::NOC1_SUCCESS,
+            &test_vectors::ICAC1_SUCCESS,
+            &test_vectors::RCA1_SUCCESS,
+            &test_vectors::CHIP_CERT_TXT_IN_DN,
+        ];
+
+        for input in test_input.iter() {
+            let cert = Cert::new(input).unwrap();
+            let mut der = [0u8; 1000];
+            let der_len = cert.as_der(&mut der).unwrap();
+            let cert = Cert::from_der(&der[..der_len]).unwrap();
+            let mut tlv = [0u8; 1024];
+            let tlv_len = cert.as_tlv(&mut tlv).unwrap();
+            assert_eq!(*input, &tlv[..tlv_len]);
+
+            let pem = cert.as_pem().unwrap();
+            assert!(pem.starts_with("-----BEGIN CERTIFICATE-----\n"));
+            let cert = Cert::from_pem(&pem).unwrap();
+            let tlv_len = cert.as_tlv(&mut tlv).unwrap();
+            assert_eq!(*input, &tlv[..tlv_len]);
+        }
+    }
+
+    #[test]
+    fn test_der_no_expiry() {
+        let key_pair = KeyPair::new().unwrap();
+        let mut pubkey = [0u8; crypto::EC_POINT_LEN_BYTES];
+        key_pair.get_public_key(&mut pubkey).unwrap();
+        let rcac = CertBuilder::new_with(CertProfile::Rcac)
+            .set_validity(1, 0)
+            .add_subject(MatterDn::RootCaId(1))
+            .set_pubkey(&pubkey)
+            .unwrap()
+            .sign(&key_pair)
+            .unwrap();
+
+        let mut der = [0u8; 1000];
+        let len = rcac.as_der(&mut der).unwrap();
+        assert!(der[..len]
+            .windows(super::NO_EXPIRY_TIME.len())
+            .any(|w| w == super::NO_EXPIRY_TIME.as_bytes()));
+        let rcac = Cert::from_der(&der[..len]).unwrap();
+        assert_eq!(rcac.get_not_after(), 0);
+    }
+
+    #[test]
+    fn test_der_not_representable() {
+        let cert = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+        let mut der = [0u8; 1000];
+        let len = cert.as_der(&mut der).unwrap();
+        let der = &der[..len];
+        assert!(Cert::from_der(der).is_ok());
+        assert!(Cert::from_der(&der[..len - 1]).is_err());
+
+        let find = |pattern: &[u8]| {
+            der.windows(pattern.len())
+                .position(|w| w == pattern)
+                .unwrap()
+                + pattern.len()
+        };
+        // The Matter Node Id as a UTF8String of 16 characters
+        let node_id = find(&[
+            0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xA2, 0x7C, 0x01, 0x01, 0x0C, 0x10,
+        ]);
+        // The Basic Constraints, marked critical
+        let basic_const = find(&[0x06, 0x03, 0x55, 0x1D, 0x13, 0x01, 0x01]);
+
+        // A Matter DN that isn't a UTF8String
+        let mut modified = der.to_vec();
+        modified[node_id - 2] = 0x13;
+        assert_eq!(Cert::from_der(&modified).err(), Some(Error::InvalidData));
+
+        // A Matter DN in lower case
+        let mut modified = der.to_vec();
+        let pos = modified[node_id..node_id + 16]
+            .iter()
+            .position(|c| c.is_ascii_uppercase())
+            .unwrap();
+        modified[node_id + pos].make_ascii_lowercase();
+        assert_eq!(Cert::from_der(&modified).err(), Some(Error::InvalidData));
+
+        // An extension that isn't critical
+        let mut modified = der.to_vec();
+        modified[basic_const] = 0x00;
+        assert_eq!(Cert::from_der(&modified).err(), Some(Error::InvalidData));
+    }
+
     mod test_vectors {
         // Group 1
         pub const NOC1_SUCCESS: [u8; 247] = [
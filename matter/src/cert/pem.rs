@@ -0,0 +1,168 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! Import and export of DER data in the PEM text format, as per RFC 7468
+
+use crate::error::Error;
+
+use super::{Cert, MAX_ASN1_CERT_SIZE};
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const LINE_LEN: usize = 64;
+
+pub const LABEL_CERTIFICATE: &str = "CERTIFICATE";
+pub const LABEL_EC_PRIVATE_KEY: &str = "EC PRIVATE KEY";
+pub const LABEL_PRIVATE_KEY: &str = "PRIVATE KEY";
+
+/// Encode the DER data as PEM, with the given label
+pub fn encode(der: &[u8], label: &str) -> String {
+    let mut encoded = Vec::with_capacity(der.len().div_ceil(3) * 4);
+    for chunk in der.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3F) as usize]);
+            } else {
+                encoded.push(b'=');
+            }
+        }
+    }
+
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.chunks(LINE_LEN) {
+        // Safe to unwrap, as the Base64 alphabet is ASCII
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// Decode the first PEM block with the given label
+pub fn decode(pem: &str, label: &str) -> Result<Vec<u8>, Error> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let start = pem.find(&begin).ok_or(Error::NotFound)? + begin.len();
+    let len = pem[start..].find(&end).ok_or(Error::Invalid)?;
+
+    let mut der = Vec::new();
+    let mut n = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+    for c in pem[start..start + len].bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => continue,
+            _ => return Err(Error::Invalid),
+        };
+        if padding > 0 {
+            // Nothing but the padding can follow the padding
+            return Err(Error::Invalid);
+        }
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            der.push((n >> bits) as u8);
+        }
+    }
+    // Each padding character stands in for 2 bits that are left over
+    if bits != 2 * padding {
+        return Err(Error::Invalid);
+    }
+    Ok(der)
+}
+
+/// The DER in a file that has either DER or PEM, the latter with any of the given labels
+pub fn decode_any(data: &[u8], labels: &[&str]) -> Result<Vec<u8>, Error> {
+    match std::str::from_utf8(data) {
+        Ok(pem) if pem.contains("-----BEGIN ") => labels
+            .iter()
+            .find_map(|label| decode(pem, label).ok())
+            .ok_or(Error::NotFound),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+impl Cert {
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        Cert::from_der(&decode(pem, LABEL_CERTIFICATE)?)
+    }
+
+    pub fn as_pem(&self) -> Result<String, Error> {
+        let mut der = [0u8; MAX_ASN1_CERT_SIZE];
+        let len = self.as_der(&mut der)?;
+        Ok(encode(&der[..len], LABEL_CERTIFICATE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_any, encode};
+    use crate::error::Error;
+
+    #[test]
+    fn test_pem_encode_decode() {
+        let inputs: [&[u8]; 4] = [b"", b"f", b"fo", b"foobar"];
+        let outputs = ["", "Zg==", "Zm8=", "Zm9vYmFy"];
+        for (input, output) in inputs.iter().zip(outputs.iter()) {
+            let pem = encode(input, "TEST");
+            let body = pem
+                .trim_start_matches("-----BEGIN TEST-----\n")
+                .trim_end_matches("-----END TEST-----\n");
+            assert_eq!(body.trim_end(), *output);
+            assert_eq!(decode(&pem, "TEST").unwrap(), *input);
+        }
+
+        // Lines are wrapped at 64 characters
+        let pem = encode(&[0xAB; 100], "TEST");
+        assert_eq!(pem.lines().nth(1).unwrap().len(), 64);
+        assert_eq!(decode(&pem, "TEST").unwrap(), [0xAB; 100]);
+
+        assert_eq!(decode(&pem, "OTHER"), Err(Error::NotFound));
+        assert_eq!(
+            decode("-----BEGIN TEST-----\nZm9v!==\n-----END TEST-----", "TEST"),
+            Err(Error::Invalid)
+        );
+        assert_eq!(
+            decode("-----BEGIN TEST-----\nZg=\n-----END TEST-----", "TEST"),
+            Err(Error::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_decode_any() {
+        let der = [0x30, 0x03, 0x02, 0x01, 0x05];
+        assert_eq!(decode_any(&der, &["TEST"]).unwrap(), der);
+        let pem = encode(&der, "OTHER");
+        assert_eq!(decode_any(pem.as_bytes(), &["TEST", "OTHER"]).unwrap(), der);
+        assert_eq!(decode_any(pem.as_bytes(), &["TEST"]), Err(Error::NotFound));
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! Conversion of X.509 certificates in DER to the Matter TLV representation
+
+use std::convert::TryFrom;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use super::{
+    asn1_reader::{
+        ASN1Reader, TAG_BIT_STRING, TAG_BOOLEAN, TAG_GENERALIZED_TIME, TAG_INTEGER,
+        TAG_OCTET_STRING, TAG_OID, TAG_PRINTABLE_STRING, TAG_SEQUENCE, TAG_SET, TAG_UTC_TIME,
+        TAG_UTF8_STRING,
+    },
+    reverse_byte, BasicConstraints, Cert, DistNameValue, DistNames, Extensions, IntToStringLen,
+    DN_ENCODING, EXT_KEY_USAGE_ENCODING, MAX_ASN1_CERT_SIZE, NO_EXPIRY_TIME, OID_AUTH_KEY_ID,
+    OID_BASIC_CONSTRAINTS, OID_ECDSA_WITH_SHA256, OID_EC_TYPE_PRIME256V1, OID_EXT_KEY_USAGE,
+    OID_KEY_USAGE, OID_PUB_KEY_ECPUBKEY, OID_SUBJ_KEY_IDENTIFIER,
+};
+use crate::{
+    cert::{EcCurveIdValue, PubKeyAlgoValue, SignAlgoValue},
+    crypto,
+    error::Error,
+};
+
+impl Cert {
+    /// Convert an X.509 certificate in DER to the Matter TLV representation
+    ///
+    /// Only those certificates that convert back to the same DER are accepted. Since the
+    /// signature is over the DER, anything else couldn't be verified in its TLV form.
+    pub fn from_der(der: &[u8]) -> Result<Self, Error> {
+        let cert = Cert::parse_der(der)?;
+
+        let mut buf = [0u8; MAX_ASN1_CERT_SIZE];
+        let len = cert.as_der(&mut buf)?;
+        if buf[..len] != *der {
+            return Err(Error::InvalidData);
+        }
+        Ok(cert)
+    }
+
+    fn parse_der(der: &[u8]) -> Result<Self, Error> {
+        let mut r = ASN1Reader::new(der);
+        let mut c = r.enter(TAG_SEQUENCE)?;
+        if !r.is_empty() {
+            return Err(Error::Invalid);
+        }
+        let mut tbs = c.enter(TAG_SEQUENCE)?;
+        c.enter(TAG_SEQUENCE)?.expect_oid(&OID_ECDSA_WITH_SHA256)?;
+        let signature = parse_der_signature(c.bitstr()?)?;
+
+        // Only v3 certificates
+        if tbs.enter(0xA0)?.read(TAG_INTEGER)? != [2] {
+            return Err(Error::InvalidData);
+        }
+        let serial_no = tbs.read(TAG_INTEGER)?.to_vec();
+        tbs.enter(TAG_SEQUENCE)?
+            .expect_oid(&OID_ECDSA_WITH_SHA256)?;
+        let issuer = parse_dist_names(tbs.enter(TAG_SEQUENCE)?)?;
+
+        let mut validity = tbs.enter(TAG_SEQUENCE)?;
+        let not_before = parse_time(&mut validity)?.ok_or(Error::InvalidData)?;
+        let not_after = parse_time(&mut validity)?.unwrap_or(0);
+
+        let subject = parse_dist_names(tbs.enter(TAG_SEQUENCE)?)?;
+
+        let pubkey = parse_pubkey(tbs.enter(TAG_SEQUENCE)?)?.to_vec();
+
+        let extensions = parse_extensions(tbs.enter(0xA3)?.enter(TAG_SEQUENCE)?)?;
+
+        Ok(Self {
+            serial_no,
+            sign_algo: SignAlgoValue::ECDSAWithSHA256 as u8,
+            issuer,
+            not_before,
+            not_after,
+            subject,
+            pubkey_algo: PubKeyAlgoValue::EcPubKey as u8,
+            ec_curve_id: EcCurveIdValue::Prime256V1 as u8,
+            pubkey,
+            extensions,
+            signature,
+        })
+    }
+}
+
+fn parse_dist_names(mut r: ASN1Reader) -> Result<DistNames, Error> {
+    let mut names = DistNames::default();
+    while !r.is_empty() {
+        // Each RDN can only have the one attribute
+        let mut rdn = r.enter(TAG_SET)?;
+        let mut attr = rdn.enter(TAG_SEQUENCE)?;
+        if !rdn.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        let oid = attr.read(TAG_OID)?;
+        let index = DN_ENCODING
+            .iter()
+            .position(|(_, dn_oid, _)| *dn_oid == oid)
+            .ok_or(Error::InvalidData)?;
+        let tag = index as u8 + 1;
+
+        let value = match (attr.peek_tag(), DN_ENCODING[index].2) {
+            (Some(TAG_UTF8_STRING), Some(len)) => {
+                let value = std::str::from_utf8(attr.read(TAG_UTF8_STRING)?)?;
+                let expected_len = match len {
+                    IntToStringLen::Len16 => 16,
+                    IntToStringLen::Len8 => 8,
+                };
+                if value.len() != expected_len {
+                    return Err(Error::InvalidData);
+                }
+                let value = u64::from_str_radix(value, 16).map_err(|_| Error::InvalidData)?;
+                (tag, DistNameValue::Uint(value))
+            }
+            (Some(TAG_UTF8_STRING), None) => (
+                tag,
+                DistNameValue::Utf8Str(attr.read(TAG_UTF8_STRING)?.to_vec()),
+            ),
+            (Some(TAG_PRINTABLE_STRING), None) => (
+                tag,
+                DistNameValue::PrintableStr(attr.read(TAG_PRINTABLE_STRING)?.to_vec()),
+            ),
+            _ => return Err(Error::InvalidData),
+        };
+        if !attr.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        names.dn.push(value);
+    }
+    Ok(names)
+}
+
+// The time in seconds since the Matter epoch, None if there is no well-defined expiration date
+pub(crate) fn parse_time(r: &mut ASN1Reader) -> Result<Option<u32>, Error> {
+    let (time, fmt) = if let Some(time) = r.read_optional(TAG_UTC_TIME)? {
+        (time, "%y%m%d%H%M%SZ")
+    } else {
+        let time = r.read(TAG_GENERALIZED_TIME)?;
+        if time == NO_EXPIRY_TIME.as_bytes() {
+            return Ok(None);
+        }
+        (time, "%Y%m%d%H%M%SZ")
+    };
+    let time = std::str::from_utf8(time)?;
+    let time = NaiveDateTime::parse_from_str(time, fmt).map_err(|_| Error::InvalidTime)?;
+    let matter_epoch = NaiveDate::from_ymd_opt(2000, 1, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .ok_or(Error::InvalidTime)?;
+    let secs = (time - matter_epoch).num_seconds();
+    // Anything before the Matter epoch can't be represented
+    u32::try_from(secs)
+        .map(Some)
+        .map_err(|_| Error::InvalidData)
+}
+
+fn parse_extensions(mut r: ASN1Reader) -> Result<Extensions, Error> {
+    let mut ext = Extensions::default();
+    while !r.is_empty() {
+        let mut e = r.enter(TAG_SEQUENCE)?;
+        let oid = e.read(TAG_OID)?;
+        // Whether or not the extension is marked critical, is checked by the conversion back
+        e.read_optional(TAG_BOOLEAN)?;
+        let mut value = e.enter(TAG_OCTET_STRING)?;
+
+        if oid == OID_BASIC_CONSTRAINTS {
+            let (is_ca, path) = parse_basic_constraints(&mut value)?;
+            ext.basic_const = Some(BasicConstraints { is_ca, path });
+        } else if oid == OID_KEY_USAGE {
+            ext.key_usage = Some(parse_key_usage(&mut value)?);
+        } else if oid == OID_EXT_KEY_USAGE {
+            let mut seq = value.enter(TAG_SEQUENCE)?;
+            let mut purposes = Vec::new();
+            while !seq.is_empty() {
+                let oid = seq.read(TAG_OID)?;
+                let purpose = EXT_KEY_USAGE_ENCODING
+                    .iter()
+                    .skip(1)
+                    .position(|(_, p)| *p == oid)
+                    .ok_or(Error::InvalidData)?;
+                purposes.push(purpose as u8 + 1);
+            }
+            ext.ext_key_usage = Some(purposes.into());
+        } else if oid == OID_SUBJ_KEY_IDENTIFIER {
+            ext.subj_key_id = Some(value.read(TAG_OCTET_STRING)?.to_vec());
+        } else if oid == OID_AUTH_KEY_ID {
+            let mut seq = value.enter(TAG_SEQUENCE)?;
+            ext.auth_key_id = Some(seq.read(0x80)?.to_vec());
+        } else {
+            return Err(Error::InvalidData);
+        }
+    }
+    Ok(ext)
+}
+
+// The uncompressed point of a P-256 public key
+pub(crate) fn parse_pubkey<'a>(mut spki: ASN1Reader<'a>) -> Result<&'a [u8], Error> {
+    let mut algo = spki.enter(TAG_SEQUENCE)?;
+    algo.expect_oid(&OID_PUB_KEY_ECPUBKEY)?;
+    algo.expect_oid(&OID_EC_TYPE_PRIME256V1)?;
+    spki.bitstr()
+}
+
+// Whether the certificate is a CA, and its path length constraint if any
+pub(crate) fn parse_basic_constraints(value: &mut ASN1Reader) -> Result<(bool, Option<u8>), Error> {
+    let mut seq = value.enter(TAG_SEQUENCE)?;
+    let is_ca = seq.read_optional(TAG_BOOLEAN)? == Some(&[0xFF]);
+    let path = match seq.read_optional(TAG_INTEGER)? {
+        Some([path]) => Some(*path),
+        Some(_) => return Err(Error::InvalidData),
+        None => None,
+    };
+    Ok((is_ca, path))
+}
+
+// The key usage bits in the same order as the KEY_USAGE_* flags
+pub(crate) fn parse_key_usage(value: &mut ASN1Reader) -> Result<u16, Error> {
+    let bits = value.read(TAG_BIT_STRING)?;
+    let mut key_usage = 0;
+    // The first byte is the number of unused bits, which follows from the value
+    for (i, b) in bits.iter().skip(1).take(2).enumerate() {
+        key_usage |= (reverse_byte(*b) as u16) << (8 * i);
+    }
+    Ok(key_usage)
+}
+
+// The X.509 signature is a sequence of DER integers, Matter has r and s back to back
+pub(crate) fn parse_der_signature(der: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut seq = ASN1Reader::new(der).enter(TAG_SEQUENCE)?;
+    let mut signature = vec![0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+    for out in signature.chunks_mut(crypto::BIGNUM_LEN_BYTES) {
+        let mut int = seq.read(TAG_INTEGER)?;
+        if int.len() > out.len() && int[0] == 0 {
+            int = &int[1..];
+        }
+        if int.len() > out.len() {
+            return Err(Error::InvalidSignature);
+        }
+        let start = out.len() - int.len();
+        out[start..].copy_from_slice(int);
+    }
+    Ok(signature)
+}
@@ -18,20 +18,39 @@
 use crate::{
     acl::AclMgr,
     data_model::{
-        cluster_basic_information::BasicInfoConfig, core::DataModel,
+        cluster_basic_information::BasicInfoConfig, core::DataModel, objects::AttrStore,
         sdm::dev_att::DevAttDataFetcher,
     },
     error::*,
     fabric::FabricMgr,
+    group_keys::GroupKeys,
     interaction_model::InteractionModel,
     mdns::Mdns,
     pairing::{print_pairing_code_and_qr, DiscoveryCapabilities},
-    secure_channel::{core::SecureChannel, pake::PaseMgr, spake2p::VerifierData},
-    transport,
+    persist::{FileKvStore, KeyWrapper, SharedKvStore},
+    secure_channel::{
+        core::SecureChannel, msg_counter_sync::MsgCounterSync, pake::PaseMgr, spake2p::VerifierData,
+    },
+    transport::{
+        self,
+        counters::GroupCounters,
+        group::GroupCtx,
+        network::NetworkInterface,
+        queue::WorkQ,
+        tcp::TcpTransport,
+        udp::{UdpListener, MATTER_PORT},
+    },
+    utils::clock::{system_clock, SharedClock},
+};
+use log::info;
+use smol::future;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
 };
-use std::sync::Arc;
 
 /// Device Commissioning Data
+#[derive(Clone)]
 pub struct CommissioningData {
     /// The data like password or verifier that is required to authenticate
     pub verifier: VerifierData,
@@ -44,6 +63,17 @@ pub struct Matter {
     transport_mgr: transport::mgr::Mgr,
     data_model: DataModel,
     fabric_mgr: Arc<FabricMgr>,
+    acl_mgr: Arc<AclMgr>,
+    attr_store: Arc<AttrStore>,
+    group_keys: Arc<GroupKeys>,
+    group_counters: Arc<Mutex<GroupCounters>>,
+    mcsp: MsgCounterSync,
+    work_q: WorkQ,
+    pase: PaseMgr,
+    dev_comm: CommissioningData,
+    mdns: Arc<Mdns>,
+    port: u16,
+    clock: SharedClock,
 }
 
 impl Matter {
@@ -53,38 +83,122 @@ impl Matter {
     /// * dev_att: An object that implements the trait [DevAttDataFetcher]. Any Matter device
     /// requires a set of device attestation certificates and keys. It is the responsibility of
     /// this object to return the device attestation details when queried upon.
-    pub fn new(
+    /// * storage_dir: The directory that the persistent state, like the fabrics and the ACLs,
+    /// is stored in. It must survive a reboot, [FileKvStore::default_dir] is a good choice.
+    ///
+    /// Use [Matter::new_with] for another kind of storage.
+    pub fn new<P: AsRef<Path>>(
+        dev_det: BasicInfoConfig,
+        dev_att: Box<dyn DevAttDataFetcher>,
+        dev_comm: CommissioningData,
+        storage_dir: P,
+    ) -> Result<Box<Matter>, Error> {
+        let storage = Arc::new(Mutex::new(FileKvStore::new(storage_dir)?));
+        Matter::new_with(
+            dev_det,
+            dev_att,
+            dev_comm,
+            storage,
+            None,
+            MATTER_PORT,
+            Box::new(UdpListener::new(MATTER_PORT)?),
+            system_clock(),
+        )
+    }
+
+    /// Creates a new Matter object with its own persistent storage, port and network
+    ///
+    /// Every Matter object owns its storage, mDNS publisher, work queue and buffer pools,
+    /// so multiple Matter objects can run in the same process. Each of them needs a
+    /// different storage and port though.
+    ///
+    /// The messages go over the given network interface, which is typically a
+    /// [UdpListener] on the same port. The tests can attach the node to a
+    /// [LoopbackHub](transport::loopback::LoopbackHub) instead.
+    ///
+    /// If a key_wrapper is given, sensitive items like the fabrics' private keys are
+    /// encrypted with it before they are stored.
+    ///
+    /// All the timeouts and timestamps of the stack are read from the given clock.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with(
         dev_det: BasicInfoConfig,
         dev_att: Box<dyn DevAttDataFetcher>,
         dev_comm: CommissioningData,
+        storage: SharedKvStore,
+        key_wrapper: Option<KeyWrapper>,
+        port: u16,
+        network: Box<dyn NetworkInterface>,
+        clock: SharedClock,
     ) -> Result<Box<Matter>, Error> {
-        let mdns = Mdns::get()?;
+        let mdns = Arc::new(Mdns::new(port));
         mdns.set_values(dev_det.vid, dev_det.pid, &dev_det.device_name);
+        let (work_q, rx_q) = WorkQ::new();
 
-        let fabric_mgr = Arc::new(FabricMgr::new()?);
+        let fabric_mgr = Arc::new(FabricMgr::new(storage.clone(), mdns.clone(), key_wrapper)?);
         let open_comm_window = fabric_mgr.is_empty();
         if open_comm_window {
             print_pairing_code_and_qr(&dev_det, &dev_comm, DiscoveryCapabilities::default());
         }
 
-        let acl_mgr = Arc::new(AclMgr::new()?);
-        let mut pase = PaseMgr::new();
-        let data_model =
-            DataModel::new(dev_det, dev_att, fabric_mgr.clone(), acl_mgr, pase.clone())?;
+        let acl_mgr = Arc::new(AclMgr::new(storage.clone())?);
+        let attr_store = Arc::new(AttrStore::new_with(storage.clone(), clock.clone()));
+        let group_keys = Arc::new(GroupKeys::new(fabric_mgr.clone(), storage.clone())?);
+        let group_counters = Arc::new(Mutex::new(GroupCounters::new(storage)?));
+        let mcsp = MsgCounterSync::new(group_counters.clone());
+        let mut transport_mgr =
+            transport::mgr::Mgr::new_with_network(rx_q, network, clock.clone())?;
+        let timers = transport_mgr.get_timers();
+        let mut pase = PaseMgr::new(mdns.clone(), work_q.clone(), timers.clone());
+        let data_model = DataModel::new(
+            dev_det,
+            dev_att,
+            fabric_mgr.clone(),
+            acl_mgr.clone(),
+            pase.clone(),
+            attr_store.clone(),
+            group_keys.clone(),
+            timers.clone(),
+            work_q.clone(),
+        )?;
+        transport_mgr.set_group_ctx(GroupCtx::new(
+            group_keys.clone(),
+            group_counters.clone(),
+            mcsp.clone(),
+            port,
+        ));
         let mut matter = Box::new(Matter {
-            transport_mgr: transport::mgr::Mgr::new()?,
+            transport_mgr,
             data_model,
             fabric_mgr,
+            acl_mgr,
+            attr_store,
+            group_keys,
+            group_counters,
+            mcsp: mcsp.clone(),
+            work_q: work_q.clone(),
+            pase: pase.clone(),
+            dev_comm: dev_comm.clone(),
+            mdns,
+            port,
+            clock: clock.clone(),
         });
-        let interaction_model =
-            Box::new(InteractionModel::new(Box::new(matter.data_model.clone())));
+        let interaction_model = Box::new(InteractionModel::new(
+            Box::new(matter.data_model.clone()),
+            timers,
+        ));
         matter.transport_mgr.register_protocol(interaction_model)?;
 
         if open_comm_window {
-            pase.enable_pase_session(dev_comm.verifier, dev_comm.discriminator)?;
+            pase.enable_pase_session(dev_comm.verifier, dev_comm.discriminator, None)?;
         }
 
-        let secure_channel = Box::new(SecureChannel::new(pase, matter.fabric_mgr.clone()));
+        let secure_channel = Box::new(SecureChannel::new(
+            pase,
+            matter.fabric_mgr.clone(),
+            work_q,
+            mcsp,
+        ));
         matter.transport_mgr.register_protocol(secure_channel)?;
         Ok(matter)
     }
@@ -98,13 +212,169 @@ impl Matter {
         self.data_model.clone()
     }
 
+    /// Returns the group keys and group memberships of the node
+    ///
+    /// The node listens on the multicast address of every group that is mapped here.
+    pub fn get_group_keys(&self) -> Arc<GroupKeys> {
+        self.group_keys.clone()
+    }
+
+    /// Returns the work queue of the transport
+    ///
+    /// Messages to groups are sent by posting a [transport::queue::Msg::Groupcast] here.
+    pub fn get_work_q(&self) -> WorkQ {
+        self.work_q.clone()
+    }
+
+    /// Accept connections over TCP as well, on the same port as UDP
+    ///
+    /// The node advertises TCP support over mDNS from then on. Large messages may go over
+    /// TCP then, without the MRP retransmissions.
+    pub fn enable_tcp(&mut self) -> Result<(), Error> {
+        let tcp = TcpTransport::new_with(self.port, self.clock.clone())?;
+        self.transport_mgr.add_network_interface(Box::new(tcp))?;
+        self.mdns.set_tcp_supported(true);
+        self.fabric_mgr.republish()?;
+        self.pase.republish()
+    }
+
+    /// Runs the Matter stack
+    ///
+    /// The returned future drives the transport, the exchanges and the data model, and
+    /// completes only on an unrecoverable error. It can be run on any async executor, next
+    /// to other work. Dropping the future stops the stack cleanly; it can be run again later.
+    ///
+    /// The persistent attributes are stored in the background, a while after they change.
+    /// Call [Matter::flush] before shutting down to store them right away.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        future::or(self.transport_mgr.run(), self.attr_store.run()).await
+    }
+
+    /// Return the device to its factory state
+    ///
+    /// This removes all the fabrics and their operational mDNS records, the ACLs, the group
+    /// keys, the stored attributes and the message counters, drops all the sessions and
+    /// subscriptions, disarms the fail-safe, and then reopens the commissioning window with
+    /// the original [CommissioningData]. The persistent attributes, the breadcrumb and the
+    /// regulatory config go back to their initial values.
+    ///
+    /// This needs exclusive access, so the future returned by [Matter::run] must be
+    /// dropped before the reset. It can be run again right after.
+    pub fn factory_reset(&mut self) -> Result<(), Error> {
+        info!("Factory reset");
+        self.data_model.failsafe.reset();
+        self.data_model.subs.remove_all();
+        self.transport_mgr.remove_all_sessions();
+        self.fabric_mgr.remove_all()?;
+        self.acl_mgr.erase_all();
+        self.group_keys.remove_all()?;
+        self.attr_store.clear()?;
+        self.data_model
+            .node
+            .write()
+            .unwrap()
+            .reset_persistent_attributes();
+        self.group_counters.lock().unwrap().reset()?;
+        self.mcsp.reset();
+
+        self.pase.enable_pase_session(
+            self.dev_comm.verifier.clone(),
+            self.dev_comm.discriminator,
+            None,
+        )?;
+        info!("Commissioning window open");
+        Ok(())
+    }
+
+    /// Store any persistent attributes that have changed, without waiting
+    pub fn flush(&self) -> Result<(), Error> {
+        self.attr_store.flush()
+    }
+
     /// Starts the Matter daemon
     ///
     /// This call does NOT return
     ///
     /// This call starts the Matter daemon that starts communication with other Matter
-    /// devices on the network.
+    /// devices on the network. This is a blocking wrapper over [Matter::run]
     pub fn start_daemon(&mut self) -> Result<(), Error> {
-        self.transport_mgr.start()
+        smol::block_on(self.run())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        data_model::{
+            cluster_basic_information::BasicInfoConfig,
+            sdm::dev_att::{DataType, DevAttDataFetcher},
+        },
+        error::Error,
+        persist::MemKvStore,
+        secure_channel::spake2p::VerifierData,
+        transport::{loopback::LoopbackHub, session::SessionMode, udp::MATTER_PORT},
+        utils::clock::{MockClock, SharedClock},
+    };
+
+    use super::{CommissioningData, Matter};
+
+    struct NoDevAtt;
+
+    impl DevAttDataFetcher for NoDevAtt {
+        fn get_devatt_data(&self, _data_type: DataType, _data: &mut [u8]) -> Result<usize, Error> {
+            Err(Error::NotFound)
+        }
+    }
+
+    fn matter(clock: SharedClock) -> Box<Matter> {
+        let hub = LoopbackHub::new_with(0, clock.clone());
+        let dev_det = BasicInfoConfig {
+            vid: 0xFFF1,
+            pid: 0x8000,
+            hw_ver: 1,
+            sw_ver: 1,
+            sw_ver_str: "1".to_string(),
+            serial_no: "aabbccdd".to_string(),
+            device_name: "Test Device".to_string(),
+        };
+        let dev_comm = CommissioningData {
+            verifier: VerifierData::new_with_pw(123456),
+            discriminator: 250,
+        };
+        Matter::new_with(
+            dev_det,
+            Box::new(NoDevAtt),
+            dev_comm,
+            Arc::new(Mutex::new(MemKvStore::new())),
+            None,
+            MATTER_PORT,
+            Box::new(hub.add_node()),
+            clock,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_factory_reset_armed() {
+        let mut matter = matter(Arc::new(MockClock::default()));
+        let timers = matter.transport_mgr.get_timers();
+        let dm = matter.get_data_model();
+
+        dm.failsafe.arm(60, SessionMode::Pase).unwrap();
+        dm.failsafe.set_bread_crumb(5);
+        let reg_config = dm.failsafe.reg_config();
+        dm.failsafe.set_reg_config(reg_config + 1);
+        dm.subs.add(1, 10, Some(1), Some(100), 5).unwrap();
+        assert!(timers.next_deadline().is_some());
+
+        matter.factory_reset().unwrap();
+        assert!(!dm.failsafe.is_armed());
+        assert_eq!(dm.failsafe.bread_crumb(), 0);
+        assert_eq!(dm.failsafe.reg_config(), reg_config);
+        assert!(dm.subs.ids().is_empty());
+        // Neither the fail-safe's nor the subscription's timer is left to fire
+        assert_eq!(timers.next_deadline(), None);
     }
 }
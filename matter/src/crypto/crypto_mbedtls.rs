@@ -30,9 +30,9 @@ use mbedtls::{
 
 use super::CryptoKeyPair;
 use crate::{
-    // TODO: We should move ASN1Writer out of Cert,
+    // TODO: We should move the signature conversions out of Cert,
     // so Crypto doesn't have to depend on Cert
-    cert::{ASN1Writer, CertConsumer},
+    cert::{encode_der_signature, x509::parse_der_signature, MAX_DER_SIGNATURE_LEN},
     error::Error,
 };
 
@@ -169,10 +169,10 @@ impl CryptoKeyPair for KeyPair {
         // mbedTLS writes the DER signature first
         // TODO: Update rust-mbedtls to provide raw level APIs to get r and s values
         let mut tmp_sign = [0u8; super::EC_SIGNATURE_LEN_BYTES * 3];
-        tmp_key.sign(hash::Type::Sha256, &msg_hash, &mut tmp_sign, &mut ctr_drbg)?;
-        let len = convert_asn1_sign_to_r_s(&mut tmp_sign)?;
-        signature[..len].copy_from_slice(&tmp_sign[..len]);
-        Ok(len)
+        let len = tmp_key.sign(hash::Type::Sha256, &msg_hash, &mut tmp_sign, &mut ctr_drbg)?;
+        let raw_sign = parse_der_signature(&tmp_sign[..len])?;
+        signature[..raw_sign.len()].copy_from_slice(&raw_sign);
+        Ok(raw_sign.len())
     }
 
     fn verify_msg(&self, msg: &[u8], signature: &[u8]) -> Result<(), Error> {
@@ -187,8 +187,11 @@ impl CryptoKeyPair for KeyPair {
         Md::hash(hash::Type::Sha256, msg, &mut msg_hash)?;
 
         // current rust-mbedTLS APIs the signature to be in DER format
-        let mut mbedtls_sign = [0u8; super::EC_SIGNATURE_LEN_BYTES * 3];
-        let len = convert_r_s_to_asn1_sign(signature, &mut mbedtls_sign)?;
+        if signature.len() != super::EC_SIGNATURE_LEN_BYTES {
+            return Err(Error::InvalidSignature);
+        }
+        let mut mbedtls_sign = [0u8; MAX_DER_SIGNATURE_LEN];
+        let len = encode_der_signature(signature, &mut mbedtls_sign)?;
         let mbedtls_sign = &mbedtls_sign[..len];
 
         if let Err(e) = tmp_key.verify(hash::Type::Sha256, &msg_hash, mbedtls_sign) {
@@ -200,78 +203,6 @@ impl CryptoKeyPair for KeyPair {
     }
 }
 
-fn convert_r_s_to_asn1_sign(signature: &[u8], mbedtls_sign: &mut [u8]) -> Result<usize, Error> {
-    let r = &signature[0..32];
-    let s = &signature[32..64];
-
-    let mut wr = ASN1Writer::new(mbedtls_sign);
-    wr.start_seq("")?;
-    wr.integer("r", r)?;
-    wr.integer("s", s)?;
-    wr.end_seq()?;
-    Ok(wr.as_slice().len())
-}
-
-// mbedTLS sign() function directly encodes the signature in ASN1. The lower level function
-// is not yet exposed to us through the Rust crate. So here, I am crudely extracting the 'r'
-// and 's' values from the ASN1 encoding and writing 'r' and 's' back sequentially as is expected
-// per the Matter spec.
-fn convert_asn1_sign_to_r_s(signature: &mut [u8]) -> Result<usize, Error> {
-    if signature[0] == 0x30 {
-        // Type 0x30 ASN1 Sequence
-        // Length: Skip
-        let mut offset: usize = 2;
-
-        // Type 0x2 is Integer (first integer is r)
-        if signature[offset] != 2 {
-            return Err(Error::Invalid);
-        }
-        offset += 1;
-
-        // Length
-        let len = signature[offset];
-        offset += 1;
-        // XXX Once, I have seen a crash in this conversion, need to dig
-        if len < 32 {
-            error!(
-                "Cannot deal with this: this will crash: the slice is: {:x?}",
-                signature
-            );
-        }
-
-        // Sometimes length is more than 32 with a 0 prefix-padded, skip over that
-        offset += (len - 32) as usize;
-
-        // Extract the 32 bytes of 'r'
-        let mut r = [0_u8; super::BIGNUM_LEN_BYTES];
-        r.copy_from_slice(&signature[offset..(offset + 32)]);
-        offset += 32;
-
-        // Type 0x2 is Integer (this integer is s)
-        if signature[offset] != 2 {
-            return Err(Error::Invalid);
-        }
-        offset += 1;
-
-        // Length
-        let len = signature[offset];
-        offset += 1;
-        // Sometimes length is more than 32 with a 0 prefix-padded, skip over that
-        offset += (len - 32) as usize;
-
-        // Extract the 32 bytes of 's'
-        let mut s = [0_u8; super::BIGNUM_LEN_BYTES];
-        s.copy_from_slice(&signature[offset..(offset + 32)]);
-
-        signature[0..32].copy_from_slice(&r);
-        signature[32..64].copy_from_slice(&s);
-
-        Ok(64)
-    } else {
-        Err(Error::Invalid)
-    }
-}
-
 pub fn pbkdf2_hmac(pass: &[u8], iter: usize, salt: &[u8], key: &mut [u8]) -> Result<(), Error> {
     mbedtls::hash::pbkdf2_hmac(Type::Sha256, pass, salt, iter as u32, key)
         .map_err(|_e| Error::TLSStack)
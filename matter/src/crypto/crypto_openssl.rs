@@ -185,12 +185,18 @@ impl CryptoKeyPair for KeyPair {
         }
         safemem::write_bytes(signature, 0);
 
+        // r and s are big-endian, a short one has to be right-aligned in its 32 bytes
         let sig = EcdsaSig::sign(&msg, self.private_key()?)?;
-        let r = sig.r().to_vec();
-        signature[0..r.len()].copy_from_slice(r.as_slice());
-        let s = sig.s().to_vec();
-        signature[32..(32 + s.len())].copy_from_slice(s.as_slice());
-        Ok(64)
+        let (r_out, s_out) =
+            signature[..super::EC_SIGNATURE_LEN_BYTES].split_at_mut(super::BIGNUM_LEN_BYTES);
+        for (int, out) in [(sig.r().to_vec(), r_out), (sig.s().to_vec(), s_out)] {
+            if int.len() > out.len() {
+                return Err(Error::Invalid);
+            }
+            let start = out.len() - int.len();
+            out[start..].copy_from_slice(&int);
+        }
+        Ok(super::EC_SIGNATURE_LEN_BYTES)
     }
 
     fn verify_msg(&self, msg: &[u8], signature: &[u8]) -> Result<(), Error> {
@@ -199,6 +205,9 @@ impl CryptoKeyPair for KeyPair {
         h.update(msg)?;
         let msg = h.finish()?;
 
+        if signature.len() != super::EC_SIGNATURE_LEN_BYTES {
+            return Err(Error::InvalidSignature);
+        }
         let r = BigNum::from_slice(&signature[0..super::BIGNUM_LEN_BYTES])?;
         let s =
             BigNum::from_slice(&signature[super::BIGNUM_LEN_BYTES..(2 * super::BIGNUM_LEN_BYTES)])?;
@@ -116,10 +116,12 @@ impl KeyPair {
     }
 
     pub fn new_from_components(pub_key: &[u8], priv_key: &[u8]) -> Result<Self, Error> {
-        let secret_key = SecretKey::from_slice(priv_key).unwrap();
-        let encoded_point = EncodedPoint::from_bytes(pub_key).unwrap();
-        let public_key = PublicKey::from_encoded_point(&encoded_point).unwrap();
-        assert_eq!(public_key, secret_key.public_key());
+        let secret_key = SecretKey::from_slice(priv_key).map_err(|_| Error::Crypto)?;
+        let encoded_point = EncodedPoint::from_bytes(pub_key).map_err(|_| Error::Crypto)?;
+        let public_key = Option::from(PublicKey::from_encoded_point(&encoded_point));
+        if public_key != Some(secret_key.public_key()) {
+            return Err(Error::Crypto);
+        }
 
         Ok(Self {
             key: KeyType::Private(secret_key),
@@ -127,9 +129,11 @@ impl KeyPair {
     }
 
     pub fn new_from_public(pub_key: &[u8]) -> Result<Self, Error> {
-        let encoded_point = EncodedPoint::from_bytes(pub_key).unwrap();
+        // The key may come from the peer, so it can't be trusted to be valid
+        let encoded_point = EncodedPoint::from_bytes(pub_key).map_err(|_| Error::Crypto)?;
+        let pub_key = Option::from(PublicKey::from_encoded_point(&encoded_point));
         Ok(Self {
-            key: KeyType::Public(PublicKey::from_encoded_point(&encoded_point).unwrap()),
+            key: KeyType::Public(pub_key.ok_or(Error::Crypto)?),
         })
     }
 
@@ -270,7 +274,7 @@ impl CryptoKeyPair for KeyPair {
         use p256::ecdsa::signature::Verifier;
 
         let verifying_key = VerifyingKey::from_affine(self.public_key_point()).unwrap();
-        let signature = Signature::try_from(signature).unwrap();
+        let signature = Signature::try_from(signature).map_err(|_| Error::InvalidSignature)?;
 
         verifying_key
             .verify(msg, &signature)
@@ -15,7 +15,13 @@
  *    limitations under the License.
  */
 
-use crate::error::Error;
+use crate::{
+    cert::{
+        asn1_reader::{ASN1Reader, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE},
+        OID_EC_TYPE_PRIME256V1, OID_PUB_KEY_ECPUBKEY,
+    },
+    error::Error,
+};
 
 pub const SYMM_KEY_LEN_BITS: usize = 128;
 pub const SYMM_KEY_LEN_BYTES: usize = SYMM_KEY_LEN_BITS / 8;
@@ -67,11 +73,39 @@ pub use self::crypto_rustcrypto::*;
 
 pub mod crypto_dummy;
 
+/// The private key of a P-256 key in DER, either as an ECPrivateKey (RFC 5915), or wrapped in
+/// a PKCS#8 PrivateKeyInfo (RFC 5208)
+pub fn decode_ec_private_key(der: &[u8]) -> Result<&[u8], Error> {
+    let mut seq = ASN1Reader::new(der).enter(TAG_SEQUENCE)?;
+    match seq.read(TAG_INTEGER)? {
+        [1] => {
+            let priv_key = seq.read(TAG_OCTET_STRING)?;
+            if let Some(params) = seq.read_optional(0xA0)? {
+                ASN1Reader::new(params).expect_oid(&OID_EC_TYPE_PRIME256V1)?;
+            }
+            if priv_key.len() != BIGNUM_LEN_BYTES {
+                return Err(Error::InvalidData);
+            }
+            Ok(priv_key)
+        }
+        [0] => {
+            let mut algo = seq.enter(TAG_SEQUENCE)?;
+            algo.expect_oid(&OID_PUB_KEY_ECPUBKEY)?;
+            algo.expect_oid(&OID_EC_TYPE_PRIME256V1)?;
+            decode_ec_private_key(seq.read(TAG_OCTET_STRING)?)
+        }
+        _ => Err(Error::InvalidData),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::error::Error;
+    use crate::{
+        cert::{encode_der_signature, x509::parse_der_signature, MAX_DER_SIGNATURE_LEN},
+        error::Error,
+    };
 
-    use super::{CryptoKeyPair, KeyPair};
+    use super::{decode_ec_private_key, CryptoKeyPair, KeyPair};
 
     #[test]
     fn test_verify_msg_success() {
@@ -88,7 +122,87 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_verify_msg_short_r() {
+        let key = KeyPair::new_from_public(&test_vectors::PUB_KEY3).unwrap();
+        key.verify_msg(test_vectors::MSG3, &test_vectors::SIGNATURE3)
+            .unwrap();
+
+        // The DER form drops the leading 0 of r, and has to come back right-aligned
+        let mut der = [0u8; MAX_DER_SIGNATURE_LEN];
+        let len = encode_der_signature(&test_vectors::SIGNATURE3, &mut der).unwrap();
+        let raw = parse_der_signature(&der[..len]).unwrap();
+        assert_eq!(raw, test_vectors::SIGNATURE3);
+
+        // A truncated signature is rejected, rather than sliced
+        assert_eq!(
+            key.verify_msg(test_vectors::MSG3, &test_vectors::SIGNATURE3[..10]),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_decode_ec_private_key() {
+        let priv_key = decode_ec_private_key(&test_vectors::EC_PRIV_KEY_SEC1).unwrap();
+        assert_eq!(priv_key, test_vectors::PRIV_KEY2);
+        let priv_key = decode_ec_private_key(&test_vectors::EC_PRIV_KEY_PKCS8).unwrap();
+        assert_eq!(priv_key, test_vectors::PRIV_KEY2);
+
+        // The key is for another curve
+        let mut der = test_vectors::EC_PRIV_KEY_SEC1;
+        der[49] ^= 0xFF;
+        assert_eq!(decode_ec_private_key(&der), Err(Error::InvalidData));
+        assert_eq!(
+            decode_ec_private_key(&test_vectors::EC_PRIV_KEY_SEC1[..40]),
+            Err(Error::TruncatedPacket)
+        );
+    }
+
     mod test_vectors {
+        pub const PUB_KEY3: [u8; 65] = [
+            0x04, 0x37, 0x65, 0xe0, 0x65, 0x1b, 0x24, 0x50, 0x0d, 0x82, 0x11, 0x0c, 0x52, 0xff,
+            0x4c, 0x12, 0xdd, 0xf3, 0x13, 0xdf, 0x38, 0x65, 0x6c, 0x13, 0xb0, 0xfb, 0x0b, 0x54,
+            0x21, 0xf3, 0x33, 0xda, 0x0b, 0x6c, 0xda, 0x21, 0x54, 0x40, 0x02, 0xee, 0x34, 0x11,
+            0xc8, 0x3d, 0xea, 0xbf, 0x49, 0x37, 0xe7, 0xe5, 0xe6, 0x49, 0xf2, 0x6e, 0x53, 0xad,
+            0x46, 0xf1, 0xf8, 0x4b, 0x57, 0xbb, 0x92, 0x23, 0xd0,
+        ];
+        pub const MSG3: &[u8] = b"short r 27";
+        // r is only 31 bytes long
+        pub const SIGNATURE3: [u8; 64] = [
+            0x00, 0xd7, 0xaa, 0x93, 0x6e, 0x4c, 0x8e, 0x3e, 0xc4, 0x7b, 0x71, 0x19, 0x45, 0xbf,
+            0x16, 0x12, 0x31, 0x18, 0x0e, 0xea, 0xdb, 0x3a, 0xea, 0x57, 0x30, 0x92, 0xf1, 0x8e,
+            0x3d, 0x6d, 0xa5, 0x4d, 0x1b, 0x66, 0x39, 0xdf, 0x72, 0xee, 0xde, 0x26, 0x79, 0x3c,
+            0x95, 0xe5, 0xb8, 0xe2, 0xed, 0x9c, 0x3b, 0x57, 0x78, 0x6f, 0x7e, 0x54, 0xbc, 0x87,
+            0x71, 0xef, 0xf6, 0xb0, 0x11, 0x7d, 0xc6, 0x77,
+        ];
+        pub const PRIV_KEY2: [u8; 32] = [
+            0xe9, 0x46, 0xce, 0x62, 0x87, 0xa3, 0x5a, 0xdd, 0x78, 0x5a, 0xec, 0x42, 0x30, 0xd9,
+            0x24, 0xd1, 0xcf, 0x9a, 0xfb, 0x73, 0xb9, 0x8b, 0xfc, 0x4b, 0xee, 0xc9, 0xf8, 0x8b,
+            0x5e, 0x2a, 0xb6, 0x2,
+        ];
+        pub const EC_PRIV_KEY_SEC1: [u8; 121] = [
+            0x30, 0x77, 0x2, 0x1, 0x1, 0x4, 0x20, 0xe9, 0x46, 0xce, 0x62, 0x87, 0xa3, 0x5a, 0xdd,
+            0x78, 0x5a, 0xec, 0x42, 0x30, 0xd9, 0x24, 0xd1, 0xcf, 0x9a, 0xfb, 0x73, 0xb9, 0x8b,
+            0xfc, 0x4b, 0xee, 0xc9, 0xf8, 0x8b, 0x5e, 0x2a, 0xb6, 0x2, 0xa0, 0xa, 0x6, 0x8, 0x2a,
+            0x86, 0x48, 0xce, 0x3d, 0x3, 0x1, 0x7, 0xa1, 0x44, 0x3, 0x42, 0x0, 0x4, 0x65, 0x55,
+            0x24, 0x0, 0xa5, 0x1d, 0x68, 0x19, 0xe7, 0x96, 0x52, 0x35, 0xfe, 0x9e, 0x3e, 0x3d,
+            0x66, 0x75, 0x73, 0x86, 0x8b, 0x4b, 0xf8, 0xb2, 0x3, 0x55, 0x47, 0xb2, 0xff, 0x46,
+            0xd5, 0x86, 0x63, 0x2a, 0xc7, 0x91, 0xc7, 0x46, 0x86, 0x5f, 0x4d, 0x96, 0x88, 0x81,
+            0x71, 0x6f, 0x61, 0xb1, 0xbc, 0x3f, 0x13, 0x1a, 0xc9, 0x1f, 0x30, 0xfd, 0x3b, 0x1e,
+            0xb2, 0x7b, 0x5e, 0xc, 0xd, 0x8c,
+        ];
+        pub const EC_PRIV_KEY_PKCS8: [u8; 138] = [
+            0x30, 0x81, 0x87, 0x2, 0x1, 0x0, 0x30, 0x13, 0x6, 0x7, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+            0x2, 0x1, 0x6, 0x8, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x3, 0x1, 0x7, 0x4, 0x6d, 0x30, 0x6b,
+            0x2, 0x1, 0x1, 0x4, 0x20, 0xe9, 0x46, 0xce, 0x62, 0x87, 0xa3, 0x5a, 0xdd, 0x78, 0x5a,
+            0xec, 0x42, 0x30, 0xd9, 0x24, 0xd1, 0xcf, 0x9a, 0xfb, 0x73, 0xb9, 0x8b, 0xfc, 0x4b,
+            0xee, 0xc9, 0xf8, 0x8b, 0x5e, 0x2a, 0xb6, 0x2, 0xa1, 0x44, 0x3, 0x42, 0x0, 0x4, 0x65,
+            0x55, 0x24, 0x0, 0xa5, 0x1d, 0x68, 0x19, 0xe7, 0x96, 0x52, 0x35, 0xfe, 0x9e, 0x3e,
+            0x3d, 0x66, 0x75, 0x73, 0x86, 0x8b, 0x4b, 0xf8, 0xb2, 0x3, 0x55, 0x47, 0xb2, 0xff,
+            0x46, 0xd5, 0x86, 0x63, 0x2a, 0xc7, 0x91, 0xc7, 0x46, 0x86, 0x5f, 0x4d, 0x96, 0x88,
+            0x81, 0x71, 0x6f, 0x61, 0xb1, 0xbc, 0x3f, 0x13, 0x1a, 0xc9, 0x1f, 0x30, 0xfd, 0x3b,
+            0x1e, 0xb2, 0x7b, 0x5e, 0xc, 0xd, 0x8c,
+        ];
         pub const PUB_KEY1: [u8; 65] = [
             0x4, 0x56, 0x19, 0x77, 0x18, 0x3f, 0xd4, 0xff, 0x2b, 0x58, 0x3d, 0xe9, 0x79, 0x34,
             0x66, 0xdf, 0xe9, 0x0, 0xfb, 0x6d, 0xa1, 0xef, 0xe0, 0xcc, 0xdc, 0x77, 0x30, 0xc0,
@@ -0,0 +1,392 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+use std::sync::Arc;
+
+use super::objects::*;
+use crate::{
+    cmd_enter,
+    error::*,
+    group_keys::{GroupKeys, MAX_GROUPS_PER_FABRIC},
+    interaction_model::{command::CommandReq, core::IMStatusCode, messages::ib},
+    tlv::{
+        FromTLV, Nullable, TLVArray, TLVArrayOwned, TLVElement, TLVWriter, TagType, ToTLV, UtfStr,
+    },
+};
+use log::info;
+use num_derive::FromPrimitive;
+
+pub const ID: u32 = 0x0004;
+
+// The longest group name, in bytes
+const MAX_GROUP_NAME_LEN: usize = 16;
+
+#[derive(FromPrimitive)]
+pub enum Attributes {
+    NameSupport = 0,
+}
+
+#[derive(FromPrimitive)]
+pub enum Commands {
+    AddGroup = 0x00,
+    ViewGroup = 0x01,
+    GetGroupMembership = 0x02,
+    RemoveGroup = 0x03,
+    RemoveAllGroups = 0x04,
+    AddGroupIfIdentifying = 0x05,
+}
+
+// The responses share the command ids of their requests
+#[derive(FromPrimitive)]
+pub enum RespCommands {
+    AddGroupResp = 0x00,
+    ViewGroupResp = 0x01,
+    GetGroupMembershipResp = 0x02,
+    RemoveGroupResp = 0x03,
+}
+
+enum FeatureMap {
+    GroupNames = 0x01,
+}
+
+enum NameSupport {
+    GroupNames = 0x80,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddGroupReq<'a> {
+    group_id: u16,
+    group_name: UtfStr<'a>,
+}
+
+#[derive(FromTLV)]
+struct GroupIdReq {
+    group_id: u16,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct GetGroupMembershipReq<'a> {
+    group_list: TLVArray<'a, u16>,
+}
+
+// The AddGroupResponse and the RemoveGroupResponse
+#[derive(ToTLV)]
+struct GroupStatusResp {
+    status: u8,
+    group_id: u16,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct ViewGroupResp<'a> {
+    status: u8,
+    group_id: u16,
+    group_name: UtfStr<'a>,
+}
+
+#[derive(ToTLV)]
+struct GetGroupMembershipResp {
+    capacity: Nullable<u8>,
+    group_list: TLVArrayOwned<u16>,
+}
+
+fn attr_name_support_new() -> Attribute {
+    Attribute::new(
+        Attributes::NameSupport as u16,
+        AttrValue::Uint8(NameSupport::GroupNames as u8),
+        Access::RV,
+        Quality::FIXED,
+    )
+}
+
+/// The Groups cluster of an endpoint
+///
+/// The group memberships of all the endpoints are kept in the group table of the
+/// [GroupKeys], so that the group messages can be dispatched to the member endpoints.
+pub struct GroupsCluster {
+    base: Cluster,
+    group_keys: Arc<GroupKeys>,
+    identify_query: Option<Box<dyn Fn() -> bool>>,
+}
+
+impl GroupsCluster {
+    pub fn new(group_keys: Arc<GroupKeys>) -> Result<Box<Self>, Error> {
+        let mut c = Box::new(GroupsCluster {
+            base: Cluster::new(ID)?,
+            group_keys,
+            identify_query: None,
+        });
+        c.base.set_feature_map(FeatureMap::GroupNames as u32)?;
+        c.base.add_attribute(attr_name_support_new())?;
+        Ok(c)
+    }
+
+    /// Set the query for whether the endpoint is currently identifying itself
+    ///
+    /// This is what AddGroupIfIdentifying looks at. Without it, the endpoint is never
+    /// identifying.
+    pub fn set_identify_query(&mut self, query: Box<dyn Fn() -> bool>) {
+        self.identify_query = Some(query);
+    }
+
+    fn is_identifying(&self) -> bool {
+        matches!(self.identify_query.as_ref(), Some(query) if query())
+    }
+
+    fn add_group(&mut self, fab_idx: u8, endpoint: u16, group_id: u16, name: &str) -> IMStatusCode {
+        if group_id == 0 || name.len() > MAX_GROUP_NAME_LEN {
+            return IMStatusCode::ConstraintError;
+        }
+        // The group can't be used until it is mapped to one of the fabric's key sets
+        match self.group_keys.key_map(fab_idx) {
+            Ok(key_map) if key_map.iter().any(|e| e.group_id == group_id) => (),
+            _ => return IMStatusCode::UnsupportedAccess,
+        }
+        match self
+            .group_keys
+            .add_group_endpoint(fab_idx, group_id, name, endpoint)
+        {
+            Ok(_) => {
+                self.base.cluster_changed();
+                IMStatusCode::Success
+            }
+            Err(Error::NoSpace) => IMStatusCode::ResourceExhausted,
+            Err(_) => IMStatusCode::Failure,
+        }
+    }
+
+    fn view_group(
+        &self,
+        fab_idx: u8,
+        endpoint: u16,
+        group_id: u16,
+    ) -> Result<String, IMStatusCode> {
+        if group_id == 0 {
+            return Err(IMStatusCode::ConstraintError);
+        }
+        self.group_keys
+            .groups(fab_idx)
+            .map_err(|_| IMStatusCode::Failure)?
+            .into_iter()
+            .find(|g| g.group_id == group_id && g.endpoints.contains(&endpoint))
+            .map(|g| g.name)
+            .ok_or(IMStatusCode::NotFound)
+    }
+
+    // Returns the remaining capacity of the group table, and the groups of the list
+    // that the endpoint is a member of. An empty list stands for all the groups.
+    fn get_group_membership(
+        &self,
+        fab_idx: u8,
+        endpoint: u16,
+        group_list: &[u16],
+    ) -> Result<(u8, Vec<u16>), IMStatusCode> {
+        let groups = self
+            .group_keys
+            .groups(fab_idx)
+            .map_err(|_| IMStatusCode::Failure)?;
+        let capacity = MAX_GROUPS_PER_FABRIC.saturating_sub(groups.len()) as u8;
+        let members = groups
+            .iter()
+            .filter(|g| g.endpoints.contains(&endpoint))
+            .map(|g| g.group_id)
+            .filter(|id| group_list.is_empty() || group_list.contains(id))
+            .collect();
+        Ok((capacity, members))
+    }
+
+    fn remove_group(&mut self, fab_idx: u8, endpoint: u16, group_id: u16) -> IMStatusCode {
+        if group_id == 0 {
+            return IMStatusCode::ConstraintError;
+        }
+        match self
+            .group_keys
+            .remove_group_endpoint(fab_idx, group_id, endpoint)
+        {
+            Ok(_) => {
+                self.base.cluster_changed();
+                IMStatusCode::Success
+            }
+            Err(Error::NotFound) => IMStatusCode::NotFound,
+            Err(_) => IMStatusCode::Failure,
+        }
+    }
+
+    fn remove_all_groups(&mut self, fab_idx: u8, endpoint: u16) -> Result<(), IMStatusCode> {
+        self.group_keys
+            .remove_endpoint_groups(fab_idx, endpoint)
+            .map_err(|_| IMStatusCode::Failure)?;
+        self.base.cluster_changed();
+        Ok(())
+    }
+
+    // The accessing fabric and the endpoint that the command is for
+    fn cmd_target(cmd_req: &CommandReq) -> Result<(u8, u16), IMStatusCode> {
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(IMStatusCode::UnsupportedAccess)?;
+        let endpoint = cmd_req
+            .cmd
+            .path
+            .endpoint
+            .ok_or(IMStatusCode::UnsupportedEndpoint)?;
+        Ok((fab_idx, endpoint))
+    }
+
+    fn send_resp(cmd_req: &mut CommandReq, cmd: RespCommands, resp: &dyn ToTLV) {
+        let invoke_resp = ib::InvResp::cmd_new(
+            cmd_req.cmd.path.endpoint.unwrap_or_default(),
+            ID,
+            cmd as u16,
+            EncodeValue::Value(resp),
+        );
+        let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
+        cmd_req.trans.complete();
+    }
+
+    fn handle_command_addgroup(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("AddGroup");
+        let (fab_idx, endpoint) = GroupsCluster::cmd_target(cmd_req)?;
+        let req = AddGroupReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        let name = req
+            .group_name
+            .to_string()
+            .map_err(|_| IMStatusCode::InvalidCommand)?;
+
+        let status = self.add_group(fab_idx, endpoint, req.group_id, &name);
+        let resp = GroupStatusResp {
+            status: status as u8,
+            group_id: req.group_id,
+        };
+        GroupsCluster::send_resp(cmd_req, RespCommands::AddGroupResp, &resp);
+        Ok(())
+    }
+
+    fn handle_command_viewgroup(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("ViewGroup");
+        let (fab_idx, endpoint) = GroupsCluster::cmd_target(cmd_req)?;
+        let req = GroupIdReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+
+        let (status, name) = match self.view_group(fab_idx, endpoint, req.group_id) {
+            Ok(name) => (IMStatusCode::Success, name),
+            Err(e) => (e, String::new()),
+        };
+        let resp = ViewGroupResp {
+            status: status as u8,
+            group_id: req.group_id,
+            group_name: UtfStr::new(name.as_bytes()),
+        };
+        GroupsCluster::send_resp(cmd_req, RespCommands::ViewGroupResp, &resp);
+        Ok(())
+    }
+
+    fn handle_command_getgroupmembership(
+        &mut self,
+        cmd_req: &mut CommandReq,
+    ) -> Result<(), IMStatusCode> {
+        cmd_enter!("GetGroupMembership");
+        let (fab_idx, endpoint) = GroupsCluster::cmd_target(cmd_req)?;
+        let req = GetGroupMembershipReq::from_tlv(&cmd_req.data)
+            .map_err(|_| IMStatusCode::InvalidCommand)?;
+        let group_list: Vec<u16> = req.group_list.iter().collect();
+
+        let (capacity, members) = self.get_group_membership(fab_idx, endpoint, &group_list)?;
+        let resp = GetGroupMembershipResp {
+            capacity: Nullable::NotNull(capacity),
+            group_list: members.into(),
+        };
+        GroupsCluster::send_resp(cmd_req, RespCommands::GetGroupMembershipResp, &resp);
+        Ok(())
+    }
+
+    fn handle_command_removegroup(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("RemoveGroup");
+        let (fab_idx, endpoint) = GroupsCluster::cmd_target(cmd_req)?;
+        let req = GroupIdReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+
+        let status = self.remove_group(fab_idx, endpoint, req.group_id);
+        let resp = GroupStatusResp {
+            status: status as u8,
+            group_id: req.group_id,
+        };
+        GroupsCluster::send_resp(cmd_req, RespCommands::RemoveGroupResp, &resp);
+        Ok(())
+    }
+
+    fn handle_command_removeallgroups(
+        &mut self,
+        cmd_req: &mut CommandReq,
+    ) -> Result<(), IMStatusCode> {
+        cmd_enter!("RemoveAllGroups");
+        let (fab_idx, endpoint) = GroupsCluster::cmd_target(cmd_req)?;
+        self.remove_all_groups(fab_idx, endpoint)?;
+        cmd_req.trans.complete();
+        Err(IMStatusCode::Success)
+    }
+
+    fn handle_command_addgroupifidentifying(
+        &mut self,
+        cmd_req: &mut CommandReq,
+    ) -> Result<(), IMStatusCode> {
+        cmd_enter!("AddGroupIfIdentifying");
+        let (fab_idx, endpoint) = GroupsCluster::cmd_target(cmd_req)?;
+        let req = AddGroupReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        let name = req
+            .group_name
+            .to_string()
+            .map_err(|_| IMStatusCode::InvalidCommand)?;
+
+        let status = if self.is_identifying() {
+            self.add_group(fab_idx, endpoint, req.group_id, &name)
+        } else {
+            info!("Not identifying, ignoring the group");
+            IMStatusCode::Success
+        };
+        cmd_req.trans.complete();
+        Err(status)
+    }
+}
+
+impl ClusterType for GroupsCluster {
+    fn base(&self) -> &Cluster {
+        &self.base
+    }
+    fn base_mut(&mut self) -> &mut Cluster {
+        &mut self.base
+    }
+
+    fn handle_command(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        let cmd = cmd_req
+            .cmd
+            .path
+            .leaf
+            .map(num::FromPrimitive::from_u32)
+            .ok_or(IMStatusCode::UnsupportedCommand)?
+            .ok_or(IMStatusCode::UnsupportedCommand)?;
+        match cmd {
+            Commands::AddGroup => self.handle_command_addgroup(cmd_req),
+            Commands::ViewGroup => self.handle_command_viewgroup(cmd_req),
+            Commands::GetGroupMembership => self.handle_command_getgroupmembership(cmd_req),
+            Commands::RemoveGroup => self.handle_command_removegroup(cmd_req),
+            Commands::RemoveAllGroups => self.handle_command_removeallgroups(cmd_req),
+            Commands::AddGroupIfIdentifying => self.handle_command_addgroupifidentifying(cmd_req),
+        }
+    }
+}
@@ -24,8 +24,8 @@ use crate::{
         messages::ib::{self},
     },
     tlv::{TLVWriter, TagType, ToTLV},
+    utils::clock::system_clock,
 };
-use chrono::{DateTime, NaiveDate};
 use num_derive::FromPrimitive;
 
 pub const ID: u32 = 0x0506;
@@ -101,15 +101,10 @@ struct PlaybackPosition {
     updated_at: u64,
     position: u64,
 }
-// Get microseconds since 2000, Jan 1, 00:00:00
+/// Get microseconds since 2000, Jan 1, 00:00:00
+#[deprecated(note = "read the time from the session's clock, with Clock::matter_epoch_us()")]
 pub fn get_epoch_us() -> u64 {
-    let epoch_start = NaiveDate::from_ymd_opt(2000, 1, 1)
-        .unwrap()
-        .and_hms_micro_opt(0, 0, 0, 0)
-        .unwrap()
-        .and_local_timezone(chrono::Utc)
-        .unwrap();
-    DateTime::timestamp_micros(&epoch_start) as u64
+    system_clock().matter_epoch_us()
 }
 
 pub struct MediaPlaybackCluster {
@@ -212,9 +207,8 @@ impl MediaPlaybackCluster {
     }
 
     // When rewinding / changing stream / etc we need to change absolute position and updateAt
-    fn update_position(&mut self, new_pos: u64) -> Result<(), IMStatusCode> {
-        let now = get_epoch_us();
-
+    // The updated_at timestamp is in microseconds since the Matter epoch
+    fn update_position(&mut self, new_pos: u64, now: u64) -> Result<(), IMStatusCode> {
         self.sampled_position.position = new_pos;
         self.sampled_position.updated_at = now;
 
@@ -269,7 +263,8 @@ impl MediaPlaybackCluster {
             AttrValue::Uint8(PlaybackState::Playing as u8),
         )?;
 
-        self.update_position(0)?;
+        let now = cmd_req.trans.session.get_clock().matter_epoch_us();
+        self.update_position(0, now)?;
         self.run_callback(Commands::StartOver);
         self.send_playback_response(CommandStatus::Success, cmd_req);
         Err(IMStatusCode::Success)
@@ -15,19 +15,20 @@
  *    limitations under the License.
  */
 
-use self::subscribe::SubsCtx;
+use self::subscribe::{SubsCtx, Subscriptions};
 
 use super::{
     cluster_basic_information::BasicInfoConfig,
     device_types::device_type_add_root_node,
     objects::{self, *},
-    sdm::dev_att::DevAttDataFetcher,
+    sdm::{dev_att::DevAttDataFetcher, failsafe::FailSafe, general_commissioning::RegLocationType},
     system_model::descriptor::DescriptorCluster,
 };
 use crate::{
     acl::{AccessReq, Accessor, AccessorSubjects, AclMgr, AuthMode},
     error::*,
     fabric::FabricMgr,
+    group_keys::GroupKeys,
     interaction_model::{
         command::CommandReq,
         core::{IMStatusCode, OpCode},
@@ -42,7 +43,9 @@ use crate::{
     tlv::{self, FromTLV, TLVArray, TLVWriter, TagType, ToTLV},
     transport::{
         proto_demux::ResponseRequired,
-        session::{Session, SessionMode},
+        queue::WorkQ,
+        session::{GroupDetails, Session, SessionMode},
+        timer::Timers,
     },
 };
 use log::{error, info};
@@ -52,23 +55,44 @@ use std::sync::{Arc, RwLock};
 pub struct DataModel {
     pub node: Arc<RwLock<Box<Node>>>,
     acl_mgr: Arc<AclMgr>,
+    group_keys: Arc<GroupKeys>,
+    pub subs: Subscriptions,
+    pub failsafe: Arc<FailSafe>,
 }
 
 impl DataModel {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         dev_details: BasicInfoConfig,
         dev_att: Box<dyn DevAttDataFetcher>,
         fabric_mgr: Arc<FabricMgr>,
         acl_mgr: Arc<AclMgr>,
         pase_mgr: PaseMgr,
+        attr_store: Arc<AttrStore>,
+        group_keys: Arc<GroupKeys>,
+        timers: Timers,
+        work_q: WorkQ,
     ) -> Result<Self, Error> {
+        let failsafe = Arc::new(FailSafe::new(
+            // TODO: Arch-Specific
+            RegLocationType::IndoorOutdoor as u8,
+            timers.clone(),
+            fabric_mgr.clone(),
+            acl_mgr.clone(),
+            group_keys.clone(),
+            work_q.clone(),
+        ));
         let dm = DataModel {
             node: Arc::new(RwLock::new(Node::new()?)),
             acl_mgr: acl_mgr.clone(),
+            group_keys: group_keys.clone(),
+            subs: Subscriptions::new(timers.clone(), work_q.clone()),
+            failsafe: failsafe.clone(),
         };
         {
             let mut node = dm.node.write()?;
             node.set_changes_cb(Box::new(dm.clone()));
+            node.set_attr_store(attr_store);
             device_type_add_root_node(
                 &mut node,
                 dev_details,
@@ -76,6 +100,9 @@ impl DataModel {
                 fabric_mgr,
                 acl_mgr,
                 pase_mgr,
+                group_keys,
+                failsafe,
+                work_q,
             )?;
         }
         Ok(dm)
@@ -175,6 +202,32 @@ impl DataModel {
         }
     }
 
+    // Handle a command that was sent to a group
+    //
+    // The command goes to all the endpoints that are members of the group. Nobody
+    // receives the responses to a group message, so all the results are dropped.
+    fn handle_group_command_path(
+        &self,
+        node: &mut Node,
+        group: GroupDetails,
+        cmd_req: &mut CommandReq,
+    ) {
+        let mut path = cmd_req.cmd.path;
+        path.endpoint = None;
+
+        let _ = node.for_each_cluster_mut(&path, |path, c| {
+            let endpoint = path.endpoint.ok_or(IMStatusCode::UnsupportedEndpoint)?;
+            if self
+                .group_keys
+                .is_group_member(group.fab_idx, group.group_id, endpoint)
+            {
+                cmd_req.cmd.path = *path;
+                let _ = c.handle_command(cmd_req);
+            }
+            Ok(())
+        });
+    }
+
     fn sess_to_accessor(&self, sess: &Session) -> Accessor {
         match sess.get_session_mode() {
             SessionMode::Case(c) => {
@@ -193,6 +246,13 @@ impl DataModel {
                 AuthMode::Pase,
                 self.acl_mgr.clone(),
             ),
+            // The subject of a group message is its group
+            SessionMode::Group(g) => Accessor::new(
+                g.fab_idx,
+                AccessorSubjects::new(g.group_id as u64),
+                AuthMode::Group,
+                self.acl_mgr.clone(),
+            ),
 
             SessionMode::PlainText => Accessor::new(
                 0,
@@ -298,13 +358,21 @@ impl InteractionConsumer for DataModel {
                     continue;
                 };
                 info!("Invoke Commmand Handler executing: {:?}", i.path);
+                let group = match trans.session.get_session_mode() {
+                    SessionMode::Group(g) => Some(g),
+                    _ => None,
+                };
                 let mut cmd_req = CommandReq {
                     cmd: i.path,
                     data,
                     trans,
                     resp: tw,
                 };
-                DataModel::handle_command_path(&mut node, &mut cmd_req);
+                if let Some(group) = group {
+                    self.handle_group_command_path(&mut node, group, &mut cmd_req);
+                } else {
+                    DataModel::handle_command_path(&mut node, &mut cmd_req);
+                }
             }
             tw.end_container()?;
         }
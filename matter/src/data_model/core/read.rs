@@ -29,7 +29,7 @@ use crate::{
         Transaction,
     },
     tlv::{self, FromTLV, TLVArray, TLVWriter, TagType, ToTLV},
-    transport::{packet::Packet, proto_demux::ResponseRequired},
+    transport::proto_demux::ResponseRequired,
     utils::writebuf::WriteBuf,
     wb_shrink, wb_unshrink,
 };
@@ -108,7 +108,7 @@ impl<'a, 'b, 'c> Encoder for AttrReadEncoder<'a, 'b, 'c> {
 pub struct ResumeReadReq {
     /// The Read Request Attribute Path that caused chunking, and this is the path
     /// that needs to be resumed.
-    pub pending_req: Option<Packet<'static>>,
+    pub pending_req: Option<Vec<u8>>,
 
     /// The Attribute that couldn't be encoded because our buffer got full. The next chunk
     /// will start encoding from this attribute onwards.
@@ -118,14 +118,8 @@ pub struct ResumeReadReq {
 }
 impl ResumeReadReq {
     pub fn new(rx_buf: &[u8], resume_from: &Option<GenericPath>) -> Result<Self, Error> {
-        let mut packet = Packet::new_rx()?;
-        let dst = packet.as_borrow_slice();
-
-        let src_len = rx_buf.len();
-        dst[..src_len].copy_from_slice(rx_buf);
-        packet.get_parsebuf()?.set_len(src_len);
         Ok(ResumeReadReq {
-            pending_req: Some(packet),
+            pending_req: Some(rx_buf.to_vec()),
             resume_from: *resume_from,
         })
     }
@@ -304,8 +298,7 @@ impl DataModel {
         trans: &mut Transaction,
         tw: &mut TLVWriter,
     ) -> Result<(OpCode, ResponseRequired), Error> {
-        if let Some(packet) = resume_read_req.pending_req.as_mut() {
-            let rx_buf = packet.get_parsebuf()?.as_borrow_slice();
+        if let Some(rx_buf) = resume_read_req.pending_req.as_ref() {
             let root = tlv::get_root_node(rx_buf)?;
             let req = ReadReq::from_tlv(&root)?;
 
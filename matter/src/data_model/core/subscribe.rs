@@ -15,19 +15,32 @@
  *    limitations under the License.
  */
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use log::{error, info};
 
 use crate::{
     error::Error,
     interaction_model::{
-        core::OpCode,
+        core::{OpCode, PROTO_ID_INTERACTION_MODEL},
         messages::{
             msg::{self, SubscribeReq, SubscribeResp},
             GenericPath,
         },
     },
     tlv::{self, get_root_node_struct, FromTLV, TLVWriter, TagType, ToTLV},
-    transport::proto_demux::ResponseRequired,
+    transport::{
+        proto_demux::ResponseRequired,
+        queue::{Msg, Unicast, WorkQ},
+        timer::{TimerAction, TimerId, Timers},
+    },
+    utils::writebuf::WriteBuf,
 };
 
 use super::{read::ResumeReadReq, DataModel, Transaction};
@@ -40,9 +53,128 @@ enum SubsState {
     Confirmed,
 }
 
+/// A confirmed subscription
+struct Subscription {
+    id: u32,
+    fab_idx: Option<u8>,
+    peer_node_id: Option<u64>,
+    timer: TimerId,
+}
+
+/// The confirmed subscriptions
+///
+/// Each subscription has a periodic timer at its max interval, which sends a report to the
+/// subscriber. The subscription ends when the subscriber replaces it, or when its session
+/// is gone, in which case the transport cancels the timer.
+#[derive(Clone)]
+pub struct Subscriptions {
+    timers: Timers,
+    work_q: WorkQ,
+    subs: Arc<Mutex<Vec<Subscription>>>,
+}
+
+impl Subscriptions {
+    pub fn new(timers: Timers, work_q: WorkQ) -> Self {
+        Self {
+            timers,
+            work_q,
+            subs: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Start sending reports for the subscription, every max_int seconds
+    pub fn add(
+        &self,
+        id: u32,
+        sess_id: u16,
+        fab_idx: Option<u8>,
+        peer_node_id: Option<u64>,
+        max_int: u16,
+    ) -> Result<(), Error> {
+        let payload = Subscriptions::keep_alive(id)?;
+        let work_q = self.work_q.clone();
+        let timer = self.timers.add_periodic(
+            Duration::from_secs(max_int.into()),
+            TimerAction::Callback(Box::new(move |timer| {
+                let msg = Msg::Unicast(Unicast {
+                    sess_id,
+                    peer_node_id,
+                    proto_id: PROTO_ID_INTERACTION_MODEL as u16,
+                    proto_opcode: OpCode::ReportData as u8,
+                    payload: payload.clone(),
+                    timer: Some(timer),
+                });
+                if let Err(e) = work_q.try_send(msg) {
+                    error!("Error posting the report of subscription {}: {:?}", id, e);
+                }
+            })),
+        )?;
+
+        let mut subs = self.subs.lock().unwrap();
+        // Forget the subscriptions whose session is gone
+        subs.retain(|s| self.timers.is_pending(s.timer));
+        subs.push(Subscription {
+            id,
+            fab_idx,
+            peer_node_id,
+            timer,
+        });
+        Ok(())
+    }
+
+    /// End the subscriptions of the subscriber
+    pub fn remove_peer(&self, fab_idx: Option<u8>, peer_node_id: Option<u64>) {
+        self.subs.lock().unwrap().retain(|s| {
+            if s.fab_idx == fab_idx && s.peer_node_id == peer_node_id {
+                info!("Ending subscription {}", s.id);
+                self.timers.cancel(s.timer);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// End all the subscriptions, on a factory reset
+    pub fn remove_all(&self) {
+        for s in self.subs.lock().unwrap().drain(..) {
+            info!("Ending subscription {}", s.id);
+            self.timers.cancel(s.timer);
+        }
+    }
+
+    /// Returns the ids of the subscriptions that are still reported on
+    pub fn ids(&self) -> Vec<u32> {
+        self.subs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| self.timers.is_pending(s.timer))
+            .map(|s| s.id)
+            .collect()
+    }
+
+    // A report without any data, which only tells the subscriber that the subscription is
+    // still alive
+    fn keep_alive(id: u32) -> Result<Vec<u8>, Error> {
+        let mut buf = [0u8; 16];
+        let len = buf.len();
+        let mut wb = WriteBuf::new(&mut buf, len);
+        let mut tw = TLVWriter::new(&mut wb);
+        tw.start_struct(TagType::Anonymous)?;
+        tw.u32(
+            TagType::Context(msg::ReportDataTag::SubscriptionId as u8),
+            id,
+        )?;
+        tw.end_container()?;
+        Ok(wb.as_borrow_slice().to_vec())
+    }
+}
+
 pub struct SubsCtx {
     state: SubsState,
     id: u32,
+    max_int: u16,
     resume_read_req: Option<ResumeReadReq>,
 }
 
@@ -56,10 +188,19 @@ impl SubsCtx {
         let root = get_root_node_struct(rx_buf)?;
         let req = SubscribeReq::from_tlv(&root)?;
 
+        if !req.keep_subs {
+            dm.subs.remove_peer(
+                trans.session.get_local_fabric_idx(),
+                trans.session.get_peer_node_id(),
+            );
+        }
+
         let mut ctx = SubsCtx {
             state: SubsState::Confirming,
             // TODO
             id: SUBS_ID.fetch_add(1, Ordering::SeqCst),
+            // A max interval of 0 would mean reporting all the time
+            max_int: req.max_int_ceil.max(req.min_int_floor).max(1),
             resume_read_req: None,
         };
 
@@ -87,8 +228,7 @@ impl SubsCtx {
         // Is there a previous resume read pending
         if self.resume_read_req.is_some() {
             let mut resume_read_req = self.resume_read_req.take().unwrap();
-            if let Some(packet) = resume_read_req.pending_req.as_mut() {
-                let rx_buf = packet.get_parsebuf()?.as_borrow_slice();
+            if let Some(rx_buf) = resume_read_req.pending_req.as_ref() {
                 let root = tlv::get_root_node(rx_buf)?;
                 let req = SubscribeReq::from_tlv(&root)?;
 
@@ -103,18 +243,25 @@ impl SubsCtx {
         }
 
         // We are here implies that the read is now complete
-        self.confirm_subscription(trans, tw)
+        self.confirm_subscription(trans, tw, dm)
     }
 
     fn confirm_subscription(
         &mut self,
         trans: &mut Transaction,
         tw: &mut TLVWriter,
+        dm: &DataModel,
     ) -> Result<(OpCode, ResponseRequired), Error> {
         self.state = SubsState::Confirmed;
 
-        // TODO
-        let resp = SubscribeResp::new(self.id, 40);
+        dm.subs.add(
+            self.id,
+            trans.session.get_local_sess_id(),
+            trans.session.get_local_fabric_idx(),
+            trans.session.get_peer_node_id(),
+            self.max_int,
+        )?;
+        let resp = SubscribeResp::new(self.id, self.max_int);
         resp.to_tlv(tw, TagType::Anonymous)?;
         trans.complete();
         Ok((OpCode::SubscriptResponse, ResponseRequired::Yes))
@@ -140,3 +287,61 @@ impl SubsCtx {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        transport::{
+            queue::{Msg, WorkQ},
+            timer::Timers,
+        },
+        utils::clock::MockClock,
+    };
+
+    use super::Subscriptions;
+
+    #[test]
+    fn test_subscriptions() {
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let (work_q, work_rx) = WorkQ::new();
+        let subs = Subscriptions::new(timers.clone(), work_q);
+
+        subs.add(1, 10, Some(1), Some(100), 5).unwrap();
+        subs.add(2, 11, Some(1), Some(200), 7).unwrap();
+        assert_eq!(subs.ids(), [1, 2]);
+
+        clock.advance(Duration::from_secs(5));
+        timers.fire_expired();
+        match work_rx.try_recv() {
+            Ok(Msg::Unicast(u)) => {
+                assert_eq!((u.sess_id, u.peer_node_id), (10, Some(100)));
+            }
+            _ => panic!("No report for subscription 1"),
+        }
+        assert!(work_rx.try_recv().is_err());
+
+        // The subscriber replaces its subscriptions
+        subs.remove_peer(Some(1), Some(100));
+        assert_eq!(subs.ids(), [2]);
+        clock.advance(Duration::from_secs(5));
+        timers.fire_expired();
+        match work_rx.try_recv() {
+            Ok(Msg::Unicast(u)) => {
+                assert_eq!((u.sess_id, u.peer_node_id), (11, Some(200)));
+                // The session is gone
+                timers.cancel(u.timer.unwrap());
+            }
+            _ => panic!("No report for subscription 2"),
+        }
+        assert!(subs.ids().is_empty());
+        assert!(timers.next_deadline().is_none());
+
+        subs.add(3, 12, Some(2), Some(300), 5).unwrap();
+        subs.remove_all();
+        assert!(subs.ids().is_empty());
+        assert!(timers.next_deadline().is_none());
+    }
+}
@@ -17,18 +17,23 @@
 
 use super::cluster_basic_information::BasicInfoCluster;
 use super::cluster_basic_information::BasicInfoConfig;
+use super::cluster_groups::GroupsCluster;
 use super::cluster_on_off::OnOffCluster;
 use super::objects::*;
 use super::sdm::admin_commissioning::AdminCommCluster;
 use super::sdm::dev_att::DevAttDataFetcher;
+use super::sdm::failsafe::FailSafe;
 use super::sdm::general_commissioning::GenCommCluster;
 use super::sdm::noc::NocCluster;
 use super::sdm::nw_commissioning::NwCommCluster;
 use super::system_model::access_control::AccessControlCluster;
+use super::system_model::group_key_management::GroupKeyManagementCluster;
 use crate::acl::AclMgr;
 use crate::error::*;
 use crate::fabric::FabricMgr;
+use crate::group_keys::GroupKeys;
 use crate::secure_channel::pake::PaseMgr;
+use crate::transport::queue::WorkQ;
 use std::sync::Arc;
 use std::sync::RwLockWriteGuard;
 
@@ -39,6 +44,7 @@ pub const DEV_TYPE_ROOT_NODE: DeviceType = DeviceType {
 
 type WriteNode<'a> = RwLockWriteGuard<'a, Box<Node>>;
 
+#[allow(clippy::too_many_arguments)]
 pub fn device_type_add_root_node(
     node: &mut WriteNode,
     dev_info: BasicInfoConfig,
@@ -46,6 +52,9 @@ pub fn device_type_add_root_node(
     fabric_mgr: Arc<FabricMgr>,
     acl_mgr: Arc<AclMgr>,
     pase_mgr: PaseMgr,
+    group_keys: Arc<GroupKeys>,
+    failsafe: Arc<FailSafe>,
+    work_q: WorkQ,
 ) -> Result<EndptId, Error> {
     // Add the root endpoint
     let endpoint = node.add_endpoint(DEV_TYPE_ROOT_NODE)?;
@@ -55,16 +64,22 @@ pub fn device_type_add_root_node(
     };
     // Add the mandatory clusters
     node.add_cluster(0, BasicInfoCluster::new(dev_info)?)?;
-    let general_commissioning = GenCommCluster::new()?;
-    let failsafe = general_commissioning.failsafe();
-    node.add_cluster(0, general_commissioning)?;
+    node.add_cluster(0, GenCommCluster::new(failsafe.clone())?)?;
     node.add_cluster(0, NwCommCluster::new()?)?;
     node.add_cluster(0, AdminCommCluster::new(pase_mgr)?)?;
     node.add_cluster(
         0,
-        NocCluster::new(dev_att, fabric_mgr, acl_mgr.clone(), failsafe)?,
+        NocCluster::new(
+            dev_att,
+            fabric_mgr,
+            acl_mgr.clone(),
+            group_keys.clone(),
+            failsafe,
+            work_q,
+        )?,
     )?;
     node.add_cluster(0, AccessControlCluster::new(acl_mgr)?)?;
+    node.add_cluster(0, GroupKeyManagementCluster::new(group_keys)?)?;
     Ok(endpoint)
 }
 
@@ -78,8 +93,12 @@ pub const DEV_TYPE_ON_SMART_SPEAKER: DeviceType = DeviceType {
     drev: 2,
 };
 
-pub fn device_type_add_on_off_light(node: &mut WriteNode) -> Result<EndptId, Error> {
+pub fn device_type_add_on_off_light(
+    node: &mut WriteNode,
+    group_keys: Arc<GroupKeys>,
+) -> Result<EndptId, Error> {
     let endpoint = node.add_endpoint(DEV_TYPE_ON_OFF_LIGHT)?;
     node.add_cluster(endpoint, OnOffCluster::new()?)?;
+    node.add_cluster(endpoint, GroupsCluster::new(group_keys)?)?;
     Ok(endpoint)
 }
@@ -0,0 +1,398 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{collections::BTreeMap, convert::TryFrom, sync::Mutex, time::Duration};
+
+use async_channel::{Receiver, Sender};
+use log::{error, info};
+use smol::future;
+
+use crate::{
+    error::Error,
+    persist::{self, SharedKvStore},
+    tlv::{TLVList, TLVWriter, TagType, ToTLV},
+    utils::{
+        clock::{system_clock, SharedClock},
+        writebuf::WriteBuf,
+    },
+};
+
+use super::{AttrId, Attribute, ClusterId, EndptId};
+
+// The schema version of the attribute record
+const ATTR_RECORD_VERSION: u8 = 1;
+const MAX_ATTR_RECORD_LEN: usize = 256;
+
+/// The time to wait after the last change of an attribute, before storing it
+pub const ATTR_STORE_DELAY: Duration = Duration::from_secs(2);
+/// The longest time that a changed attribute remains unstored, even if it keeps changing
+pub const ATTR_STORE_MAX_DELAY: Duration = Duration::from_secs(10);
+
+// The attribute keys are "attr.<endpoint>.<cluster>.<attribute>", with the ids in hex
+const ATTR_KEY_NAMESPACE: &str = "attr";
+
+fn attr_key(endpoint: EndptId, cluster: ClusterId, attr: AttrId) -> String {
+    format!(
+        "{}.{:x}.{:x}.{:x}",
+        ATTR_KEY_NAMESPACE, endpoint, cluster, attr
+    )
+}
+
+// Whether the key is exactly one that attr_key() produces
+fn is_attr_key(key: &str) -> bool {
+    let mut parts = key.split('.');
+    if parts.next() != Some(ATTR_KEY_NAMESPACE) {
+        return false;
+    }
+    let mut ids = [0u32; 3];
+    for id in ids.iter_mut() {
+        match parts.next().map(|p| u32::from_str_radix(p, 16)) {
+            Some(Ok(v)) => *id = v,
+            _ => return false,
+        }
+    }
+    if parts.next().is_some() {
+        return false;
+    }
+    match (
+        EndptId::try_from(ids[0]),
+        ClusterId::try_from(ids[1]),
+        AttrId::try_from(ids[2]),
+    ) {
+        (Ok(endpoint), Ok(cluster), Ok(attr)) => attr_key(endpoint, cluster, attr) == key,
+        _ => false,
+    }
+}
+
+/// Stores the attributes with [Quality::PERSISTENT](super::Quality::PERSISTENT)
+///
+/// Changed attributes are not written out immediately. An attribute like OnOff may be
+/// toggled many times in a row, so the changes are held back until they settle for
+/// [ATTR_STORE_DELAY]. This happens in [AttrStore::run], which must be driven next to the
+/// transport.
+pub struct AttrStore {
+    storage: SharedKvStore,
+    pending: Mutex<BTreeMap<String, Vec<u8>>>,
+    changed_tx: Sender<()>,
+    changed_rx: Receiver<()>,
+    clock: SharedClock,
+}
+
+impl AttrStore {
+    pub fn new(storage: SharedKvStore) -> Self {
+        AttrStore::new_with(storage, system_clock())
+    }
+
+    /// Create an AttrStore whose changes are held back by the given clock
+    pub fn new_with(storage: SharedKvStore, clock: SharedClock) -> Self {
+        let (changed_tx, changed_rx) = async_channel::bounded(1);
+        Self {
+            storage,
+            pending: Mutex::new(BTreeMap::new()),
+            changed_tx,
+            changed_rx,
+            clock,
+        }
+    }
+
+    /// Restore the stored value of an attribute, if there is one
+    pub(super) fn load(&self, endpoint: EndptId, cluster: ClusterId, attr: &mut Attribute) {
+        let key = attr_key(endpoint, cluster, attr.id);
+        let result = {
+            let storage = self.storage.lock().unwrap();
+            persist::get_record(&*storage, &key, ATTR_RECORD_VERSION, persist::no_migration)
+        };
+        let data = match result {
+            Ok(data) => data,
+            Err(Error::NotFound) => return,
+            Err(e) => {
+                error!("Error loading attribute {}: {:?}", key, e);
+                return;
+            }
+        };
+
+        let mut value = attr.value.clone();
+        let result = TLVList::new(&data)
+            .iter()
+            .next()
+            .ok_or(Error::Invalid)
+            .and_then(|t| value.update_from_tlv(&t))
+            .and_then(|_| attr.set_value(value));
+        if let Err(e) = result {
+            error!("Error restoring attribute {}: {:?}", key, e);
+        }
+    }
+
+    /// Queue the current value of an attribute for storing
+    pub(super) fn changed(&self, endpoint: EndptId, cluster: ClusterId, attr: &Attribute) {
+        let key = attr_key(endpoint, cluster, attr.id);
+        let mut buf = [0u8; MAX_ATTR_RECORD_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MAX_ATTR_RECORD_LEN);
+        let mut tw = TLVWriter::new(&mut wb);
+        if let Err(e) = attr.value.to_tlv(&mut tw, TagType::Anonymous) {
+            error!("Error encoding attribute {}: {:?}", key, e);
+            return;
+        }
+
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(key, wb.as_borrow_slice().to_vec());
+        // A notification that is already queued covers this change too
+        let _ = self.changed_tx.try_send(());
+    }
+
+    /// Store all the changed attributes right away
+    ///
+    /// This can be called before shutting down, so that no change is lost
+    pub fn flush(&self) -> Result<(), Error> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut storage = self.storage.lock().unwrap();
+        for (key, data) in pending {
+            persist::set_record(&mut *storage, &key, ATTR_RECORD_VERSION, &data)?;
+        }
+        info!("Stored the changed attributes");
+        Ok(())
+    }
+
+    /// Remove all the stored attributes, including the changes that aren't stored yet
+    pub fn clear(&self) -> Result<(), Error> {
+        self.pending.lock().unwrap().clear();
+
+        let mut storage = self.storage.lock().unwrap();
+        let mut keys = Vec::new();
+        storage.for_each(&mut |key, _| {
+            if is_attr_key(key) {
+                keys.push(key.to_owned());
+            }
+        })?;
+        for key in keys {
+            storage.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Store the changed attributes, once they have settled
+    ///
+    /// The returned future completes only on an unrecoverable error
+    pub async fn run(&self) -> Result<(), Error> {
+        loop {
+            self.changed_rx.recv().await?;
+
+            let deadline = self.clock.now() + ATTR_STORE_MAX_DELAY;
+            loop {
+                let now = self.clock.now();
+                let delay = deadline
+                    .duration_since(now)
+                    .unwrap_or_default()
+                    .min(ATTR_STORE_DELAY);
+                let changed = async {
+                    self.changed_rx.recv().await?;
+                    Ok::<_, Error>(true)
+                };
+                let sleep = self.clock.sleep_until(now + delay);
+                let settled = async {
+                    sleep.await;
+                    Ok(false)
+                };
+                if !future::or(changed, settled).await? {
+                    break;
+                }
+            }
+
+            if let Err(e) = self.flush() {
+                error!("Error storing attributes: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use smol::future;
+
+    use crate::{
+        data_model::{
+            cluster_on_off::{self, OnOffCluster},
+            device_types::DEV_TYPE_ON_SMART_SPEAKER,
+            objects::{AttrValue, Node},
+        },
+        persist::{MemKvStore, SharedKvStore},
+        utils::clock::MockClock,
+    };
+
+    use super::{attr_key, is_attr_key, AttrStore, ATTR_STORE_DELAY};
+
+    const ON_OFF: u16 = cluster_on_off::Attributes::OnOff as u16;
+
+    fn node_with_light(store: &Arc<AttrStore>) -> Box<Node> {
+        let mut node = Node::new().unwrap();
+        node.set_attr_store(store.clone());
+        let endpoint = node.add_endpoint(DEV_TYPE_ON_SMART_SPEAKER).unwrap();
+        node.add_cluster(endpoint, OnOffCluster::new().unwrap())
+            .unwrap();
+        node
+    }
+
+    fn on_off(node: &Node) -> AttrValue {
+        node.get_cluster(0, cluster_on_off::ID)
+            .unwrap()
+            .base()
+            .read_attribute_raw(ON_OFF)
+            .unwrap()
+            .clone()
+    }
+
+    // Let the store take in what happened meanwhile
+    async fn yield_many() {
+        for _ in 0..10 {
+            future::yield_now().await;
+        }
+    }
+
+    fn set_on_off(node: &mut Node, value: bool) {
+        node.get_cluster_mut(0, cluster_on_off::ID)
+            .unwrap()
+            .base_mut()
+            .write_attribute_raw(ON_OFF, AttrValue::Bool(value))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_restore() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let store = Arc::new(AttrStore::new(storage.clone()));
+        let mut node = node_with_light(&store);
+        assert_eq!(on_off(&node), AttrValue::Bool(false));
+
+        set_on_off(&mut node, true);
+        // Nothing is stored until the changes are flushed
+        let mut buf = Vec::new();
+        let key = attr_key(0, cluster_on_off::ID, ON_OFF);
+        assert!(storage.lock().unwrap().get(&key, &mut buf).is_err());
+        store.flush().unwrap();
+
+        // A fresh node picks the value up, when the cluster is added
+        let store = Arc::new(AttrStore::new(storage));
+        let node = node_with_light(&store);
+        assert_eq!(on_off(&node), AttrValue::Bool(true));
+    }
+
+    #[test]
+    fn test_clear() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        storage.lock().unwrap().set("acls", &[1]).unwrap();
+        storage.lock().unwrap().set("attest", &[1]).unwrap();
+        storage.lock().unwrap().set("attr.0.6", &[1]).unwrap();
+        let store = Arc::new(AttrStore::new(storage.clone()));
+        let mut node = node_with_light(&store);
+        set_on_off(&mut node, true);
+        store.flush().unwrap();
+        set_on_off(&mut node, false);
+
+        // Both the stored and the pending values go, anything else stays
+        store.clear().unwrap();
+        store.flush().unwrap();
+        let node = node_with_light(&store);
+        assert_eq!(on_off(&node), AttrValue::Bool(false));
+        let mut count = 0;
+        storage
+            .lock()
+            .unwrap()
+            .for_each(&mut |_, _| count += 1)
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_reset() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let store = Arc::new(AttrStore::new(storage.clone()));
+        let mut node = node_with_light(&store);
+        set_on_off(&mut node, true);
+        store.flush().unwrap();
+
+        // The restored value goes back to the initial one, along with the store
+        let store = Arc::new(AttrStore::new(storage));
+        let mut node = node_with_light(&store);
+        assert_eq!(on_off(&node), AttrValue::Bool(true));
+        store.clear().unwrap();
+        node.reset_persistent_attributes();
+        assert_eq!(on_off(&node), AttrValue::Bool(false));
+    }
+
+    #[test]
+    fn test_attr_key() {
+        assert!(is_attr_key(&attr_key(1, cluster_on_off::ID, ON_OFF)));
+        assert!(is_attr_key("attr.1.6.0"));
+        assert!(!is_attr_key("attr.1.6"));
+        assert!(!is_attr_key("attr.1.6.0.0"));
+        assert!(!is_attr_key("attr.01.6.0"));
+        assert!(!is_attr_key("attr.1.6.x"));
+        assert!(!is_attr_key("attr.10000.6.0"));
+        assert!(!is_attr_key("attributes"));
+        assert!(!is_attr_key("at1-6-0"));
+    }
+
+    #[test]
+    fn test_debounce() {
+        let clock = Arc::new(MockClock::default());
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let store = Arc::new(AttrStore::new_with(storage.clone(), clock.clone()));
+        let mut node = node_with_light(&store);
+        let key = attr_key(0, cluster_on_off::ID, ON_OFF);
+        let is_stored = || {
+            let mut buf = Vec::new();
+            storage.lock().unwrap().get(&key, &mut buf).is_ok()
+        };
+
+        let result = smol::block_on(future::or(
+            async {
+                store.run().await.unwrap();
+                false
+            },
+            async {
+                for i in 0..4 {
+                    set_on_off(&mut node, i % 2 == 0);
+                    yield_many().await;
+                    clock.advance(ATTR_STORE_DELAY / 4);
+                    yield_many().await;
+                    // Still changing, so nothing is stored yet
+                    assert!(!is_stored());
+                }
+                // The last change was a quarter of the delay ago
+                clock.advance(ATTR_STORE_DELAY / 2);
+                yield_many().await;
+                assert!(!is_stored());
+                clock.advance(ATTR_STORE_DELAY / 4);
+                yield_many().await;
+                is_stored()
+            },
+        ));
+        assert!(result);
+
+        let store = Arc::new(AttrStore::new(storage));
+        let node = node_with_light(&store);
+        assert_eq!(on_off(&node), AttrValue::Bool(false));
+    }
+}
@@ -142,6 +142,7 @@ impl AttrValue {
             AttrValue::Uint16(v) => *v = tr.u16()?,
             AttrValue::Uint32(v) => *v = tr.u32()?,
             AttrValue::Uint64(v) => *v = tr.u64()?,
+            AttrValue::Utf8(v) => *v = String::from_utf8(tr.slice()?.to_vec())?,
             _ => {
                 error!("Attribute type not yet supported");
                 return Err(Error::AttributeNotFound);
@@ -189,6 +190,12 @@ impl Attribute {
         }
     }
 
+    /// Whether the value of this attribute survives a reboot
+    pub fn is_persistent(&self) -> bool {
+        // FIXED shares its bits with PERSISTENT, but a fixed value never changes anyway
+        self.quality.contains(Quality::PERSISTENT) && !self.quality.contains(Quality::FIXED)
+    }
+
     pub fn is_system_attr(attr_id: AttrId) -> bool {
         attr_id >= (GlobalElements::ServerGenCmd as AttrId)
     }
@@ -26,9 +26,12 @@ use crate::{
 use log::error;
 use num_derive::FromPrimitive;
 use rand::Rng;
-use std::fmt::{self, Debug};
+use std::{
+    fmt::{self, Debug},
+    sync::Arc,
+};
 
-use super::{AttrId, ClusterId, Encoder};
+use super::{AttrId, AttrStore, ClusterId, Encoder, EndptId};
 
 pub const ATTRS_PER_CLUSTER: usize = 10;
 pub const CMDS_PER_CLUSTER: usize = 8;
@@ -105,6 +108,9 @@ pub struct Cluster {
     pub(super) id: ClusterId,
     attributes: Vec<Attribute>,
     data_ver: u32,
+    store: Option<(EndptId, Arc<AttrStore>)>,
+    // The values of the persistent attributes before they were restored from the store
+    defaults: Vec<(AttrId, AttrValue)>,
 }
 
 impl Cluster {
@@ -113,6 +119,8 @@ impl Cluster {
             id,
             attributes: Vec::with_capacity(ATTRS_PER_CLUSTER),
             data_ver: rand::thread_rng().gen_range(0..0xFFFFFFFF),
+            store: None,
+            defaults: Vec::new(),
         };
         c.add_default_attributes()?;
         Ok(c)
@@ -167,6 +175,36 @@ impl Cluster {
         }
     }
 
+    /// Persist the attributes of this cluster in the given store
+    ///
+    /// The stored values of the persistent attributes are restored, and any later change
+    /// to them is stored
+    pub(super) fn set_store(&mut self, endpoint: EndptId, store: Arc<AttrStore>) {
+        for a in self.attributes.iter_mut().filter(|a| a.is_persistent()) {
+            self.defaults.push((a.id, a.value.clone()));
+            store.load(endpoint, self.id, a);
+        }
+        self.store = Some((endpoint, store));
+    }
+
+    /// Return the persistent attributes to the values they had before they were restored
+    ///
+    /// This goes with clearing the store, so the new values are not stored.
+    pub(super) fn reset_persistent(&mut self) {
+        let mut changed = false;
+        for (id, value) in &self.defaults {
+            if let Some(a) = self.attributes.iter_mut().find(|a| a.id == *id) {
+                if a.value != *value {
+                    a.value = value.clone();
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.cluster_changed();
+        }
+    }
+
     fn get_attribute_index(&self, attr_id: AttrId) -> Option<usize> {
         self.attributes.iter().position(|c| c.id == attr_id)
     }
@@ -310,10 +348,9 @@ impl Cluster {
                 .update_from_tlv(data)
                 .map_err(|_| IMStatusCode::Failure)?;
             a.set_value(value)
-                .map(|_| {
-                    self.cluster_changed();
-                })
-                .map_err(|_| IMStatusCode::UnsupportedWrite)
+                .map_err(|_| IMStatusCode::UnsupportedWrite)?;
+            self.attribute_changed(attr_id);
+            Ok(())
         } else {
             Err(IMStatusCode::UnsupportedAttribute)
         }
@@ -321,9 +358,20 @@ impl Cluster {
 
     pub fn write_attribute_raw(&mut self, attr_id: AttrId, value: AttrValue) -> Result<(), Error> {
         let a = self.get_attribute_mut(attr_id)?;
-        a.set_value(value).map(|_| {
-            self.cluster_changed();
-        })
+        a.set_value(value)?;
+        self.attribute_changed(attr_id);
+        Ok(())
+    }
+
+    fn attribute_changed(&mut self, attr_id: AttrId) {
+        self.cluster_changed();
+        if let Some((endpoint, store)) = &self.store {
+            if let Ok(a) = self.get_attribute(attr_id) {
+                if a.is_persistent() {
+                    store.changed(*endpoint, self.id, a);
+                }
+            }
+        }
     }
 
     /// This method must be called for any changes to the data model
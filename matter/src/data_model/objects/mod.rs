@@ -23,6 +23,9 @@ pub type CmdId = u32;
 mod attribute;
 pub use attribute::*;
 
+mod attr_store;
+pub use attr_store::*;
+
 mod cluster;
 pub use cluster::*;
 
@@ -21,9 +21,9 @@ use crate::{
     interaction_model::{core::IMStatusCode, messages::GenericPath},
     // TODO: This layer shouldn't really depend on the TLV layer, should create an abstraction layer
 };
-use std::fmt;
+use std::{fmt, sync::Arc};
 
-use super::{ClusterId, DeviceType, EndptId};
+use super::{AttrStore, ClusterId, DeviceType, EndptId};
 
 pub trait ChangeConsumer {
     fn endpoint_added(&self, id: EndptId, endpoint: &mut Endpoint) -> Result<(), Error>;
@@ -37,6 +37,7 @@ pub type BoxedEndpoints = [Option<Box<Endpoint>>];
 pub struct Node {
     endpoints: [Option<Box<Endpoint>>; ENDPTS_PER_ACC],
     changes_cb: Option<Box<dyn ChangeConsumer>>,
+    attr_store: Option<Arc<AttrStore>>,
 }
 
 impl std::fmt::Display for Node {
@@ -61,6 +62,24 @@ impl Node {
         self.changes_cb = Some(consumer);
     }
 
+    /// Persist the persistent attributes of the clusters that are added from now on
+    pub fn set_attr_store(&mut self, store: Arc<AttrStore>) {
+        self.attr_store = Some(store);
+    }
+
+    /// Return the persistent attributes of all the clusters to their initial values
+    ///
+    /// This is for when the attribute store is cleared, so the live values match it.
+    pub fn reset_persistent_attributes(&mut self) {
+        for endpoint in self.endpoints.iter_mut().flatten() {
+            if let Ok((clusters, _)) = endpoint.get_wildcard_clusters_mut(None) {
+                for cluster in clusters.iter_mut() {
+                    cluster.base_mut().reset_persistent();
+                }
+            }
+        }
+    }
+
     pub fn add_endpoint(&mut self, dev_type: DeviceType) -> Result<EndptId, Error> {
         let index = self
             .endpoints
@@ -112,8 +131,11 @@ impl Node {
     pub fn add_cluster(
         &mut self,
         endpoint_id: EndptId,
-        cluster: Box<dyn ClusterType>,
+        mut cluster: Box<dyn ClusterType>,
     ) -> Result<(), Error> {
+        if let Some(store) = &self.attr_store {
+            cluster.base_mut().set_store(endpoint_id, store.clone());
+        }
         let endpoint_id = endpoint_id as usize;
         if endpoint_id < ENDPTS_PER_ACC {
             self.endpoints[endpoint_id]
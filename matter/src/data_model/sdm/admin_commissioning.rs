@@ -24,6 +24,7 @@ use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement};
 use crate::{error::*, interaction_model::command::CommandReq};
 use log::{error, info};
 use num_derive::FromPrimitive;
+use std::time::Duration;
 
 pub const ID: u32 = 0x003C;
 
@@ -143,8 +144,9 @@ impl AdminCommCluster {
         let req =
             OpenCommWindowReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
         let verifier = VerifierData::new(req.verifier.0, req.iterations, req.salt.0);
+        let timeout = Duration::from_secs(req.timeout as u64);
         self.pase_mgr
-            .enable_pase_session(verifier, req.discriminator)?;
+            .enable_pase_session(verifier, req.discriminator, Some(timeout))?;
         Err(IMStatusCode::Success)
     }
 }
@@ -152,7 +154,8 @@ impl AdminCommCluster {
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 pub struct OpenCommWindowReq<'a> {
-    _timeout: u16,
+    // The time in seconds that the window stays open for
+    timeout: u16,
     verifier: OctetStr<'a>,
     discriminator: u16,
     iterations: u32,
@@ -35,6 +35,9 @@ pub enum DataType {
 ///
 /// Objects that implement this trait allow the Matter subsystem to query the object
 /// for the Device Attestation data that is programmed in the Matter device.
+///
+/// [FileDevAtt](super::dev_att_file::FileDevAtt) is an implementation that loads the data
+/// from files.
 pub trait DevAttDataFetcher {
     /// Get Device Attestation Data
     ///
@@ -0,0 +1,303 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! A [DevAttDataFetcher] that loads the device attestation data from disk
+//!
+//! The data is either in separate files, or in a single factory-data bundle. The latter is a
+//! TLV structure with the DAC, the PAI and the Certification Declaration in DER, and the raw
+//! private key of the DAC.
+
+use std::{fs, path::Path};
+
+use log::error;
+
+use super::dev_att::{DataType, DevAttDataFetcher};
+use crate::{
+    attestation::{AttCert, MAX_CERT_DECLARATION_LEN},
+    cert::pem,
+    crypto::{self, CryptoKeyPair, KeyPair},
+    error::Error,
+    tlv::{get_root_node_struct, FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV},
+    utils::writebuf::WriteBuf,
+};
+
+// The DAC and the PAI have to fit in the Certificate Chain Response
+const MAX_CERT_LEN: usize = 600;
+const MAX_BUNDLE_LEN: usize = 2 * MAX_CERT_LEN + MAX_CERT_DECLARATION_LEN + 64;
+
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct FactoryData<'a> {
+    dac: OctetStr<'a>,
+    pai: OctetStr<'a>,
+    cert_dec: OctetStr<'a>,
+    dac_priv_key: OctetStr<'a>,
+}
+
+/// The device attestation data, checked for consistency when it is loaded
+pub struct FileDevAtt {
+    dac: Vec<u8>,
+    pai: Vec<u8>,
+    cert_dec: Vec<u8>,
+    dac_pub_key: Vec<u8>,
+    dac_priv_key: Vec<u8>,
+}
+
+impl FileDevAtt {
+    /// Load the data from separate files
+    ///
+    /// The certificates and the key can be either in DER or PEM, the Certification
+    /// Declaration is in DER.
+    pub fn new(dac: &Path, pai: &Path, cert_dec: &Path, dac_key: &Path) -> Result<Self, Error> {
+        let read = |path: &Path, labels: &[&str]| {
+            fs::read(path)
+                .map_err(Error::from)
+                .and_then(|data| pem::decode_any(&data, labels))
+                .inspect_err(|e| error!("Error reading {}: {}", path.display(), e))
+        };
+        let dac_key = read(
+            dac_key,
+            &[pem::LABEL_EC_PRIVATE_KEY, pem::LABEL_PRIVATE_KEY],
+        )?;
+        Self::new_with(
+            read(dac, &[pem::LABEL_CERTIFICATE])?,
+            read(pai, &[pem::LABEL_CERTIFICATE])?,
+            fs::read(cert_dec)?,
+            crypto::decode_ec_private_key(&dac_key)?,
+        )
+    }
+
+    /// Load the data from a factory-data bundle
+    pub fn from_bundle(path: &Path) -> Result<Self, Error> {
+        let bundle = fs::read(path)?;
+        let root = get_root_node_struct(&bundle)?;
+        let data = FactoryData::from_tlv(&root)?;
+        Self::new_with(
+            data.dac.0.to_vec(),
+            data.pai.0.to_vec(),
+            data.cert_dec.0.to_vec(),
+            data.dac_priv_key.0,
+        )
+    }
+
+    /// Create from the DAC, the PAI and the Certification Declaration in DER, and the raw
+    /// private key of the DAC
+    ///
+    /// The key has to be the one of the DAC, and the DAC has to be issued by the PAI.
+    pub fn new_with(
+        dac: Vec<u8>,
+        pai: Vec<u8>,
+        cert_dec: Vec<u8>,
+        dac_priv_key: &[u8],
+    ) -> Result<Self, Error> {
+        if dac.len() > MAX_CERT_LEN || pai.len() > MAX_CERT_LEN {
+            return Err(Error::NoSpace);
+        }
+        if cert_dec.len() > MAX_CERT_DECLARATION_LEN {
+            return Err(Error::NoSpace);
+        }
+        let dac_pub_key = {
+            let dac_cert = AttCert::new(&dac)?;
+            let pai_cert = AttCert::new(&pai)?;
+            dac_cert.is_valid_usage(0)?;
+            pai_cert.is_valid_usage(1)?;
+            dac_cert
+                .verify_issued_by(&pai_cert)
+                .inspect_err(|e| error!("The DAC isn't issued by the PAI: {}", e))?;
+            dac_cert.get_pubkey().to_vec()
+        };
+
+        // Not every crypto backend checks that the components match, a signature always tells
+        let key_matches = || {
+            let key = KeyPair::new_from_components(&dac_pub_key, dac_priv_key)?;
+            let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+            key.sign_msg(&dac, &mut signature)?;
+            KeyPair::new_from_public(&dac_pub_key)?.verify_msg(&dac, &signature)
+        };
+        key_matches().map_err(|_| {
+            error!("The private key isn't the one of the DAC");
+            Error::InvalidAuthKey
+        })?;
+
+        Ok(Self {
+            dac,
+            pai,
+            cert_dec,
+            dac_pub_key,
+            dac_priv_key: dac_priv_key.to_vec(),
+        })
+    }
+
+    /// Save the data as a factory-data bundle
+    pub fn write_bundle(&self, path: &Path) -> Result<(), Error> {
+        let mut buf = [0u8; MAX_BUNDLE_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MAX_BUNDLE_LEN);
+        let mut tw = TLVWriter::new(&mut wb);
+        FactoryData {
+            dac: OctetStr(&self.dac),
+            pai: OctetStr(&self.pai),
+            cert_dec: OctetStr(&self.cert_dec),
+            dac_priv_key: OctetStr(&self.dac_priv_key),
+        }
+        .to_tlv(&mut tw, TagType::Anonymous)?;
+        fs::write(path, wb.as_slice())?;
+        Ok(())
+    }
+}
+
+impl DevAttDataFetcher for FileDevAtt {
+    fn get_devatt_data(&self, data_type: DataType, data: &mut [u8]) -> Result<usize, Error> {
+        let src = match data_type {
+            DataType::CertDeclaration => &self.cert_dec,
+            DataType::PAI => &self.pai,
+            DataType::DAC => &self.dac,
+            DataType::DACPubKey => &self.dac_pub_key,
+            DataType::DACPrivKey => &self.dac_priv_key,
+        };
+        let dst = data.get_mut(..src.len()).ok_or(Error::NoSpace)?;
+        dst.copy_from_slice(src);
+        Ok(src.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::FileDevAtt;
+    use crate::{
+        attestation::test_vectors,
+        cert::pem,
+        data_model::sdm::dev_att::{DataType, DevAttDataFetcher},
+        error::Error,
+    };
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("matter_dev_att_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn check(dev_att: &FileDevAtt) {
+        let mut buf = [0u8; 600];
+        let mut get = |data_type| {
+            let len = dev_att.get_devatt_data(data_type, &mut buf).unwrap();
+            buf[..len].to_vec()
+        };
+        assert_eq!(get(DataType::DAC), test_vectors::DAC);
+        assert_eq!(get(DataType::PAI), test_vectors::PAI);
+        assert_eq!(get(DataType::CertDeclaration), test_vectors::CD);
+        assert_eq!(get(DataType::DACPubKey), test_vectors::DAC_PUBKEY);
+        assert_eq!(get(DataType::DACPrivKey), test_vectors::DAC_PRIVKEY);
+
+        let mut small = [0u8; 100];
+        assert_eq!(
+            dev_att.get_devatt_data(DataType::DAC, &mut small),
+            Err(Error::NoSpace)
+        );
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = test_dir("files");
+        fs::write(
+            dir.join("dac.pem"),
+            pem::encode(&test_vectors::DAC, pem::LABEL_CERTIFICATE),
+        )
+        .unwrap();
+        fs::write(dir.join("pai.der"), test_vectors::PAI).unwrap();
+        fs::write(dir.join("cd.der"), test_vectors::CD).unwrap();
+        fs::write(
+            dir.join("dac.key"),
+            pem::encode(&test_vectors::DAC_KEY_SEC1, pem::LABEL_EC_PRIVATE_KEY),
+        )
+        .unwrap();
+
+        let dev_att = FileDevAtt::new(
+            &dir.join("dac.pem"),
+            &dir.join("pai.der"),
+            &dir.join("cd.der"),
+            &dir.join("dac.key"),
+        )
+        .unwrap();
+        check(&dev_att);
+
+        // The bundle has the same data
+        dev_att.write_bundle(&dir.join("factory.bin")).unwrap();
+        let dev_att = FileDevAtt::from_bundle(&dir.join("factory.bin")).unwrap();
+        check(&dev_att);
+
+        assert!(FileDevAtt::new(
+            &dir.join("dac.pem"),
+            &dir.join("pai.der"),
+            &dir.join("cd.der"),
+            &dir.join("missing.key"),
+        )
+        .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validation() {
+        let new = |dac: &[u8], pai: &[u8], dac_priv_key: &[u8]| {
+            FileDevAtt::new_with(
+                dac.to_vec(),
+                pai.to_vec(),
+                test_vectors::CD.to_vec(),
+                dac_priv_key,
+            )
+            .map(|_| ())
+        };
+        assert_eq!(
+            new(
+                &test_vectors::DAC,
+                &test_vectors::PAI,
+                &test_vectors::DAC_PRIVKEY
+            ),
+            Ok(())
+        );
+        // The key of another certificate
+        assert_eq!(
+            new(
+                &test_vectors::DAC,
+                &test_vectors::PAI,
+                &test_vectors::CD_SIGNER_PRIVKEY
+            ),
+            Err(Error::InvalidAuthKey)
+        );
+        // A PAI that isn't the issuer of the DAC
+        assert_eq!(
+            new(
+                &test_vectors::DAC,
+                &test_vectors::PAA,
+                &test_vectors::DAC_PRIVKEY
+            ),
+            Err(Error::InvalidIssuer)
+        );
+        // The certificates in the wrong order
+        assert_eq!(
+            new(
+                &test_vectors::PAI,
+                &test_vectors::DAC,
+                &test_vectors::DAC_PRIVKEY
+            ),
+            Err(Error::InvalidBasicConstraints)
+        );
+    }
+}
@@ -14,13 +14,24 @@
  *    See the License for the specific language governing permissions and
  *    limitations under the License.
  */
-
-use crate::{error::Error, transport::session::SessionMode};
-use log::error;
-use std::sync::RwLock;
+use crate::{
+    acl::AclMgr,
+    error::Error,
+    fabric::{Fabric, FabricMgr},
+    group_keys::GroupKeys,
+    transport::{
+        queue::{Msg, WorkQ},
+        session::SessionMode,
+        timer::{TimerAction, TimerId, Timers},
+    },
+};
+use log::{error, info};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 #[derive(PartialEq)]
-#[allow(dead_code)]
 #[allow(clippy::enum_variant_names)]
 enum NocState {
     NocNotRecvd,
@@ -32,8 +43,11 @@ enum NocState {
 #[derive(PartialEq)]
 pub struct ArmedCtx {
     session_mode: SessionMode,
-    timeout: u8,
+    // The fail-safe expires when this timer fires
+    timer: TimerId,
     noc_state: NocState,
+    // The regulatory config to go back to, if the fail-safe expires
+    prev_reg_config: u8,
 }
 
 #[derive(PartialEq)]
@@ -44,40 +58,152 @@ pub enum State {
 
 pub struct FailSafeInner {
     state: State,
+    bread_crumb: u64,
+    reg_config: u8,
+    // The regulatory config that the device starts with
+    initial_reg_config: u8,
+    // The fabric as it was before UpdateNOC
+    prev_fabric: Option<Fabric>,
+    fabric_mgr: Arc<FabricMgr>,
+    acl_mgr: Arc<AclMgr>,
+    group_keys: Arc<GroupKeys>,
+    work_q: WorkQ,
+}
+
+impl FailSafeInner {
+    /// Undo everything that was done while the fail-safe was armed
+    ///
+    /// The fabric added by AddNOC goes away with its ACLs and group keys, the fabric
+    /// updated by UpdateNOC gets its previous NOC back, the trusted root goes with the PASE
+    /// session it was stored in, and the regulatory config and the breadcrumb are reset.
+    fn rollback(&mut self) {
+        let ctx = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Armed(ctx) => ctx,
+            State::Idle => return,
+        };
+
+        let fab_idx = match ctx.noc_state {
+            NocState::AddNocRecvd(fab_idx) => {
+                info!("Removing the provisional fabric {}", fab_idx);
+                if let Err(e) = self.fabric_mgr.remove(fab_idx) {
+                    error!("Error removing the provisional fabric: {:?}", e);
+                }
+                let _ = self.acl_mgr.delete_for_fabric(fab_idx);
+                let _ = self.group_keys.remove_fabric(fab_idx);
+                Some(fab_idx)
+            }
+            NocState::UpdateNocRecvd(fab_idx) => {
+                info!("Restoring the previous NOC of fabric {}", fab_idx);
+                if let Some(prev) = self.prev_fabric.take() {
+                    if let Err(e) = self.fabric_mgr.replace(fab_idx, prev) {
+                        error!("Error restoring the previous NOC: {:?}", e);
+                    }
+                }
+                Some(fab_idx)
+            }
+            NocState::NocNotRecvd => None,
+        };
+        self.reg_config = ctx.prev_reg_config;
+        self.bread_crumb = 0;
+
+        // The sessions are owned by the transport
+        let msg = Msg::RemoveSessions {
+            pase: true,
+            fab_idx,
+            keep_sess_id: None,
+        };
+        if let Err(e) = self.work_q.try_send(msg) {
+            error!("Error removing the provisional sessions: {:?}", e);
+        }
+    }
 }
 
 pub struct FailSafe {
-    state: RwLock<FailSafeInner>,
+    state: Arc<RwLock<FailSafeInner>>,
+    timers: Timers,
 }
 
 impl FailSafe {
-    pub fn new() -> Self {
+    pub fn new(
+        reg_config: u8,
+        timers: Timers,
+        fabric_mgr: Arc<FabricMgr>,
+        acl_mgr: Arc<AclMgr>,
+        group_keys: Arc<GroupKeys>,
+        work_q: WorkQ,
+    ) -> Self {
         Self {
-            state: RwLock::new(FailSafeInner { state: State::Idle }),
+            state: Arc::new(RwLock::new(FailSafeInner {
+                state: State::Idle,
+                bread_crumb: 0,
+                reg_config,
+                initial_reg_config: reg_config,
+                prev_fabric: None,
+                fabric_mgr,
+                acl_mgr,
+                group_keys,
+                work_q,
+            })),
+            timers,
         }
     }
 
-    pub fn arm(&self, timeout: u8, session_mode: SessionMode) -> Result<(), Error> {
+    /// Arm the fail-safe for timeout seconds, or extend it if it is already armed
+    ///
+    /// A timeout of 0 expires an armed fail-safe right away.
+    pub fn arm(&self, timeout: u16, session_mode: SessionMode) -> Result<(), Error> {
         let mut inner = self.state.write()?;
+        let prev_reg_config = inner.reg_config;
         match &mut inner.state {
             State::Idle => {
+                if timeout == 0 {
+                    return Ok(());
+                }
+                let timer = self.start_timer(timeout);
                 inner.state = State::Armed(ArmedCtx {
                     session_mode,
-                    timeout,
+                    timer,
                     noc_state: NocState::NocNotRecvd,
+                    prev_reg_config,
                 })
             }
             State::Armed(c) => {
                 if c.session_mode != session_mode {
                     return Err(Error::Invalid);
                 }
-                // re-arm
-                c.timeout = timeout;
+                self.timers.cancel(c.timer);
+                if timeout == 0 {
+                    info!("Fail-Safe disarmed before commissioning completed");
+                    inner.rollback();
+                } else {
+                    // re-arm
+                    c.timer = self.start_timer(timeout);
+                }
             }
         }
         Ok(())
     }
 
+    fn start_timer(&self, timeout: u16) -> TimerId {
+        let state = self.state.clone();
+        self.timers.add_oneshot(
+            Duration::from_secs(timeout as u64),
+            TimerAction::Callback(Box::new(move |id| FailSafe::expire(&state, id))),
+        )
+    }
+
+    fn expire(state: &RwLock<FailSafeInner>, timer: TimerId) {
+        let mut inner = state.write().unwrap();
+        if let State::Armed(c) = &inner.state {
+            // The fail-safe may have been re-armed in the meantime
+            if c.timer == timer {
+                info!("Fail-Safe timer expired");
+                inner.rollback();
+            }
+        }
+    }
+
+    /// Disarm the fail-safe on CommissioningComplete, keeping what was done under it
     pub fn disarm(&self, session_mode: SessionMode) -> Result<(), Error> {
         let mut inner = self.state.write()?;
         match &mut inner.state {
@@ -102,12 +228,30 @@ impl FailSafe {
                         }
                     }
                 }
+                self.timers.cancel(c.timer);
                 inner.state = State::Idle;
+                inner.bread_crumb = 0;
+                inner.prev_fabric = None;
             }
         }
         Ok(())
     }
 
+    /// Go back to the factory state on a factory reset
+    ///
+    /// An armed fail-safe is disarmed without a rollback, as everything that was done under
+    /// it is being removed anyway.
+    pub fn reset(&self) {
+        let mut inner = self.state.write().unwrap();
+        if let State::Armed(c) = &inner.state {
+            self.timers.cancel(c.timer);
+        }
+        inner.state = State::Idle;
+        inner.prev_fabric = None;
+        inner.bread_crumb = 0;
+        inner.reg_config = inner.initial_reg_config;
+    }
+
     pub fn is_armed(&self) -> bool {
         self.state.read().unwrap().state != State::Idle
     }
@@ -127,6 +271,23 @@ impl FailSafe {
         }
     }
 
+    /// Record UpdateNOC, along with the fabric as it was before, for the rollback
+    pub fn record_update_noc(&self, fabric_index: u8, prev: Fabric) -> Result<(), Error> {
+        let mut inner = self.state.write()?;
+        match &mut inner.state {
+            State::Idle => Err(Error::Invalid),
+            State::Armed(c) => {
+                if c.noc_state == NocState::NocNotRecvd {
+                    c.noc_state = NocState::UpdateNocRecvd(fabric_index);
+                    inner.prev_fabric = Some(prev);
+                    Ok(())
+                } else {
+                    Err(Error::Invalid)
+                }
+            }
+        }
+    }
+
     pub fn allow_noc_change(&self) -> Result<bool, Error> {
         let mut inner = self.state.write()?;
         let allow = match &mut inner.state {
@@ -135,10 +296,150 @@ impl FailSafe {
         };
         Ok(allow)
     }
+
+    /// The Breadcrumb attribute of the General Commissioning cluster
+    pub fn bread_crumb(&self) -> u64 {
+        self.state.read().unwrap().bread_crumb
+    }
+
+    pub fn set_bread_crumb(&self, bread_crumb: u64) {
+        self.state.write().unwrap().bread_crumb = bread_crumb;
+    }
+
+    /// The RegulatoryConfig attribute of the General Commissioning cluster
+    pub fn reg_config(&self) -> u8 {
+        self.state.read().unwrap().reg_config
+    }
+
+    pub fn set_reg_config(&self, reg_config: u8) {
+        self.state.write().unwrap().reg_config = reg_config;
+    }
 }
 
-impl Default for FailSafe {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{
+        acl::{AclEntry, AclMgr, AuthMode},
+        data_model::objects::Privilege,
+        fabric::FabricMgr,
+        group_keys::GroupKeys,
+        mdns::Mdns,
+        persist::MemKvStore,
+        transport::{
+            queue::{Msg, WorkQ},
+            session::SessionMode,
+            timer::Timers,
+            udp::MATTER_PORT,
+        },
+        utils::clock::MockClock,
+    };
+
+    use super::FailSafe;
+
+    struct TestFailSafe {
+        clock: Arc<MockClock>,
+        timers: Timers,
+        acl_mgr: Arc<AclMgr>,
+        rx_q: async_channel::Receiver<Msg>,
+        failsafe: FailSafe,
+    }
+
+    fn failsafe() -> TestFailSafe {
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let storage = Arc::new(Mutex::new(MemKvStore::new()));
+        let mdns = Arc::new(Mdns::new(MATTER_PORT));
+        let fabric_mgr = Arc::new(FabricMgr::new(storage.clone(), mdns, None).unwrap());
+        let acl_mgr = Arc::new(AclMgr::new_with(None).unwrap());
+        let group_keys = Arc::new(GroupKeys::new(fabric_mgr.clone(), storage).unwrap());
+        let (work_q, rx_q) = WorkQ::new();
+        let failsafe = FailSafe::new(
+            0,
+            timers.clone(),
+            fabric_mgr,
+            acl_mgr.clone(),
+            group_keys,
+            work_q,
+        );
+        TestFailSafe {
+            clock,
+            timers,
+            acl_mgr,
+            rx_q,
+            failsafe,
+        }
+    }
+
+    #[test]
+    fn test_failsafe_expiry() {
+        let t = failsafe();
+
+        t.failsafe.arm(60, SessionMode::Pase).unwrap();
+        assert!(t.failsafe.is_armed());
+        t.clock.advance(Duration::from_secs(59));
+        t.timers.fire_expired();
+        assert!(t.failsafe.is_armed());
+
+        // Re-arming restarts the timer
+        t.failsafe.arm(60, SessionMode::Pase).unwrap();
+        t.clock.advance(Duration::from_secs(59));
+        t.timers.fire_expired();
+        assert!(t.failsafe.is_armed());
+
+        t.clock.advance(Duration::from_secs(1));
+        t.timers.fire_expired();
+        assert!(!t.failsafe.is_armed());
+        assert_eq!(t.timers.next_deadline(), None);
+        assert!(matches!(
+            t.rx_q.try_recv(),
+            Ok(Msg::RemoveSessions {
+                pase: true,
+                fab_idx: None,
+                keep_sess_id: None
+            })
+        ));
+    }
+
+    #[test]
+    fn test_failsafe_rollback() {
+        let t = failsafe();
+        t.failsafe.set_reg_config(1);
+
+        t.failsafe.arm(60, SessionMode::Pase).unwrap();
+        t.failsafe.set_bread_crumb(5);
+        t.failsafe.set_reg_config(2);
+        let fab_idx = 1;
+        let mut acl = AclEntry::new(fab_idx, Privilege::ADMIN, AuthMode::Case);
+        acl.add_subject(0x1234).unwrap();
+        t.acl_mgr.add(acl).unwrap();
+        t.failsafe.record_add_noc(fab_idx).unwrap();
+
+        // Only a disarm from another session mode is refused
+        assert!(t.failsafe.arm(0, SessionMode::PlainText).is_err());
+        t.failsafe.arm(0, SessionMode::Pase).unwrap();
+        assert!(!t.failsafe.is_armed());
+        assert_eq!(t.failsafe.bread_crumb(), 0);
+        assert_eq!(t.failsafe.reg_config(), 1);
+        let mut acls = 0;
+        t.acl_mgr.for_each_acl(|_| acls += 1).unwrap();
+        assert_eq!(acls, 0);
+        assert!(matches!(
+            t.rx_q.try_recv(),
+            Ok(Msg::RemoveSessions {
+                pase: true,
+                fab_idx: Some(1),
+                keep_sess_id: None
+            })
+        ));
+
+        // Nothing more happens when the disarmed fail-safe's timer would have expired
+        t.clock.advance(Duration::from_secs(60));
+        assert_eq!(t.timers.fire_expired(), 0);
+        assert!(t.rx_q.try_recv().is_err());
     }
 }
@@ -20,7 +20,7 @@ use crate::data_model::objects::*;
 use crate::data_model::sdm::failsafe::FailSafe;
 use crate::interaction_model::core::IMStatusCode;
 use crate::interaction_model::messages::ib;
-use crate::tlv::{FromTLV, TLVElement, TLVWriter, TagType, ToTLV};
+use crate::tlv::{FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV};
 use crate::{error::*, interaction_model::command::CommandReq};
 use log::{error, info};
 use num_derive::FromPrimitive;
@@ -62,19 +62,21 @@ pub enum RegLocationType {
     IndoorOutdoor = 2,
 }
 
-fn attr_bread_crumb_new(bread_crumb: u64) -> Attribute {
+// The breadcrumb and the regulatory config are kept by the fail-safe, which resets them if
+// it expires
+fn attr_bread_crumb_new() -> Attribute {
     Attribute::new(
         Attributes::BreadCrumb as u16,
-        AttrValue::Uint64(bread_crumb),
+        AttrValue::Custom,
         Access::READ | Access::WRITE | Access::NEED_ADMIN,
         Quality::NONE,
     )
 }
 
-fn attr_reg_config_new(reg_config: RegLocationType) -> Attribute {
+fn attr_reg_config_new() -> Attribute {
     Attribute::new(
         Attributes::RegConfig as u16,
-        AttrValue::Uint8(reg_config as u8),
+        AttrValue::Custom,
         Access::RV,
         Quality::NONE,
     )
@@ -100,8 +102,16 @@ fn attr_comm_info_new() -> Attribute {
 
 #[derive(FromTLV, ToTLV)]
 struct FailSafeParams {
-    expiry_len: u8,
-    bread_crumb: u8,
+    expiry_len: u16,
+    bread_crumb: u64,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct RegulatoryConfigParams<'a> {
+    reg_config: u8,
+    country_code: OctetStr<'a>,
+    bread_crumb: u64,
 }
 
 pub struct GenCommCluster {
@@ -127,12 +137,33 @@ impl ClusterType for GenCommCluster {
                     let _ = tw.end_container();
                 }))
             }
+            Some(Attributes::BreadCrumb) => {
+                encoder.encode(EncodeValue::Value(&self.failsafe.bread_crumb()))
+            }
+            Some(Attributes::RegConfig) => {
+                encoder.encode(EncodeValue::Value(&self.failsafe.reg_config()))
+            }
             _ => {
                 error!("Unsupported Attribute: this shouldn't happen");
             }
         }
     }
 
+    fn write_attribute(
+        &mut self,
+        attr: &AttrDetails,
+        data: &TLVElement,
+    ) -> Result<(), IMStatusCode> {
+        if let Some(Attributes::BreadCrumb) = num::FromPrimitive::from_u16(attr.attr_id) {
+            let bread_crumb = data.u64().map_err(|_| IMStatusCode::InvalidDataType)?;
+            self.failsafe.set_bread_crumb(bread_crumb);
+            self.base.cluster_changed();
+            Ok(())
+        } else {
+            self.base.write_attribute_from_tlv(attr.attr_id, data)
+        }
+    }
+
     fn handle_command(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         let cmd = cmd_req
             .cmd
@@ -151,19 +182,15 @@ impl ClusterType for GenCommCluster {
 }
 
 impl GenCommCluster {
-    pub fn new() -> Result<Box<Self>, Error> {
-        let failsafe = Arc::new(FailSafe::new());
-
+    pub fn new(failsafe: Arc<FailSafe>) -> Result<Box<Self>, Error> {
         let mut c = Box::new(GenCommCluster {
             // TODO: Arch-Specific
             expiry_len: 120,
             failsafe,
             base: Cluster::new(ID)?,
         });
-        c.base.add_attribute(attr_bread_crumb_new(0))?;
-        // TODO: Arch-Specific
-        c.base
-            .add_attribute(attr_reg_config_new(RegLocationType::IndoorOutdoor))?;
+        c.base.add_attribute(attr_bread_crumb_new())?;
+        c.base.add_attribute(attr_reg_config_new())?;
         // TODO: Arch-Specific
         c.base
             .add_attribute(attr_location_capability_new(RegLocationType::IndoorOutdoor))?;
@@ -172,10 +199,6 @@ impl GenCommCluster {
         Ok(c)
     }
 
-    pub fn failsafe(&self) -> Arc<FailSafe> {
-        self.failsafe.clone()
-    }
-
     fn handle_command_armfailsafe(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         cmd_enter!("ARM Fail Safe");
 
@@ -188,6 +211,8 @@ impl GenCommCluster {
             .is_err()
         {
             status = CommissioningError::ErrBusyWithOtherAdmin as u8;
+        } else if self.failsafe.is_armed() {
+            self.failsafe.set_bread_crumb(p.bread_crumb);
         }
 
         let cmd_data = CommonResponse {
@@ -210,13 +235,12 @@ impl GenCommCluster {
         cmd_req: &mut CommandReq,
     ) -> Result<(), IMStatusCode> {
         cmd_enter!("Set Regulatory Config");
-        let country_code = cmd_req
-            .data
-            .find_tag(1)
-            .map_err(|_| IMStatusCode::InvalidCommand)?
-            .slice()
+        let p = RegulatoryConfigParams::from_tlv(&cmd_req.data)
             .map_err(|_| IMStatusCode::InvalidCommand)?;
-        info!("Received country code: {:?}", country_code);
+        info!("Received country code: {:?}", p.country_code);
+        // Any change made while the fail-safe is armed is undone if it expires
+        self.failsafe.set_reg_config(p.reg_config);
+        self.failsafe.set_bread_crumb(p.bread_crumb);
 
         let cmd_data = CommonResponse {
             error_code: 0,
@@ -17,6 +17,7 @@
 
 pub mod admin_commissioning;
 pub mod dev_att;
+pub mod dev_att_file;
 pub mod failsafe;
 pub mod general_commissioning;
 pub mod noc;
@@ -16,18 +16,19 @@
  */
 
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::acl::{AclEntry, AclMgr, AuthMode};
-use crate::cert::Cert;
+use crate::cert::{Cert, CertTime};
 use crate::crypto::{self, CryptoKeyPair, KeyPair};
 use crate::data_model::objects::*;
 use crate::data_model::sdm::dev_att;
-use crate::fabric::{Fabric, FabricMgr, MAX_SUPPORTED_FABRICS};
+use crate::fabric::{Fabric, FabricMgr, MAX_CERT_TLV_LEN, MAX_SUPPORTED_FABRICS};
+use crate::group_keys::GroupKeys;
 use crate::interaction_model::command::CommandReq;
 use crate::interaction_model::core::IMStatusCode;
 use crate::interaction_model::messages::ib;
-use crate::tlv::{FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV, UtfStr};
+use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement, TLVWriter, TagType, ToTLV, UtfStr};
+use crate::transport::queue::{Msg, WorkQ};
 use crate::transport::session::SessionMode;
 use crate::utils::writebuf::WriteBuf;
 use crate::{cmd_enter, error::*, secure_channel};
@@ -74,6 +75,7 @@ pub enum Commands {
     CSRReq = 0x04,
     CSRResp = 0x05,
     AddNOC = 0x06,
+    UpdateNOC = 0x07,
     NOCResp = 0x08,
     UpdateFabricLabel = 0x09,
     RemoveFabric = 0x0a,
@@ -95,18 +97,23 @@ pub struct NocCluster {
     dev_att: Box<dyn DevAttDataFetcher>,
     fabric_mgr: Arc<FabricMgr>,
     acl_mgr: Arc<AclMgr>,
+    group_keys: Arc<GroupKeys>,
     failsafe: Arc<FailSafe>,
+    work_q: WorkQ,
 }
 struct NocData {
     pub key_pair: KeyPair,
     pub root_ca: Cert,
+    // The CSR was requested for UpdateNOC, rather than AddNOC
+    pub for_update_noc: bool,
 }
 
 impl NocData {
-    pub fn new(key_pair: KeyPair) -> Self {
+    pub fn new(key_pair: KeyPair, for_update_noc: bool) -> Self {
         Self {
             key_pair,
             root_ca: Cert::default(),
+            for_update_noc,
         }
     }
 }
@@ -116,16 +123,32 @@ impl NocCluster {
         dev_att: Box<dyn DevAttDataFetcher>,
         fabric_mgr: Arc<FabricMgr>,
         acl_mgr: Arc<AclMgr>,
+        group_keys: Arc<GroupKeys>,
         failsafe: Arc<FailSafe>,
+        work_q: WorkQ,
     ) -> Result<Box<Self>, Error> {
         let mut c = Box::new(Self {
             dev_att,
             fabric_mgr,
             acl_mgr,
+            group_keys,
             failsafe,
+            work_q,
             base: Cluster::new(ID)?,
         });
         let attrs = [
+            Attribute::new(
+                Attributes::NOCs as u16,
+                AttrValue::Custom,
+                Access::READ | Access::NEED_ADMIN | Access::FAB_SCOPED,
+                Quality::NONE,
+            ),
+            Attribute::new(
+                Attributes::TrustedRootCerts as u16,
+                AttrValue::Custom,
+                Access::RV,
+                Quality::NONE,
+            ),
             Attribute::new(
                 Attributes::CurrentFabricIndex as u16,
                 AttrValue::Custom,
@@ -166,6 +189,7 @@ impl NocCluster {
             .trans
             .session
             .take_data::<NocData>()
+            .filter(|n| !n.for_update_noc)
             .ok_or(NocStatus::MissingCsr)?;
 
         if !self
@@ -223,6 +247,74 @@ impl NocCluster {
         Ok(())
     }
 
+    fn _handle_command_updatenoc(&mut self, cmd_req: &mut CommandReq) -> Result<u8, NocStatus> {
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(NocStatus::InsufficientPrivlege)?;
+        let noc_data = cmd_req
+            .trans
+            .session
+            .take_data::<NocData>()
+            .filter(|n| n.for_update_noc)
+            .ok_or(NocStatus::MissingCsr)?;
+
+        if !self
+            .failsafe
+            .allow_noc_change()
+            .map_err(|_| NocStatus::InsufficientPrivlege)?
+        {
+            error!("UpdateNOC not allowed by Fail Safe");
+            return Err(NocStatus::InsufficientPrivlege);
+        }
+
+        let r = UpdateNocReq::from_tlv(&cmd_req.data).map_err(|_| NocStatus::InvalidNOC)?;
+
+        let noc_value = Cert::new(r.noc_value.0).map_err(|_| NocStatus::InvalidNOC)?;
+        info!("Received NOC as: {}", noc_value);
+        let icac_value = match r.icac_value {
+            Some(icac) if !icac.0.is_empty() => {
+                let cert = Cert::new(icac.0).map_err(|_| NocStatus::InvalidNOC)?;
+                info!("Received ICAC as: {}", cert);
+                Some(cert)
+            }
+            _ => None,
+        };
+
+        let time = CertTime::from_clock(cmd_req.trans.session.get_clock().as_ref());
+        let fabric = {
+            let fabric = self
+                .fabric_mgr
+                .get_fabric(fab_idx as usize)
+                .map_err(|_| NocStatus::InvalidFabricIndex)?;
+            (*fabric)
+                .as_ref()
+                .ok_or(NocStatus::InvalidFabricIndex)?
+                .with_noc(noc_data.key_pair, icac_value, noc_value, time)
+                .map_err(|_| NocStatus::InvalidNOC)?
+        };
+        let prev = self
+            .fabric_mgr
+            .replace(fab_idx, fabric)
+            .map_err(|_| NocStatus::InvalidFabricIndex)?;
+
+        if self.failsafe.record_update_noc(fab_idx, prev).is_err() {
+            error!("Failed to record NoC in the FailSafe, what to do?");
+        }
+
+        // The other sessions of the fabric were established with the previous NOC
+        let msg = Msg::RemoveSessions {
+            pase: false,
+            fab_idx: Some(fab_idx),
+            keep_sess_id: Some(cmd_req.trans.session.get_local_sess_id()),
+        };
+        if let Err(e) = self.work_q.try_send(msg) {
+            error!("Error removing the sessions of the previous NOC: {:?}", e);
+        }
+        Ok(fab_idx)
+    }
+
     fn create_nocresponse(
         tw: &mut TLVWriter,
         status_code: NocStatus,
@@ -277,6 +369,7 @@ impl NocCluster {
             RemoveFabricReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
         if self.fabric_mgr.remove(req.fab_idx).is_ok() {
             let _ = self.acl_mgr.delete_for_fabric(req.fab_idx);
+            let _ = self.group_keys.remove_fabric(req.fab_idx);
             cmd_req.trans.terminate();
         } else {
             NocCluster::create_nocresponse(
@@ -299,6 +392,17 @@ impl NocCluster {
         Ok(())
     }
 
+    fn handle_command_updatenoc(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("UpdateNOC");
+        let (status, fab_idx) = match self._handle_command_updatenoc(cmd_req) {
+            Ok(fab_idx) => (NocStatus::Ok, fab_idx),
+            Err(e) => (e, 0),
+        };
+        NocCluster::create_nocresponse(cmd_req.resp, status, fab_idx, "".to_owned());
+        cmd_req.trans.complete();
+        Ok(())
+    }
+
     fn handle_command_attrequest(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         cmd_enter!("AttestationRequest");
 
@@ -307,13 +411,19 @@ impl NocCluster {
 
         let mut attest_challenge = [0u8; crypto::SYMM_KEY_LEN_BYTES];
         attest_challenge.copy_from_slice(cmd_req.trans.session.get_att_challenge());
+        let epoch = cmd_req.trans.session.get_clock().epoch_secs() as u32;
 
         let cmd_data = |tag: TagType, t: &mut TLVWriter| {
             let mut buf: [u8; RESP_MAX] = [0; RESP_MAX];
             let mut attest_element = WriteBuf::new(&mut buf, RESP_MAX);
             let _ = t.start_struct(tag);
-            let _ =
-                add_attestation_element(self.dev_att.as_ref(), req.str.0, &mut attest_element, t);
+            let _ = add_attestation_element(
+                self.dev_att.as_ref(),
+                req.str.0,
+                epoch,
+                &mut attest_element,
+                t,
+            );
             let _ = add_attestation_signature(
                 self.dev_att.as_ref(),
                 &mut attest_element,
@@ -367,12 +477,17 @@ impl NocCluster {
     fn handle_command_csrrequest(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         cmd_enter!("CSRRequest");
 
-        let req = CommonReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
-        info!("Received CSR Nonce:{:?}", req.str);
+        let req = CsrReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        info!("Received CSR Nonce:{:?}", req.nonce);
 
         if !self.failsafe.is_armed() {
             return Err(IMStatusCode::UnsupportedAccess);
         }
+        // The NOC of a fabric can only be updated from within the fabric
+        let for_update_noc = req.for_update_noc.unwrap_or(false);
+        if for_update_noc && cmd_req.trans.session.get_local_fabric_idx().is_none() {
+            return Err(IMStatusCode::InvalidCommand);
+        }
 
         let noc_keypair = KeyPair::new().map_err(|_| IMStatusCode::Failure)?;
         let mut attest_challenge = [0u8; crypto::SYMM_KEY_LEN_BYTES];
@@ -382,7 +497,7 @@ impl NocCluster {
             let mut buf: [u8; RESP_MAX] = [0; RESP_MAX];
             let mut nocsr_element = WriteBuf::new(&mut buf, RESP_MAX);
             let _ = t.start_struct(tag);
-            let _ = add_nocsrelement(&noc_keypair, req.str.0, &mut nocsr_element, t);
+            let _ = add_nocsrelement(&noc_keypair, req.nonce.0, &mut nocsr_element, t);
             let _ = add_attestation_signature(
                 self.dev_att.as_ref(),
                 &mut nocsr_element,
@@ -399,7 +514,7 @@ impl NocCluster {
         );
 
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
-        let noc_data = Box::new(NocData::new(noc_keypair));
+        let noc_data = Box::new(NocData::new(noc_keypair, for_update_noc));
         // Store this in the session data instead of cluster data, so it gets cleared
         // if the session goes away for some reason
         cmd_req.trans.session.set_data(noc_data);
@@ -458,6 +573,7 @@ impl ClusterType for NocCluster {
             .ok_or(IMStatusCode::UnsupportedCommand)?;
         match cmd {
             Commands::AddNOC => self.handle_command_addnoc(cmd_req),
+            Commands::UpdateNOC => self.handle_command_updatenoc(cmd_req),
             Commands::CSRReq => self.handle_command_csrrequest(cmd_req),
             Commands::AddTrustedRootCert => self.handle_command_addtrustedrootcert(cmd_req),
             Commands::AttReq => self.handle_command_attrequest(cmd_req),
@@ -484,6 +600,19 @@ impl ClusterType for NocCluster {
                 });
                 let _ = tw.end_container();
             })),
+            Some(Attributes::NOCs) => encoder.encode(EncodeValue::Closure(&|tag, tw| {
+                let _ = tw.start_array(tag);
+                let _ = self.fabric_mgr.for_each(|entry, fab_idx| {
+                    // The NOCs are fabric-sensitive, only those of the accessing fabric are read
+                    if attr.fab_idx == fab_idx {
+                        let _ = encode_noc_entry(entry, fab_idx, tw);
+                    }
+                });
+                let _ = tw.end_container();
+            })),
+            Some(Attributes::TrustedRootCerts) => {
+                encoder.encode(EncodeValue::Value(&TrustedRootCerts(&self.fabric_mgr)))
+            }
             Some(Attributes::CommissionedFabrics) => {
                 let count = self.fabric_mgr.used_count() as u8;
                 encoder.encode(EncodeValue::Value(&count))
@@ -495,9 +624,53 @@ impl ClusterType for NocCluster {
     }
 }
 
+// The root certificates of all the fabrics, in the Matter TLV form
+struct TrustedRootCerts<'a>(&'a FabricMgr);
+
+impl<'a> ToTLV for TrustedRootCerts<'a> {
+    fn to_tlv(&self, tw: &mut TLVWriter, tag: TagType) -> Result<(), Error> {
+        tw.start_array(tag)?;
+        let mut result = Ok(());
+        self.0.for_each(|entry, _| {
+            if result.is_ok() {
+                let mut buf = [0u8; MAX_CERT_TLV_LEN];
+                result = entry
+                    .root_ca
+                    .as_tlv(&mut buf)
+                    .and_then(|len| OctetStr(&buf[..len]).to_tlv(tw, TagType::Anonymous));
+            }
+        })?;
+        result?;
+        tw.end_container()
+    }
+}
+
+fn encode_noc_entry(fabric: &Fabric, fab_idx: u8, tw: &mut TLVWriter) -> Result<(), Error> {
+    let mut noc = [0u8; MAX_CERT_TLV_LEN];
+    let len = fabric.noc.as_tlv(&mut noc)?;
+    let noc = &noc[..len];
+
+    let mut icac = [0u8; MAX_CERT_TLV_LEN];
+    let icac = match &fabric.icac {
+        Some(c) => {
+            let len = c.as_tlv(&mut icac)?;
+            Nullable::NotNull(OctetStr(&icac[..len]))
+        }
+        None => Nullable::Null,
+    };
+
+    NocEntry {
+        noc: OctetStr(noc),
+        icac,
+        fab_idx,
+    }
+    .to_tlv(tw, TagType::Anonymous)
+}
+
 fn add_attestation_element(
     dev_att: &dyn DevAttDataFetcher,
     att_nonce: &[u8],
+    epoch: u32,
     write_buf: &mut WriteBuf,
     t: &mut TLVWriter,
 ) -> Result<(), Error> {
@@ -505,7 +678,6 @@ fn add_attestation_element(
     let len = dev_att.get_devatt_data(dev_att::DataType::CertDeclaration, &mut cert_dec)?;
     let cert_dec = &cert_dec[0..len];
 
-    let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
     let mut writer = TLVWriter::new(write_buf);
     writer.start_struct(TagType::Anonymous)?;
     writer.str16(TagType::Context(1), cert_dec)?;
@@ -576,12 +748,35 @@ struct AddNocReq<'a> {
     vendor_id: u16,
 }
 
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct UpdateNocReq<'a> {
+    noc_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct NocEntry<'a> {
+    noc: OctetStr<'a>,
+    icac: Nullable<OctetStr<'a>>,
+    #[tagval(0xFE)]
+    fab_idx: u8,
+}
+
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 struct CommonReq<'a> {
     str: OctetStr<'a>,
 }
 
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct CsrReq<'a> {
+    nonce: OctetStr<'a>,
+    for_update_noc: Option<bool>,
+}
+
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 struct UpdateFabricLabelReq<'a> {
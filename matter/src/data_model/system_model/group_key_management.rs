@@ -0,0 +1,727 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::sync::Arc;
+
+use num_derive::FromPrimitive;
+
+use crate::cmd_enter;
+use crate::crypto;
+use crate::data_model::objects::*;
+use crate::error::*;
+use crate::group_keys::{self, EpochKey, GroupKeyMapEntry, GroupKeySet, GroupKeys, IPK_KEY_SET_ID};
+use crate::interaction_model::command::CommandReq;
+use crate::interaction_model::core::IMStatusCode;
+use crate::interaction_model::messages::ib::{self, attr_list_write, ListOperation};
+use crate::tlv::{
+    FromTLV, Nullable, OctetStr, TLVArray, TLVArrayOwned, TLVElement, TLVWriter, TagType, ToTLV,
+    UtfStr,
+};
+use log::{error, info};
+
+pub const ID: u32 = 0x003F;
+
+#[derive(FromPrimitive)]
+pub enum Attributes {
+    GroupKeyMap = 0,
+    GroupTable = 1,
+    MaxGroupsPerFabric = 2,
+    MaxGroupKeysPerFabric = 3,
+}
+
+#[derive(FromPrimitive)]
+pub enum Commands {
+    KeySetWrite = 0,
+    KeySetRead = 1,
+    KeySetReadResp = 2,
+    KeySetRemove = 3,
+    KeySetReadAllIndices = 4,
+    KeySetReadAllIndicesResp = 5,
+}
+
+#[derive(FromTLV, ToTLV, Debug, Clone, Copy, PartialEq)]
+#[tlvargs(start = 1)]
+struct GroupKeyMapStruct {
+    group_id: u16,
+    key_set_id: u16,
+    #[tagval(0xFE)]
+    fab_idx: Option<u8>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct GroupInfoStruct<'a> {
+    group_id: u16,
+    endpoints: TLVArray<'a, u16>,
+    group_name: Option<UtfStr<'a>>,
+    #[tagval(0xFE)]
+    fab_idx: u8,
+}
+
+#[derive(FromTLV, ToTLV, Debug, Clone, Copy, PartialEq)]
+#[tlvargs(lifetime = "'a")]
+struct GroupKeySetStruct<'a> {
+    key_set_id: u16,
+    policy: u8,
+    epoch_key0: Nullable<OctetStr<'a>>,
+    epoch_start_time0: Nullable<u64>,
+    epoch_key1: Nullable<OctetStr<'a>>,
+    epoch_start_time1: Nullable<u64>,
+    epoch_key2: Nullable<OctetStr<'a>>,
+    epoch_start_time2: Nullable<u64>,
+}
+
+// This is also the layout of the KeySetReadResponse
+#[derive(FromTLV, ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct KeySetWriteReq<'a> {
+    key_set: GroupKeySetStruct<'a>,
+}
+
+#[derive(FromTLV)]
+struct KeySetIdReq {
+    key_set_id: u16,
+}
+
+#[derive(ToTLV)]
+struct KeySetReadAllIndicesResp {
+    key_set_ids: TLVArrayOwned<u16>,
+}
+
+// Validate the key set of a KeySetWrite
+fn key_set_from_struct(s: &GroupKeySetStruct) -> Result<GroupKeySet, IMStatusCode> {
+    if s.key_set_id == IPK_KEY_SET_ID {
+        return Err(IMStatusCode::InvalidCommand);
+    }
+    let policy = num::FromPrimitive::from_u8(s.policy).ok_or(IMStatusCode::ConstraintError)?;
+    let keys = [
+        (s.epoch_key0, s.epoch_start_time0),
+        (s.epoch_key1, s.epoch_start_time1),
+        (s.epoch_key2, s.epoch_start_time2),
+    ];
+
+    let mut epoch_keys: Vec<EpochKey> = Vec::new();
+    let mut missing = false;
+    for (key, start_time) in keys {
+        match (key, start_time) {
+            (Nullable::NotNull(key), Nullable::NotNull(start_time)) => {
+                // Keys can't be skipped, and each one has to start after the previous one
+                if missing
+                    || start_time == 0
+                    || matches!(epoch_keys.last(), Some(k) if k.start_time >= start_time)
+                {
+                    return Err(IMStatusCode::InvalidCommand);
+                }
+                if key.0.len() != crypto::SYMM_KEY_LEN_BYTES {
+                    return Err(IMStatusCode::ConstraintError);
+                }
+                epoch_keys.push(EpochKey {
+                    key: key.0.to_vec(),
+                    start_time,
+                });
+            }
+            (Nullable::Null, Nullable::Null) => missing = true,
+            _ => return Err(IMStatusCode::InvalidCommand),
+        }
+    }
+    if epoch_keys.is_empty() {
+        return Err(IMStatusCode::InvalidCommand);
+    }
+
+    Ok(GroupKeySet {
+        key_set_id: s.key_set_id,
+        policy,
+        epoch_keys,
+    })
+}
+
+// The keys themselves are never read back
+fn start_time(key_set: &GroupKeySet, index: usize) -> Nullable<u64> {
+    key_set
+        .epoch_keys
+        .get(index)
+        .map_or(Nullable::Null, |k| Nullable::NotNull(k.start_time))
+}
+
+pub struct GroupKeyManagementCluster {
+    base: Cluster,
+    group_keys: Arc<GroupKeys>,
+}
+
+impl GroupKeyManagementCluster {
+    pub fn new(group_keys: Arc<GroupKeys>) -> Result<Box<Self>, Error> {
+        let mut c = Box::new(GroupKeyManagementCluster {
+            base: Cluster::new(ID)?,
+            group_keys,
+        });
+        c.base.add_attribute(attr_group_key_map_new())?;
+        c.base.add_attribute(attr_group_table_new())?;
+        c.base.add_attribute(attr_max_groups_per_fabric_new())?;
+        c.base.add_attribute(attr_max_group_keys_per_fabric_new())?;
+        Ok(c)
+    }
+
+    /// Write the GroupKeyMap Attribute
+    ///
+    /// The list index for edit and delete is relative to the accessing fabric's entries
+    fn write_key_map_attr(
+        &mut self,
+        op: &ListOperation,
+        data: &TLVElement,
+        fab_idx: u8,
+    ) -> Result<(), IMStatusCode> {
+        info!("Performing Group Key Map operation {:?}", op);
+        let mut key_map = self
+            .group_keys
+            .key_map(fab_idx)
+            .map_err(|_| IMStatusCode::Failure)?;
+        match op {
+            ListOperation::AddItem | ListOperation::EditItem(_) => {
+                let entry =
+                    GroupKeyMapStruct::from_tlv(data).map_err(|_| IMStatusCode::ConstraintError)?;
+                if entry.key_set_id == IPK_KEY_SET_ID {
+                    return Err(IMStatusCode::ConstraintError);
+                }
+                let entry = GroupKeyMapEntry {
+                    group_id: entry.group_id,
+                    key_set_id: entry.key_set_id,
+                };
+                let index = if let ListOperation::EditItem(index) = op {
+                    let index = *index as usize;
+                    if index >= key_map.len() {
+                        return Err(IMStatusCode::NotFound);
+                    }
+                    index
+                } else {
+                    key_map.len()
+                };
+                // A group can only be mapped to a single key set
+                if key_map
+                    .iter()
+                    .enumerate()
+                    .any(|(i, e)| i != index && e.group_id == entry.group_id)
+                {
+                    return Err(IMStatusCode::ConstraintError);
+                }
+                if index == key_map.len() {
+                    key_map.push(entry);
+                } else {
+                    key_map[index] = entry;
+                }
+            }
+            ListOperation::DeleteItem(index) => {
+                let index = *index as usize;
+                if index >= key_map.len() {
+                    return Err(IMStatusCode::NotFound);
+                }
+                key_map.remove(index);
+            }
+            ListOperation::DeleteList => key_map.clear(),
+        }
+        match self.group_keys.set_key_map(fab_idx, key_map) {
+            Ok(_) => Ok(()),
+            Err(Error::NoSpace) => Err(IMStatusCode::ResourceExhausted),
+            _ => Err(IMStatusCode::Failure),
+        }
+    }
+
+    fn write_key_set(&mut self, fab_idx: u8, req: &KeySetWriteReq) -> Result<(), IMStatusCode> {
+        let key_set = key_set_from_struct(&req.key_set)?;
+        match self.group_keys.set_key_set(fab_idx, key_set) {
+            Ok(_) => Ok(()),
+            Err(Error::NoSpace) => Err(IMStatusCode::ResourceExhausted),
+            _ => Err(IMStatusCode::Failure),
+        }
+    }
+
+    fn read_key_set(&self, fab_idx: u8, key_set_id: u16) -> Result<GroupKeySet, IMStatusCode> {
+        self.group_keys
+            .get_key_set(fab_idx, key_set_id)
+            .map_err(|e| match e {
+                Error::NotFound => IMStatusCode::NotFound,
+                _ => IMStatusCode::Failure,
+            })
+    }
+
+    fn remove_key_set(&mut self, fab_idx: u8, key_set_id: u16) -> Result<(), IMStatusCode> {
+        if key_set_id == IPK_KEY_SET_ID {
+            return Err(IMStatusCode::InvalidCommand);
+        }
+        self.group_keys
+            .remove_key_set(fab_idx, key_set_id)
+            .map_err(|e| match e {
+                Error::NotFound => IMStatusCode::NotFound,
+                _ => IMStatusCode::Failure,
+            })
+    }
+
+    fn handle_command_keysetwrite(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("KeySetWrite");
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(IMStatusCode::UnsupportedAccess)?;
+        let req =
+            KeySetWriteReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        self.write_key_set(fab_idx, &req)?;
+        self.base.cluster_changed();
+        cmd_req.trans.complete();
+        Err(IMStatusCode::Success)
+    }
+
+    fn handle_command_keysetread(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("KeySetRead");
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(IMStatusCode::UnsupportedAccess)?;
+        let req = KeySetIdReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        let key_set = self.read_key_set(fab_idx, req.key_set_id)?;
+
+        let resp = KeySetWriteReq {
+            key_set: GroupKeySetStruct {
+                key_set_id: key_set.key_set_id,
+                policy: key_set.policy as u8,
+                epoch_key0: Nullable::Null,
+                epoch_start_time0: start_time(&key_set, 0),
+                epoch_key1: Nullable::Null,
+                epoch_start_time1: start_time(&key_set, 1),
+                epoch_key2: Nullable::Null,
+                epoch_start_time2: start_time(&key_set, 2),
+            },
+        };
+        let invoke_resp = ib::InvResp::cmd_new(
+            0,
+            ID,
+            Commands::KeySetReadResp as u16,
+            EncodeValue::Value(&resp),
+        );
+        let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
+        cmd_req.trans.complete();
+        Ok(())
+    }
+
+    fn handle_command_keysetremove(
+        &mut self,
+        cmd_req: &mut CommandReq,
+    ) -> Result<(), IMStatusCode> {
+        cmd_enter!("KeySetRemove");
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(IMStatusCode::UnsupportedAccess)?;
+        let req = KeySetIdReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        self.remove_key_set(fab_idx, req.key_set_id)?;
+        self.base.cluster_changed();
+        cmd_req.trans.complete();
+        Err(IMStatusCode::Success)
+    }
+
+    fn handle_command_keysetreadallindices(
+        &mut self,
+        cmd_req: &mut CommandReq,
+    ) -> Result<(), IMStatusCode> {
+        cmd_enter!("KeySetReadAllIndices");
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(IMStatusCode::UnsupportedAccess)?;
+        let key_set_ids = self
+            .group_keys
+            .key_set_ids(fab_idx)
+            .map_err(|_| IMStatusCode::Failure)?;
+
+        let resp = KeySetReadAllIndicesResp {
+            key_set_ids: key_set_ids.into(),
+        };
+        let invoke_resp = ib::InvResp::cmd_new(
+            0,
+            ID,
+            Commands::KeySetReadAllIndicesResp as u16,
+            EncodeValue::Value(&resp),
+        );
+        let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
+        cmd_req.trans.complete();
+        Ok(())
+    }
+}
+
+impl ClusterType for GroupKeyManagementCluster {
+    fn base(&self) -> &Cluster {
+        &self.base
+    }
+    fn base_mut(&mut self) -> &mut Cluster {
+        &mut self.base
+    }
+
+    fn handle_command(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        let cmd = cmd_req
+            .cmd
+            .path
+            .leaf
+            .map(num::FromPrimitive::from_u32)
+            .ok_or(IMStatusCode::UnsupportedCommand)?
+            .ok_or(IMStatusCode::UnsupportedCommand)?;
+        match cmd {
+            Commands::KeySetWrite => self.handle_command_keysetwrite(cmd_req),
+            Commands::KeySetRead => self.handle_command_keysetread(cmd_req),
+            Commands::KeySetRemove => self.handle_command_keysetremove(cmd_req),
+            Commands::KeySetReadAllIndices => self.handle_command_keysetreadallindices(cmd_req),
+            _ => Err(IMStatusCode::UnsupportedCommand),
+        }
+    }
+
+    fn read_custom_attribute(&self, encoder: &mut dyn Encoder, attr: &AttrDetails) {
+        match num::FromPrimitive::from_u16(attr.attr_id) {
+            Some(Attributes::GroupKeyMap) => encoder.encode(EncodeValue::Closure(&|tag, tw| {
+                let _ = tw.start_array(tag);
+                let _ = self.group_keys.for_each_key_map(|fab_idx, entry| {
+                    if !attr.fab_filter || attr.fab_idx == fab_idx {
+                        let entry = GroupKeyMapStruct {
+                            group_id: entry.group_id,
+                            key_set_id: entry.key_set_id,
+                            fab_idx: Some(fab_idx),
+                        };
+                        let _ = entry.to_tlv(tw, TagType::Anonymous);
+                    }
+                });
+                let _ = tw.end_container();
+            })),
+            Some(Attributes::GroupTable) => encoder.encode(EncodeValue::Closure(&|tag, tw| {
+                let _ = tw.start_array(tag);
+                let _ = self.group_keys.for_each_group_info(|fab_idx, group| {
+                    if !attr.fab_filter || attr.fab_idx == fab_idx {
+                        let entry = GroupInfoStruct {
+                            group_id: group.group_id,
+                            endpoints: TLVArray::new(&group.endpoints),
+                            group_name: Some(UtfStr::new(group.name.as_bytes())),
+                            fab_idx,
+                        };
+                        let _ = entry.to_tlv(tw, TagType::Anonymous);
+                    }
+                });
+                let _ = tw.end_container();
+            })),
+            _ => {
+                error!("Attribute not yet supported: this shouldn't happen");
+            }
+        }
+    }
+
+    fn write_attribute(
+        &mut self,
+        attr: &AttrDetails,
+        data: &TLVElement,
+    ) -> Result<(), IMStatusCode> {
+        let result =
+            if let Some(Attributes::GroupKeyMap) = num::FromPrimitive::from_u16(attr.attr_id) {
+                attr_list_write(attr, data, |op, data| {
+                    self.write_key_map_attr(&op, data, attr.fab_idx)
+                })
+            } else {
+                error!("Attribute not yet supported: this shouldn't happen");
+                Err(IMStatusCode::NotFound)
+            };
+        if result.is_ok() {
+            self.base.cluster_changed();
+        }
+        result
+    }
+}
+
+fn attr_group_key_map_new() -> Attribute {
+    Attribute::new(
+        Attributes::GroupKeyMap as u16,
+        AttrValue::Custom,
+        Access::RWFA,
+        Quality::NONE,
+    )
+}
+
+fn attr_group_table_new() -> Attribute {
+    Attribute::new(
+        Attributes::GroupTable as u16,
+        AttrValue::Custom,
+        Access::RV | Access::FAB_SCOPED,
+        Quality::NONE,
+    )
+}
+
+fn attr_max_groups_per_fabric_new() -> Attribute {
+    Attribute::new(
+        Attributes::MaxGroupsPerFabric as u16,
+        AttrValue::Uint16(group_keys::MAX_GROUPS_PER_FABRIC as u16),
+        Access::RV,
+        Quality::FIXED,
+    )
+}
+
+fn attr_max_group_keys_per_fabric_new() -> Attribute {
+    Attribute::new(
+        Attributes::MaxGroupKeysPerFabric as u16,
+        AttrValue::Uint16(group_keys::MAX_GROUP_KEYS_PER_FABRIC as u16),
+        Access::RV,
+        Quality::FIXED,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        data_model::{
+            core::read::AttrReadEncoder,
+            objects::{AttrDetails, ClusterType},
+        },
+        fabric::FabricMgr,
+        group_keys::{GroupKeyMapEntry, GroupKeys},
+        interaction_model::{core::IMStatusCode, messages::ib::ListOperation},
+        mdns::Mdns,
+        persist::{MemKvStore, SharedKvStore},
+        tlv::{
+            get_root_node_struct, ElementType, Nullable, OctetStr, TLVElement, TLVWriter, TagType,
+            ToTLV,
+        },
+        utils::writebuf::WriteBuf,
+    };
+
+    use super::{GroupKeyManagementCluster, GroupKeyMapStruct, GroupKeySetStruct, KeySetWriteReq};
+
+    const KEY: [u8; 16] = [0x42; 16];
+
+    fn new_cluster() -> (Box<GroupKeyManagementCluster>, Arc<GroupKeys>) {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let mdns = Arc::new(Mdns::new(0));
+        let fabric_mgr = Arc::new(FabricMgr::new(storage.clone(), mdns, None).unwrap());
+        let group_keys = Arc::new(GroupKeys::new(fabric_mgr, storage).unwrap());
+        (
+            GroupKeyManagementCluster::new(group_keys.clone()).unwrap(),
+            group_keys,
+        )
+    }
+
+    fn key_set(key_set_id: u16, start_time0: u64) -> GroupKeySetStruct<'static> {
+        GroupKeySetStruct {
+            key_set_id,
+            policy: 0,
+            epoch_key0: Nullable::NotNull(OctetStr(&KEY)),
+            epoch_start_time0: Nullable::NotNull(start_time0),
+            epoch_key1: Nullable::Null,
+            epoch_start_time1: Nullable::Null,
+            epoch_key2: Nullable::Null,
+            epoch_start_time2: Nullable::Null,
+        }
+    }
+
+    fn write_key_map(
+        gkm: &mut GroupKeyManagementCluster,
+        op: ListOperation,
+        group_id: u16,
+        key_set_id: u16,
+        fab_idx: u8,
+    ) -> Result<(), IMStatusCode> {
+        let mut buf = [0u8; 100];
+        let buf_len = buf.len();
+        let mut writebuf = WriteBuf::new(&mut buf, buf_len);
+        let mut tw = TLVWriter::new(&mut writebuf);
+        let entry = GroupKeyMapStruct {
+            group_id,
+            key_set_id,
+            // Ignored, the accessing fabric is used
+            fab_idx: Some(5),
+        };
+        entry.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        let data = get_root_node_struct(writebuf.as_borrow_slice()).unwrap();
+        gkm.write_key_map_attr(&op, &data, fab_idx)
+    }
+
+    #[test]
+    fn test_key_set_write() {
+        let (mut gkm, group_keys) = new_cluster();
+        let write = |gkm: &mut GroupKeyManagementCluster, key_set| {
+            gkm.write_key_set(0, &KeySetWriteReq { key_set })
+        };
+
+        assert_eq!(write(&mut gkm, key_set(1, 10)), Ok(()));
+        assert_eq!(group_keys.key_set_ids(0).unwrap(), [0, 1]);
+        let read = gkm.read_key_set(0, 1).unwrap();
+        assert_eq!(read.epoch_keys[0].start_time, 10);
+
+        // The IPK can't be written
+        assert_eq!(
+            write(&mut gkm, key_set(0, 10)),
+            Err(IMStatusCode::InvalidCommand)
+        );
+        // The first key is mandatory
+        let mut ks = key_set(2, 10);
+        ks.epoch_key0 = Nullable::Null;
+        assert_eq!(write(&mut gkm, ks), Err(IMStatusCode::InvalidCommand));
+        // Start times must increase
+        let mut ks = key_set(2, 10);
+        ks.epoch_key1 = Nullable::NotNull(OctetStr(&KEY));
+        ks.epoch_start_time1 = Nullable::NotNull(10);
+        assert_eq!(write(&mut gkm, ks), Err(IMStatusCode::InvalidCommand));
+        // Keys can't be skipped
+        let mut ks = key_set(2, 10);
+        ks.epoch_key2 = Nullable::NotNull(OctetStr(&KEY));
+        ks.epoch_start_time2 = Nullable::NotNull(20);
+        assert_eq!(write(&mut gkm, ks), Err(IMStatusCode::InvalidCommand));
+        let mut ks = key_set(2, 10);
+        ks.epoch_key0 = Nullable::NotNull(OctetStr(&KEY[..15]));
+        assert_eq!(write(&mut gkm, ks), Err(IMStatusCode::ConstraintError));
+
+        assert_eq!(write(&mut gkm, key_set(2, 10)), Ok(()));
+        // The IPK takes up one of the slots
+        assert_eq!(
+            write(&mut gkm, key_set(3, 10)),
+            Err(IMStatusCode::ResourceExhausted)
+        );
+
+        assert_eq!(gkm.remove_key_set(0, 0), Err(IMStatusCode::InvalidCommand));
+        assert_eq!(gkm.remove_key_set(0, 2), Ok(()));
+        assert_eq!(gkm.remove_key_set(0, 2), Err(IMStatusCode::NotFound));
+        assert_eq!(gkm.read_key_set(0, 2).err(), Some(IMStatusCode::NotFound));
+        // The IPK can always be read
+        assert!(gkm.read_key_set(0, 0).is_ok());
+    }
+
+    #[test]
+    fn test_key_map_write() {
+        let (mut gkm, group_keys) = new_cluster();
+
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::AddItem, 0x101, 1, 1),
+            Ok(())
+        );
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::AddItem, 0x102, 1, 2),
+            Ok(())
+        );
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::AddItem, 0x103, 2, 1),
+            Ok(())
+        );
+        // Key set 0 and duplicate groups are not allowed
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::AddItem, 0x104, 0, 1),
+            Err(IMStatusCode::ConstraintError)
+        );
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::AddItem, 0x101, 2, 1),
+            Err(IMStatusCode::ConstraintError)
+        );
+        // Edits are relative to the fabric's entries
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::EditItem(1), 0x103, 3, 1),
+            Ok(())
+        );
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::EditItem(1), 0x103, 3, 2),
+            Err(IMStatusCode::NotFound)
+        );
+        assert_eq!(
+            group_keys.key_map(1).unwrap(),
+            [
+                GroupKeyMapEntry {
+                    group_id: 0x101,
+                    key_set_id: 1
+                },
+                GroupKeyMapEntry {
+                    group_id: 0x103,
+                    key_set_id: 3
+                }
+            ]
+        );
+
+        for group_id in 0x104..0x106 {
+            assert_eq!(
+                write_key_map(&mut gkm, ListOperation::AddItem, group_id, 1, 1),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            write_key_map(&mut gkm, ListOperation::AddItem, 0x106, 1, 1),
+            Err(IMStatusCode::ResourceExhausted)
+        );
+
+        // data is don't-care for deletes
+        let data = TLVElement::new(TagType::Anonymous, ElementType::True);
+        assert_eq!(
+            gkm.write_key_map_attr(&ListOperation::DeleteItem(0), &data, 1),
+            Ok(())
+        );
+        assert_eq!(group_keys.key_map(1).unwrap().len(), 3);
+        assert_eq!(
+            gkm.write_key_map_attr(&ListOperation::DeleteList, &data, 1),
+            Ok(())
+        );
+        assert!(group_keys.key_map(1).unwrap().is_empty());
+        assert_eq!(group_keys.key_map(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_read_with_fabric_filter() {
+        let (mut gkm, group_keys) = new_cluster();
+        write_key_map(&mut gkm, ListOperation::AddItem, 0x101, 1, 1).unwrap();
+        write_key_map(&mut gkm, ListOperation::AddItem, 0x102, 1, 2).unwrap();
+        group_keys.add_group_endpoint(2, 0x102, "A", 1).unwrap();
+
+        let mut buf = [0u8; 100];
+        let buf_len = buf.len();
+        let mut writebuf = WriteBuf::new(&mut buf, buf_len);
+        {
+            let mut tw = TLVWriter::new(&mut writebuf);
+            let mut encoder = AttrReadEncoder::new(&mut tw);
+            let attr_details = AttrDetails {
+                attr_id: 0,
+                list_index: None,
+                fab_idx: 2,
+                fab_filter: true,
+            };
+            gkm.read_custom_attribute(&mut encoder, &attr_details);
+            assert_eq!(
+                &[
+                    21, 53, 1, 36, 0, 0, 55, 1, 24, 54, 2, 21, 37, 1, 2, 1, 36, 2, 1, 36, 254, 2,
+                    24, 24, 24, 24
+                ],
+                writebuf.as_borrow_slice()
+            );
+        }
+        writebuf.reset(0);
+        {
+            let mut tw = TLVWriter::new(&mut writebuf);
+            let mut encoder = AttrReadEncoder::new(&mut tw);
+            let attr_details = AttrDetails {
+                attr_id: 1,
+                list_index: None,
+                fab_idx: 1,
+                fab_filter: true,
+            };
+            gkm.read_custom_attribute(&mut encoder, &attr_details);
+            // Fabric 1 isn't a member of any group
+            assert_eq!(
+                &[21, 53, 1, 36, 0, 0, 55, 1, 24, 54, 2, 24, 24, 24],
+                writebuf.as_borrow_slice()
+            );
+        }
+    }
+}
@@ -17,3 +17,4 @@
 
 pub mod access_control;
 pub mod descriptor;
+pub mod group_key_management;
@@ -16,10 +16,11 @@
  */
 
 use std::{
-    array::TryFromSliceError, fmt, string::FromUtf8Error, sync::PoisonError, time::SystemTimeError,
+    array::TryFromSliceError, fmt, str::Utf8Error, string::FromUtf8Error, sync::PoisonError,
+    time::SystemTimeError,
 };
 
-use async_channel::{SendError, TryRecvError};
+use async_channel::{RecvError, SendError, TryRecvError, TrySendError};
 use log::error;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -27,6 +28,8 @@ pub enum Error {
     AttributeNotFound,
     AttributeIsCustom,
     BufferTooSmall,
+    CertExpired,
+    CertNotYetValid,
     ClusterNotFound,
     CommandNotFound,
     Duplicate,
@@ -35,6 +38,7 @@ pub enum Error {
     TLSStack,
     MdnsError,
     Network,
+    NoAck,
     NoCommand,
     NoEndpoint,
     NoExchange,
@@ -50,6 +54,7 @@ pub enum Error {
     NoTagFound,
     NotFound,
     PacketPoolExhaust,
+    PathLenExceeded,
     StdIoError,
     SysTimeFail,
     Invalid,
@@ -60,6 +65,10 @@ pub enum Error {
     InvalidPeerAddr,
     // Invalid Auth Key in the Matter Certificate
     InvalidAuthKey,
+    // Invalid Basic Constraints, Key Usage or Issuer in the Matter Certificate
+    InvalidBasicConstraints,
+    InvalidIssuer,
+    InvalidKeyUsage,
     InvalidSignature,
     InvalidState,
     InvalidTime,
@@ -132,6 +141,26 @@ impl From<FromUtf8Error> for Error {
     }
 }
 
+impl From<Utf8Error> for Error {
+    fn from(_e: Utf8Error) -> Self {
+        Self::Utf8Fail
+    }
+}
+
+impl<T> From<TrySendError<T>> for Error {
+    fn from(e: TrySendError<T>) -> Self {
+        error!("Error in channel try_send {}", e);
+        Self::Invalid
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(e: RecvError) -> Self {
+        error!("Error in channel recv {}", e);
+        Self::Invalid
+    }
+}
+
 impl From<TryRecvError> for Error {
     fn from(e: TryRecvError) -> Self {
         error!("Error in channel try_recv {}", e);
@@ -15,23 +15,25 @@
  *    limitations under the License.
  */
 
-use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::sync::{Arc, RwLock};
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use log::{error, info};
 use owning_ref::RwLockReadGuardRef;
 
 use crate::{
-    cert::Cert,
+    cert::{Cert, CertTime},
     crypto::{self, crypto_dummy::KeyPairDummy, hkdf_sha256, CryptoKeyPair, HmacSha256, KeyPair},
     error::Error,
     group_keys::KeySet,
     mdns::{self, Mdns},
-    sys::{Psm, SysMdnsService},
-    tlv::{OctetStr, TLVWriter, TagType, ToTLV, UtfStr},
+    persist::{self, KeyWrapper, KvStore, SharedKvStore},
+    sys::SysMdnsService,
+    tlv::{FromTLV, OctetStr, TLVElement, TLVList, TLVWriter, TagType, ToTLV, UtfStr},
+    utils::writebuf::WriteBuf,
 };
 
-const MAX_CERT_TLV_LEN: usize = 350;
+pub const MAX_CERT_TLV_LEN: usize = 350;
 const COMPRESSED_FABRIC_ID_LEN: usize = 8;
 
 macro_rules! fb_key {
@@ -40,6 +42,44 @@ macro_rules! fb_key {
     };
 }
 
+fn fabric_key(index: usize) -> String {
+    format!("fb{}", index)
+}
+
+// The schema version of the fabric record, to be bumped on any change to FabricRecord
+const FABRIC_RECORD_VERSION: u8 = 2;
+const MAX_FABRIC_RECORD_LEN: usize = 1300;
+
+#[derive(ToTLV, FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct FabricRecord<'a> {
+    root_ca: OctetStr<'a>,
+    icac: Option<OctetStr<'a>>,
+    noc: OctetStr<'a>,
+    ipk: OctetStr<'a>,
+    label: UtfStr<'a>,
+    pub_key: OctetStr<'a>,
+    priv_key: OctetStr<'a>,
+    vendor_id: u16,
+    // Whether the IPK and the private key are wrapped with the KeyWrapper
+    wrapped: Option<bool>,
+}
+
+fn migrate_fabric_record(version: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match version {
+        // Version 2 only added the optional 'wrapped' field
+        1 => Ok(data.to_vec()),
+        _ => persist::no_migration(version, data),
+    }
+}
+
+// The associated data for wrapping the item of a fabric, this ties the wrapped item to
+// the fabric index
+fn wrap_ad(index: usize, item: &str) -> String {
+    format!("{}/{}", fabric_key(index), item)
+}
+
+// The keys of the legacy layout, where every detail of a fabric was stored separately
 const ST_VID: &str = "vid";
 const ST_RCA: &str = "rca";
 const ST_ICA: &str = "ica";
@@ -104,22 +144,66 @@ impl Fabric {
         };
         Fabric::get_compressed_id(f.root_ca.get_pubkey(), fabric_id, &mut f.compressed_id)?;
         f.ipk = KeySet::new(ipk, &f.compressed_id)?;
+        Ok(f)
+    }
 
+    /// A copy of the fabric with the operational key pair and certificates of UpdateNOC
+    ///
+    /// The root certificate, the IPK, the vendor and the label stay the same. The new NOC
+    /// must be for the same fabric and for the public key of the key pair, and it must chain
+    /// up to the root certificate, through the ICAC if there is one.
+    pub fn with_noc(
+        &self,
+        key_pair: KeyPair,
+        icac: Option<Cert>,
+        noc: Cert,
+        time: CertTime,
+    ) -> Result<Self, Error> {
+        if noc.get_fabric_id()? != self.fabric_id {
+            error!("The new NOC is for another fabric");
+            return Err(Error::Invalid);
+        }
+        let mut pubkey = [0; crypto::EC_POINT_LEN_BYTES];
+        let len = key_pair.get_public_key(&mut pubkey)?;
+        if noc.get_pubkey() != &pubkey[..len] {
+            error!("The new NOC is for another public key");
+            return Err(Error::Invalid);
+        }
+        let mut verifier = noc.verify_chain_start(time);
+        if let Some(icac) = &icac {
+            verifier = verifier.add_cert(icac)?;
+        }
+        if let Err(e) = verifier.add_cert(&self.root_ca)?.finalise() {
+            error!("The new NOC doesn't chain up to the root: {}", e);
+            return Err(e);
+        }
+        let mut f = Fabric::new(
+            key_pair,
+            copy_cert(&self.root_ca)?,
+            icac,
+            noc,
+            self.ipk.epoch_key(),
+            self.vendor_id,
+        )?;
+        f.label = self.label.clone();
+        Ok(f)
+    }
+
+    fn publish(&mut self, mdns: &Mdns) -> Result<(), Error> {
         let mut mdns_service_name = String::with_capacity(33);
-        for c in f.compressed_id {
+        for c in self.compressed_id {
             mdns_service_name.push_str(&format!("{:02X}", c));
         }
         mdns_service_name.push('-');
         let mut node_id_be: [u8; 8] = [0; 8];
-        BigEndian::write_u64(&mut node_id_be, node_id);
+        BigEndian::write_u64(&mut node_id_be, self.node_id);
         for c in node_id_be {
             mdns_service_name.push_str(&format!("{:02X}", c));
         }
         info!("MDNS Service Name: {}", mdns_service_name);
-        f.mdns_service = Some(
-            Mdns::get()?.publish_service(&mdns_service_name, mdns::ServiceMode::Commissioned)?,
-        );
-        Ok(f)
+        self.mdns_service =
+            Some(mdns.publish_service(&mdns_service_name, mdns::ServiceMode::Commissioned)?);
+        Ok(())
     }
 
     pub fn dummy() -> Result<Self, Error> {
@@ -184,6 +268,10 @@ impl Fabric {
         self.fabric_id
     }
 
+    pub fn get_compressed_fabric_id(&self) -> &[u8] {
+        &self.compressed_id
+    }
+
     pub fn get_fabric_desc(&self, fab_idx: u8) -> FabricDescriptor {
         FabricDescriptor {
             root_public_key: OctetStr::new(self.root_ca.get_pubkey()),
@@ -195,55 +283,149 @@ impl Fabric {
         }
     }
 
-    fn rm_store(&self, index: usize, psm: &MutexGuard<Psm>) {
-        psm.rm(fb_key!(index, ST_RCA));
-        psm.rm(fb_key!(index, ST_ICA));
-        psm.rm(fb_key!(index, ST_NOC));
-        psm.rm(fb_key!(index, ST_IPK));
-        psm.rm(fb_key!(index, ST_LBL));
-        psm.rm(fb_key!(index, ST_PBKEY));
-        psm.rm(fb_key!(index, ST_PRKEY));
-        psm.rm(fb_key!(index, ST_VID));
+    fn rm_store(index: usize, storage: &mut dyn KvStore) -> Result<(), Error> {
+        storage.remove(&fabric_key(index))
     }
 
-    fn store(&self, index: usize, psm: &MutexGuard<Psm>) -> Result<(), Error> {
-        let mut key = [0u8; MAX_CERT_TLV_LEN];
-        let len = self.root_ca.as_tlv(&mut key)?;
-        psm.set_kv_slice(fb_key!(index, ST_RCA), &key[..len])?;
+    fn store(
+        &self,
+        index: usize,
+        storage: &mut dyn KvStore,
+        wrapper: Option<&KeyWrapper>,
+    ) -> Result<(), Error> {
+        let mut root_ca = [0u8; MAX_CERT_TLV_LEN];
+        let len = self.root_ca.as_tlv(&mut root_ca)?;
+        let root_ca = &root_ca[..len];
+
+        let mut icac = [0u8; MAX_CERT_TLV_LEN];
+        let icac = if let Some(c) = &self.icac {
+            let len = c.as_tlv(&mut icac)?;
+            Some(OctetStr(&icac[..len]))
+        } else {
+            None
+        };
+
+        let mut noc = [0u8; MAX_CERT_TLV_LEN];
+        let len = self.noc.as_tlv(&mut noc)?;
+        let noc = &noc[..len];
+
+        let mut pub_key = [0_u8; crypto::EC_POINT_LEN_BYTES];
+        let len = self.key_pair.get_public_key(&mut pub_key)?;
+        let pub_key = &pub_key[..len];
+
+        let mut priv_key = [0_u8; crypto::BIGNUM_LEN_BYTES];
+        let len = self.key_pair.get_private_key(&mut priv_key)?;
+        let priv_key = &priv_key[..len];
+
+        let ipk = self.ipk.epoch_key();
+        let (wrapped_ipk, wrapped_priv_key);
+        let (ipk, priv_key) = if let Some(wrapper) = wrapper {
+            wrapped_ipk = wrapper.wrap(wrap_ad(index, ST_IPK).as_bytes(), ipk)?;
+            wrapped_priv_key = wrapper.wrap(wrap_ad(index, ST_PRKEY).as_bytes(), priv_key)?;
+            (wrapped_ipk.as_slice(), wrapped_priv_key.as_slice())
+        } else {
+            (ipk, priv_key)
+        };
+
+        let record = FabricRecord {
+            root_ca: OctetStr(root_ca),
+            icac,
+            noc: OctetStr(noc),
+            ipk: OctetStr(ipk),
+            label: UtfStr(self.label.as_bytes()),
+            pub_key: OctetStr(pub_key),
+            priv_key: OctetStr(priv_key),
+            vendor_id: self.vendor_id,
+            wrapped: Some(wrapper.is_some()),
+        };
+        let mut buf = [0u8; MAX_FABRIC_RECORD_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MAX_FABRIC_RECORD_LEN);
+        let mut tw = TLVWriter::new(&mut wb);
+        record.to_tlv(&mut tw, TagType::Anonymous)?;
+
+        // All the details of the fabric go in a single record, so they are updated together
+        persist::set_record(
+            storage,
+            &fabric_key(index),
+            FABRIC_RECORD_VERSION,
+            wb.as_slice(),
+        )
+    }
 
-        let len = if let Some(icac) = &self.icac {
-            icac.as_tlv(&mut key)?
+    fn load(
+        index: usize,
+        storage: &mut dyn KvStore,
+        wrapper: Option<&KeyWrapper>,
+    ) -> Result<Self, Error> {
+        let data = persist::get_record(
+            storage,
+            &fabric_key(index),
+            FABRIC_RECORD_VERSION,
+            migrate_fabric_record,
+        )?;
+        let root = TLVList::new(&data).iter().next().ok_or(Error::Invalid)?;
+        let record = FabricRecord::from_tlv(&root)?;
+
+        let is_wrapped = record.wrapped.unwrap_or(false);
+        let (unwrapped_ipk, unwrapped_priv_key);
+        let (ipk, priv_key) = if is_wrapped {
+            let wrapper = wrapper.ok_or_else(|| {
+                error!("Fabric {} is wrapped, but there is no key wrapper", index);
+                Error::Invalid
+            })?;
+            unwrapped_ipk = wrapper.unwrap(wrap_ad(index, ST_IPK).as_bytes(), record.ipk.0)?;
+            unwrapped_priv_key =
+                wrapper.unwrap(wrap_ad(index, ST_PRKEY).as_bytes(), record.priv_key.0)?;
+            (unwrapped_ipk.as_slice(), unwrapped_priv_key.as_slice())
         } else {
-            0
+            (record.ipk.0, record.priv_key.0)
         };
-        psm.set_kv_slice(fb_key!(index, ST_ICA), &key[..len])?;
 
-        let len = self.noc.as_tlv(&mut key)?;
-        psm.set_kv_slice(fb_key!(index, ST_NOC), &key[..len])?;
-        psm.set_kv_slice(fb_key!(index, ST_IPK), self.ipk.epoch_key())?;
-        psm.set_kv_slice(fb_key!(index, ST_LBL), self.label.as_bytes())?;
+        let root_ca = Cert::new(record.root_ca.0)?;
+        let icac = match record.icac {
+            Some(icac) => Some(Cert::new(icac.0)?),
+            None => None,
+        };
+        let noc = Cert::new(record.noc.0)?;
+        let label = String::from_utf8(record.label.0.to_vec()).map_err(|_| {
+            error!("Couldn't read label");
+            Error::Invalid
+        })?;
+        let keypair = KeyPair::new_from_components(record.pub_key.0, priv_key)?;
 
-        let mut key = [0_u8; crypto::EC_POINT_LEN_BYTES];
-        let len = self.key_pair.get_public_key(&mut key)?;
-        let key = &key[..len];
-        psm.set_kv_slice(fb_key!(index, ST_PBKEY), key)?;
+        let mut f = Fabric::new(keypair, root_ca, icac, noc, ipk, record.vendor_id)?;
+        f.label = label;
 
-        let mut key = [0_u8; crypto::BIGNUM_LEN_BYTES];
-        let len = self.key_pair.get_private_key(&mut key)?;
-        let key = &key[..len];
-        psm.set_kv_slice(fb_key!(index, ST_PRKEY), key)?;
+        if !is_wrapped && wrapper.is_some() {
+            // Stored before the key wrapper was configured, wrap it now
+            info!("Wrapping the keys of fabric {}", index);
+            f.store(index, storage, wrapper)?;
+        }
+        Ok(f)
+    }
 
-        psm.set_kv_u64(fb_key!(index, ST_VID), self.vendor_id.into())?;
+    // Remove the fabric as stored by the releases before the fabric record, where every
+    // detail was a separate key
+    fn rm_legacy_store(index: usize, storage: &mut dyn KvStore) -> Result<(), Error> {
+        storage.remove(fb_key!(index, ST_RCA))?;
+        storage.remove(fb_key!(index, ST_ICA))?;
+        storage.remove(fb_key!(index, ST_NOC))?;
+        storage.remove(fb_key!(index, ST_IPK))?;
+        storage.remove(fb_key!(index, ST_LBL))?;
+        storage.remove(fb_key!(index, ST_PBKEY))?;
+        storage.remove(fb_key!(index, ST_PRKEY))?;
+        storage.remove(fb_key!(index, ST_VID))?;
         Ok(())
     }
 
-    fn load(index: usize, psm: &MutexGuard<Psm>) -> Result<Self, Error> {
+    // Load the fabric as stored by the releases before the fabric record
+    fn load_legacy(index: usize, storage: &dyn KvStore) -> Result<Self, Error> {
         let mut root_ca = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_RCA), &mut root_ca)?;
+        storage.get(fb_key!(index, ST_RCA), &mut root_ca)?;
         let root_ca = Cert::new(root_ca.as_slice())?;
 
         let mut icac = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_ICA), &mut icac)?;
+        storage.get(fb_key!(index, ST_ICA), &mut icac)?;
         let icac = if !icac.is_empty() {
             Some(Cert::new(icac.as_slice())?)
         } else {
@@ -251,27 +433,26 @@ impl Fabric {
         };
 
         let mut noc = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_NOC), &mut noc)?;
+        storage.get(fb_key!(index, ST_NOC), &mut noc)?;
         let noc = Cert::new(noc.as_slice())?;
 
         let mut ipk = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_IPK), &mut ipk)?;
+        storage.get(fb_key!(index, ST_IPK), &mut ipk)?;
 
         let mut label = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_LBL), &mut label)?;
+        storage.get(fb_key!(index, ST_LBL), &mut label)?;
         let label = String::from_utf8(label).map_err(|_| {
             error!("Couldn't read label");
             Error::Invalid
         })?;
 
         let mut pub_key = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_PBKEY), &mut pub_key)?;
+        storage.get(fb_key!(index, ST_PBKEY), &mut pub_key)?;
         let mut priv_key = Vec::new();
-        psm.get_kv_slice(fb_key!(index, ST_PRKEY), &mut priv_key)?;
+        storage.get(fb_key!(index, ST_PRKEY), &mut priv_key)?;
         let keypair = KeyPair::new_from_components(pub_key.as_slice(), priv_key.as_slice())?;
 
-        let mut vendor_id = 0;
-        psm.get_kv_u64(fb_key!(index, ST_VID), &mut vendor_id)?;
+        let vendor_id = storage.get_u64(fb_key!(index, ST_VID))?;
 
         let f = Fabric::new(
             keypair,
@@ -288,6 +469,12 @@ impl Fabric {
     }
 }
 
+fn copy_cert(cert: &Cert) -> Result<Cert, Error> {
+    let mut buf = [0u8; MAX_CERT_TLV_LEN];
+    let len = cert.as_tlv(&mut buf)?;
+    Cert::new(&buf[..len])
+}
+
 pub const MAX_SUPPORTED_FABRICS: usize = 3;
 #[derive(Default)]
 pub struct FabricMgrInner {
@@ -298,41 +485,88 @@ pub struct FabricMgrInner {
 
 pub struct FabricMgr {
     inner: RwLock<FabricMgrInner>,
-    psm: Arc<Mutex<Psm>>,
+    storage: SharedKvStore,
+    mdns: Arc<Mdns>,
+    key_wrapper: Option<KeyWrapper>,
 }
 
 impl FabricMgr {
-    pub fn new() -> Result<Self, Error> {
+    /// Create the FabricMgr, loading the fabrics from the storage
+    ///
+    /// This fails if a stored fabric can't be loaded, like a wrapped one without the
+    /// key_wrapper that it was stored with. If a key_wrapper is given, the fabrics' IPKs and private keys are wrapped with it
+    /// before they are stored
+    pub fn new(
+        storage: SharedKvStore,
+        mdns: Arc<Mdns>,
+        key_wrapper: Option<KeyWrapper>,
+    ) -> Result<Self, Error> {
         let dummy_fabric = Fabric::dummy()?;
         let mut mgr = FabricMgrInner::default();
         mgr.fabrics[0] = Some(dummy_fabric);
         let mut fm = Self {
             inner: RwLock::new(mgr),
-            psm: Psm::get()?,
+            storage,
+            mdns,
+            key_wrapper,
         };
         fm.load()?;
         Ok(fm)
     }
 
+    /// The key wrapper that the fabric secrets are stored with, if any
+    pub(crate) fn key_wrapper(&self) -> Option<&KeyWrapper> {
+        self.key_wrapper.as_ref()
+    }
+
     fn store(&self, index: usize, fabric: &Fabric) -> Result<(), Error> {
-        let psm = self.psm.lock().unwrap();
-        fabric.store(index, &psm)
+        let mut storage = self.storage.lock().unwrap();
+        fabric.store(index, &mut *storage, self.key_wrapper.as_ref())
     }
 
+    // A fabric that is stored, but can't be loaded, fails the whole load. Carrying on
+    // without it would free its slot, and the next fabric added would overwrite it.
     fn load(&mut self) -> Result<(), Error> {
         let mut mgr = self.inner.write()?;
-        let psm = self.psm.lock().unwrap();
+        let mut storage = self.storage.lock().unwrap();
         for i in 0..MAX_SUPPORTED_FABRICS {
-            let result = Fabric::load(i, &psm);
-            if let Ok(fabric) = result {
-                info!("Adding new fabric at index {}", i);
-                mgr.fabrics[i] = Some(fabric);
+            let wrapper = self.key_wrapper.as_ref();
+            let result = match Fabric::load(i, &mut *storage, wrapper) {
+                Err(Error::NotFound) => FabricMgr::migrate_legacy(i, &mut *storage, wrapper),
+                result => result,
+            };
+            match result {
+                Ok(mut fabric) => {
+                    fabric.publish(&self.mdns)?;
+                    info!("Adding new fabric at index {}", i);
+                    mgr.fabrics[i] = Some(fabric);
+                }
+                Err(Error::NotFound) => (),
+                Err(e) => {
+                    error!("Error loading the fabric at index {}: {:?}", i, e);
+                    return Err(e);
+                }
             }
         }
         Ok(())
     }
 
-    pub fn add(&self, f: Fabric) -> Result<u8, Error> {
+    // Move a fabric from the legacy layout over to the fabric record
+    fn migrate_legacy(
+        index: usize,
+        storage: &mut dyn KvStore,
+        wrapper: Option<&KeyWrapper>,
+    ) -> Result<Fabric, Error> {
+        let fabric = Fabric::load_legacy(index, storage)?;
+        info!("Migrating fabric at index {} to the fabric record", index);
+        // The fabric record is complete before the legacy keys go, so a crash in between
+        // only leaves some stale keys behind
+        fabric.store(index, storage, wrapper)?;
+        Fabric::rm_legacy_store(index, storage)?;
+        Ok(fabric)
+    }
+
+    pub fn add(&self, mut f: Fabric) -> Result<u8, Error> {
         let mut mgr = self.inner.write()?;
         let index = mgr
             .fabrics
@@ -340,18 +574,47 @@ impl FabricMgr {
             .position(|f| f.is_none())
             .ok_or(Error::NoSpace)?;
 
+        f.publish(&self.mdns)?;
         self.store(index, &f)?;
 
         mgr.fabrics[index] = Some(f);
         Ok(index as u8)
     }
 
+    /// Replace the fabric at the index, returning the fabric that it replaces
+    ///
+    /// All the details of a fabric are stored in a single record, so the stored fabric is
+    /// either the old or the new one, never a mix of the two. If either the new fabric can't
+    /// be published or stored, the old one stays in place, and is published again.
+    pub fn replace(&self, fab_idx: u8, mut f: Fabric) -> Result<Fabric, Error> {
+        let index = fab_idx as usize;
+        if index == 0 || index >= MAX_SUPPORTED_FABRICS {
+            return Err(Error::Invalid);
+        }
+        let mut mgr = self.inner.write()?;
+        let old = mgr.fabrics[index].as_mut().ok_or(Error::NotFound)?;
+
+        // The old service goes first, so the two don't clash if the node id stays the same
+        old.mdns_service = None;
+        let result = f.publish(&self.mdns).and_then(|_| self.store(index, &f));
+        if let Err(e) = result {
+            error!("Failed to replace fabric {}: {:?}", index, e);
+            f.mdns_service = None;
+            if old.publish(&self.mdns).is_err() {
+                error!("Failed to publish fabric {} again", index);
+            }
+            return Err(e);
+        }
+
+        mgr.fabrics[index].replace(f).ok_or(Error::NotFound)
+    }
+
     pub fn remove(&self, fab_idx: u8) -> Result<(), Error> {
         let fab_idx = fab_idx as usize;
         let mut mgr = self.inner.write().unwrap();
-        let psm = self.psm.lock().unwrap();
-        if let Some(f) = &mgr.fabrics[fab_idx] {
-            f.rm_store(fab_idx, &psm);
+        let mut storage = self.storage.lock().unwrap();
+        if mgr.fabrics[fab_idx].is_some() {
+            Fabric::rm_store(fab_idx, &mut *storage)?;
             mgr.fabrics[fab_idx] = None;
             Ok(())
         } else {
@@ -359,6 +622,20 @@ impl FabricMgr {
         }
     }
 
+    /// Remove all the fabrics, along with their operational mDNS records
+    pub fn remove_all(&self) -> Result<(), Error> {
+        let mut mgr = self.inner.write().unwrap();
+        let mut storage = self.storage.lock().unwrap();
+        for i in 1..MAX_SUPPORTED_FABRICS {
+            if mgr.fabrics[i].is_some() {
+                Fabric::rm_store(i, &mut *storage)?;
+                // Dropping the fabric withdraws its mDNS record
+                mgr.fabrics[i] = None;
+            }
+        }
+        Ok(())
+    }
+
     pub fn match_dest_id(&self, random: &[u8], target: &[u8]) -> Result<usize, Error> {
         let mgr = self.inner.read()?;
         for i in 0..MAX_SUPPORTED_FABRICS {
@@ -413,6 +690,19 @@ impl FabricMgr {
         Ok(())
     }
 
+    /// Publish the operational services of all the fabrics again
+    ///
+    /// This picks up any change in what the node advertises over mDNS.
+    pub fn republish(&self) -> Result<(), Error> {
+        let mut mgr = self.inner.write()?;
+        for fabric in mgr.fabrics.iter_mut().skip(1).flatten() {
+            // The old service goes first, so the two don't clash
+            fabric.mdns_service = None;
+            fabric.publish(&self.mdns)?;
+        }
+        Ok(())
+    }
+
     pub fn set_label(&self, index: u8, label: String) -> Result<(), Error> {
         let index = index as usize;
         let mut mgr = self.inner.write()?;
@@ -428,8 +718,11 @@ impl FabricMgr {
         if let Some(fabric) = &mut mgr.fabrics[index] {
             let old = fabric.label.clone();
             fabric.label = label;
-            let psm = self.psm.lock().unwrap();
-            if fabric.store(index, &psm).is_err() {
+            let mut storage = self.storage.lock().unwrap();
+            if fabric
+                .store(index, &mut *storage, self.key_wrapper.as_ref())
+                .is_err()
+            {
                 fabric.label = old;
                 return Err(Error::StdIoError);
             }
@@ -437,3 +730,121 @@ impl FabricMgr {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        cert::{Cert, CertBuilder, CertProfile, CertTime, MatterDn},
+        crypto::{self, CryptoKeyPair, KeyPair},
+        error::Error,
+        mdns::Mdns,
+        persist::{KeyWrapper, MemKvStore, SharedKvStore, StaticKeyProvider},
+    };
+
+    use super::{copy_cert, Fabric, FabricMgr};
+
+    const FABRIC_ID: u64 = 0xABCD;
+
+    fn pubkey(key_pair: &KeyPair) -> Vec<u8> {
+        let mut pubkey = [0u8; crypto::EC_POINT_LEN_BYTES];
+        let len = key_pair.get_public_key(&mut pubkey).unwrap();
+        pubkey[..len].to_vec()
+    }
+
+    fn new_rcac(key_pair: &KeyPair) -> Cert {
+        CertBuilder::new_with(CertProfile::Rcac)
+            .add_subject(MatterDn::RootCaId(1))
+            .set_pubkey(&pubkey(key_pair))
+            .unwrap()
+            .sign(key_pair)
+            .unwrap()
+    }
+
+    fn issue_noc(rcac: &Cert, rcac_key: &KeyPair, pubkey: &[u8]) -> Cert {
+        CertBuilder::new_with(CertProfile::Noc)
+            .add_subject(MatterDn::NodeId(0x1234))
+            .add_subject(MatterDn::FabricId(FABRIC_ID))
+            .set_pubkey(pubkey)
+            .unwrap()
+            .set_issuer(rcac)
+            .unwrap()
+            .sign(rcac_key)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_with_noc() {
+        let rcac_key = KeyPair::new().unwrap();
+        let rcac = new_rcac(&rcac_key);
+        let key = KeyPair::new().unwrap();
+        let noc_value = issue_noc(&rcac, &rcac_key, &pubkey(&key));
+        let fabric = Fabric::new(
+            key,
+            copy_cert(&rcac).unwrap(),
+            None,
+            noc_value,
+            &[0; 16],
+            0xFFF1,
+        )
+        .unwrap();
+
+        let key = KeyPair::new().unwrap();
+        let noc_value = issue_noc(&rcac, &rcac_key, &pubkey(&key));
+        let updated = fabric
+            .with_noc(key, None, noc_value, CertTime::Unknown)
+            .unwrap();
+        assert_eq!(updated.get_fabric_id(), FABRIC_ID);
+
+        // A NOC for some other key than the one of the CSR
+        let key = KeyPair::new().unwrap();
+        let other_key = KeyPair::new().unwrap();
+        let noc_value = issue_noc(&rcac, &rcac_key, &pubkey(&other_key));
+        assert_eq!(
+            fabric
+                .with_noc(key, None, noc_value, CertTime::Unknown)
+                .err(),
+            Some(Error::Invalid)
+        );
+
+        // A NOC that doesn't chain up to the root of the fabric
+        let other_rcac_key = KeyPair::new().unwrap();
+        let other_rcac = new_rcac(&other_rcac_key);
+        let key = KeyPair::new().unwrap();
+        let noc_value = issue_noc(&other_rcac, &other_rcac_key, &pubkey(&key));
+        assert!(fabric
+            .with_noc(key, None, noc_value, CertTime::Unknown)
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_wrapped_without_wrapper() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let mdns = Arc::new(Mdns::new(0));
+        let wrapper = || {
+            Some(KeyWrapper::new(Box::new(StaticKeyProvider::new(
+                [0x55; 16],
+            ))))
+        };
+
+        let rcac_key = KeyPair::new().unwrap();
+        let rcac = new_rcac(&rcac_key);
+        let key = KeyPair::new().unwrap();
+        let noc_value = issue_noc(&rcac, &rcac_key, &pubkey(&key));
+        let fabric = Fabric::new(key, rcac, None, noc_value, &[0; 16], 0xFFF1).unwrap();
+        let fabric_mgr = FabricMgr::new(storage.clone(), mdns.clone(), wrapper()).unwrap();
+        let fab_idx = fabric_mgr.add(fabric).unwrap();
+        drop(fabric_mgr);
+
+        // The wrapped fabric can't be loaded, which must not free its slot
+        assert_eq!(
+            FabricMgr::new(storage.clone(), mdns.clone(), None).err(),
+            Some(Error::Invalid)
+        );
+
+        let fabric_mgr = FabricMgr::new(storage, mdns, wrapper()).unwrap();
+        let fabric = fabric_mgr.get_fabric(fab_idx as usize).unwrap();
+        assert_eq!((*fabric).as_ref().unwrap().get_fabric_id(), FABRIC_ID);
+    }
+}
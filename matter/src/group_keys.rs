@@ -15,31 +15,745 @@
  *    limitations under the License.
  */
 
-use std::sync::{Arc, Mutex, Once};
+use std::sync::{Arc, RwLock};
 
-use crate::{crypto, error::Error};
+use log::{error, info, warn};
+use num_derive::FromPrimitive;
 
-// This is just makeshift implementation for now, not used anywhere
-pub struct GroupKeys {}
+use crate::{
+    crypto,
+    error::Error,
+    fabric::{FabricMgr, MAX_SUPPORTED_FABRICS},
+    persist::{self, KeyWrapper, KvStore, SharedKvStore},
+    tlv::{FromTLV, OctetStr, TLVArrayOwned, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
+    utils::writebuf::WriteBuf,
+};
 
-static mut G_GRP_KEYS: Option<Arc<Mutex<GroupKeys>>> = None;
-static INIT: Once = Once::new();
+pub const MAX_GROUPS_PER_FABRIC: usize = 4;
+// This includes the IPK, which is always key set 0
+pub const MAX_GROUP_KEYS_PER_FABRIC: usize = 3;
+pub const MAX_EPOCH_KEYS: usize = 3;
+pub const IPK_KEY_SET_ID: u16 = 0;
+
+const GROUP_KEYS_RECORD_VERSION: u8 = 1;
+const MAX_GROUP_KEYS_RECORD_LEN: usize = 1024;
+// Room for the wrapping and the outer record
+const MAX_WRAPPED_RECORD_LEN: usize = MAX_GROUP_KEYS_RECORD_LEN + 64;
+
+fn group_keys_key(fab_idx: u8) -> String {
+    format!("gk{}", fab_idx)
+}
+
+/// An operational group key, along with the group session id derived from it
+#[derive(Debug, Clone)]
+pub struct GroupKey {
+    pub fab_idx: u8,
+    pub key_set_id: u16,
+    pub session_id: u16,
+    /// How the message counters of the peers using this key get trusted
+    pub policy: KeySetPolicy,
+    op_key: [u8; crypto::SYMM_KEY_LEN_BYTES],
+}
+
+impl GroupKey {
+    pub fn new(
+        fab_idx: u8,
+        key_set_id: u16,
+        epoch_key: &[u8],
+        compressed_id: &[u8],
+    ) -> Result<Self, Error> {
+        let ks = KeySet::new(epoch_key, compressed_id)?;
+        let session_id = GroupKey::session_id_from_op_key(ks.op_key())?;
+        Ok(Self {
+            fab_idx,
+            key_set_id,
+            session_id,
+            policy: KeySetPolicy::TrustFirst,
+            op_key: ks.op_key,
+        })
+    }
+
+    fn session_id_from_op_key(op_key: &[u8]) -> Result<u16, Error> {
+        const GRP_KEY_HASH_INFO: [u8; 12] = [
+            0x47, 0x72, 0x6f, 0x75, 0x70, 0x4b, 0x65, 0x79, 0x48, 0x61, 0x73, 0x68,
+        ];
+
+        let mut hash = [0u8; 2];
+        crypto::hkdf_sha256(&[], op_key, &GRP_KEY_HASH_INFO, &mut hash)
+            .map_err(|_| Error::NoSpace)?;
+        Ok(u16::from_be_bytes(hash))
+    }
+
+    pub fn op_key(&self) -> &[u8] {
+        &self.op_key
+    }
+}
+
+/// The key and addressing needed to send a message to a group
+pub struct GroupTx {
+    pub key: GroupKey,
+    pub fabric_id: u64,
+    pub node_id: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum KeySetPolicy {
+    TrustFirst = 0,
+    CacheAndSync = 1,
+}
+
+/// One of the epoch keys of a key set
+#[derive(Debug, Clone, PartialEq, Eq, ToTLV, FromTLV)]
+pub struct EpochKey {
+    pub key: Vec<u8>,
+    /// The time from which this key is used for transmission, in microseconds since the
+    /// Matter epoch
+    pub start_time: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupKeySet {
+    pub key_set_id: u16,
+    pub policy: KeySetPolicy,
+    pub epoch_keys: Vec<EpochKey>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToTLV, FromTLV)]
+pub struct GroupKeyMapEntry {
+    pub group_id: u16,
+    pub key_set_id: u16,
+}
+
+/// A group that some of the endpoints of this node are members of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub group_id: u16,
+    pub name: String,
+    pub endpoints: Vec<u16>,
+}
+
+#[derive(ToTLV, FromTLV)]
+struct KeySetRecord {
+    key_set_id: u16,
+    policy: u8,
+    epoch_keys: TLVArrayOwned<EpochKey>,
+}
+
+#[derive(ToTLV, FromTLV)]
+struct GroupRecord {
+    group_id: u16,
+    name: String,
+    endpoints: TLVArrayOwned<u16>,
+}
+
+#[derive(ToTLV, FromTLV)]
+struct FabricGroupsRecord {
+    key_sets: TLVArrayOwned<KeySetRecord>,
+    key_map: TLVArrayOwned<GroupKeyMapEntry>,
+    groups: TLVArrayOwned<GroupRecord>,
+}
+
+// The FabricGroupsRecord, wrapped as a whole if there is a key wrapper
+#[derive(ToTLV, FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct WrappedRecord<'a> {
+    wrapped: bool,
+    data: OctetStr<'a>,
+}
+
+#[derive(Clone)]
+struct OpKey {
+    key: GroupKey,
+    start_time: u64,
+}
+
+#[derive(Default, Clone)]
+struct FabricGroups {
+    key_sets: Vec<GroupKeySet>,
+    key_map: Vec<GroupKeyMapEntry>,
+    groups: Vec<GroupInfo>,
+    // The operational keys derived from the epoch keys of all the key sets
+    op_keys: Vec<OpKey>,
+}
+
+impl FabricGroups {
+    fn is_empty(&self) -> bool {
+        self.key_sets.is_empty() && self.key_map.is_empty() && self.groups.is_empty()
+    }
+
+    fn derive_op_keys(&mut self, fab_idx: u8, compressed_id: &[u8]) -> Result<(), Error> {
+        let mut op_keys = Vec::new();
+        for ks in &self.key_sets {
+            for ek in &ks.epoch_keys {
+                let mut key = GroupKey::new(fab_idx, ks.key_set_id, &ek.key, compressed_id)?;
+                key.policy = ks.policy;
+                op_keys.push(OpKey {
+                    key,
+                    start_time: ek.start_time,
+                });
+            }
+        }
+        self.op_keys = op_keys;
+        Ok(())
+    }
+
+    // The key with the latest start time that has already passed. If none has started
+    // yet, the one that will start first.
+    fn tx_key(&self, key_set_id: u16, now: u64) -> Option<&GroupKey> {
+        let keys = self
+            .op_keys
+            .iter()
+            .filter(|k| k.key.key_set_id == key_set_id);
+        keys.clone()
+            .filter(|k| k.start_time <= now)
+            .max_by_key(|k| k.start_time)
+            .or_else(|| keys.min_by_key(|k| k.start_time))
+            .map(|k| &k.key)
+    }
+
+    fn is_member(&self, group_id: u16) -> bool {
+        self.groups.iter().any(|g| g.group_id == group_id)
+    }
+
+    fn store(
+        &self,
+        fab_idx: u8,
+        storage: &mut dyn KvStore,
+        wrapper: Option<&KeyWrapper>,
+    ) -> Result<(), Error> {
+        let key = group_keys_key(fab_idx);
+        if self.is_empty() {
+            return storage.remove(&key);
+        }
+
+        let record = FabricGroupsRecord {
+            key_sets: self
+                .key_sets
+                .iter()
+                .map(|ks| KeySetRecord {
+                    key_set_id: ks.key_set_id,
+                    policy: ks.policy as u8,
+                    epoch_keys: ks.epoch_keys.clone().into(),
+                })
+                .collect::<Vec<_>>()
+                .into(),
+            key_map: self.key_map.clone().into(),
+            groups: self
+                .groups
+                .iter()
+                .map(|g| GroupRecord {
+                    group_id: g.group_id,
+                    name: g.name.clone(),
+                    endpoints: g.endpoints.clone().into(),
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        };
+        let mut buf = [0u8; MAX_GROUP_KEYS_RECORD_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MAX_GROUP_KEYS_RECORD_LEN);
+        let mut tw = TLVWriter::new(&mut wb);
+        record.to_tlv(&mut tw, TagType::Anonymous)?;
+
+        let wrapped_data;
+        let data = if let Some(wrapper) = wrapper {
+            wrapped_data = wrapper.wrap(key.as_bytes(), wb.as_slice())?;
+            wrapped_data.as_slice()
+        } else {
+            wb.as_slice()
+        };
+        let record = WrappedRecord {
+            wrapped: wrapper.is_some(),
+            data: OctetStr(data),
+        };
+        let mut buf = [0u8; MAX_WRAPPED_RECORD_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MAX_WRAPPED_RECORD_LEN);
+        let mut tw = TLVWriter::new(&mut wb);
+        record.to_tlv(&mut tw, TagType::Anonymous)?;
+
+        persist::set_record(storage, &key, GROUP_KEYS_RECORD_VERSION, wb.as_slice())
+    }
+
+    // Returns the groups, and whether they were stored without wrapping
+    fn load(
+        fab_idx: u8,
+        storage: &dyn KvStore,
+        wrapper: Option<&KeyWrapper>,
+    ) -> Result<(Self, bool), Error> {
+        let key = group_keys_key(fab_idx);
+        let data = persist::get_record(
+            storage,
+            &key,
+            GROUP_KEYS_RECORD_VERSION,
+            persist::no_migration,
+        )?;
+        let root = TLVList::new(&data).iter().next().ok_or(Error::Invalid)?;
+        let record = WrappedRecord::from_tlv(&root)?;
+        let wrapped = record.wrapped;
+
+        let unwrapped;
+        let data = if wrapped {
+            let wrapper = wrapper.ok_or_else(|| {
+                error!(
+                    "Groups of fabric {} are wrapped, but there is no key wrapper",
+                    fab_idx
+                );
+                Error::Invalid
+            })?;
+            unwrapped = wrapper.unwrap(key.as_bytes(), record.data.0)?;
+            unwrapped.as_slice()
+        } else {
+            record.data.0
+        };
+        let root = TLVList::new(data).iter().next().ok_or(Error::Invalid)?;
+        let record = FabricGroupsRecord::from_tlv(&root)?;
+
+        let mut key_sets = Vec::new();
+        for ks in record.key_sets.iter() {
+            key_sets.push(GroupKeySet {
+                key_set_id: ks.key_set_id,
+                policy: num::FromPrimitive::from_u8(ks.policy).ok_or(Error::Invalid)?,
+                epoch_keys: ks.epoch_keys.iter().cloned().collect(),
+            });
+        }
+        let groups = record
+            .groups
+            .iter()
+            .map(|g| GroupInfo {
+                group_id: g.group_id,
+                name: g.name.clone(),
+                endpoints: g.endpoints.iter().cloned().collect(),
+            })
+            .collect();
+
+        let fabric_groups = Self {
+            key_sets,
+            key_map: record.key_map.into(),
+            groups,
+            op_keys: Vec::new(),
+        };
+        Ok((fabric_groups, !wrapped))
+    }
+}
+
+#[derive(Default)]
+struct GroupKeysInner {
+    fabrics: [FabricGroups; MAX_SUPPORTED_FABRICS],
+    generation: u32,
+}
+
+/// The group key sets, the group key map and the group table of all the fabrics
+///
+/// Each group of a fabric is mapped to one of the fabric's key sets. The transport uses
+/// this to decrypt the incoming group messages, to encrypt the outgoing ones and to know
+/// which multicast addresses it should listen on. Everything is persisted per fabric.
+pub struct GroupKeys {
+    fabric_mgr: Arc<FabricMgr>,
+    storage: SharedKvStore,
+    inner: RwLock<GroupKeysInner>,
+}
 
 impl GroupKeys {
-    fn new() -> Self {
-        Self {}
+    /// Create the GroupKeys, loading the groups of the fabrics from the storage
+    pub fn new(fabric_mgr: Arc<FabricMgr>, storage: SharedKvStore) -> Result<Self, Error> {
+        let gk = Self {
+            fabric_mgr,
+            storage,
+            inner: RwLock::new(Default::default()),
+        };
+        gk.load()?;
+        Ok(gk)
     }
 
-    pub fn get() -> Result<Arc<Mutex<Self>>, Error> {
-        unsafe {
-            INIT.call_once(|| {
-                G_GRP_KEYS = Some(Arc::new(Mutex::new(GroupKeys::new())));
+    fn load(&self) -> Result<(), Error> {
+        let mut inner = self.inner.write()?;
+        let mut storage = self.storage.lock().unwrap();
+        let wrapper = self.fabric_mgr.key_wrapper();
+        for (i, fabric_groups) in inner.fabrics.iter_mut().enumerate() {
+            let fab_idx = i as u8;
+            let (mut groups, unwrapped) = match FabricGroups::load(fab_idx, &*storage, wrapper) {
+                Ok(result) => result,
+                Err(Error::NotFound) => continue,
+                Err(e) => {
+                    error!("Couldn't load the groups of fabric {}: {:?}", fab_idx, e);
+                    continue;
+                }
+            };
+            let compressed_id = match self.compressed_id(fab_idx) {
+                Ok(compressed_id) => compressed_id,
+                Err(_) => {
+                    warn!("Dropping the groups of the missing fabric {}", fab_idx);
+                    storage.remove(&group_keys_key(fab_idx))?;
+                    continue;
+                }
+            };
+            groups.derive_op_keys(fab_idx, &compressed_id)?;
+            if unwrapped && wrapper.is_some() {
+                info!("Wrapping the groups of fabric {}", fab_idx);
+                groups.store(fab_idx, &mut *storage, wrapper)?;
+            }
+            *fabric_groups = groups;
+        }
+        inner.generation = inner.generation.wrapping_add(1);
+        Ok(())
+    }
+
+    fn compressed_id(&self, fab_idx: u8) -> Result<Vec<u8>, Error> {
+        if fab_idx as usize >= MAX_SUPPORTED_FABRICS {
+            return Err(Error::NotFound);
+        }
+        let fabric = self.fabric_mgr.get_fabric(fab_idx as usize)?;
+        let fabric = (*fabric).as_ref().ok_or(Error::NotFound)?;
+        Ok(fabric.get_compressed_fabric_id().to_vec())
+    }
+
+    // Apply a change to the groups of a fabric. The change is only kept if it could be
+    // stored.
+    fn update<T, F>(&self, fab_idx: u8, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut FabricGroups) -> Result<T, Error>,
+    {
+        let mut inner = self.inner.write()?;
+        let fabric_groups = inner
+            .fabrics
+            .get_mut(fab_idx as usize)
+            .ok_or(Error::NotFound)?;
+        let mut updated = fabric_groups.clone();
+        let result = f(&mut updated)?;
+        {
+            let mut storage = self.storage.lock().unwrap();
+            updated.store(fab_idx, &mut *storage, self.fabric_mgr.key_wrapper())?;
+        }
+        let groups_changed = fabric_groups.groups != updated.groups;
+        *fabric_groups = updated;
+        if groups_changed {
+            inner.generation = inner.generation.wrapping_add(1);
+        }
+        Ok(result)
+    }
+
+    fn read<T, F>(&self, fab_idx: u8, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&FabricGroups) -> T,
+    {
+        let inner = self.inner.read()?;
+        let fabric_groups = inner.fabrics.get(fab_idx as usize).ok_or(Error::NotFound)?;
+        Ok(f(fabric_groups))
+    }
+
+    /// Install (or replace) a key set of a fabric
+    ///
+    /// The IPK can't be changed through this, it is always key set 0
+    pub fn set_key_set(&self, fab_idx: u8, key_set: GroupKeySet) -> Result<(), Error> {
+        if key_set.key_set_id == IPK_KEY_SET_ID
+            || key_set.epoch_keys.is_empty()
+            || key_set.epoch_keys.len() > MAX_EPOCH_KEYS
+        {
+            return Err(Error::Invalid);
+        }
+        if key_set
+            .epoch_keys
+            .iter()
+            .any(|k| k.key.len() != crypto::SYMM_KEY_LEN_BYTES)
+        {
+            return Err(Error::InvalidKeyLength);
+        }
+        let compressed_id = self.compressed_id(fab_idx)?;
+
+        self.update(fab_idx, |groups| {
+            if let Some(ks) = groups
+                .key_sets
+                .iter_mut()
+                .find(|ks| ks.key_set_id == key_set.key_set_id)
+            {
+                *ks = key_set;
+            } else if groups.key_sets.len() < MAX_GROUP_KEYS_PER_FABRIC - 1 {
+                groups.key_sets.push(key_set);
+            } else {
+                return Err(Error::NoSpace);
+            }
+            groups.derive_op_keys(fab_idx, &compressed_id)
+        })
+    }
+
+    /// Returns a key set of a fabric, key set 0 being the fabric's IPK
+    pub fn get_key_set(&self, fab_idx: u8, key_set_id: u16) -> Result<GroupKeySet, Error> {
+        if key_set_id == IPK_KEY_SET_ID {
+            if fab_idx as usize >= MAX_SUPPORTED_FABRICS {
+                return Err(Error::NotFound);
+            }
+            let fabric = self.fabric_mgr.get_fabric(fab_idx as usize)?;
+            let fabric = (*fabric).as_ref().ok_or(Error::NotFound)?;
+            return Ok(GroupKeySet {
+                key_set_id,
+                policy: KeySetPolicy::TrustFirst,
+                epoch_keys: vec![EpochKey {
+                    key: fabric.ipk.epoch_key().to_vec(),
+                    start_time: 0,
+                }],
             });
-            Ok(G_GRP_KEYS.as_ref().ok_or(Error::Invalid)?.clone())
         }
+        self.read(fab_idx, |groups| {
+            groups
+                .key_sets
+                .iter()
+                .find(|ks| ks.key_set_id == key_set_id)
+                .cloned()
+        })?
+        .ok_or(Error::NotFound)
+    }
+
+    /// Returns the ids of all the key sets of a fabric, including the IPK's
+    pub fn key_set_ids(&self, fab_idx: u8) -> Result<Vec<u16>, Error> {
+        self.read(fab_idx, |groups| {
+            let mut ids = vec![IPK_KEY_SET_ID];
+            ids.extend(groups.key_sets.iter().map(|ks| ks.key_set_id));
+            ids
+        })
+    }
+
+    /// Remove a key set of a fabric, along with the groups' mappings to it
+    pub fn remove_key_set(&self, fab_idx: u8, key_set_id: u16) -> Result<(), Error> {
+        if key_set_id == IPK_KEY_SET_ID {
+            return Err(Error::Invalid);
+        }
+        self.update(fab_idx, |groups| {
+            let index = groups
+                .key_sets
+                .iter()
+                .position(|ks| ks.key_set_id == key_set_id)
+                .ok_or(Error::NotFound)?;
+            groups.key_sets.remove(index);
+            groups.op_keys.retain(|k| k.key.key_set_id != key_set_id);
+            groups.key_map.retain(|e| e.key_set_id != key_set_id);
+            Ok(())
+        })
+    }
+
+    pub fn key_map(&self, fab_idx: u8) -> Result<Vec<GroupKeyMapEntry>, Error> {
+        self.read(fab_idx, |groups| groups.key_map.clone())
+    }
+
+    /// Replace the whole group key map of a fabric
+    pub fn set_key_map(&self, fab_idx: u8, key_map: Vec<GroupKeyMapEntry>) -> Result<(), Error> {
+        if key_map.len() > MAX_GROUPS_PER_FABRIC {
+            return Err(Error::NoSpace);
+        }
+        self.update(fab_idx, |groups| {
+            groups.key_map = key_map;
+            Ok(())
+        })
     }
 
-    pub fn insert_key() -> Result<(), Error> {
+    /// Parameters to T are the Fabric Index and each entry of its group key map
+    pub fn for_each_key_map<T>(&self, mut f: T) -> Result<(), Error>
+    where
+        T: FnMut(u8, &GroupKeyMapEntry),
+    {
+        let inner = self.inner.read()?;
+        for (i, groups) in inner.fabrics.iter().enumerate() {
+            for e in &groups.key_map {
+                f(i as u8, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an endpoint to a group of a fabric, adding the group if required
+    ///
+    /// The group's name is updated to the given one
+    pub fn add_group_endpoint(
+        &self,
+        fab_idx: u8,
+        group_id: u16,
+        name: &str,
+        endpoint: u16,
+    ) -> Result<(), Error> {
+        self.update(fab_idx, |groups| {
+            if let Some(group) = groups.groups.iter_mut().find(|g| g.group_id == group_id) {
+                group.name = name.to_owned();
+                if !group.endpoints.contains(&endpoint) {
+                    group.endpoints.push(endpoint);
+                }
+            } else if groups.groups.len() < MAX_GROUPS_PER_FABRIC {
+                groups.groups.push(GroupInfo {
+                    group_id,
+                    name: name.to_owned(),
+                    endpoints: vec![endpoint],
+                });
+                info!("Added group {:x} of fabric {}", group_id, fab_idx);
+            } else {
+                return Err(Error::NoSpace);
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove an endpoint from a group of a fabric
+    ///
+    /// The group goes once it has no endpoints left
+    pub fn remove_group_endpoint(
+        &self,
+        fab_idx: u8,
+        group_id: u16,
+        endpoint: u16,
+    ) -> Result<(), Error> {
+        self.update(fab_idx, |groups| {
+            let index = groups
+                .groups
+                .iter()
+                .position(|g| g.group_id == group_id && g.endpoints.contains(&endpoint))
+                .ok_or(Error::NotFound)?;
+            let group = &mut groups.groups[index];
+            group.endpoints.retain(|e| *e != endpoint);
+            if group.endpoints.is_empty() {
+                groups.groups.remove(index);
+                info!("Removed group {:x} of fabric {}", group_id, fab_idx);
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove an endpoint from all the groups of a fabric
+    pub fn remove_endpoint_groups(&self, fab_idx: u8, endpoint: u16) -> Result<(), Error> {
+        self.update(fab_idx, |groups| {
+            for g in groups.groups.iter_mut() {
+                g.endpoints.retain(|e| *e != endpoint);
+            }
+            groups.groups.retain(|g| !g.endpoints.is_empty());
+            Ok(())
+        })
+    }
+
+    pub fn groups(&self, fab_idx: u8) -> Result<Vec<GroupInfo>, Error> {
+        self.read(fab_idx, |groups| groups.groups.clone())
+    }
+
+    pub fn is_group_member(&self, fab_idx: u8, group_id: u16, endpoint: u16) -> bool {
+        self.read(fab_idx, |groups| {
+            groups
+                .groups
+                .iter()
+                .any(|g| g.group_id == group_id && g.endpoints.contains(&endpoint))
+        })
+        .unwrap_or(false)
+    }
+
+    /// Parameters to T are the Fabric Index and the details of each group
+    pub fn for_each_group_info<T>(&self, mut f: T) -> Result<(), Error>
+    where
+        T: FnMut(u8, &GroupInfo),
+    {
+        let inner = self.inner.read()?;
+        for (i, groups) in inner.fabrics.iter().enumerate() {
+            for g in &groups.groups {
+                f(i as u8, g);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all the key sets and the groups of a fabric
+    pub fn remove_fabric(&self, fab_idx: u8) -> Result<(), Error> {
+        self.update(fab_idx, |groups| {
+            *groups = Default::default();
+            Ok(())
+        })
+    }
+
+    pub fn remove_all(&self) -> Result<(), Error> {
+        for i in 0..MAX_SUPPORTED_FABRICS {
+            self.remove_fabric(i as u8)?;
+        }
+        Ok(())
+    }
+
+    /// A number that changes whenever the set of groups changes
+    pub fn generation(&self) -> u32 {
+        self.inner.read().unwrap().generation
+    }
+
+    /// Returns all the keys that may have been used to encrypt a message to this group
+    /// with this group session id
+    ///
+    /// Only the groups that this node is a member of are considered
+    pub fn rx_keys(&self, session_id: u16, group_id: u16) -> Vec<GroupKey> {
+        let inner = self.inner.read().unwrap();
+        let mut keys = Vec::new();
+        for groups in inner.fabrics.iter().filter(|g| g.is_member(group_id)) {
+            for entry in groups.key_map.iter().filter(|e| e.group_id == group_id) {
+                keys.extend(
+                    groups
+                        .op_keys
+                        .iter()
+                        .filter(|k| {
+                            k.key.key_set_id == entry.key_set_id && k.key.session_id == session_id
+                        })
+                        .map(|k| k.key.clone()),
+                );
+            }
+        }
+        keys
+    }
+
+    /// The keys a group unicast message with this group session id may be encrypted with
+    ///
+    /// Unlike with the messages to a group, these can use the keys of any group of the
+    /// fabrics, whether this node is a member of it or not.
+    pub fn unicast_rx_keys(&self, session_id: u16) -> Vec<GroupKey> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .fabrics
+            .iter()
+            .flat_map(|groups| groups.op_keys.iter())
+            .filter(|k| k.key.session_id == session_id)
+            .map(|k| k.key.clone())
+            .collect()
+    }
+
+    /// The node id of this node in a fabric
+    pub fn node_id(&self, fab_idx: u8) -> Result<u64, Error> {
+        let fabric = self.fabric_mgr.get_fabric(fab_idx as usize)?;
+        let fabric = (*fabric).as_ref().ok_or(Error::NotFound)?;
+        Ok(fabric.get_node_id())
+    }
+
+    /// Returns the key and the addressing for sending a message to a group of a fabric
+    ///
+    /// Of the epoch keys of the group's key set, the one with the latest start time that
+    /// has passed is used. The time is in microseconds since the Matter epoch.
+    pub fn tx_info(&self, fab_idx: u8, group_id: u16, now_us: u64) -> Result<GroupTx, Error> {
+        let key = self.read(fab_idx, |groups| {
+            let entry = groups.key_map.iter().find(|e| e.group_id == group_id)?;
+            groups.tx_key(entry.key_set_id, now_us).cloned()
+        })?;
+        let key = key.ok_or(Error::NotFound)?;
+        let fabric = self.fabric_mgr.get_fabric(fab_idx as usize)?;
+        let fabric = (*fabric).as_ref().ok_or(Error::NotFound)?;
+        Ok(GroupTx {
+            key,
+            fabric_id: fabric.get_fabric_id(),
+            node_id: fabric.get_node_id(),
+        })
+    }
+
+    /// Parameters to T are the Fabric ID and the Group ID of each group that this node
+    /// is a member of
+    pub fn for_each_group<T>(&self, mut f: T) -> Result<(), Error>
+    where
+        T: FnMut(u64, u16),
+    {
+        let inner = self.inner.read()?;
+        for (i, groups) in inner.fabrics.iter().enumerate() {
+            if groups.groups.is_empty() {
+                continue;
+            }
+            let fabric = self.fabric_mgr.get_fabric(i)?;
+            if let Some(fabric) = (*fabric).as_ref() {
+                for g in &groups.groups {
+                    f(fabric.get_fabric_id(), g.group_id);
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -74,3 +788,273 @@ impl KeySet {
         &self.epoch_key
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        error::Error,
+        fabric::FabricMgr,
+        mdns::Mdns,
+        persist::{KeyWrapper, MemKvStore, SharedKvStore, StaticKeyProvider},
+    };
+
+    use super::{
+        group_keys_key, EpochKey, GroupKey, GroupKeyMapEntry, GroupKeySet, GroupKeys, KeySetPolicy,
+        IPK_KEY_SET_ID,
+    };
+
+    const EPOCH_KEY: [u8; 16] = [
+        0x23, 0x5b, 0xf7, 0xe6, 0x28, 0x23, 0xd3, 0x58, 0xdc, 0xa4, 0xba, 0x50, 0xb1, 0x53, 0x5f,
+        0x4b,
+    ];
+    const OTHER_EPOCH_KEY: [u8; 16] = [0x11; 16];
+
+    fn new_fabric_mgr(storage: SharedKvStore, key_wrapper: Option<KeyWrapper>) -> Arc<FabricMgr> {
+        let mdns = Arc::new(Mdns::new(0));
+        // Only the placeholder fabric at index 0 exists
+        Arc::new(FabricMgr::new(storage, mdns, key_wrapper).unwrap())
+    }
+
+    fn new_group_keys() -> GroupKeys {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        GroupKeys::new(new_fabric_mgr(storage.clone(), None), storage).unwrap()
+    }
+
+    fn key_set(key_set_id: u16, epoch_keys: &[(&[u8], u64)]) -> GroupKeySet {
+        GroupKeySet {
+            key_set_id,
+            policy: KeySetPolicy::CacheAndSync,
+            epoch_keys: epoch_keys
+                .iter()
+                .map(|(key, start_time)| EpochKey {
+                    key: key.to_vec(),
+                    start_time: *start_time,
+                })
+                .collect(),
+        }
+    }
+
+    fn map(group_id: u16, key_set_id: u16) -> GroupKeyMapEntry {
+        GroupKeyMapEntry {
+            group_id,
+            key_set_id,
+        }
+    }
+
+    #[test]
+    fn test_group_key_derivation() {
+        // The test vector from the Matter Specification
+        let compressed_id = [0x87, 0xe1, 0xb0, 0x04, 0xe2, 0x35, 0xa1, 0x30];
+        let key = GroupKey::new(1, 0x42, &EPOCH_KEY, &compressed_id).unwrap();
+        assert_eq!(
+            key.op_key(),
+            [
+                0xa6, 0xf5, 0x30, 0x6b, 0xaf, 0x6d, 0x05, 0x0a, 0xf2, 0x3b, 0xa4, 0xbd, 0x6b, 0x9d,
+                0xd9, 0x60
+            ]
+        );
+        assert_eq!(key.session_id, 0xb9f7);
+    }
+
+    #[test]
+    fn test_rx_and_tx_keys() {
+        let gk = new_group_keys();
+        gk.set_key_set(
+            0,
+            key_set(1, &[(&EPOCH_KEY, 0), (&OTHER_EPOCH_KEY, u64::MAX)]),
+        )
+        .unwrap();
+        let current = GroupKey::new(0, 1, &EPOCH_KEY, &[0; 8]).unwrap();
+        let next = GroupKey::new(0, 1, &OTHER_EPOCH_KEY, &[0; 8]).unwrap();
+
+        // Nothing until a group is mapped to the key set
+        assert!(gk.rx_keys(current.session_id, 0x101).is_empty());
+        assert_eq!(gk.tx_info(0, 0x101, 1).err(), Some(Error::NotFound));
+
+        gk.set_key_map(0, vec![map(0x101, 1)]).unwrap();
+        // Nor until this node is a member of the group
+        assert!(gk.rx_keys(current.session_id, 0x101).is_empty());
+        let generation = gk.generation();
+        gk.add_group_endpoint(0, 0x101, "Kitchen", 1).unwrap();
+        assert_ne!(gk.generation(), generation);
+
+        // All the epoch keys are accepted, the one that has started is used for sending
+        let rx = gk.rx_keys(next.session_id, 0x101);
+        assert_eq!(rx.len(), 1);
+        assert_eq!(rx[0].op_key(), next.op_key());
+        assert!(gk.rx_keys(current.session_id, 0x102).is_empty());
+        let tx = gk.tx_info(0, 0x101, 1).unwrap();
+        assert_eq!(tx.key.op_key(), current.op_key());
+        assert_eq!(tx.key.session_id, current.session_id);
+
+        let mut groups = Vec::new();
+        gk.for_each_group(|fabric_id, group_id| groups.push((fabric_id, group_id)))
+            .unwrap();
+        assert_eq!(groups, [(0, 0x101)]);
+
+        gk.remove_key_set(0, 1).unwrap();
+        assert!(gk.rx_keys(current.session_id, 0x101).is_empty());
+        assert_eq!(gk.remove_key_set(0, 1), Err(Error::NotFound));
+
+        gk.remove_fabric(0).unwrap();
+        gk.for_each_group(|_, _| panic!("No groups expected"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_epoch_key_rotation() {
+        let gk = new_group_keys();
+        gk.set_key_map(0, vec![map(0x101, 1)]).unwrap();
+        let first = GroupKey::new(0, 1, &EPOCH_KEY, &[0; 8]).unwrap();
+        let second = GroupKey::new(0, 1, &OTHER_EPOCH_KEY, &[0; 8]).unwrap();
+        let now = 1_000_000_000;
+
+        // The latest key that has started wins
+        gk.set_key_set(0, key_set(1, &[(&EPOCH_KEY, 1), (&OTHER_EPOCH_KEY, now)]))
+            .unwrap();
+        let tx = gk.tx_info(0, 0x101, now).unwrap();
+        assert_eq!(tx.key.op_key(), second.op_key());
+
+        // If no key has started yet, the one that starts first
+        gk.set_key_set(
+            0,
+            key_set(
+                1,
+                &[
+                    (&EPOCH_KEY, now + 2_000_000),
+                    (&OTHER_EPOCH_KEY, now + 1_000_000),
+                ],
+            ),
+        )
+        .unwrap();
+        let tx = gk.tx_info(0, 0x101, now).unwrap();
+        assert_eq!(tx.key.op_key(), second.op_key());
+
+        gk.set_key_set(
+            0,
+            key_set(1, &[(&EPOCH_KEY, now), (&OTHER_EPOCH_KEY, u64::MAX)]),
+        )
+        .unwrap();
+        let tx = gk.tx_info(0, 0x101, now).unwrap();
+        assert_eq!(tx.key.op_key(), first.op_key());
+    }
+
+    #[test]
+    fn test_key_sets() {
+        let gk = new_group_keys();
+        assert_eq!(gk.key_set_ids(0).unwrap(), [IPK_KEY_SET_ID]);
+        let ipk = gk.get_key_set(0, IPK_KEY_SET_ID).unwrap();
+        assert_eq!(ipk.epoch_keys.len(), 1);
+
+        gk.set_key_set(0, key_set(1, &[(&EPOCH_KEY, 0)])).unwrap();
+        gk.set_key_set(0, key_set(2, &[(&EPOCH_KEY, 0)])).unwrap();
+        // The IPK takes up one of the slots
+        assert_eq!(
+            gk.set_key_set(0, key_set(3, &[(&EPOCH_KEY, 0)])),
+            Err(Error::NoSpace)
+        );
+        // Replacing is fine
+        gk.set_key_set(0, key_set(2, &[(&OTHER_EPOCH_KEY, 5)]))
+            .unwrap();
+        assert_eq!(
+            gk.get_key_set(0, 2).unwrap(),
+            key_set(2, &[(&OTHER_EPOCH_KEY, 5)])
+        );
+        assert_eq!(gk.key_set_ids(0).unwrap(), [0, 1, 2]);
+        assert_eq!(gk.get_key_set(0, 3), Err(Error::NotFound));
+
+        assert_eq!(
+            gk.set_key_set(0, key_set(IPK_KEY_SET_ID, &[(&EPOCH_KEY, 0)])),
+            Err(Error::Invalid)
+        );
+        assert_eq!(gk.set_key_set(0, key_set(1, &[])), Err(Error::Invalid));
+        assert_eq!(
+            gk.set_key_set(0, key_set(1, &[(&EPOCH_KEY[..8], 0)])),
+            Err(Error::InvalidKeyLength)
+        );
+        assert_eq!(gk.remove_key_set(0, IPK_KEY_SET_ID), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn test_group_table() {
+        let gk = new_group_keys();
+        for group_id in 1..=4 {
+            gk.add_group_endpoint(0, group_id, "", 1).unwrap();
+        }
+        assert_eq!(gk.add_group_endpoint(0, 5, "", 1), Err(Error::NoSpace));
+        gk.add_group_endpoint(0, 1, "Hall", 2).unwrap();
+
+        let mut groups = Vec::new();
+        gk.for_each_group_info(|fab_idx, g| groups.push((fab_idx, g.clone())))
+            .unwrap();
+        assert_eq!(groups.len(), 4);
+        assert_eq!(groups[0].0, 0);
+        assert_eq!(groups[0].1.name, "Hall");
+        assert_eq!(groups[0].1.endpoints, [1, 2]);
+
+        gk.remove_group_endpoint(0, 1, 1).unwrap();
+        assert_eq!(gk.remove_group_endpoint(0, 1, 1), Err(Error::NotFound));
+        gk.remove_group_endpoint(0, 1, 2).unwrap();
+        let mut count = 0;
+        gk.for_each_group_info(|_, _| count += 1).unwrap();
+        assert_eq!(count, 3);
+
+        gk.add_group_endpoint(0, 2, "", 2).unwrap();
+        assert!(gk.is_group_member(0, 2, 2));
+        assert!(!gk.is_group_member(1, 2, 2));
+        gk.remove_endpoint_groups(0, 1).unwrap();
+        assert!(!gk.is_group_member(0, 3, 1));
+        let groups = gk.groups(0).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].endpoints, [2]);
+    }
+
+    #[test]
+    fn test_persistence() {
+        for wrapped in [false, true] {
+            let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+            let wrapper =
+                || wrapped.then(|| KeyWrapper::new(Box::new(StaticKeyProvider::new([0x55; 16]))));
+            let fabric_mgr = new_fabric_mgr(storage.clone(), wrapper());
+            let gk = GroupKeys::new(fabric_mgr.clone(), storage.clone()).unwrap();
+            gk.set_key_set(0, key_set(1, &[(&EPOCH_KEY, 7)])).unwrap();
+            gk.set_key_map(0, vec![map(0x101, 1)]).unwrap();
+            gk.add_group_endpoint(0, 0x101, "Kitchen", 1).unwrap();
+
+            let mut stored = Vec::new();
+            storage
+                .lock()
+                .unwrap()
+                .get(&group_keys_key(0), &mut stored)
+                .unwrap();
+            let has_key = stored.windows(EPOCH_KEY.len()).any(|w| w == EPOCH_KEY);
+            assert_eq!(has_key, !wrapped);
+
+            let gk = GroupKeys::new(new_fabric_mgr(storage.clone(), wrapper()), storage.clone())
+                .unwrap();
+            assert_eq!(
+                gk.get_key_set(0, 1).unwrap(),
+                key_set(1, &[(&EPOCH_KEY, 7)])
+            );
+            assert_eq!(gk.key_map(0).unwrap(), [map(0x101, 1)]);
+            let key = GroupKey::new(0, 1, &EPOCH_KEY, &[0; 8]).unwrap();
+            assert_eq!(gk.rx_keys(key.session_id, 0x101).len(), 1);
+
+            gk.remove_all().unwrap();
+            let gk = GroupKeys::new(fabric_mgr, storage).unwrap();
+            assert!(gk.key_map(0).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_unknown_fabric() {
+        let gk = new_group_keys();
+        assert_eq!(
+            gk.set_key_set(1, key_set(1, &[(&EPOCH_KEY, 0)])),
+            Err(Error::NotFound)
+        );
+        assert_eq!(gk.key_map(5).err(), Some(Error::NotFound));
+    }
+}
@@ -51,19 +51,18 @@ impl InteractionModel {
         rx_buf: &[u8],
         proto_tx: &mut Packet,
     ) -> Result<ResponseRequired, Error> {
-        if InteractionModel::req_timeout_handled(trans, proto_tx)? {
-            return Ok(ResponseRequired::Yes);
-        }
+        let timed_tx = match InteractionModel::req_timeout_handled(trans, proto_tx)? {
+            Some(timed_tx) => timed_tx,
+            None => return Ok(ResponseRequired::Yes),
+        };
 
         proto_tx.set_proto_opcode(OpCode::InvokeResponse as u8);
         let mut tw = TLVWriter::new(proto_tx.get_writebuf()?);
         let root = get_root_node_struct(rx_buf)?;
         let inv_req = InvReq::from_tlv(&root)?;
 
-        let timed_tx = trans.get_timeout().map(|_| true);
-        let timed_request = inv_req.timed_request.filter(|a| *a);
-        // Either both should be None, or both should be Some(true)
-        if timed_tx != timed_request {
+        // Either both should be timed, or neither
+        if timed_tx != inv_req.timed_request.unwrap_or(false) {
             InteractionModel::create_status_response(proto_tx, IMStatusCode::TimedRequestMisMatch)?;
             return Ok(ResponseRequired::Yes);
         }
@@ -15,7 +15,13 @@
  *    limitations under the License.
  */
 
-use std::time::{Duration, SystemTime};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use crate::{
     error::*,
@@ -26,6 +32,7 @@ use crate::{
         packet::Packet,
         proto_demux::{self, ProtoCtx, ResponseRequired},
         session::SessionHandle,
+        timer::{TimerAction, TimerId, Timers},
     },
 };
 use colored::Colorize;
@@ -42,7 +49,7 @@ use super::{messages::msg::TimedReq, InteractionConsumer};
  */
 
 /* Interaction Model ID as per the Matter Spec */
-const PROTO_ID_INTERACTION_MODEL: usize = 0x01;
+pub(crate) const PROTO_ID_INTERACTION_MODEL: usize = 0x01;
 
 #[derive(FromPrimitive, Debug, Copy, Clone, PartialEq)]
 pub enum OpCode {
@@ -85,29 +92,48 @@ impl<'a, 'b> Transaction<'a, 'b> {
     pub fn is_complete(&self) -> bool {
         self.state == TransactionState::Complete
     }
+}
 
-    pub fn set_timeout(&mut self, timeout: u64) {
-        self.exch
-            .set_data_time(SystemTime::now().checked_add(Duration::from_millis(timeout)));
+/// The window of a timed request, within which the action must follow
+///
+/// A timer closes the window, it is cancelled when the window is dropped along with the
+/// exchange data.
+struct TimedWindow {
+    timers: Timers,
+    timer: TimerId,
+    expired: Arc<AtomicBool>,
+}
+
+impl TimedWindow {
+    fn new(timers: &Timers, timeout: Duration) -> Self {
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        let timer = timers.add_oneshot(
+            timeout,
+            TimerAction::Callback(Box::new(move |_| flag.store(true, Ordering::SeqCst))),
+        );
+        Self {
+            timers: timers.clone(),
+            timer,
+            expired,
+        }
     }
 
-    pub fn get_timeout(&mut self) -> Option<SystemTime> {
-        self.exch.get_data_time()
+    fn has_expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst)
     }
+}
 
-    pub fn has_timed_out(&self) -> bool {
-        if let Some(timeout) = self.exch.get_data_time() {
-            if SystemTime::now() > timeout {
-                return true;
-            }
-        }
-        false
+impl Drop for TimedWindow {
+    fn drop(&mut self) {
+        self.timers.cancel(self.timer);
     }
 }
 
 impl InteractionModel {
-    pub fn new(consumer: Box<dyn InteractionConsumer>) -> InteractionModel {
-        InteractionModel { consumer }
+    /// Create the interaction model, the timers close the windows of the timed requests
+    pub fn new(consumer: Box<dyn InteractionConsumer>, timers: Timers) -> InteractionModel {
+        InteractionModel { consumer, timers }
     }
 
     pub fn handle_subscribe_req(
@@ -146,7 +172,8 @@ impl InteractionModel {
 
         let root = get_root_node_struct(rx_buf)?;
         let req = TimedReq::from_tlv(&root)?;
-        trans.set_timeout(req.timeout.into());
+        let window = TimedWindow::new(&self.timers, Duration::from_millis(req.timeout.into()));
+        trans.exch.set_data_boxed(Box::new(window));
 
         let status = StatusResp {
             status: IMStatusCode::Success,
@@ -157,18 +184,20 @@ impl InteractionModel {
     }
 
     /// Handle Request Timeouts
-    /// This API checks if a request was a timed request, and if so, and if the timeout has
-    /// expired, it will generate the appropriate response as expected
+    /// This API checks if a request follows a timed request, closing its window, and if the
+    /// timer of the window has expired, it will generate the appropriate response as expected.
+    /// Returns None if the response was generated, or else whether the request is timed.
     pub(super) fn req_timeout_handled(
         trans: &mut Transaction,
         proto_tx: &mut Packet,
-    ) -> Result<bool, Error> {
-        if trans.has_timed_out() {
-            trans.complete();
-            InteractionModel::create_status_response(proto_tx, IMStatusCode::Timeout)?;
-            Ok(true)
-        } else {
-            Ok(false)
+    ) -> Result<Option<bool>, Error> {
+        match trans.exch.take_data_boxed::<TimedWindow>() {
+            Some(window) if window.has_expired() => {
+                trans.complete();
+                InteractionModel::create_status_response(proto_tx, IMStatusCode::Timeout)?;
+                Ok(None)
+            }
+            window => Ok(Some(window.is_some())),
         }
     }
 
@@ -18,7 +18,9 @@
 use crate::{
     error::Error,
     tlv::TLVWriter,
-    transport::{exchange::Exchange, proto_demux::ResponseRequired, session::SessionHandle},
+    transport::{
+        exchange::Exchange, proto_demux::ResponseRequired, session::SessionHandle, timer::Timers,
+    },
 };
 
 use self::{
@@ -79,6 +81,7 @@ pub trait InteractionConsumer {
 
 pub struct InteractionModel {
     consumer: Box<dyn InteractionConsumer>,
+    timers: Timers,
 }
 pub mod command;
 pub mod core;
@@ -32,7 +32,7 @@ impl InteractionModel {
         rx_buf: &[u8],
         proto_tx: &mut Packet,
     ) -> Result<ResponseRequired, Error> {
-        if InteractionModel::req_timeout_handled(trans, proto_tx)? {
+        if InteractionModel::req_timeout_handled(trans, proto_tx)?.is_none() {
             return Ok(ResponseRequired::Yes);
         }
         proto_tx.set_proto_opcode(OpCode::WriteResponse as u8);
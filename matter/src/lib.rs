@@ -54,14 +54,19 @@
 //!     device_name: "OnOff Light".to_string(),
 //! };
 //!
+//! /// The directory for the persistent state of this device. A real device would use one
+//! /// that survives a reboot, like FileKvStore::default_dir()
+//! let storage_dir = std::env::temp_dir().join("matter_doc");
+//!
 //! /// Get the Matter Object
 //! /// The dev_att is an object that implements the DevAttDataFetcher trait.
-//! let mut matter = Matter::new(dev_info, dev_att, comm_data).unwrap();
+//! let mut matter = Matter::new(dev_info, dev_att, comm_data, storage_dir).unwrap();
 //! let dm = matter.get_data_model();
+//! let group_keys = matter.get_group_keys();
 //! {
 //!     let mut node = dm.node.write().unwrap();
 //!     /// Add our device-types
-//!     let endpoint = device_type_add_on_off_light(&mut node).unwrap();
+//!     let endpoint = device_type_add_on_off_light(&mut node, group_keys).unwrap();
 //! }
 //! // Start the Matter Daemon
 //! // matter.start_daemon().unwrap();
@@ -69,6 +74,7 @@
 //! Start off exploring by going to the [Matter] object.
 
 pub mod acl;
+pub mod attestation;
 pub mod cert;
 pub mod codec;
 pub mod core;
@@ -80,6 +86,7 @@ pub mod group_keys;
 pub mod interaction_model;
 pub mod mdns;
 pub mod pairing;
+pub mod persist;
 pub mod secure_channel;
 pub mod sys;
 pub mod tlv;
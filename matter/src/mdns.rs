@@ -15,12 +15,11 @@
  *    limitations under the License.
  */
 
-use std::sync::{Arc, Mutex, Once};
+use std::sync::Mutex;
 
 use crate::{
     error::Error,
     sys::{sys_publish_service, SysMdnsService},
-    transport::udp::MATTER_PORT,
 };
 
 #[derive(Default)]
@@ -32,18 +31,19 @@ pub struct MdnsInner {
     pid: u16,
     /// Device name
     device_name: String,
+    /// Whether the node also accepts TCP connections
+    tcp_supported: bool,
 }
 
 pub struct Mdns {
     inner: Mutex<MdnsInner>,
+    /// The port that the services are published with
+    port: u16,
 }
 
 const SHORT_DISCRIMINATOR_MASK: u16 = 0xF00;
 const SHORT_DISCRIMINATOR_SHIFT: u16 = 8;
 
-static mut G_MDNS: Option<Arc<Mdns>> = None;
-static INIT: Once = Once::new();
-
 pub enum ServiceMode {
     /// The commissioned state
     Commissioned,
@@ -52,21 +52,13 @@ pub enum ServiceMode {
 }
 
 impl Mdns {
-    fn new() -> Self {
+    /// Create an mDNS publisher for a node that listens on the given port
+    pub fn new(port: u16) -> Self {
         Self {
             inner: Mutex::new(MdnsInner {
                 ..Default::default()
             }),
-        }
-    }
-
-    /// Get a handle to the globally unique mDNS instance
-    pub fn get() -> Result<Arc<Self>, Error> {
-        unsafe {
-            INIT.call_once(|| {
-                G_MDNS = Some(Arc::new(Mdns::new()));
-            });
-            Ok(G_MDNS.as_ref().ok_or(Error::Invalid)?.clone())
+            port,
         }
     }
 
@@ -80,32 +72,45 @@ impl Mdns {
         inner.device_name = device_name.chars().take(32).collect();
     }
 
+    /// Advertise whether the node supports TCP, through the T key of the TXT record
+    ///
+    /// This only applies to the services that are published afterwards.
+    pub fn set_tcp_supported(&self, supported: bool) {
+        self.inner.lock().unwrap().tcp_supported = supported;
+    }
+
     /// Publish a mDNS service
     /// name - is the service name (comma separated subtypes may follow)
     /// mode - the current service mode
     #[allow(clippy::needless_pass_by_value)]
     pub fn publish_service(&self, name: &str, mode: ServiceMode) -> Result<SysMdnsService, Error> {
+        let inner = self.inner.lock().unwrap();
+        let tcp_kv = ["T", "1"];
         match mode {
             ServiceMode::Commissioned => {
-                sys_publish_service(name, "_matter._tcp", MATTER_PORT, &[])
+                let txt_kvs: &[[&str; 2]] = if inner.tcp_supported { &[tcp_kv] } else { &[] };
+                sys_publish_service(name, "_matter._tcp", self.port, txt_kvs)
             }
             ServiceMode::Commissionable(discriminator) => {
-                let inner = self.inner.lock().unwrap();
                 let short = compute_short_discriminator(discriminator);
                 let serv_type = format!("_matterc._udp,_S{},_L{}", short, discriminator);
 
                 let str_discriminator = format!("{}", discriminator);
-                let txt_kvs = [
+                let str_vp = format!("{}+{}", inner.vid, inner.pid);
+                let mut txt_kvs = vec![
                     ["D", &str_discriminator],
                     ["CM", "1"],
                     ["DN", &inner.device_name],
-                    ["VP", &format!("{}+{}", inner.vid, inner.pid)],
+                    ["VP", &str_vp],
                     ["SII", "5000"], /* Sleepy Idle Interval */
                     ["SAI", "300"],  /* Sleepy Active Interval */
                     ["PH", "33"],    /* Pairing Hint */
                     ["PI", ""],      /* Pairing Instruction */
                 ];
-                sys_publish_service(name, &serv_type, MATTER_PORT, &txt_kvs)
+                if inner.tcp_supported {
+                    txt_kvs.push(tcp_kv);
+                }
+                sys_publish_service(name, &serv_type, self.port, &txt_kvs)
             }
         }
     }
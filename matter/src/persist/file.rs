@@ -0,0 +1,234 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    fs::{self, DirBuilder, File},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use log::error;
+
+use crate::error::Error;
+
+use super::KvStore;
+
+/// Write a file atomically
+///
+/// The data is written to a temporary file next to the destination, which is then renamed
+/// over the destination. A crash leaves either the old or the new file in place.
+pub(super) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or(Error::Invalid)?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let mut f = File::create(&tmp_path)?;
+    f.write_all(data)?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    // Make the rename itself durable. Not all platforms allow syncing a directory, hence
+    // this is best effort
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// A KvStore that keeps every key in a separate file of a directory
+///
+/// Keys can't start with a '.', those names are reserved for temporary files
+pub struct FileKvStore {
+    dir: PathBuf,
+}
+
+impl FileKvStore {
+    /// The directory that the state of a node is kept in, if there is no better place for it
+    ///
+    /// This follows the XDG base directories: it is `$XDG_STATE_HOME/matter`, or
+    /// `~/.local/state/matter` if XDG_STATE_HOME isn't set. Returns None if HOME isn't set
+    /// either.
+    pub fn default_dir() -> Option<PathBuf> {
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            // Relative paths are to be ignored
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state"))
+            })?;
+        Some(state_home.join("matter"))
+    }
+
+    /// Create a store in the given directory, creating the directory if required
+    ///
+    /// Nodes that run in the same process should each use their own directory
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        DirBuilder::new().recursive(true).create(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        // The key is used as the file name, it shouldn't be able to escape the directory
+        if key.is_empty() || key.starts_with('.') || key.contains(['/', '\\']) {
+            error!("Invalid key {}", key);
+            return Err(Error::Invalid);
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+impl KvStore for FileKvStore {
+    fn get(&self, key: &str, val: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut f = File::open(self.path(key)?).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::NotFound,
+            _ => e.into(),
+        })?;
+        Ok(f.read_to_end(val)?)
+    }
+
+    fn set(&mut self, key: &str, val: &[u8]) -> Result<(), Error> {
+        write_atomic(&self.path(key)?, val)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&str, &[u8])) -> Result<(), Error> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            match entry.file_name().to_str() {
+                // Skip the leftovers of interrupted writes
+                Some(key) if !key.starts_with('.') => {
+                    let val = fs::read(entry.path())?;
+                    f(key, &val);
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::{error::Error, persist::KvStore};
+
+    use super::FileKvStore;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("matter_kv_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_get_set_remove() {
+        let dir = test_dir("file_get_set");
+        let mut store = FileKvStore::new(&dir).unwrap();
+        let mut val = Vec::new();
+        assert_eq!(store.get("a", &mut val), Err(Error::NotFound));
+
+        store.set("a", &[1, 2, 3]).unwrap();
+        store.set_u64("b", 0x1234).unwrap();
+
+        // The values are visible to another store on the same directory
+        let store2 = FileKvStore::new(&dir).unwrap();
+        assert_eq!(store2.get("a", &mut val), Ok(3));
+        assert_eq!(val, [1, 2, 3]);
+        assert_eq!(store2.get_u64("b"), Ok(0x1234));
+
+        let mut keys = Vec::new();
+        store2
+            .for_each(&mut |key, _| keys.push(key.to_owned()))
+            .unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+
+        store.remove("a").unwrap();
+        store.remove("a").unwrap();
+        assert_eq!(store.get("a", &mut val), Err(Error::NotFound));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let dir = test_dir("file_invalid_key");
+        let mut store = FileKvStore::new(&dir).unwrap();
+        assert_eq!(store.set("../a", &[1]), Err(Error::Invalid));
+        assert_eq!(store.set("", &[1]), Err(Error::Invalid));
+        assert_eq!(store.set(".a.tmp", &[1]), Err(Error::Invalid));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_interrupted_write() {
+        let dir = test_dir("file_interrupted");
+        let mut store = FileKvStore::new(&dir).unwrap();
+        store.set("a", &[1, 2, 3]).unwrap();
+
+        // A write that crashed before the rename leaves the old value intact
+        fs::write(dir.join(".a.tmp"), [4]).unwrap();
+        let mut val = Vec::new();
+        assert_eq!(store.get("a", &mut val), Ok(3));
+        assert_eq!(val, [1, 2, 3]);
+
+        let mut keys = Vec::new();
+        store
+            .for_each(&mut |key, _| keys.push(key.to_owned()))
+            .unwrap();
+        assert_eq!(keys, ["a"]);
+
+        // The next write simply replaces the leftover
+        store.set("a", &[5]).unwrap();
+        val.clear();
+        assert_eq!(store.get("a", &mut val), Ok(1));
+        assert_eq!(val, [5]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_default_dir() {
+        std::env::set_var("XDG_STATE_HOME", "/var/state");
+        assert_eq!(
+            FileKvStore::default_dir(),
+            Some(PathBuf::from("/var/state/matter"))
+        );
+
+        // A relative XDG_STATE_HOME is ignored
+        std::env::set_var("XDG_STATE_HOME", "state");
+        std::env::set_var("HOME", "/home/user");
+        assert_eq!(
+            FileKvStore::default_dir(),
+            Some(PathBuf::from("/home/user/.local/state/matter"))
+        );
+    }
+}
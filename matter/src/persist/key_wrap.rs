@@ -0,0 +1,225 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{env, fs, path::PathBuf};
+
+use log::error;
+use rand::RngCore;
+
+use crate::{
+    crypto::{self, AEAD_MIC_LEN_BYTES, AEAD_NONCE_LEN_BYTES, SYMM_KEY_LEN_BYTES},
+    error::Error,
+};
+
+/// Provides the device-unique key that sensitive items are wrapped with
+pub trait KeyProvider: Send + Sync {
+    /// Fill key with the device-unique key
+    fn get_key(&self, key: &mut [u8; SYMM_KEY_LEN_BYTES]) -> Result<(), Error>;
+}
+
+/// Reads the device key from a file that holds the raw 16 bytes
+pub struct FileKeyProvider {
+    path: PathBuf,
+}
+
+impl FileKeyProvider {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn get_key(&self, key: &mut [u8; SYMM_KEY_LEN_BYTES]) -> Result<(), Error> {
+        let data = fs::read(&self.path)?;
+        if data.len() != SYMM_KEY_LEN_BYTES {
+            error!("Invalid device key length {}", data.len());
+            return Err(Error::Invalid);
+        }
+        key.copy_from_slice(&data);
+        Ok(())
+    }
+}
+
+/// Reads the device key, as 32 hex digits, from an environment variable
+pub struct EnvKeyProvider {
+    var: String,
+}
+
+impl EnvKeyProvider {
+    pub fn new(var: &str) -> Self {
+        Self { var: var.into() }
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn get_key(&self, key: &mut [u8; SYMM_KEY_LEN_BYTES]) -> Result<(), Error> {
+        let hex = env::var(&self.var).map_err(|_| {
+            error!("Device key variable {} not set", self.var);
+            Error::NotFound
+        })?;
+        // Work on the bytes, the variable may well have non-ASCII characters in it
+        let hex = hex.as_bytes();
+        if hex.len() != SYMM_KEY_LEN_BYTES * 2 {
+            error!("Invalid device key length {}", hex.len());
+            return Err(Error::Invalid);
+        }
+        for (b, digits) in key.iter_mut().zip(hex.chunks(2)) {
+            *b = (hex_digit(digits[0])? << 4) | hex_digit(digits[1])?;
+        }
+        Ok(())
+    }
+}
+
+fn hex_digit(c: u8) -> Result<u8, Error> {
+    (c as char)
+        .to_digit(16)
+        .map(|d| d as u8)
+        .ok_or(Error::Invalid)
+}
+
+/// A device key that is fixed at build time, or given by the application
+pub struct StaticKeyProvider {
+    key: [u8; SYMM_KEY_LEN_BYTES],
+}
+
+impl StaticKeyProvider {
+    pub fn new(key: [u8; SYMM_KEY_LEN_BYTES]) -> Self {
+        Self { key }
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn get_key(&self, key: &mut [u8; SYMM_KEY_LEN_BYTES]) -> Result<(), Error> {
+        key.copy_from_slice(&self.key);
+        Ok(())
+    }
+}
+
+/// Wraps sensitive items, like private keys, before they are persisted
+///
+/// Items are encrypted with AES-CCM under the key from the [KeyProvider]. A wrapped item is
+/// the random nonce, followed by the cipher text and the MIC. The associated data binds the
+/// wrapped item to where it is stored, so that it can't be moved elsewhere.
+pub struct KeyWrapper {
+    provider: Box<dyn KeyProvider>,
+}
+
+impl KeyWrapper {
+    pub fn new(provider: Box<dyn KeyProvider>) -> Self {
+        Self { provider }
+    }
+
+    pub fn wrap(&self, ad: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut key = [0u8; SYMM_KEY_LEN_BYTES];
+        self.provider.get_key(&mut key)?;
+
+        let mut wrapped = vec![0u8; AEAD_NONCE_LEN_BYTES + data.len() + AEAD_MIC_LEN_BYTES];
+        let (nonce, cipher_text) = wrapped.split_at_mut(AEAD_NONCE_LEN_BYTES);
+        rand::thread_rng().fill_bytes(nonce);
+        cipher_text[..data.len()].copy_from_slice(data);
+        crypto::encrypt_in_place(&key, nonce, ad, cipher_text, data.len())?;
+        Ok(wrapped)
+    }
+
+    pub fn unwrap(&self, ad: &[u8], wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+        if wrapped.len() < AEAD_NONCE_LEN_BYTES + AEAD_MIC_LEN_BYTES {
+            error!("Wrapped item too short");
+            return Err(Error::Invalid);
+        }
+        let mut key = [0u8; SYMM_KEY_LEN_BYTES];
+        self.provider.get_key(&mut key)?;
+
+        let (nonce, cipher_text) = wrapped.split_at(AEAD_NONCE_LEN_BYTES);
+        let mut data = cipher_text.to_vec();
+        crypto::decrypt_in_place(&key, nonce, ad, &mut data)?;
+        data.truncate(cipher_text.len() - AEAD_MIC_LEN_BYTES);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        crypto::{AEAD_MIC_LEN_BYTES, AEAD_NONCE_LEN_BYTES},
+        error::Error,
+    };
+
+    use super::{EnvKeyProvider, KeyProvider, KeyWrapper, StaticKeyProvider};
+
+    const KEY: [u8; 16] = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc,
+        0xfe,
+    ];
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let wrapper = KeyWrapper::new(Box::new(StaticKeyProvider::new(KEY)));
+        let data = [0x55u8; 32];
+        let wrapped = wrapper.wrap(b"fb1", &data).unwrap();
+        assert_eq!(
+            wrapped.len(),
+            AEAD_NONCE_LEN_BYTES + data.len() + AEAD_MIC_LEN_BYTES
+        );
+        assert!(!wrapped.windows(data.len()).any(|w| w == data));
+        assert_eq!(wrapper.unwrap(b"fb1", &wrapped).unwrap(), data);
+
+        // Wrong associated data, wrong key or a modified item all fail
+        assert!(wrapper.unwrap(b"fb2", &wrapped).is_err());
+        let other = KeyWrapper::new(Box::new(StaticKeyProvider::new([0; 16])));
+        assert!(other.unwrap(b"fb1", &wrapped).is_err());
+        let mut modified = wrapped.clone();
+        modified[AEAD_NONCE_LEN_BYTES] ^= 1;
+        assert!(wrapper.unwrap(b"fb1", &modified).is_err());
+        assert!(wrapper.unwrap(b"fb1", &wrapped[..10]).is_err());
+    }
+
+    #[test]
+    fn test_env_provider() {
+        std::env::set_var("MATTER_TEST_DEVICE_KEY", "0123456789abcdef1032547698badcfe");
+        let mut key = [0u8; 16];
+        EnvKeyProvider::new("MATTER_TEST_DEVICE_KEY")
+            .get_key(&mut key)
+            .unwrap();
+        assert_eq!(key, KEY);
+
+        for (var, value) in [
+            ("MATTER_TEST_DEVICE_KEY_SHORT", "0123"),
+            (
+                "MATTER_TEST_DEVICE_KEY_ODD",
+                "0123456789abcdef1032547698badcf",
+            ),
+            (
+                "MATTER_TEST_DEVICE_KEY_SIGN",
+                "+123456789abcdef1032547698badcfe",
+            ),
+            // 32 bytes, but not 32 characters
+            (
+                "MATTER_TEST_DEVICE_KEY_UTF8",
+                "é123456789abcdef1032547698badcf",
+            ),
+        ] {
+            std::env::set_var(var, value);
+            assert_eq!(
+                EnvKeyProvider::new(var).get_key(&mut key),
+                Err(Error::Invalid)
+            );
+        }
+        assert!(EnvKeyProvider::new("MATTER_TEST_DEVICE_KEY_UNSET")
+            .get_key(&mut key)
+            .is_err());
+    }
+}
@@ -0,0 +1,105 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+
+use super::KvStore;
+
+/// A KvStore that only lives in memory
+#[derive(Default)]
+pub struct MemKvStore {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemKvStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KvStore for MemKvStore {
+    fn get(&self, key: &str, val: &mut Vec<u8>) -> Result<usize, Error> {
+        let entry = self.entries.get(key).ok_or(Error::NotFound)?;
+        val.extend_from_slice(entry);
+        Ok(entry.len())
+    }
+
+    fn set(&mut self, key: &str, val: &[u8]) -> Result<(), Error> {
+        self.entries.insert(key.into(), val.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Error> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&str, &[u8])) -> Result<(), Error> {
+        for (key, val) in &self.entries {
+            f(key, val);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Error, persist::KvStore};
+
+    use super::MemKvStore;
+
+    #[test]
+    fn test_get_set_remove() {
+        let mut store = MemKvStore::new();
+        let mut val = Vec::new();
+        assert_eq!(store.get("a", &mut val), Err(Error::NotFound));
+
+        store.set("a", &[1, 2, 3]).unwrap();
+        store.set_u64("b", 0x1234).unwrap();
+        assert_eq!(store.get("a", &mut val), Ok(3));
+        assert_eq!(val, [1, 2, 3]);
+        assert_eq!(store.get_u64("b"), Ok(0x1234));
+
+        // Overwrite
+        store.set("a", &[4]).unwrap();
+        val.clear();
+        assert_eq!(store.get("a", &mut val), Ok(1));
+        assert_eq!(val, [4]);
+
+        store.remove("a").unwrap();
+        store.remove("a").unwrap();
+        assert_eq!(store.get("a", &mut val), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn test_for_each() {
+        let mut store = MemKvStore::new();
+        store.set("a", &[1]).unwrap();
+        store.set("b", &[2, 3]).unwrap();
+
+        let mut entries = Vec::new();
+        store
+            .for_each(&mut |key, val| entries.push((key.to_owned(), val.to_vec())))
+            .unwrap();
+        assert_eq!(
+            entries,
+            [("a".to_owned(), vec![1]), ("b".to_owned(), vec![2, 3])]
+        );
+    }
+}
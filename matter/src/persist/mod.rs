@@ -0,0 +1,82 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! Persistent storage for the state of a Matter node
+//!
+//! Everything that has to survive a reboot, like the fabrics and the ACLs, is stored
+//! through the [KvStore] trait. A few backends are provided:
+//! * [FileKvStore]: every key is a file in a configurable directory
+//! * [MemKvStore]: nothing is persisted, useful for tests
+//! * [SingleFileKvStore]: all the keys are kept in a single file, useful for flash-like targets
+//!
+//! State that spans several values is kept in a single versioned record, see [set_record]
+//! and [get_record], so that it is always updated atomically.
+//!
+//! Sensitive items, like private keys, can additionally be encrypted with a [KeyWrapper]
+//! before they are stored.
+
+use std::{
+    convert::TryInto,
+    sync::{Arc, Mutex},
+};
+
+use crate::error::Error;
+
+mod file;
+mod key_wrap;
+mod mem;
+mod record;
+mod single_file;
+
+pub use file::FileKvStore;
+pub use key_wrap::{EnvKeyProvider, FileKeyProvider, KeyProvider, KeyWrapper, StaticKeyProvider};
+pub use mem::MemKvStore;
+pub use record::{get_record, no_migration, set_record, MigrateFn};
+pub use single_file::SingleFileKvStore;
+
+/// A key-value store
+pub trait KvStore: Send {
+    /// Read the value of the key, appending it to val
+    ///
+    /// Returns the length of the value, or [Error::NotFound] if the key doesn't exist
+    fn get(&self, key: &str, val: &mut Vec<u8>) -> Result<usize, Error>;
+
+    /// Set the value of the key, replacing any previous value
+    ///
+    /// This must be atomic: if it is interrupted, say by a power cut, the key holds either
+    /// the previous or the new value afterwards, never a mix of the two
+    fn set(&mut self, key: &str, val: &[u8]) -> Result<(), Error>;
+
+    /// Remove the key, removing a key that doesn't exist is not an error
+    fn remove(&mut self, key: &str) -> Result<(), Error>;
+
+    /// Call f on every key and its value
+    fn for_each(&self, f: &mut dyn FnMut(&str, &[u8])) -> Result<(), Error>;
+
+    fn set_u64(&mut self, key: &str, val: u64) -> Result<(), Error> {
+        self.set(key, &val.to_be_bytes())
+    }
+
+    fn get_u64(&self, key: &str) -> Result<u64, Error> {
+        let mut vec = Vec::new();
+        self.get(key, &mut vec)?;
+        Ok(u64::from_be_bytes(vec.as_slice().try_into()?))
+    }
+}
+
+/// A KvStore that can be shared between the different parts of the stack
+pub type SharedKvStore = Arc<Mutex<dyn KvStore>>;
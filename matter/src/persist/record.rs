@@ -0,0 +1,126 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use log::error;
+
+use crate::error::Error;
+
+use super::KvStore;
+
+/// Migrate the contents of a record from an older schema version
+///
+/// This is called with the version that the record was stored with, and its contents. It
+/// returns the contents in the current schema version.
+pub type MigrateFn = fn(version: u8, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+/// The migration hook for records that haven't changed their layout yet
+pub fn no_migration(version: u8, _data: &[u8]) -> Result<Vec<u8>, Error> {
+    error!("No migration from version {}", version);
+    Err(Error::Invalid)
+}
+
+/// Store a record in the given schema version
+///
+/// The version is stored as the first byte of the value, followed by the contents
+pub fn set_record(
+    storage: &mut dyn KvStore,
+    key: &str,
+    version: u8,
+    data: &[u8],
+) -> Result<(), Error> {
+    let mut val = Vec::with_capacity(data.len() + 1);
+    val.push(version);
+    val.extend_from_slice(data);
+    storage.set(key, &val)
+}
+
+/// Load a record, migrating it to the current schema version if required
+///
+/// Records from a newer schema version are rejected, as there is no way to know what
+/// they contain
+pub fn get_record(
+    storage: &dyn KvStore,
+    key: &str,
+    version: u8,
+    migrate: MigrateFn,
+) -> Result<Vec<u8>, Error> {
+    let mut val = Vec::new();
+    storage.get(key, &mut val)?;
+    let (stored_version, data) = val.split_first().ok_or(Error::Invalid)?;
+    if *stored_version == version {
+        Ok(data.to_vec())
+    } else if *stored_version < version {
+        migrate(*stored_version, data)
+    } else {
+        error!(
+            "Record {} has version {}, newer than {}",
+            key, stored_version, version
+        );
+        Err(Error::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        persist::{KvStore, MemKvStore},
+    };
+
+    use super::{get_record, no_migration, set_record};
+
+    fn migrate_v1(version: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        assert_eq!(version, 1);
+        // Version 2 added a trailing byte
+        let mut data = data.to_vec();
+        data.push(0xff);
+        Ok(data)
+    }
+
+    #[test]
+    fn test_record_same_version() {
+        let mut store = MemKvStore::new();
+        set_record(&mut store, "r", 1, &[1, 2]).unwrap();
+        assert_eq!(get_record(&store, "r", 1, no_migration), Ok(vec![1, 2]));
+        assert_eq!(
+            get_record(&store, "x", 1, no_migration),
+            Err(Error::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_record_migration() {
+        let mut store = MemKvStore::new();
+        set_record(&mut store, "r", 1, &[1, 2]).unwrap();
+        assert_eq!(get_record(&store, "r", 2, migrate_v1), Ok(vec![1, 2, 0xff]));
+        assert_eq!(
+            get_record(&store, "r", 2, no_migration),
+            Err(Error::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_record_newer_version() {
+        let mut store = MemKvStore::new();
+        set_record(&mut store, "r", 3, &[1, 2]).unwrap();
+        assert_eq!(get_record(&store, "r", 2, migrate_v1), Err(Error::Invalid));
+
+        // An empty value has no version at all
+        store.set("r", &[]).unwrap();
+        assert_eq!(get_record(&store, "r", 2, migrate_v1), Err(Error::Invalid));
+    }
+}
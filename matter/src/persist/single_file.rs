@@ -0,0 +1,163 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use log::error;
+
+use crate::error::Error;
+
+use super::{file::write_atomic, KvStore, MemKvStore};
+
+/// A KvStore that keeps all the keys in a single file
+///
+/// The whole store is cached in memory, and the file is atomically rewritten on every change.
+/// This suits targets where the storage is a single flash partition rather than a file system.
+///
+/// The file is a sequence of records, each of which is:
+/// * the length of the key (u16, little endian)
+/// * the key
+/// * the length of the value (u32, little endian)
+/// * the value
+pub struct SingleFileKvStore {
+    path: PathBuf,
+    entries: MemKvStore,
+}
+
+impl SingleFileKvStore {
+    /// Open the store in the given file, the file is created on the first write
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut entries = MemKvStore::new();
+        match fs::read(path.as_ref()) {
+            Ok(buf) => Self::parse(&buf, &mut entries)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            entries,
+        })
+    }
+
+    fn parse(mut buf: &[u8], entries: &mut MemKvStore) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let key = Self::take(&mut buf, 2)?;
+            let key = String::from_utf8(key.to_vec())?;
+            let val = Self::take(&mut buf, 4)?;
+            entries.set(&key, val)?;
+        }
+        Ok(())
+    }
+
+    // Take a length-prefixed field from the front of buf
+    fn take<'a>(buf: &mut &'a [u8], len_size: usize) -> Result<&'a [u8], Error> {
+        if buf.len() < len_size {
+            error!("Truncated store");
+            return Err(Error::Invalid);
+        }
+        let len = LittleEndian::read_uint(buf, len_size) as usize;
+        if buf.len() < len_size + len {
+            error!("Truncated store");
+            return Err(Error::Invalid);
+        }
+        let field = &buf[len_size..len_size + len];
+        *buf = &buf[len_size + len..];
+        Ok(field)
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let mut result = Ok(());
+        self.entries.for_each(&mut |key, val| {
+            if key.len() > u16::MAX as usize || val.len() > u32::MAX as usize {
+                result = Err(Error::NoSpace);
+                return;
+            }
+            let mut len = [0; 4];
+            LittleEndian::write_u16(&mut len, key.len() as u16);
+            buf.extend_from_slice(&len[..2]);
+            buf.extend_from_slice(key.as_bytes());
+            LittleEndian::write_u32(&mut len, val.len() as u32);
+            buf.extend_from_slice(&len);
+            buf.extend_from_slice(val);
+        })?;
+        result?;
+        write_atomic(&self.path, &buf)
+    }
+}
+
+impl KvStore for SingleFileKvStore {
+    fn get(&self, key: &str, val: &mut Vec<u8>) -> Result<usize, Error> {
+        self.entries.get(key, val)
+    }
+
+    fn set(&mut self, key: &str, val: &[u8]) -> Result<(), Error> {
+        self.entries.set(key, val)?;
+        self.flush()
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Error> {
+        self.entries.remove(key)?;
+        self.flush()
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&str, &[u8])) -> Result<(), Error> {
+        self.entries.for_each(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{error::Error, persist::KvStore};
+
+    use super::SingleFileKvStore;
+
+    #[test]
+    fn test_reopen() {
+        let path = std::env::temp_dir().join(format!("matter_kv_single_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = SingleFileKvStore::new(&path).unwrap();
+        store.set("a", &[1, 2, 3]).unwrap();
+        store.set("b", &[]).unwrap();
+        store.set_u64("c", 0x1234).unwrap();
+        store.remove("b").unwrap();
+
+        let store = SingleFileKvStore::new(&path).unwrap();
+        let mut val = Vec::new();
+        assert_eq!(store.get("a", &mut val), Ok(3));
+        assert_eq!(val, [1, 2, 3]);
+        assert_eq!(store.get("b", &mut val), Err(Error::NotFound));
+        assert_eq!(store.get_u64("c"), Ok(0x1234));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let path = std::env::temp_dir().join(format!("matter_kv_truncated_{}", std::process::id()));
+        fs::write(&path, [1, 0, b'a', 4, 0, 0, 0, 1]).unwrap();
+        assert!(SingleFileKvStore::new(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
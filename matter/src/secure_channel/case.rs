@@ -22,7 +22,7 @@ use owning_ref::RwLockReadGuardRef;
 use rand::prelude::*;
 
 use crate::{
-    cert::Cert,
+    cert::{Cert, CertTime},
     crypto::{self, CryptoKeyPair, KeyPair, Sha256},
     error::Error,
     fabric::{Fabric, FabricMgr, FabricMgrInner},
@@ -30,6 +30,7 @@ use crate::{
     secure_channel::common::{self, OpCode},
     tlv::{get_root_node_struct, FromTLV, OctetStr, TLVElement, TLVWriter, TagType},
     transport::{
+        mrp::{MrpParams, SessionParams},
         network::Address,
         proto_demux::{ProtoCtx, ResponseRequired},
         queue::{Msg, WorkQ},
@@ -53,6 +54,7 @@ pub struct CaseSession {
     our_pub_key: [u8; crypto::EC_POINT_LEN_BYTES],
     peer_pub_key: [u8; crypto::EC_POINT_LEN_BYTES],
     local_fabric_idx: usize,
+    mrp_params: MrpParams,
 }
 impl CaseSession {
     pub fn new(peer_sessid: u16, local_sessid: u16) -> Result<Self, Error> {
@@ -65,17 +67,19 @@ impl CaseSession {
             our_pub_key: [0; crypto::EC_POINT_LEN_BYTES],
             peer_pub_key: [0; crypto::EC_POINT_LEN_BYTES],
             local_fabric_idx: 0,
+            mrp_params: Default::default(),
         })
     }
 }
 
 pub struct Case {
     fabric_mgr: Arc<FabricMgr>,
+    work_q: WorkQ,
 }
 
 impl Case {
-    pub fn new(fabric_mgr: Arc<FabricMgr>) -> Self {
-        Self { fabric_mgr }
+    pub fn new(fabric_mgr: Arc<FabricMgr>, work_q: WorkQ) -> Self {
+        Self { fabric_mgr, work_q }
     }
 
     pub fn casesigma3_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
@@ -124,7 +128,8 @@ impl Case {
         if let Some(icac) = d.initiator_icac {
             initiator_icac = Some(Cert::new(icac.0)?);
         }
-        if let Err(e) = Case::validate_certs(fabric, &initiator_noc, &initiator_icac) {
+        let time = CertTime::from_clock(ctx.exch_ctx.sess.get_clock().as_ref());
+        if let Err(e) = Case::validate_certs(fabric, &initiator_noc, &initiator_icac, time) {
             error!("Certificate Chain doesn't match: {}", e);
             common::create_sc_status_report(
                 &mut ctx.tx,
@@ -167,7 +172,7 @@ impl Case {
             &peer_catids,
         )?;
         // Queue a transport mgr request to add a new session
-        WorkQ::get()?.sync_send(Msg::NewSession(clone_data))?;
+        self.work_q.try_send(Msg::NewSession(clone_data))?;
 
         common::create_sc_status_report(
             &mut ctx.tx,
@@ -205,6 +210,7 @@ impl Case {
         let mut case_session = Box::new(CaseSession::new(r.initiator_sessid, local_sessid)?);
         case_session.tt_hash.update(rx_buf)?;
         case_session.local_fabric_idx = local_fabric_idx?;
+        case_session.mrp_params = r.sed_params.into();
         if r.peer_pub_key.0.len() != crypto::EC_POINT_LEN_BYTES {
             error!("Invalid public key length");
             return Err(Error::Invalid);
@@ -311,6 +317,7 @@ impl Case {
         clone_data
             .att_challenge
             .copy_from_slice(&session_keys[32..48]);
+        clone_data.mrp_params = case_session.mrp_params;
         Ok(clone_data)
     }
 
@@ -339,8 +346,13 @@ impl Case {
         Ok(())
     }
 
-    fn validate_certs(fabric: &Fabric, noc: &Cert, icac: &Option<Cert>) -> Result<(), Error> {
-        let mut verifier = noc.verify_chain_start();
+    fn validate_certs(
+        fabric: &Fabric,
+        noc: &Cert,
+        icac: &Option<Cert>,
+        time: CertTime,
+    ) -> Result<(), Error> {
+        let mut verifier = noc.verify_chain_start(time);
 
         if fabric.get_fabric_id() != noc.get_fabric_id()? {
             return Err(Error::Invalid);
@@ -552,6 +564,7 @@ struct Sigma1Req<'a> {
     initiator_sessid: u16,
     dest_id: OctetStr<'a>,
     peer_pub_key: OctetStr<'a>,
+    sed_params: Option<SessionParams>,
 }
 
 #[derive(FromTLV)]
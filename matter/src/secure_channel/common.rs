@@ -15,17 +15,12 @@
  *    limitations under the License.
  */
 
-use boxslab::Slab;
 use log::info;
 use num_derive::FromPrimitive;
 
 use crate::{
     error::Error,
-    transport::{
-        exchange::Exchange,
-        packet::{Packet, PacketPool},
-        session::SessionHandle,
-    },
+    transport::{exchange::Exchange, packet::Packet, session::SessionHandle},
 };
 
 use super::status_report::{create_status_report, GeneralCode};
@@ -95,7 +90,7 @@ pub fn create_mrp_standalone_ack(proto_tx: &mut Packet) {
 
 pub fn send_mrp_standalone_ack(exch: &mut Exchange, sess: &mut SessionHandle) -> Result<(), Error> {
     info!("Sending standalone ACK");
-    let mut ack_packet = Slab::<PacketPool>::try_new(Packet::new_tx()?).ok_or(Error::NoMemory)?;
+    let mut ack_packet = sess.new_tx()?;
     create_mrp_standalone_ack(&mut ack_packet);
     exch.send(ack_packet, sess)
 }
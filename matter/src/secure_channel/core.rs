@@ -22,12 +22,15 @@ use crate::{
     fabric::FabricMgr,
     secure_channel::common::*,
     tlv,
-    transport::proto_demux::{self, ProtoCtx, ResponseRequired},
+    transport::{
+        proto_demux::{self, ProtoCtx, ResponseRequired},
+        queue::WorkQ,
+    },
 };
 use log::{error, info};
 use num;
 
-use super::{case::Case, pake::PaseMgr};
+use super::{case::Case, msg_counter_sync::MsgCounterSync, pake::PaseMgr};
 
 /* Handle messages related to the Secure Channel
  */
@@ -35,13 +38,20 @@ use super::{case::Case, pake::PaseMgr};
 pub struct SecureChannel {
     case: Case,
     pase: PaseMgr,
+    mcsp: MsgCounterSync,
 }
 
 impl SecureChannel {
-    pub fn new(pase: PaseMgr, fabric_mgr: Arc<FabricMgr>) -> SecureChannel {
+    pub fn new(
+        pase: PaseMgr,
+        fabric_mgr: Arc<FabricMgr>,
+        work_q: WorkQ,
+        mcsp: MsgCounterSync,
+    ) -> SecureChannel {
         SecureChannel {
             pase,
-            case: Case::new(fabric_mgr),
+            case: Case::new(fabric_mgr, work_q),
+            mcsp,
         }
     }
 }
@@ -56,6 +66,8 @@ impl proto_demux::HandleProto for SecureChannel {
         tlv::print_tlv_list(ctx.rx.as_borrow_slice());
         let result = match proto_opcode {
             OpCode::MRPStandAloneAck => Ok(ResponseRequired::No),
+            OpCode::MsgCounterSyncReq => self.mcsp.req_handler(ctx),
+            OpCode::MsgCounterSyncResp => self.mcsp.resp_handler(ctx),
             OpCode::PBKDFParamRequest => self.pase.pbkdfparamreq_handler(ctx),
             OpCode::PASEPake1 => self.pase.pasepake1_handler(ctx),
             OpCode::PASEPake3 => self.pase.pasepake3_handler(ctx),
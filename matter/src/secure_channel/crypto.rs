@@ -18,7 +18,7 @@
 use crate::error::Error;
 
 // This trait allows us to switch between crypto providers like OpenSSL and mbedTLS for Spake2
-// Currently this is only validate for a verifier(responder)
+// Both the verifier(responder) and the prover(initiator) are supported
 
 // A verifier will typically do:
 // Step 1: w0 and L
@@ -27,6 +27,13 @@ use crate::error::Error;
 // Step 2: get_pB
 // Step 3: get_TT_as_verifier(pA)
 // Step 4: Computation of cA and cB happens outside since it doesn't use either BigNum or EcPoint
+//
+// A prover will typically do:
+// Step 1: w0 and w1
+//      set_w0_from_w0s
+//      set_w1_from_w1s
+// Step 2: get_pA
+// Step 3: get_TT_as_prover(pB)
 pub trait CryptoSpake2 {
     fn new() -> Result<Self, Error>
     where
@@ -51,4 +58,14 @@ pub trait CryptoSpake2 {
         pB: &[u8],
         out: &mut [u8],
     ) -> Result<(), Error>;
+    #[allow(non_snake_case)]
+    fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error>;
+    #[allow(non_snake_case)]
+    fn get_TT_as_prover(
+        &mut self,
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), Error>;
 }
@@ -163,6 +163,79 @@ impl CryptoSpake2 for CryptoMbedTLS {
         pA: &[u8],
         pB: &[u8],
         out: &mut [u8],
+    ) -> Result<(), Error> {
+        let X = EcPoint::from_binary(&self.group, pA)?;
+        let (Z, V) = CryptoMbedTLS::get_ZV_as_verifier(
+            &self.w0,
+            &self.L,
+            &mut self.M,
+            &X,
+            &self.xy,
+            &self.order,
+            &mut self.group,
+        )?;
+
+        self.get_TT(context, pA, pB, &Z, &V, out)
+    }
+
+    #[allow(non_snake_case)]
+    fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error> {
+        // From the SPAKE2+ spec (https://datatracker.ietf.org/doc/draft-bar-cfrg-spake2plus/)
+        //   for x
+        //   - select random x between 0 to p
+        //   - X = x*P + w0*M
+        //   - pA = X
+
+        // A private key on this curve is a random number between 0 to p
+        let mut ctr_drbg = CtrDrbg::new(Arc::new(OsEntropy::new()), None)?;
+        self.xy = Pk::generate_ec(&mut ctr_drbg, EcGroupId::SecP256R1)?.ec_private()?;
+
+        let P = self.group.generator()?;
+        let X = EcPoint::muladd(&mut self.group, &P, &self.xy, &self.M, &self.w0)?;
+
+        let pA_internal = X.to_binary(&self.group, false)?;
+        let pA_internal = pA_internal.as_slice();
+        if pA_internal.len() != pA.len() {
+            error!("pA length mismatch");
+            return Err(Error::Invalid);
+        }
+        pA.copy_from_slice(pA_internal);
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn get_TT_as_prover(
+        &mut self,
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        let Y = EcPoint::from_binary(&self.group, pB)?;
+        let (Z, V) = CryptoMbedTLS::get_ZV_as_prover(
+            &self.w0,
+            &self.w1,
+            &mut self.N,
+            &Y,
+            &self.xy,
+            &self.order,
+            &mut self.group,
+        )?;
+
+        self.get_TT(context, pA, pB, &Z, &V, out)
+    }
+}
+
+impl CryptoMbedTLS {
+    #[allow(non_snake_case)]
+    fn get_TT(
+        &self,
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        Z: &EcPoint,
+        V: &EcPoint,
+        out: &mut [u8],
     ) -> Result<(), Error> {
         let mut TT = Md::new(mbedtls::hash::Type::Sha256)?;
         // context
@@ -179,17 +252,6 @@ impl CryptoSpake2 for CryptoMbedTLS {
         // Y = pB
         CryptoMbedTLS::add_to_tt(&mut TT, pB)?;
 
-        let X = EcPoint::from_binary(&self.group, pA)?;
-        let (Z, V) = CryptoMbedTLS::get_ZV_as_verifier(
-            &self.w0,
-            &self.L,
-            &mut self.M,
-            &X,
-            &self.xy,
-            &self.order,
-            &mut self.group,
-        )?;
-
         // Z
         let tmp = Z.to_binary(&self.group, false)?;
         let tmp = tmp.as_slice();
@@ -208,9 +270,7 @@ impl CryptoSpake2 for CryptoMbedTLS {
         TT.finish(out)?;
         Ok(())
     }
-}
 
-impl CryptoMbedTLS {
     fn add_to_tt(tt: &mut Md, buf: &[u8]) -> Result<(), Error> {
         let mut len_buf: [u8; 8] = [0; 8];
         LittleEndian::write_u64(&mut len_buf, buf.len() as u64);
@@ -223,7 +283,6 @@ impl CryptoMbedTLS {
 
     #[inline(always)]
     #[allow(non_snake_case)]
-    #[allow(dead_code)]
     fn get_ZV_as_prover(
         w0: &Mpi,
         w1: &Mpi,
@@ -188,6 +188,62 @@ impl CryptoSpake2 for CryptoRustCrypto {
         pA: &[u8],
         pB: &[u8],
         out: &mut [u8],
+    ) -> Result<(), Error> {
+        let X = p256::EncodedPoint::from_bytes(pA).unwrap();
+        let X = p256::AffinePoint::from_encoded_point(&X).unwrap();
+        let L = p256::AffinePoint::from_encoded_point(&self.L).unwrap();
+        let M = p256::AffinePoint::from_encoded_point(&self.M).unwrap();
+        let (Z, V) = Self::get_ZV_as_verifier(self.w0, L, M, X, self.xy)?;
+
+        Self::get_TT(context, pA, pB, &Z, &V, self.w0, out)
+    }
+
+    #[allow(non_snake_case)]
+    fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error> {
+        // From the SPAKE2+ spec (https://datatracker.ietf.org/doc/draft-bar-cfrg-spake2plus/)
+        //   for x
+        //   - select random x between 0 to p
+        //   - X = x*P + w0*M
+        //   - pA = X
+        let mut rng = rand::thread_rng();
+        self.xy = p256::Scalar::random(&mut rng);
+
+        let P = p256::AffinePoint::GENERATOR;
+        let M = p256::AffinePoint::from_encoded_point(&self.M).unwrap();
+        let X = Self::do_add_mul(P, self.xy, M, self.w0)?;
+        pA.copy_from_slice(X.as_bytes());
+
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn get_TT_as_prover(
+        &mut self,
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        let Y = p256::EncodedPoint::from_bytes(pB).map_err(|_| Error::Invalid)?;
+        let Y = Option::<p256::AffinePoint>::from(p256::AffinePoint::from_encoded_point(&Y))
+            .ok_or(Error::Invalid)?;
+        let N = p256::AffinePoint::from_encoded_point(&self.N).unwrap();
+        let (Z, V) = Self::get_ZV_as_prover(self.w0, self.w1, N, Y, self.xy)?;
+
+        Self::get_TT(context, pA, pB, &Z, &V, self.w0, out)
+    }
+}
+
+impl CryptoRustCrypto {
+    #[allow(non_snake_case)]
+    fn get_TT(
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        Z: &p256::EncodedPoint,
+        V: &p256::EncodedPoint,
+        w0: p256::Scalar,
+        out: &mut [u8],
     ) -> Result<(), Error> {
         let mut TT = sha2::Sha256::new();
         // Context
@@ -203,28 +259,19 @@ impl CryptoSpake2 for CryptoRustCrypto {
         Self::add_to_tt(&mut TT, pA)?;
         // Y = pB
         Self::add_to_tt(&mut TT, pB)?;
-
-        let X = p256::EncodedPoint::from_bytes(pA).unwrap();
-        let X = p256::AffinePoint::from_encoded_point(&X).unwrap();
-        let L = p256::AffinePoint::from_encoded_point(&self.L).unwrap();
-        let M = p256::AffinePoint::from_encoded_point(&self.M).unwrap();
-        let (Z, V) = Self::get_ZV_as_verifier(self.w0, L, M, X, self.xy)?;
-
         // Z
         Self::add_to_tt(&mut TT, Z.as_bytes())?;
         // V
         Self::add_to_tt(&mut TT, V.as_bytes())?;
         // w0
-        Self::add_to_tt(&mut TT, self.w0.to_bytes().to_vec().as_ref())?;
+        Self::add_to_tt(&mut TT, w0.to_bytes().to_vec().as_ref())?;
 
         let h = TT.finalize();
         out.copy_from_slice(h.as_slice());
 
         Ok(())
     }
-}
 
-impl CryptoRustCrypto {
     fn add_to_tt(tt: &mut sha2::Sha256, buf: &[u8]) -> Result<(), Error> {
         tt.update((buf.len() as u64).to_le_bytes());
         if !buf.is_empty() {
@@ -245,7 +292,6 @@ impl CryptoRustCrypto {
 
     #[inline(always)]
     #[allow(non_snake_case)]
-    #[allow(dead_code)]
     fn get_ZV_as_prover(
         w0: p256::Scalar,
         w1: p256::Scalar,
@@ -28,6 +28,7 @@ pub mod crypto_rustcrypto;
 
 pub mod core;
 pub mod crypto;
+pub mod msg_counter_sync;
 pub mod pake;
 pub mod spake2p;
 pub mod spake2p_test_vectors;
@@ -0,0 +1,488 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    sync::{Arc, Mutex},
+};
+
+use log::{error, info};
+use rand::RngCore;
+
+use crate::{
+    error::Error,
+    secure_channel::common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+    transport::{
+        counters::GroupCounters,
+        dedup::{RxCtrState, MSG_RX_STATE_BITMAP_LEN},
+        network::Address,
+        packet::Packet,
+        proto_demux::{ProtoCtx, ResponseRequired},
+    },
+    utils::writebuf::WriteBuf,
+};
+
+const MCSP_CHALLENGE_LEN: usize = 8;
+const MCSP_RESP_LEN: usize = 4 + MCSP_CHALLENGE_LEN;
+const MAX_PENDING_SYNCS: usize = 4;
+const MAX_SYNCED_PEERS: usize = 16;
+const MAX_CACHED_MSGS: usize = 4;
+
+struct PendingSync {
+    fab_idx: u8,
+    peer_nodeid: u64,
+    challenge: [u8; MCSP_CHALLENGE_LEN],
+}
+
+struct SyncedPeer {
+    fab_idx: u8,
+    peer_nodeid: u64,
+    rx_ctr_state: RxCtrState,
+}
+
+// A data message from a peer that isn't synchronized yet, as it came off the wire
+struct CachedMsg {
+    fab_idx: u8,
+    peer_nodeid: u64,
+    ctr: u32,
+    msg: Vec<u8>,
+    addr: Address,
+}
+
+struct MsgCounterSyncInner {
+    counters: Arc<Mutex<GroupCounters>>,
+    pending: Vec<PendingSync>,
+    // The peers whose data message counter is known
+    peers: Vec<SyncedPeer>,
+    // The peers whose control message counter is known, these are always trusted first
+    ctrl_peers: Vec<SyncedPeer>,
+    cached: Vec<CachedMsg>,
+    // The cached messages of the peers that got synchronized, ready to be received again
+    released: VecDeque<CachedMsg>,
+    // The counters of the released messages, which are not duplicates the first time
+    accepted: Vec<(u8, u64, u32)>,
+}
+
+/// The Message Counter Synchronization Protocol
+///
+/// A node that receives a group message from a peer whose group counter it doesn't
+/// know yet, may ask the peer for it with a MsgCounterSyncReq. The peer responds with the
+/// current value of its Global Group Encrypted Data Message Counter, after which the
+/// messages from that peer can be checked for duplicates and replays.
+///
+/// Until then, what happens to the data messages depends on the policy of the key set:
+/// with TrustFirst, the counter of the first message is trusted; with CacheAndSync, the
+/// messages are cached, and only received once the peer is synchronized. Control
+/// messages have their own counter, which is always trusted first.
+///
+/// The peers are identified by their fabric index and node id.
+#[derive(Clone)]
+pub struct MsgCounterSync(Arc<Mutex<MsgCounterSyncInner>>);
+
+impl MsgCounterSync {
+    pub fn new(counters: Arc<Mutex<GroupCounters>>) -> Self {
+        Self(Arc::new(Mutex::new(MsgCounterSyncInner {
+            counters,
+            pending: Vec::with_capacity(MAX_PENDING_SYNCS),
+            peers: Vec::with_capacity(MAX_SYNCED_PEERS),
+            ctrl_peers: Vec::with_capacity(MAX_SYNCED_PEERS),
+            cached: Vec::with_capacity(MAX_CACHED_MSGS),
+            released: VecDeque::with_capacity(MAX_CACHED_MSGS),
+            accepted: Vec::with_capacity(MAX_CACHED_MSGS),
+        })))
+    }
+
+    /// Prepare a MsgCounterSyncReq towards the given peer
+    pub fn prepare_req(&self, fab_idx: u8, peer_nodeid: u64, tx: &mut Packet) -> Result<(), Error> {
+        tx.set_proto_id(PROTO_ID_SECURE_CHANNEL as u16);
+        tx.set_proto_opcode(OpCode::MsgCounterSyncReq as u8);
+        let challenge = self.new_challenge(fab_idx, peer_nodeid);
+        tx.get_writebuf()?.append(&challenge)
+    }
+
+    fn new_challenge(&self, fab_idx: u8, peer_nodeid: u64) -> [u8; MCSP_CHALLENGE_LEN] {
+        let mut challenge = [0u8; MCSP_CHALLENGE_LEN];
+        rand::thread_rng().fill_bytes(&mut challenge);
+
+        let mut s = self.0.lock().unwrap();
+        // Only the latest request to a peer is valid
+        s.pending
+            .retain(|p| !(p.fab_idx == fab_idx && p.peer_nodeid == peer_nodeid));
+        if s.pending.len() == MAX_PENDING_SYNCS {
+            s.pending.remove(0);
+        }
+        s.pending.push(PendingSync {
+            fab_idx,
+            peer_nodeid,
+            challenge,
+        });
+        challenge
+    }
+
+    pub fn req_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
+        ctx.tx.set_proto_opcode(OpCode::MsgCounterSyncResp as u8);
+        self.handle_req(ctx.rx.as_borrow_slice(), ctx.tx.get_writebuf()?)?;
+        Ok(ResponseRequired::Yes)
+    }
+
+    pub(crate) fn handle_req(&self, req: &[u8], resp: &mut WriteBuf) -> Result<(), Error> {
+        if req.len() != MCSP_CHALLENGE_LEN {
+            error!("Invalid MsgCounterSyncReq length {}", req.len());
+            return Err(Error::Invalid);
+        }
+        let s = self.0.lock().unwrap();
+        // The current value of the counter is the one of the next message, the last
+        // message that was sent has the one before
+        let ctr = s.counters.lock().unwrap().data.current().wrapping_sub(1);
+        resp.le_u32(ctr)?;
+        resp.append(req)
+    }
+
+    pub fn resp_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
+        let sess = &ctx.exch_ctx.sess;
+        let (fab_idx, peer_nodeid) = sess
+            .get_local_fabric_idx()
+            .zip(sess.get_peer_node_id())
+            .ok_or_else(|| {
+                error!("MsgCounterSyncResp outside of a CASE session");
+                Error::Invalid
+            })?;
+        self.handle_resp(fab_idx, peer_nodeid, ctx.rx.as_borrow_slice())?;
+        Ok(ResponseRequired::No)
+    }
+
+    pub(crate) fn handle_resp(
+        &self,
+        fab_idx: u8,
+        peer_nodeid: u64,
+        resp: &[u8],
+    ) -> Result<(), Error> {
+        if resp.len() != MCSP_RESP_LEN {
+            error!("Invalid MsgCounterSyncResp length {}", resp.len());
+            return Err(Error::Invalid);
+        }
+        let ctr = u32::from_le_bytes(resp[..4].try_into()?);
+        let challenge = &resp[4..];
+
+        let mut s = self.0.lock().unwrap();
+        let index = s
+            .pending
+            .iter()
+            .position(|p| {
+                p.fab_idx == fab_idx && p.peer_nodeid == peer_nodeid && p.challenge == challenge
+            })
+            .ok_or_else(|| {
+                error!("Unexpected MsgCounterSyncResp from {:x}", peer_nodeid);
+                Error::Invalid
+            })?;
+        s.pending.remove(index);
+
+        info!("Synchronized with {:x} at counter {}", peer_nodeid, ctr);
+        s.add_peer(fab_idx, peer_nodeid, ctr);
+        s.release(fab_idx, peer_nodeid, ctr);
+        Ok(())
+    }
+
+    /// Returns whether the data message counter of the peer is known
+    pub fn is_synced(&self, fab_idx: u8, peer_nodeid: u64) -> bool {
+        let mut s = self.0.lock().unwrap();
+        find_peer(&mut s.peers, fab_idx, peer_nodeid).is_some()
+    }
+
+    /// Check the counter of a group message from a peer
+    ///
+    /// Returns whether the message is a duplicate. The data messages of a peer that
+    /// isn't synchronized yet are only received here with the TrustFirst policy, so
+    /// their counter is trusted, and so is that of the control messages.
+    pub fn recv(&self, fab_idx: u8, peer_nodeid: u64, msg_ctr: u32, is_control: bool) -> bool {
+        let mut s = self.0.lock().unwrap();
+        let s = &mut *s;
+        if !is_control {
+            if let Some(index) = s
+                .accepted
+                .iter()
+                .position(|a| *a == (fab_idx, peer_nodeid, msg_ctr))
+            {
+                s.accepted.remove(index);
+                return false;
+            }
+        }
+        let peers = if is_control {
+            &mut s.ctrl_peers
+        } else {
+            &mut s.peers
+        };
+        if let Some(p) = find_peer(peers, fab_idx, peer_nodeid) {
+            p.rx_ctr_state.recv(msg_ctr, true)
+        } else {
+            info!("Trusting counter {} of {:x}", msg_ctr, peer_nodeid);
+            add_peer(peers, fab_idx, peer_nodeid, msg_ctr);
+            false
+        }
+    }
+
+    /// Cache a data message from a peer that isn't synchronized yet
+    ///
+    /// The message is received again once the peer is, see [MsgCounterSync::take_released].
+    /// If the cache is full, the oldest message is dropped.
+    pub fn cache(&self, fab_idx: u8, peer_nodeid: u64, ctr: u32, msg: &[u8], addr: Address) {
+        let mut s = self.0.lock().unwrap();
+        if s.cached
+            .iter()
+            .any(|c| c.fab_idx == fab_idx && c.peer_nodeid == peer_nodeid && c.ctr == ctr)
+        {
+            return;
+        }
+        if s.cached.len() == MAX_CACHED_MSGS {
+            s.cached.remove(0);
+        }
+        s.cached.push(CachedMsg {
+            fab_idx,
+            peer_nodeid,
+            ctr,
+            msg: msg.to_vec(),
+            addr,
+        });
+    }
+
+    /// Returns whether there are cached messages ready to be received again
+    pub fn has_released(&self) -> bool {
+        !self.0.lock().unwrap().released.is_empty()
+    }
+
+    /// Take the next cached message that is ready to be received again, with the address
+    /// it came from
+    pub fn take_released(&self) -> Option<(Vec<u8>, Address)> {
+        let mut s = self.0.lock().unwrap();
+        s.released.pop_front().map(|c| (c.msg, c.addr))
+    }
+
+    /// Forget all the synchronized peers, and the cached messages
+    pub fn reset(&self) {
+        let mut s = self.0.lock().unwrap();
+        s.pending.clear();
+        s.peers.clear();
+        s.ctrl_peers.clear();
+        s.cached.clear();
+        s.released.clear();
+        s.accepted.clear();
+    }
+}
+
+impl MsgCounterSyncInner {
+    fn add_peer(&mut self, fab_idx: u8, peer_nodeid: u64, ctr: u32) {
+        add_peer(&mut self.peers, fab_idx, peer_nodeid, ctr);
+    }
+
+    // The synchronized counter is that of the last message the peer sent. The cached
+    // messages at or below it, within the window of the duplicate detection, are
+    // received again and the others are dropped.
+    fn release(&mut self, fab_idx: u8, peer_nodeid: u64, ctr: u32) {
+        let (mut cached, others): (Vec<_>, Vec<_>) = std::mem::take(&mut self.cached)
+            .into_iter()
+            .partition(|c| c.fab_idx == fab_idx && c.peer_nodeid == peer_nodeid);
+        self.cached = others;
+
+        cached.sort_by_key(|c| c.ctr);
+        for c in cached {
+            if ctr.wrapping_sub(c.ctr) > MSG_RX_STATE_BITMAP_LEN {
+                info!("Dropping cached message {} of {:x}", c.ctr, peer_nodeid);
+                continue;
+            }
+            if self.released.len() == MAX_CACHED_MSGS {
+                self.released.pop_front();
+            }
+            if self.accepted.len() == MAX_CACHED_MSGS {
+                self.accepted.remove(0);
+            }
+            self.accepted.push((fab_idx, peer_nodeid, c.ctr));
+            self.released.push_back(c);
+        }
+    }
+}
+
+fn find_peer(peers: &mut [SyncedPeer], fab_idx: u8, peer_nodeid: u64) -> Option<&mut SyncedPeer> {
+    peers
+        .iter_mut()
+        .find(|p| p.fab_idx == fab_idx && p.peer_nodeid == peer_nodeid)
+}
+
+fn add_peer(peers: &mut Vec<SyncedPeer>, fab_idx: u8, peer_nodeid: u64, ctr: u32) {
+    peers.retain(|p| !(p.fab_idx == fab_idx && p.peer_nodeid == peer_nodeid));
+    if peers.len() == MAX_SYNCED_PEERS {
+        peers.remove(0);
+    }
+    peers.push(SyncedPeer {
+        fab_idx,
+        peer_nodeid,
+        rx_ctr_state: RxCtrState::new(ctr),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        persist::MemKvStore,
+        transport::{counters::GroupCounters, network::Address},
+        utils::writebuf::WriteBuf,
+    };
+
+    use super::{MsgCounterSync, MCSP_CHALLENGE_LEN, MCSP_RESP_LEN};
+
+    const FAB_IDX: u8 = 1;
+    const SENDER: u64 = 0x1234;
+    const DATA: bool = false;
+    const CONTROL: bool = true;
+
+    fn new_mcsp() -> (MsgCounterSync, Arc<Mutex<GroupCounters>>) {
+        let storage = Arc::new(Mutex::new(MemKvStore::new()));
+        let counters = Arc::new(Mutex::new(GroupCounters::new(storage).unwrap()));
+        (MsgCounterSync::new(counters.clone()), counters)
+    }
+
+    fn respond(sender: &MsgCounterSync, challenge: &[u8]) -> [u8; MCSP_RESP_LEN] {
+        let mut buf = [0u8; MCSP_RESP_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MCSP_RESP_LEN);
+        sender.handle_req(challenge, &mut wb).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_sync() {
+        let (sender, counters) = new_mcsp();
+        let (receiver, _) = new_mcsp();
+        let sent_before = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+
+        let challenge = receiver.new_challenge(FAB_IDX, SENDER);
+        let resp = respond(&sender, &challenge);
+        receiver.handle_resp(FAB_IDX, SENDER, &resp).unwrap();
+
+        // Anything sent up to the sync is a replay, anything after it is new
+        assert!(receiver.recv(FAB_IDX, SENDER, sent_before, DATA));
+        let ctr = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        assert!(!receiver.recv(FAB_IDX, SENDER, ctr, DATA));
+        assert!(receiver.recv(FAB_IDX, SENDER, ctr, DATA));
+        let ctr = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        assert!(!receiver.recv(FAB_IDX, SENDER, ctr, DATA));
+    }
+
+    #[test]
+    fn test_trust_first() {
+        let (receiver, _) = new_mcsp();
+
+        // The first counter of a peer is trusted, whatever its value
+        assert!(!receiver.recv(FAB_IDX, SENDER, 1000, DATA));
+        assert!(receiver.recv(FAB_IDX, SENDER, 1000, DATA));
+        assert!(receiver.recv(FAB_IDX, SENDER, 999, DATA));
+        assert!(!receiver.recv(FAB_IDX, SENDER, 1001, DATA));
+        // The same node id on another fabric is another peer
+        assert!(!receiver.recv(FAB_IDX + 1, SENDER, 5, DATA));
+
+        receiver.reset();
+        assert!(!receiver.recv(FAB_IDX, SENDER, 1000, DATA));
+    }
+
+    #[test]
+    fn test_control_trust_first() {
+        let (sender, counters) = new_mcsp();
+        let (receiver, _) = new_mcsp();
+
+        // The control counter is trusted first, even once the data counter is synchronized
+        let challenge = receiver.new_challenge(FAB_IDX, SENDER);
+        let resp = respond(&sender, &challenge);
+        receiver.handle_resp(FAB_IDX, SENDER, &resp).unwrap();
+        assert!(receiver.is_synced(FAB_IDX, SENDER));
+        let ctr = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        assert!(!receiver.recv(FAB_IDX, SENDER, 5, CONTROL));
+        assert!(receiver.recv(FAB_IDX, SENDER, 5, CONTROL));
+        assert!(!receiver.recv(FAB_IDX, SENDER, ctr, DATA));
+        assert!(!receiver.is_synced(FAB_IDX + 1, SENDER));
+    }
+
+    #[test]
+    fn test_cache_and_release() {
+        let (sender, counters) = new_mcsp();
+        let (receiver, _) = new_mcsp();
+        let addr = Address::default();
+
+        let old = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        // Skip past the window of the duplicate detection
+        for _ in 0..20 {
+            counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        }
+        let first = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        let second = counters.lock().unwrap().data.get_msg_ctr().unwrap();
+        receiver.cache(FAB_IDX, SENDER, second, &[2], addr);
+        receiver.cache(FAB_IDX, SENDER, first, &[1], addr);
+        receiver.cache(FAB_IDX, SENDER, first, &[1], addr);
+        receiver.cache(FAB_IDX, SENDER, old, &[0], addr);
+        receiver.cache(FAB_IDX + 1, SENDER, first, &[3], addr);
+        assert!(!receiver.has_released());
+
+        let challenge = receiver.new_challenge(FAB_IDX, SENDER);
+        let resp = respond(&sender, &challenge);
+        receiver.handle_resp(FAB_IDX, SENDER, &resp).unwrap();
+
+        // The messages within the window are released in order, the older one is dropped,
+        // and so is the copy. The messages of the other peer stay cached.
+        assert!(receiver.has_released());
+        assert_eq!(receiver.take_released().unwrap().0, [1]);
+        assert_eq!(receiver.take_released().unwrap().0, [2]);
+        assert!(receiver.take_released().is_none());
+
+        // The released messages are received once
+        assert!(!receiver.recv(FAB_IDX, SENDER, first, DATA));
+        assert!(receiver.recv(FAB_IDX, SENDER, first, DATA));
+        assert!(!receiver.recv(FAB_IDX, SENDER, second, DATA));
+        assert!(receiver.recv(FAB_IDX, SENDER, second, DATA));
+        assert!(receiver.recv(FAB_IDX, SENDER, old, DATA));
+    }
+
+    #[test]
+    fn test_unexpected_resp() {
+        let (sender, _) = new_mcsp();
+        let (receiver, _) = new_mcsp();
+
+        // A response without a request
+        let resp = respond(&sender, &[1; MCSP_CHALLENGE_LEN]);
+        assert!(receiver.handle_resp(FAB_IDX, SENDER, &resp).is_err());
+
+        // A response from the wrong peer, or to an older request
+        let old = receiver.new_challenge(FAB_IDX, SENDER);
+        let challenge = receiver.new_challenge(FAB_IDX, SENDER);
+        let resp = respond(&sender, &challenge);
+        assert!(receiver.handle_resp(FAB_IDX, SENDER + 1, &resp).is_err());
+        assert!(receiver.handle_resp(FAB_IDX + 1, SENDER, &resp).is_err());
+        let old_resp = respond(&sender, &old);
+        assert!(receiver.handle_resp(FAB_IDX, SENDER, &old_resp).is_err());
+        assert!(receiver.handle_resp(FAB_IDX, SENDER, &resp[..8]).is_err());
+        receiver.handle_resp(FAB_IDX, SENDER, &resp).unwrap();
+        // The request is answered only once
+        assert!(receiver.handle_resp(FAB_IDX, SENDER, &resp).is_err());
+    }
+
+    #[test]
+    fn test_invalid_req() {
+        let (sender, _) = new_mcsp();
+        let mut buf = [0u8; MCSP_RESP_LEN];
+        let mut wb = WriteBuf::new(&mut buf, MCSP_RESP_LEN);
+        assert!(sender.handle_req(&[1, 2, 3], &mut wb).is_err());
+    }
+}
@@ -33,10 +33,12 @@ use crate::{
     tlv::{self, get_root_node_struct, FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV},
     transport::{
         exchange::ExchangeCtx,
+        mrp::{MrpParams, SessionParams},
         network::Address,
         proto_demux::{ProtoCtx, ResponseRequired},
         queue::{Msg, WorkQ},
         session::{CloneData, SessionMode},
+        timer::{TimerAction, TimerId, Timers},
     },
 };
 use log::{error, info};
@@ -49,6 +51,26 @@ enum PaseMgrState {
 
 pub struct PaseMgrInternal {
     state: PaseMgrState,
+    // The discriminator that the commissionable service is published with
+    discriminator: u16,
+    mdns: Arc<Mdns>,
+    work_q: WorkQ,
+    timers: Timers,
+    // Closes the commissioning window, if it was opened with a timeout
+    window_timer: Option<TimerId>,
+    // Discards the PASE session that is being established, if it doesn't complete in time
+    discard_timer: Option<TimerId>,
+}
+
+impl PaseMgrInternal {
+    fn cancel_timers(&mut self) {
+        if let Some(timer) = self.window_timer.take() {
+            self.timers.cancel(timer);
+        }
+        if let Some(timer) = self.discard_timer.take() {
+            self.timers.cancel(timer);
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -56,28 +78,103 @@ pub struct PaseMgrInternal {
 pub struct PaseMgr(Arc<Mutex<PaseMgrInternal>>);
 
 impl PaseMgr {
-    pub fn new() -> Self {
+    pub fn new(mdns: Arc<Mdns>, work_q: WorkQ, timers: Timers) -> Self {
         Self(Arc::new(Mutex::new(PaseMgrInternal {
             state: PaseMgrState::Disabled,
+            discriminator: 0,
+            mdns,
+            work_q,
+            timers,
+            window_timer: None,
+            discard_timer: None,
         })))
     }
 
+    /// Open the commissioning window
+    ///
+    /// The window closes by itself after the timeout, if one is given.
     pub fn enable_pase_session(
         &mut self,
         verifier: VerifierData,
         discriminator: u16,
+        timeout: Option<Duration>,
     ) -> Result<(), Error> {
         let mut s = self.0.lock().unwrap();
+        let mdns = PaseMgr::publish(&s.mdns, discriminator)?;
+        s.cancel_timers();
+        s.state = PaseMgrState::Enabled(PAKE::new(verifier), mdns);
+        s.discriminator = discriminator;
+        if let Some(timeout) = timeout {
+            let pase_mgr = self.clone();
+            s.window_timer = Some(s.timers.add_oneshot(
+                timeout,
+                TimerAction::Callback(Box::new(move |id| pase_mgr.close_window(id))),
+            ));
+        }
+        Ok(())
+    }
+
+    fn close_window(&self, timer: TimerId) {
+        let mut s = self.0.lock().unwrap();
+        if s.window_timer == Some(timer) {
+            info!("Commissioning window timed out");
+            s.window_timer = None;
+            s.cancel_timers();
+            s.state = PaseMgrState::Disabled;
+        }
+    }
+
+    fn discard_session(&self, timer: TimerId) {
+        let mut s = self.0.lock().unwrap();
+        if s.discard_timer == Some(timer) {
+            s.discard_timer = None;
+            if let PaseMgrState::Enabled(pake, _) = &mut s.state {
+                if !pake.state.is_idle() {
+                    info!("PASE session not established in time, discarding it");
+                    pake.state = PakeState::Idle;
+                }
+            }
+        }
+    }
+
+    // Start the discard timer of the session that is being established, if there is one
+    fn start_discard_timer(&self) {
+        let mut s = self.0.lock().unwrap();
+        let in_progress =
+            matches!(&s.state, PaseMgrState::Enabled(pake, _) if !pake.state.is_idle());
+        if let Some(timer) = s.discard_timer.take() {
+            s.timers.cancel(timer);
+        }
+        if in_progress {
+            let pase_mgr = self.clone();
+            s.discard_timer = Some(s.timers.add_oneshot(
+                PASE_DISCARD_TIMEOUT_SECS,
+                TimerAction::Callback(Box::new(move |id| pase_mgr.discard_session(id))),
+            ));
+        }
+    }
+
+    fn publish(mdns: &Mdns, discriminator: u16) -> Result<SysMdnsService, Error> {
         let name: u64 = rand::thread_rng().gen_range(0..0xFFFFFFFFFFFFFFFF);
         let name = format!("{:016X}", name);
-        let mdns = Mdns::get()?
-            .publish_service(&name, mdns::ServiceMode::Commissionable(discriminator))?;
-        s.state = PaseMgrState::Enabled(PAKE::new(verifier), mdns);
+        mdns.publish_service(&name, mdns::ServiceMode::Commissionable(discriminator))
+    }
+
+    /// Publish the commissionable service again, if the PASE session is enabled
+    ///
+    /// This picks up any change in what the node advertises over mDNS.
+    pub fn republish(&mut self) -> Result<(), Error> {
+        let mut s = self.0.lock().unwrap();
+        let s = &mut *s;
+        if let PaseMgrState::Enabled(_, service) = &mut s.state {
+            *service = PaseMgr::publish(&s.mdns, s.discriminator)?;
+        }
         Ok(())
     }
 
     pub fn disable_pase_session(&mut self) {
         let mut s = self.0.lock().unwrap();
+        s.cancel_timers();
         s.state = PaseMgrState::Disabled;
     }
 
@@ -99,6 +196,7 @@ impl PaseMgr {
     pub fn pbkdfparamreq_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
         ctx.tx.set_proto_opcode(OpCode::PBKDFParamResponse as u8);
         self.if_enabled(ctx, |pake, ctx| pake.handle_pbkdfparamrequest(ctx))?;
+        self.start_discard_timer();
         Ok(ResponseRequired::Yes)
     }
 
@@ -109,18 +207,13 @@ impl PaseMgr {
     }
 
     pub fn pasepake3_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
-        self.if_enabled(ctx, |pake, ctx| pake.handle_pasepake3(ctx))?;
+        let work_q = self.0.lock().unwrap().work_q.clone();
+        self.if_enabled(ctx, |pake, ctx| pake.handle_pasepake3(ctx, &work_q))?;
         self.disable_pase_session();
         Ok(ResponseRequired::Yes)
     }
 }
 
-impl Default for PaseMgr {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 // This file basically deals with the handlers for the PASE secure channel protocol
 // TLV extraction and encoding is done in this file.
 // We create a Spake2p object and set it up in the exchange-data. This object then
@@ -135,11 +228,12 @@ struct SessionData {
     exch_id: u16,
     peer_addr: Address,
     spake2p: Box<Spake2P>,
+    mrp_params: MrpParams,
 }
 
 impl SessionData {
-    fn is_sess_expired(&self) -> Result<bool, Error> {
-        if SystemTime::now().duration_since(self.start_time)? > PASE_DISCARD_TIMEOUT_SECS {
+    fn is_sess_expired(&self, now: SystemTime) -> Result<bool, Error> {
+        if now.duration_since(self.start_time)? > PASE_DISCARD_TIMEOUT_SECS {
             Ok(true)
         } else {
             Ok(false)
@@ -149,11 +243,11 @@ impl SessionData {
 
 enum PakeState {
     Idle,
-    InProgress(SessionData),
+    InProgress(Box<SessionData>),
 }
 
 impl PakeState {
-    fn take(&mut self) -> Result<SessionData, Error> {
+    fn take(&mut self) -> Result<Box<SessionData>, Error> {
         let new = std::mem::replace(self, PakeState::Idle);
         if let PakeState::InProgress(s) = new {
             Ok(s)
@@ -166,7 +260,7 @@ impl PakeState {
         std::mem::discriminant(self) == std::mem::discriminant(&PakeState::Idle)
     }
 
-    fn take_sess_data(&mut self, exch_ctx: &ExchangeCtx) -> Result<SessionData, Error> {
+    fn take_sess_data(&mut self, exch_ctx: &ExchangeCtx) -> Result<Box<SessionData>, Error> {
         let sd = self.take()?;
         if sd.exch_id != exch_ctx.exch.get_id() || sd.peer_addr != exch_ctx.sess.get_peer_addr() {
             Err(Error::InvalidState)
@@ -175,16 +269,22 @@ impl PakeState {
         }
     }
 
-    fn make_in_progress(&mut self, spake2p: Box<Spake2P>, exch_ctx: &ExchangeCtx) {
-        *self = PakeState::InProgress(SessionData {
-            start_time: SystemTime::now(),
+    fn make_in_progress(
+        &mut self,
+        spake2p: Box<Spake2P>,
+        mrp_params: MrpParams,
+        exch_ctx: &ExchangeCtx,
+    ) {
+        *self = PakeState::InProgress(Box::new(SessionData {
+            start_time: exch_ctx.sess.get_clock().now(),
             spake2p,
+            mrp_params,
             exch_id: exch_ctx.exch.get_id(),
             peer_addr: exch_ctx.sess.get_peer_addr(),
-        });
+        }));
     }
 
-    fn set_sess_data(&mut self, sd: SessionData) {
+    fn set_sess_data(&mut self, sd: Box<SessionData>) {
         *self = PakeState::InProgress(sd);
     }
 }
@@ -210,7 +310,7 @@ impl PAKE {
     }
 
     #[allow(non_snake_case)]
-    pub fn handle_pasepake3(&mut self, ctx: &mut ProtoCtx) -> Result<(), Error> {
+    pub fn handle_pasepake3(&mut self, ctx: &mut ProtoCtx, work_q: &WorkQ) -> Result<(), Error> {
         let mut sd = self.state.take_sess_data(&ctx.exch_ctx)?;
 
         let cA = extract_pasepake_1_or_3_params(ctx.rx.as_borrow_slice())?;
@@ -240,9 +340,10 @@ impl PAKE {
             clone_data
                 .att_challenge
                 .copy_from_slice(&session_keys[32..48]);
+            clone_data.mrp_params = sd.mrp_params;
 
             // Queue a transport mgr request to add a new session
-            WorkQ::get()?.sync_send(Msg::NewSession(clone_data))?;
+            work_q.try_send(Msg::NewSession(clone_data))?;
         }
 
         create_sc_status_report(&mut ctx.tx, status_code, None)?;
@@ -275,7 +376,7 @@ impl PAKE {
     pub fn handle_pbkdfparamrequest(&mut self, ctx: &mut ProtoCtx) -> Result<(), Error> {
         if !self.state.is_idle() {
             let sd = self.state.take()?;
-            if sd.is_sess_expired()? {
+            if sd.is_sess_expired(ctx.exch_ctx.sess.get_clock().now())? {
                 info!("Previous session expired, clearing it");
                 self.state = PakeState::Idle;
             } else {
@@ -292,6 +393,7 @@ impl PAKE {
             error!("Can't yet handle passcode_id != 0");
             return Err(Error::Invalid);
         }
+        let mrp_params: MrpParams = a.sed_params.into();
 
         let mut our_random: [u8; 32] = [0; 32];
         rand::thread_rng().fill_bytes(&mut our_random);
@@ -319,7 +421,8 @@ impl PAKE {
         resp.to_tlv(&mut tw, TagType::Anonymous)?;
 
         spake2p.set_context(ctx.rx.as_borrow_slice(), ctx.tx.as_borrow_slice())?;
-        self.state.make_in_progress(spake2p, &ctx.exch_ctx);
+        self.state
+            .make_in_progress(spake2p, mrp_params, &ctx.exch_ctx);
 
         Ok(())
     }
@@ -362,4 +465,5 @@ struct PBKDFParamReq<'a> {
     initiator_ssid: u16,
     passcode_id: u16,
     has_params: bool,
+    sed_params: Option<SessionParams>,
 }
@@ -49,7 +49,8 @@ use super::{common::SCStatusCodes, crypto::CryptoSpake2};
 // out the specific implementations.
 //
 // In the case of the verifier, we don't actually release the Ke until we
-// validate that the cA is confirmed.
+// validate that the cA is confirmed. In the case of the prover, the Ke is
+// released as soon as the cB from the verifier is confirmed.
 
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Spake2VerifierState {
@@ -113,6 +114,7 @@ impl Default for Spake2P {
     }
 }
 
+#[derive(Clone)]
 pub struct VerifierData {
     pub data: VerifierOption,
     // For the VerifierOption::Verifier, the following fields only serve
@@ -121,6 +123,7 @@ pub struct VerifierData {
     pub count: u32,
 }
 
+#[derive(Clone)]
 pub enum VerifierOption {
     /// With Password
     Password(u32),
@@ -259,9 +262,61 @@ impl Spake2P {
         }
     }
 
+    pub fn start_prover(&mut self, pw: u32, count: u32, salt: &[u8]) -> Result<(), Error> {
+        let mut crypto_spake2 = crypto_spake2_new()?;
+        // Derive w0 and w1 from the password
+        let mut w0w1s: [u8; 2 * CRYPTO_W_SIZE_BYTES] = [0; 2 * CRYPTO_W_SIZE_BYTES];
+        Spake2P::get_w0w1s(pw, count, salt, &mut w0w1s);
+
+        let w0s_len = w0w1s.len() / 2;
+        crypto_spake2.set_w0_from_w0s(&w0w1s[0..w0s_len])?;
+        crypto_spake2.set_w1_from_w1s(&w0w1s[w0s_len..])?;
+        self.crypto_spake2 = Some(crypto_spake2);
+        self.mode = Spake2Mode::Prover;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error> {
+        if self.mode != Spake2Mode::Prover {
+            return Err(Error::InvalidState);
+        }
+        let crypto_spake2 = self.crypto_spake2.as_mut().ok_or(Error::InvalidState)?;
+        crypto_spake2.get_pA(pA)
+    }
+
+    #[allow(non_snake_case)]
+    pub fn handle_pB(
+        &mut self,
+        pA: &[u8],
+        pB: &[u8],
+        cB: &[u8],
+        cA: &mut [u8],
+    ) -> Result<&[u8], Error> {
+        if self.mode != Spake2Mode::Prover {
+            return Err(Error::InvalidState);
+        }
+        // We are finished with using the crypto_spake2 after this
+        let mut crypto_spake2 = self.crypto_spake2.take().ok_or(Error::InvalidState)?;
+        let context = self.context.take().ok_or(Error::InvalidState)?;
+
+        let mut hash = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        context.finish(&mut hash)?;
+        let mut TT = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        crypto_spake2.get_TT_as_prover(&hash, pA, pB, &mut TT)?;
+
+        let mut our_cB = [0u8; 32];
+        Spake2P::get_Ke_and_cAcB(&TT, pA, pB, &mut self.Ke, cA, &mut our_cB)?;
+        if cB.ct_eq(&our_cB).unwrap_u8() == 1 {
+            Ok(&self.Ke)
+        } else {
+            error!("Verifier confirmation cB doesn't match");
+            Err(Error::Invalid)
+        }
+    }
+
     #[inline(always)]
     #[allow(non_snake_case)]
-    #[allow(dead_code)]
     fn get_Ke_and_cAcB(
         TT: &[u8],
         pA: &[u8],
@@ -304,10 +359,13 @@ impl Spake2P {
 #[cfg(test)]
 mod tests {
 
-    use super::Spake2P;
+    use super::{Spake2P, VerifierData};
     use crate::{
         crypto,
-        secure_channel::{spake2p::CRYPTO_W_SIZE_BYTES, spake2p_test_vectors::test_vectors::*},
+        secure_channel::{
+            common::SCStatusCodes, spake2p::CRYPTO_W_SIZE_BYTES,
+            spake2p_test_vectors::test_vectors::*,
+        },
     };
 
     #[test]
@@ -349,4 +407,55 @@ mod tests {
             assert_eq!(cB, t.cB);
         }
     }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_prover_verifier() {
+        let verifier_data = VerifierData::new_with_pw(123456);
+
+        let mut verifier = Spake2P::new();
+        verifier.set_context(b"req", b"resp").unwrap();
+        verifier.start_verifier(&verifier_data).unwrap();
+
+        let mut prover = Spake2P::new();
+        prover.set_context(b"req", b"resp").unwrap();
+        prover
+            .start_prover(123456, verifier_data.count, &verifier_data.salt)
+            .unwrap();
+
+        let mut pA = [0u8; 65];
+        let mut pB = [0u8; 65];
+        let mut cA = [0u8; 32];
+        let mut cB = [0u8; 32];
+        prover.get_pA(&mut pA).unwrap();
+        verifier.handle_pA(&pA, &mut pB, &mut cB).unwrap();
+        let prover_Ke = prover.handle_pB(&pA, &pB, &cB, &mut cA).unwrap().to_vec();
+        let (status, verifier_Ke) = verifier.handle_cA(&cA);
+        assert!(status == SCStatusCodes::SessionEstablishmentSuccess);
+        assert_eq!(verifier_Ke.unwrap(), prover_Ke.as_slice());
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_prover_wrong_passcode() {
+        let verifier_data = VerifierData::new_with_pw(123456);
+
+        let mut verifier = Spake2P::new();
+        verifier.set_context(b"req", b"resp").unwrap();
+        verifier.start_verifier(&verifier_data).unwrap();
+
+        let mut prover = Spake2P::new();
+        prover.set_context(b"req", b"resp").unwrap();
+        prover
+            .start_prover(654321, verifier_data.count, &verifier_data.salt)
+            .unwrap();
+
+        let mut pA = [0u8; 65];
+        let mut pB = [0u8; 65];
+        let mut cA = [0u8; 32];
+        let mut cB = [0u8; 32];
+        prover.get_pA(&mut pA).unwrap();
+        verifier.handle_pA(&pA, &mut pB, &mut cB).unwrap();
+        assert!(prover.handle_pB(&pA, &pB, &cB, &mut cA).is_err());
+    }
 }
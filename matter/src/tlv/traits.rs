@@ -293,6 +293,18 @@ impl<T> TLVArrayOwned<T> {
     }
 }
 
+impl<T> From<Vec<T>> for TLVArrayOwned<T> {
+    fn from(vec: Vec<T>) -> Self {
+        Self(vec)
+    }
+}
+
+impl<T> From<TLVArrayOwned<T>> for Vec<T> {
+    fn from(array: TLVArrayOwned<T>) -> Self {
+        array.0
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum TLVArray<'a, T> {
     // This is used for the to-tlv path
@@ -0,0 +1,194 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use log::info;
+use rand::Rng;
+
+use crate::{error::Error, persist::SharedKvStore};
+
+/// The range that a random initial message counter is picked from
+pub const MSG_CTR_INIT_RANGE: u32 = 0x0fffffff;
+
+/// The number of counter values that are reserved at a time
+///
+/// Only the end of the reservation is stored, so a counter causes a write to the storage
+/// only once every so many messages. After a reboot, the counter resumes from the end of
+/// the last reservation, which is never lower than any value that was already used.
+pub const MSG_CTR_EPOCH: u32 = 1000;
+
+const KEY_GROUP_DATA_CTR: &str = "ctr_gd";
+const KEY_GROUP_CONTROL_CTR: &str = "ctr_gc";
+
+/// A message counter that keeps increasing across reboots
+pub struct PersistedCounter {
+    storage: SharedKvStore,
+    key: &'static str,
+    next: u32,
+    limit: u32,
+}
+
+impl PersistedCounter {
+    /// Load the counter stored under key
+    ///
+    /// A counter that was never stored starts from a random value
+    pub fn new(storage: SharedKvStore, key: &'static str) -> Result<Self, Error> {
+        let stored = storage.lock().unwrap().get_u64(key);
+        let next = match stored {
+            Ok(limit) => limit as u32,
+            Err(Error::NotFound) => rand::thread_rng().gen_range(0..MSG_CTR_INIT_RANGE),
+            Err(e) => return Err(e),
+        };
+        let mut ctr = Self {
+            storage,
+            key,
+            next,
+            limit: next,
+        };
+        ctr.reserve()?;
+        Ok(ctr)
+    }
+
+    fn reserve(&mut self) -> Result<(), Error> {
+        let limit = self.next.wrapping_add(MSG_CTR_EPOCH);
+        self.storage
+            .lock()
+            .unwrap()
+            .set_u64(self.key, limit as u64)?;
+        self.limit = limit;
+        Ok(())
+    }
+
+    /// The value that the next message will carry
+    pub fn current(&self) -> u32 {
+        self.next
+    }
+
+    /// Return the value for the next message, and increment the counter
+    pub fn get_msg_ctr(&mut self) -> Result<u32, Error> {
+        if self.next == self.limit {
+            self.reserve()?;
+        }
+        let ctr = self.next;
+        self.next = self.next.wrapping_add(1);
+        Ok(ctr)
+    }
+
+    /// Forget the stored counter, and start again from a random value
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.storage.lock().unwrap().remove(self.key)?;
+        self.next = rand::thread_rng().gen_range(0..MSG_CTR_INIT_RANGE);
+        self.reserve()
+    }
+}
+
+/// The global message counters that are shared by all the group sessions of the node
+pub struct GroupCounters {
+    /// The Global Group Encrypted Data Message Counter
+    pub data: PersistedCounter,
+    /// The Global Group Encrypted Control Message Counter
+    pub control: PersistedCounter,
+}
+
+impl GroupCounters {
+    pub fn new(storage: SharedKvStore) -> Result<Self, Error> {
+        Ok(Self {
+            data: PersistedCounter::new(storage.clone(), KEY_GROUP_DATA_CTR)?,
+            control: PersistedCounter::new(storage, KEY_GROUP_CONTROL_CTR)?,
+        })
+    }
+
+    pub fn reset(&mut self) -> Result<(), Error> {
+        info!("Resetting the group message counters");
+        self.data.reset()?;
+        self.control.reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        error::Error,
+        persist::{KvStore, MemKvStore, SharedKvStore},
+    };
+
+    use super::{PersistedCounter, MSG_CTR_EPOCH};
+
+    struct CountingStore {
+        inner: MemKvStore,
+        writes: usize,
+    }
+
+    impl KvStore for CountingStore {
+        fn get(&self, key: &str, value: &mut Vec<u8>) -> Result<usize, Error> {
+            self.inner.get(key, value)
+        }
+
+        fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Error> {
+            self.writes += 1;
+            self.inner.set(key, value)
+        }
+
+        fn remove(&mut self, key: &str) -> Result<(), Error> {
+            self.inner.remove(key)
+        }
+
+        fn for_each(&self, f: &mut dyn FnMut(&str, &[u8])) -> Result<(), Error> {
+            self.inner.for_each(f)
+        }
+    }
+
+    #[test]
+    fn test_monotonic_across_reboots() {
+        let storage: SharedKvStore = Arc::new(Mutex::new(MemKvStore::new()));
+        let mut ctr = PersistedCounter::new(storage.clone(), "ctr").unwrap();
+        let first = ctr.get_msg_ctr().unwrap();
+        let mut last = first;
+        for _ in 0..(MSG_CTR_EPOCH + 10) {
+            let v = ctr.get_msg_ctr().unwrap();
+            assert_eq!(v, last.wrapping_add(1));
+            last = v;
+        }
+
+        // A 'reboot' resumes after the last reservation, so it never reuses a value that
+        // may have gone out
+        let mut ctr = PersistedCounter::new(storage, "ctr").unwrap();
+        assert_eq!(ctr.current(), first.wrapping_add(2 * MSG_CTR_EPOCH));
+        assert_eq!(
+            ctr.get_msg_ctr().unwrap(),
+            first.wrapping_add(2 * MSG_CTR_EPOCH)
+        );
+    }
+
+    #[test]
+    fn test_epoch_writes() {
+        let store = Arc::new(Mutex::new(CountingStore {
+            inner: MemKvStore::new(),
+            writes: 0,
+        }));
+        let storage: SharedKvStore = store.clone();
+        let mut ctr = PersistedCounter::new(storage, "ctr").unwrap();
+        assert_eq!(store.lock().unwrap().writes, 1);
+        for _ in 0..MSG_CTR_EPOCH {
+            ctr.get_msg_ctr().unwrap();
+        }
+        assert_eq!(store.lock().unwrap().writes, 1);
+        ctr.get_msg_ctr().unwrap();
+        assert_eq!(store.lock().unwrap().writes, 2);
+    }
+}
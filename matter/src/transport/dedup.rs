@@ -15,7 +15,7 @@
  *    limitations under the License.
  */
 
-const MSG_RX_STATE_BITMAP_LEN: u32 = 16;
+pub(crate) const MSG_RX_STATE_BITMAP_LEN: u32 = 16;
 
 #[derive(Debug)]
 pub struct RxCtrState {
@@ -15,7 +15,7 @@
  *    limitations under the License.
  */
 
-use boxslab::{BoxSlab, Slab};
+use boxslab::BoxSlab;
 use colored::*;
 use log::{error, info, trace};
 use std::any::Any;
@@ -24,12 +24,14 @@ use std::time::SystemTime;
 
 use crate::error::Error;
 use crate::secure_channel;
+use crate::utils::clock::SharedClock;
 
 use heapless::LinearMap;
 
+use super::network::Address;
 use super::packet::PacketPool;
-use super::session::CloneData;
-use super::{mrp::ReliableMessage, packet::Packet, session::SessionHandle, session::SessionMgr};
+use super::session::{CloneData, Session};
+use super::{mrp::ReliableMessage, session::SessionHandle, session::SessionMgr};
 
 pub struct ExchangeCtx<'a> {
     pub exch: &'a mut Exchange,
@@ -70,7 +72,6 @@ impl Default for State {
 #[derive(Debug)]
 pub enum DataOption {
     Boxed(Box<dyn Any>),
-    Time(SystemTime),
     None,
 }
 
@@ -162,19 +163,6 @@ impl Exchange {
         }
     }
 
-    pub fn set_data_time(&mut self, expiry_ts: Option<SystemTime>) {
-        if let Some(t) = expiry_ts {
-            self.data = DataOption::Time(t);
-        }
-    }
-
-    pub fn get_data_time(&self) -> Option<SystemTime> {
-        match self.data {
-            DataOption::Time(t) => Some(t),
-            _ => None,
-        }
-    }
-
     pub fn send(
         &mut self,
         mut proto_tx: BoxSlab<PacketPool>,
@@ -198,9 +186,48 @@ impl Exchange {
             proto_tx.proto.set_initiator();
         }
 
+        if session.is_reliable_transport() {
+            // The transport takes care of the delivery
+            proto_tx.unset_reliable();
+        }
         session.pre_send(&mut proto_tx)?;
         self.mrp.pre_send(&mut proto_tx)?;
-        session.send(proto_tx)
+        session.encode(&mut proto_tx)?;
+        let now = session.get_clock().now();
+        self.mrp
+            .post_encode(&mut proto_tx, session.get_mrp_interval(), now)?;
+        session.transmit(proto_tx.as_borrow_slice())
+    }
+
+    /// Retransmit the pending reliable message on this exchange, if any
+    ///
+    /// Returns an error if the maximum number of transmissions has been reached
+    pub fn retrans(&mut self, session: &mut SessionHandle) -> Result<(), Error> {
+        let entry = if let Some(entry) = self.mrp.get_retrans_entry() {
+            entry
+        } else {
+            return Ok(());
+        };
+
+        if entry.is_exhausted() {
+            error!(
+                "Message {} on exchange {} not acknowledged after {} transmissions",
+                entry.get_msg_ctr(),
+                self.id,
+                entry.get_send_count()
+            );
+            self.mrp.clear_retrans();
+            return Err(Error::NoAck);
+        }
+
+        info!(
+            "Retransmitting message {} on exchange {}, attempt {}",
+            entry.get_msg_ctr(),
+            self.id,
+            entry.get_send_count() + 1
+        );
+        entry.record_retrans(session.get_clock().now())?;
+        session.transmit(entry.get_payload())
     }
 }
 
@@ -253,6 +280,10 @@ impl ExchangeMgr {
         &mut self.sess_mgr
     }
 
+    pub fn get_clock(&self) -> &SharedClock {
+        self.sess_mgr.get_clock()
+    }
+
     pub fn _get_with_id(
         exchanges: &mut LinearMap<u16, Exchange, MAX_EXCHANGES>,
         exch_id: u16,
@@ -299,24 +330,63 @@ impl ExchangeMgr {
         }
     }
 
+    /// Wait for the next message, see [SessionMgr::read]
+    pub async fn read(&self) -> Result<BoxSlab<PacketPool>, Error> {
+        self.sess_mgr.read().await
+    }
+
+    /// Send the messages that wait for their network interface, see [SessionMgr::flush]
+    pub async fn flush(&self) {
+        self.sess_mgr.flush().await
+    }
+
+    /// Wait for a connection to go away, see [SessionMgr::closed]
+    pub async fn closed(&self) -> Address {
+        self.sess_mgr.closed().await
+    }
+
+    pub fn has_pending_tx(&self) -> bool {
+        self.sess_mgr.has_pending_tx()
+    }
+
     /// The Exchange Mgr receive is like a big processing function
-    pub fn recv(&mut self) -> Result<Option<(BoxSlab<PacketPool>, ExchangeCtx)>, Error> {
+    pub fn recv(
+        &mut self,
+        rx: BoxSlab<PacketPool>,
+    ) -> Result<Option<(BoxSlab<PacketPool>, ExchangeCtx)>, Error> {
         // Get the session
-        let (mut proto_rx, index) = self.sess_mgr.recv()?;
+        let (mut proto_rx, index) = match self.sess_mgr.recv(rx)? {
+            Some(r) => r,
+            // The transport consumed the message
+            None => return Ok(None),
+        };
 
         let index = if let Some(s) = index {
             s
         } else {
             // The sessions were full, evict one session, and re-perform post-recv
-            let evict_index = self.sess_mgr.get_lru();
+            let evict_index = if proto_rx.plain.is_group() {
+                self.sess_mgr.get_group_lru()
+            } else {
+                self.sess_mgr.get_lru()
+            };
             self.evict_session(evict_index)?;
             info!("Reattempting session creation");
-            self.sess_mgr.post_recv(&proto_rx)?.ok_or(Error::Invalid)?
+            self.sess_mgr
+                .post_recv(&mut proto_rx)?
+                .ok_or(Error::Invalid)?
         };
         let mut session = self.sess_mgr.get_session_handle(index);
 
         // Decrypt the message
         session.recv(&mut proto_rx)?;
+        let now = session.get_clock().now();
+
+        if session.is_group() || session.is_reliable_transport() {
+            // Group messages are never acknowledged, and neither are the messages over
+            // a reliable transport
+            proto_rx.unset_reliable();
+        }
 
         // Get the exchange
         let exch = ExchangeMgr::_get(
@@ -329,7 +399,7 @@ impl ExchangeMgr {
         )?;
 
         // Message Reliability Protocol
-        exch.mrp.recv(&proto_rx)?;
+        exch.mrp.recv(&proto_rx, now)?;
 
         if exch.is_state_open() {
             Ok(Some((
@@ -353,6 +423,45 @@ impl ExchangeMgr {
         exchange.send(proto_tx, &mut session)
     }
 
+    /// Send a message to the peer of a session, on a new exchange that we initiate
+    ///
+    /// The session is looked up by its local id and the id of the peer, so that a message
+    /// meant for a session that is gone doesn't go to a later session that reuses the id.
+    ///
+    /// Returns the id of the new exchange, which the response comes in on.
+    pub fn initiate(
+        &mut self,
+        sess_id: u16,
+        peer_node_id: Option<u64>,
+        proto_tx: BoxSlab<PacketPool>,
+    ) -> Result<u16, Error> {
+        let sess_idx = self
+            .sess_mgr
+            .find_all(|s| {
+                !s.is_group()
+                    && s.get_local_sess_id() == sess_id
+                    && s.get_peer_node_id() == peer_node_id
+            })
+            .first()
+            .copied()
+            .ok_or(Error::NotFound)?;
+
+        let mut exch_id: u16 = rand::random();
+        while self.exchanges.contains_key(&exch_id) {
+            exch_id = exch_id.wrapping_add(1);
+        }
+        let exchange = ExchangeMgr::_get(
+            &mut self.exchanges,
+            sess_idx,
+            exch_id,
+            Role::Initiator,
+            true,
+        )?;
+        let mut session = self.sess_mgr.get_session_handle(sess_idx);
+        exchange.send(proto_tx, &mut session)?;
+        Ok(exch_id)
+    }
+
     pub fn purge(&mut self) {
         let mut to_purge: LinearMap<u16, (), MAX_EXCHANGES> = LinearMap::new();
 
@@ -367,34 +476,59 @@ impl ExchangeMgr {
     }
 
     pub fn pending_acks(&mut self, expired_entries: &mut LinearMap<u16, (), MAX_MRP_ENTRIES>) {
+        let now = self.sess_mgr.get_clock().now();
         for (exch_id, exchange) in self.exchanges.iter() {
-            if exchange.mrp.is_ack_ready() {
-                expired_entries.insert(*exch_id, ()).unwrap();
+            if exchange.mrp.is_ack_ready(now) && expired_entries.insert(*exch_id, ()).is_err() {
+                // The rest will be picked up in the next iteration
+                break;
             }
         }
     }
 
-    pub fn evict_session(&mut self, index: usize) -> Result<(), Error> {
-        info!("Sessions full, vacating session with index: {}", index);
-        // If we enter here, we have an LRU session that needs to be reclaimed
-        // As per the spec, we need to send a CLOSE here
+    /// Returns the earliest time at which any of the exchanges needs MRP processing
+    pub fn next_timeout(&self) -> Option<SystemTime> {
+        self.exchanges
+            .values()
+            .filter_map(|e| e.mrp.next_timeout())
+            .min()
+    }
 
-        let mut session = self.sess_mgr.get_session_handle(index);
-        let mut tx = Slab::<PacketPool>::try_new(Packet::new_tx()?).ok_or(Error::NoSpace)?;
-        secure_channel::common::create_sc_status_report(
-            &mut tx,
-            secure_channel::common::SCStatusCodes::CloseSession,
-            None,
-        )?;
+    pub fn pending_retrans(&mut self, expired_entries: &mut LinearMap<u16, (), MAX_MRP_ENTRIES>) {
+        let now = self.sess_mgr.get_clock().now();
+        for (exch_id, exchange) in self.exchanges.iter() {
+            if exchange.mrp.is_retrans_ready(now) && expired_entries.insert(*exch_id, ()).is_err() {
+                // The rest will be picked up in the next iteration
+                break;
+            }
+        }
+    }
 
-        if let Some((_, exchange)) = self.exchanges.iter_mut().find(|(_, e)| e.sess_idx == index) {
-            // Send Close_session on this exchange, and then close the session
-            // Should this be done for all exchanges?
-            error!("Sending Close Session");
-            exchange.send(tx, &mut session)?;
-            // TODO: This wouldn't actually send it out, because 'transport' isn't owned yet.
+    /// Retransmit the pending reliable message of an exchange
+    ///
+    /// If the peer hasn't acknowledged the message even after the maximum number of
+    /// transmissions, the peer is considered unreachable, and the session along with all
+    /// its exchanges is removed
+    pub fn retrans(&mut self, exch_id: u16) -> Result<(), Error> {
+        let exchange =
+            ExchangeMgr::_get_with_id(&mut self.exchanges, exch_id).ok_or(Error::NoExchange)?;
+        let sess_idx = exchange.sess_idx;
+        let mut session = self.sess_mgr.get_session_handle(sess_idx);
+        match exchange.retrans(&mut session) {
+            Err(Error::NoAck) => {
+                error!(
+                    "Peer unreachable, removing session with index: {}",
+                    sess_idx
+                );
+                self.remove_session(sess_idx);
+                Err(Error::NoAck)
+            }
+            result => result,
         }
+    }
 
+    /// Remove a session and all the exchanges that belong to it, without any
+    /// communication with the peer
+    fn remove_session(&mut self, index: usize) {
         let remove_exchanges: Vec<u16> = self
             .exchanges
             .iter()
@@ -415,6 +549,53 @@ impl ExchangeMgr {
             self.exchanges.remove(&exch_id);
         }
         self.sess_mgr.remove(index);
+    }
+
+    /// Remove all the sessions and their exchanges, without any communication with the
+    /// peers
+    pub fn remove_all_sessions(&mut self) {
+        self.exchanges.clear();
+        self.sess_mgr.remove_all();
+    }
+
+    /// Remove the sessions that match the filter, along with their exchanges, without any
+    /// communication with the peers
+    pub fn remove_sessions<F>(&mut self, f: F)
+    where
+        F: Fn(&Session) -> bool,
+    {
+        for index in self.sess_mgr.find_all(f) {
+            self.remove_session(index);
+        }
+    }
+
+    pub fn evict_session(&mut self, index: usize) -> Result<(), Error> {
+        info!("Sessions full, vacating session with index: {}", index);
+        // If we enter here, we have an LRU session that needs to be reclaimed
+        // As per the spec, we need to send a CLOSE here
+
+        let mut session = self.sess_mgr.get_session_handle(index);
+        if session.is_group() {
+            // There is nothing to close on the peer's side for a group session
+            self.remove_session(index);
+            return Ok(());
+        }
+        let mut tx = session.new_tx()?;
+        secure_channel::common::create_sc_status_report(
+            &mut tx,
+            secure_channel::common::SCStatusCodes::CloseSession,
+            None,
+        )?;
+
+        if let Some((_, exchange)) = self.exchanges.iter_mut().find(|(_, e)| e.sess_idx == index) {
+            // Send Close_session on this exchange, and then close the session
+            // Should this be done for all exchanges?
+            error!("Sending Close Session");
+            exchange.send(tx, &mut session)?;
+            // TODO: This wouldn't actually send it out, because 'transport' isn't owned yet.
+        }
+
+        self.remove_session(index);
         Ok(())
     }
 
@@ -449,6 +630,7 @@ impl fmt::Display for ExchangeMgr {
 #[cfg(test)]
 #[allow(clippy::bool_assert_comparison)]
 mod tests {
+    use std::task::{Context, Poll};
 
     use crate::{
         error::Error,
@@ -527,12 +709,21 @@ mod tests {
     }
 
     impl NetworkInterface for DummyNetwork {
-        fn recv(&self, _in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
-            Ok((0, Address::default()))
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            _in_buf: &mut [u8],
+        ) -> Poll<Result<(usize, Address), Error>> {
+            Poll::Ready(Ok((0, Address::default())))
         }
 
-        fn send(&self, _out_buf: &[u8], _addr: Address) -> Result<usize, Error> {
-            Ok(0)
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            _out_buf: &[u8],
+            _addr: Address,
+        ) -> Poll<Result<usize, Error>> {
+            Poll::Ready(Ok(0))
         }
     }
 
@@ -601,4 +792,29 @@ mod tests {
         }
         //        println!("Session mgr {}", mgr.sess_mgr);
     }
+
+    #[test]
+    fn test_initiate() {
+        let mut sess_mgr = SessionMgr::new();
+        let transport = Box::new(DummyNetwork::new());
+        sess_mgr.add_network_interface(transport).unwrap();
+        let mut mgr = ExchangeMgr::new(sess_mgr);
+        mgr.add_session(&get_clone_data(100, 1)).unwrap();
+
+        // Only the session with both the id and the peer
+        for (sess_id, peer) in [(2, Some(43211234)), (1, Some(1234)), (1, None)] {
+            let tx = mgr.sess_mgr.new_tx().unwrap();
+            assert_eq!(mgr.initiate(sess_id, peer, tx), Err(Error::NotFound));
+        }
+        assert_eq!(mgr.exchanges.len(), 0);
+
+        let tx = mgr.sess_mgr.new_tx().unwrap();
+        let exch_id = mgr.initiate(1, Some(43211234), tx).unwrap();
+        let (id, exch) = mgr.exchanges.iter().next().unwrap();
+        assert_eq!(*id, exch_id);
+        assert_eq!(exch.get_role(), Role::Initiator);
+        assert_eq!(exch.sess_idx, 0);
+        // The message waits for its acknowledgement
+        assert!(exch.mrp.next_timeout().is_some());
+    }
 }
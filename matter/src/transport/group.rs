@@ -0,0 +1,614 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use log::{error, info};
+use rand::Rng;
+
+use crate::{
+    error::Error,
+    group_keys::{GroupKey, GroupKeys, KeySetPolicy},
+    secure_channel::{
+        common::{OpCode, PROTO_ID_SECURE_CHANNEL},
+        msg_counter_sync::MsgCounterSync,
+    },
+    utils::clock::SharedClock,
+};
+
+use super::{
+    counters::GroupCounters,
+    network::{Address, NetworkInterface},
+    packet::Packet,
+    plain_hdr::{self, SessionType},
+    proto_hdr,
+    session::Session,
+    udp::MAX_RX_BUF_SIZE,
+};
+
+/// The IPv6 multicast address of a group
+///
+/// This is FF35:0040:FD<Fabric ID>00:<Group ID>, a unicast-prefix-based address with the
+/// fabric ID as the prefix.
+pub fn multicast_addr(fabric_id: u64, group_id: u16) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets[..5].copy_from_slice(&[0xff, 0x35, 0x00, 0x40, 0xfd]);
+    octets[5..13].copy_from_slice(&fabric_id.to_be_bytes());
+    octets[14..].copy_from_slice(&group_id.to_be_bytes());
+    Ipv6Addr::from(octets)
+}
+
+/// The state the transport needs for receiving and sending group messages
+pub struct GroupCtx {
+    keys: Arc<GroupKeys>,
+    counters: Arc<Mutex<GroupCounters>>,
+    mcsp: MsgCounterSync,
+    // The port the group members listen on
+    port: u16,
+    joined: Vec<Ipv6Addr>,
+    generation: Option<u32>,
+}
+
+impl GroupCtx {
+    pub fn new(
+        keys: Arc<GroupKeys>,
+        counters: Arc<Mutex<GroupCounters>>,
+        mcsp: MsgCounterSync,
+        port: u16,
+    ) -> Self {
+        Self {
+            keys,
+            counters,
+            mcsp,
+            port,
+            joined: Vec::new(),
+            generation: None,
+        }
+    }
+
+    /// Join and leave the multicast addresses, so they match the current groups
+    pub fn sync_memberships(&mut self, network: &dyn NetworkInterface) -> Result<(), Error> {
+        let generation = self.keys.generation();
+        if self.generation == Some(generation) {
+            return Ok(());
+        }
+
+        let mut wanted = Vec::new();
+        self.keys.for_each_group(|fabric_id, group_id| {
+            let addr = multicast_addr(fabric_id, group_id);
+            if !wanted.contains(&addr) {
+                wanted.push(addr);
+            }
+        })?;
+
+        for addr in self.joined.iter().filter(|a| !wanted.contains(a)) {
+            if let Err(e) = network.leave_multicast(addr) {
+                error!("Failed to leave {}: {:?}", addr, e);
+            }
+        }
+        for addr in wanted.iter().filter(|a| !self.joined.contains(a)) {
+            match network.join_multicast(addr) {
+                Ok(()) => info!("Joined {}", addr),
+                Err(e) => error!("Failed to join {}: {:?}", addr, e),
+            }
+        }
+        self.joined = wanted;
+        self.generation = Some(generation);
+        Ok(())
+    }
+
+    /// Find the key with which a group message was encrypted
+    ///
+    /// Different groups may share a group session id, so all the candidate keys are tried
+    /// on a copy of the message, until one of them authenticates it.
+    pub fn find_rx_key(&self, rx: &mut Packet) -> Result<GroupKey, Error> {
+        let group_id = rx.plain.get_dest_group().ok_or(Error::Invalid)?;
+        let keys = self.keys.rx_keys(rx.plain.sess_id, group_id);
+        Self::try_keys(rx, keys).ok_or_else(|| {
+            info!("No key for the message to group {:x}", group_id);
+            Error::NotFound
+        })
+    }
+
+    // The first of the keys that authenticates the message
+    fn try_keys(rx: &mut Packet, keys: Vec<GroupKey>) -> Option<GroupKey> {
+        let src = rx.plain.get_src_u64()?;
+        let ctr = rx.plain.ctr;
+        let sec_flags = rx.plain.get_sec_flags();
+
+        let pb = rx.get_parsebuf().ok()?;
+        let mut aad = [0u8; plain_hdr::max_plain_hdr_len()];
+        let aad_len = pb.parsed_as_slice().len();
+        aad[..aad_len].copy_from_slice(pb.parsed_as_slice());
+        let cipher_text = pb.as_borrow_slice();
+        let mut buf = [0u8; MAX_RX_BUF_SIZE];
+        let buf = buf.get_mut(..cipher_text.len())?;
+
+        keys.into_iter().find(|key| {
+            buf.copy_from_slice(cipher_text);
+            proto_hdr::decrypt_payload(ctr, sec_flags, src, &aad[..aad_len], buf, key.op_key())
+                .is_ok()
+        })
+    }
+
+    /// Cache an authentic data message, if its sender isn't synchronized yet and the
+    /// policy of its key is CacheAndSync
+    ///
+    /// Returns whether the message was cached. It is received again once the sender is
+    /// synchronized.
+    pub fn cache_unsynced(&self, key: &GroupKey, rx: &mut Packet) -> Result<bool, Error> {
+        let src = rx.plain.get_src_u64().ok_or(Error::Invalid)?;
+        if rx.plain.is_control
+            || key.policy == KeySetPolicy::TrustFirst
+            || self.mcsp.is_synced(key.fab_idx, src)
+        {
+            return Ok(false);
+        }
+
+        let ctr = rx.plain.ctr;
+        let peer = rx.peer;
+        let pb = rx.get_parsebuf()?;
+        let mut msg = pb.parsed_as_slice().to_vec();
+        msg.extend_from_slice(pb.as_borrow_slice());
+        info!("Caching message {} of unsynchronized {:x}", ctr, src);
+        self.mcsp.cache(key.fab_idx, src, ctr, &msg, peer);
+        Ok(true)
+    }
+
+    /// Prepare a MsgCounterSyncReq to the sender of a cached message, returning the
+    /// session to send it over
+    pub fn prepare_sync_req(
+        &self,
+        key: &GroupKey,
+        peer_nodeid: u64,
+        peer: Address,
+        tx: &mut Packet,
+        clock: &SharedClock,
+    ) -> Result<Session, Error> {
+        self.mcsp.prepare_req(key.fab_idx, peer_nodeid, tx)?;
+        tx.proto.exch_id = rand::thread_rng().gen();
+        tx.proto.set_initiator();
+        self.prepare_ctrl_tx(key, peer_nodeid, peer, tx, clock)
+    }
+
+    /// Receive a group unicast message, which is only ever a message of the Message
+    /// Counter Synchronization Protocol
+    ///
+    /// Returns the session to send the response over, if there is one.
+    pub fn recv_ctrl(
+        &self,
+        rx: &mut Packet,
+        tx: &mut Packet,
+        clock: &SharedClock,
+    ) -> Result<Option<Session>, Error> {
+        let src = rx.plain.get_src_u64().ok_or(Error::Invalid)?;
+        let dest = rx.plain.get_dest_u64().ok_or(Error::Invalid)?;
+        if !rx.plain.is_control {
+            error!("Group unicast message that isn't a control message");
+            return Err(Error::Invalid);
+        }
+        let keys = self.keys.unicast_rx_keys(rx.plain.sess_id);
+        let key = Self::try_keys(rx, keys).ok_or_else(|| {
+            info!("No key for the group unicast message from {:x}", src);
+            Error::NotFound
+        })?;
+        if self.keys.node_id(key.fab_idx)? != dest {
+            error!("Group unicast message to another node {:x}", dest);
+            return Err(Error::Invalid);
+        }
+        if self.mcsp.recv(key.fab_idx, src, rx.plain.ctr, true) {
+            info!("Dropping duplicate control message");
+            return Err(Error::Duplicate);
+        }
+        rx.proto_decode(src, Some(key.op_key()))?;
+
+        if rx.get_proto_id() != PROTO_ID_SECURE_CHANNEL as u16 {
+            return Err(Error::Invalid);
+        }
+        match num::FromPrimitive::from_u8(rx.get_proto_opcode()) {
+            Some(OpCode::MsgCounterSyncReq) => {
+                tx.set_proto_id(PROTO_ID_SECURE_CHANNEL as u16);
+                tx.set_proto_opcode(OpCode::MsgCounterSyncResp as u8);
+                self.mcsp
+                    .handle_req(rx.as_borrow_slice(), tx.get_writebuf()?)?;
+                tx.proto.exch_id = rx.proto.exch_id;
+                let peer = rx.peer;
+                self.prepare_ctrl_tx(&key, src, peer, tx, clock).map(Some)
+            }
+            Some(OpCode::MsgCounterSyncResp) => {
+                self.mcsp
+                    .handle_resp(key.fab_idx, src, rx.as_borrow_slice())?;
+                Ok(None)
+            }
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    // The control messages between two group members are unicast, and encrypted with
+    // the group key. They have their own message counter.
+    fn prepare_ctrl_tx(
+        &self,
+        key: &GroupKey,
+        peer_nodeid: u64,
+        peer: Address,
+        tx: &mut Packet,
+        clock: &SharedClock,
+    ) -> Result<Session, Error> {
+        let node_id = self.keys.node_id(key.fab_idx)?;
+
+        tx.unset_reliable();
+        tx.plain.sess_type = SessionType::Group;
+        tx.plain.sess_id = key.session_id;
+        tx.plain.ctr = self.counters.lock()?.control.get_msg_ctr()?;
+        tx.plain.is_control = true;
+        tx.plain.set_src_u64(node_id);
+        tx.plain.set_dest_u64(peer_nodeid);
+
+        Ok(Session::new_group(
+            peer,
+            node_id,
+            Some(peer_nodeid),
+            key,
+            0,
+            clock.clone(),
+        ))
+    }
+
+    /// Returns whether there are cached messages ready to be received again
+    pub fn has_released(&self) -> bool {
+        self.mcsp.has_released()
+    }
+
+    /// Take the next cached message that is ready to be received again
+    pub fn take_released(&self) -> Option<(Vec<u8>, Address)> {
+        self.mcsp.take_released()
+    }
+
+    /// Check a group message from a peer for duplicates
+    pub fn is_duplicate(&self, fab_idx: u8, src: u64, ctr: u32, is_control: bool) -> bool {
+        self.mcsp.recv(fab_idx, src, ctr, is_control)
+    }
+
+    /// Prepare the plain-text header of a message to a group, returning the session to
+    /// send it over
+    ///
+    /// Group messages are not acknowledged, so they are never sent with MRP.
+    pub fn prepare_tx(
+        &self,
+        fab_idx: u8,
+        group_id: u16,
+        proto_tx: &mut Packet,
+        clock: &SharedClock,
+    ) -> Result<Session, Error> {
+        let tx = self
+            .keys
+            .tx_info(fab_idx, group_id, clock.matter_epoch_us())?;
+
+        proto_tx.unset_reliable();
+        proto_tx.plain.sess_type = SessionType::Group;
+        proto_tx.plain.sess_id = tx.key.session_id;
+        proto_tx.plain.ctr = self.counters.lock()?.data.get_msg_ctr()?;
+        proto_tx.plain.set_src_u64(tx.node_id);
+        proto_tx.plain.set_dest_group(group_id);
+
+        let peer = Address::Udp(SocketAddr::new(
+            IpAddr::V6(multicast_addr(tx.fabric_id, group_id)),
+            self.port,
+        ));
+        Ok(Session::new_group(
+            peer,
+            tx.node_id,
+            None,
+            &tx.key,
+            group_id,
+            clock.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        net::{IpAddr, Ipv6Addr, SocketAddr},
+        rc::Rc,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use crate::{
+        error::Error,
+        fabric::FabricMgr,
+        group_keys::{EpochKey, GroupKeyMapEntry, GroupKeySet, GroupKeys, KeySetPolicy},
+        mdns::Mdns,
+        persist::MemKvStore,
+        secure_channel::msg_counter_sync::MsgCounterSync,
+        transport::{
+            counters::GroupCounters,
+            network::{Address, NetworkInterface},
+            session::{GroupDetails, RxMsg, SessionMgr, SessionMode},
+        },
+    };
+
+    use super::{multicast_addr, GroupCtx};
+
+    const GROUP_ID: u16 = 0x101;
+    // Not the default one, to check that it is used
+    const PORT: u16 = 5555;
+
+    type Datagram = (Vec<u8>, Address);
+
+    #[derive(Default, Clone)]
+    struct Loopback {
+        queue: Rc<RefCell<VecDeque<Datagram>>>,
+        joined: Rc<RefCell<Vec<Ipv6Addr>>>,
+    }
+
+    impl NetworkInterface for Loopback {
+        // An empty queue is an error, rather than something to wait for
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            in_buf: &mut [u8],
+        ) -> Poll<Result<(usize, Address), Error>> {
+            let (data, _) = self.queue.borrow_mut().pop_front().ok_or(Error::Network)?;
+            in_buf[..data.len()].copy_from_slice(&data);
+            Poll::Ready(Ok((data.len(), Address::default())))
+        }
+
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            out_buf: &[u8],
+            addr: Address,
+        ) -> Poll<Result<usize, Error>> {
+            self.queue.borrow_mut().push_back((out_buf.to_vec(), addr));
+            Poll::Ready(Ok(out_buf.len()))
+        }
+
+        fn join_multicast(&self, addr: &Ipv6Addr) -> Result<(), Error> {
+            self.joined.borrow_mut().push(*addr);
+            Ok(())
+        }
+
+        fn leave_multicast(&self, addr: &Ipv6Addr) -> Result<(), Error> {
+            self.joined.borrow_mut().retain(|a| a != addr);
+            Ok(())
+        }
+    }
+
+    fn recv(sess_mgr: &mut SessionMgr) -> Result<Option<RxMsg>, Error> {
+        let rx = smol::block_on(sess_mgr.read())?;
+        sess_mgr.recv(rx)
+    }
+
+    fn key_set(key: u8) -> GroupKeySet {
+        key_set_with(key, KeySetPolicy::TrustFirst)
+    }
+
+    fn key_set_with(key: u8, policy: KeySetPolicy) -> GroupKeySet {
+        GroupKeySet {
+            key_set_id: 1,
+            policy,
+            epoch_keys: vec![EpochKey {
+                key: vec![key; 16],
+                start_time: 0,
+            }],
+        }
+    }
+
+    fn key_map(group_ids: &[u16]) -> Vec<GroupKeyMapEntry> {
+        group_ids
+            .iter()
+            .map(|group_id| GroupKeyMapEntry {
+                group_id: *group_id,
+                key_set_id: 1,
+            })
+            .collect()
+    }
+
+    fn new_group_keys() -> Arc<GroupKeys> {
+        let storage = Arc::new(Mutex::new(MemKvStore::new()));
+        let mdns = Arc::new(Mdns::new(0));
+        let fabric_mgr = Arc::new(FabricMgr::new(storage.clone(), mdns, None).unwrap());
+        let keys = Arc::new(GroupKeys::new(fabric_mgr, storage).unwrap());
+        // The placeholder fabric at index 0 will do for the tests
+        keys.set_key_set(0, key_set(0x42)).unwrap();
+        keys.set_key_map(0, key_map(&[GROUP_ID, GROUP_ID + 1]))
+            .unwrap();
+        keys.add_group_endpoint(0, GROUP_ID, "", 1).unwrap();
+        keys
+    }
+
+    fn new_sess_mgr(keys: Arc<GroupKeys>, network: &Loopback) -> SessionMgr {
+        let storage = Arc::new(Mutex::new(MemKvStore::new()));
+        let counters = Arc::new(Mutex::new(GroupCounters::new(storage).unwrap()));
+        let mcsp = MsgCounterSync::new(counters.clone());
+        let mut sess_mgr = SessionMgr::new();
+        sess_mgr
+            .add_network_interface(Box::new(network.clone()))
+            .unwrap();
+        sess_mgr.set_group_ctx(GroupCtx::new(keys, counters, mcsp, PORT));
+        sess_mgr
+    }
+
+    #[test]
+    fn test_multicast_addr() {
+        assert_eq!(
+            multicast_addr(0x2906c908d115d362, 0x1234),
+            "ff35:40:fd29:6c9:8d1:15d3:6200:1234"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_memberships() {
+        let keys = new_group_keys();
+        let network = Loopback::default();
+        let mut sess_mgr = new_sess_mgr(keys.clone(), &network);
+
+        sess_mgr.sync_group_memberships().unwrap();
+        assert_eq!(*network.joined.borrow(), [multicast_addr(0, GROUP_ID)]);
+
+        keys.add_group_endpoint(0, GROUP_ID + 1, "", 1).unwrap();
+        keys.remove_group_endpoint(0, GROUP_ID, 1).unwrap();
+        sess_mgr.sync_group_memberships().unwrap();
+        assert_eq!(*network.joined.borrow(), [multicast_addr(0, GROUP_ID + 1)]);
+    }
+
+    #[test]
+    fn test_groupcast() {
+        let keys = new_group_keys();
+        let network = Loopback::default();
+        let mut sender = new_sess_mgr(keys.clone(), &network);
+        let mut receiver = new_sess_mgr(keys, &network);
+
+        let mut tx = sender.new_tx().unwrap();
+        tx.set_proto_id(0x01);
+        tx.set_proto_opcode(0x08);
+        tx.set_reliable();
+        tx.get_writebuf().unwrap().append(b"hello").unwrap();
+        sender.send_groupcast(0, GROUP_ID, tx).unwrap();
+
+        let (data, addr) = network.queue.borrow().front().unwrap().clone();
+        let expected = SocketAddr::new(IpAddr::V6(multicast_addr(0, GROUP_ID)), PORT);
+        assert!(addr == Address::Udp(expected));
+        // Replay the same message
+        network.queue.borrow_mut().push_back((data, addr));
+
+        let (mut rx, index) = recv(&mut receiver).unwrap().unwrap();
+        let mut session = receiver.get_session_handle(index.unwrap());
+        assert_eq!(
+            session.get_session_mode(),
+            SessionMode::Group(GroupDetails {
+                fab_idx: 0,
+                group_id: GROUP_ID
+            })
+        );
+        assert_eq!(session.get_peer_node_id(), Some(0));
+        session.recv(&mut rx).unwrap();
+        assert!(!rx.proto.is_reliable());
+        assert_eq!(rx.get_proto_id(), 0x01);
+        assert_eq!(rx.get_proto_opcode(), 0x08);
+        assert_eq!(rx.as_borrow_slice(), b"hello");
+
+        assert_eq!(recv(&mut receiver).err(), Some(Error::Duplicate));
+    }
+
+    fn send_hello(sender: &mut SessionMgr, is_control: bool) {
+        let mut tx = sender.new_tx().unwrap();
+        tx.set_proto_id(0x01);
+        tx.set_proto_opcode(0x08);
+        tx.plain.is_control = is_control;
+        tx.get_writebuf().unwrap().append(b"hello").unwrap();
+        sender.send_groupcast(0, GROUP_ID, tx).unwrap();
+    }
+
+    #[test]
+    fn test_groupcast_cache_and_sync() {
+        let keys = new_group_keys();
+        keys.set_key_set(0, key_set_with(0x42, KeySetPolicy::CacheAndSync))
+            .unwrap();
+        let network = Loopback::default();
+        let mut sender = new_sess_mgr(keys.clone(), &network);
+        let mut receiver = new_sess_mgr(keys, &network);
+
+        // The data messages of an unsynchronized peer are not trusted, they are cached
+        send_hello(&mut sender, false);
+        assert!(recv(&mut receiver).unwrap().is_none());
+        assert!(receiver.find_all(|s| s.is_group()).is_empty());
+        // Leave out the MsgCounterSyncReq that went out
+        network.queue.borrow_mut().clear();
+
+        // The control messages always are
+        send_hello(&mut sender, true);
+        let (mut rx, index) = recv(&mut receiver).unwrap().unwrap();
+        receiver
+            .get_session_handle(index.unwrap())
+            .recv(&mut rx)
+            .unwrap();
+        assert_eq!(rx.as_borrow_slice(), b"hello");
+    }
+
+    #[test]
+    fn test_groupcast_msg_counter_sync() {
+        let keys = new_group_keys();
+        keys.set_key_set(0, key_set_with(0x42, KeySetPolicy::CacheAndSync))
+            .unwrap();
+        let network = Loopback::default();
+        let mut sender = new_sess_mgr(keys.clone(), &network);
+        let mut receiver = new_sess_mgr(keys, &network);
+
+        send_hello(&mut sender, false);
+        let groupcast = network.queue.borrow().front().unwrap().clone();
+
+        // The message is cached, and the sender is asked for its counter
+        assert!(recv(&mut receiver).unwrap().is_none());
+        let (req, addr) = network.queue.borrow().front().unwrap().clone();
+        assert!(addr == Address::default());
+        // Group session type, control message
+        assert_eq!(req[3], 0x41);
+
+        // The sender responds with it
+        assert!(recv(&mut sender).unwrap().is_none());
+        assert_eq!(network.queue.borrow().front().unwrap().0[3], 0x41);
+
+        // Which synchronizes the receiver, and releases the cached message
+        assert!(recv(&mut receiver).unwrap().is_none());
+        assert!(network.queue.borrow().is_empty());
+        let (mut rx, index) = recv(&mut receiver).unwrap().unwrap();
+        receiver
+            .get_session_handle(index.unwrap())
+            .recv(&mut rx)
+            .unwrap();
+        assert_eq!(rx.as_borrow_slice(), b"hello");
+
+        // Replays are now detected
+        network.queue.borrow_mut().push_back(groupcast);
+        assert_eq!(recv(&mut receiver).err(), Some(Error::Duplicate));
+        // And the next messages are received straight away
+        send_hello(&mut sender, false);
+        assert!(recv(&mut receiver).unwrap().unwrap().1.is_some());
+        // A replayed request is dropped too
+        network.queue.borrow_mut().push_back((req, addr));
+        assert_eq!(recv(&mut sender).err(), Some(Error::Duplicate));
+    }
+
+    #[test]
+    fn test_groupcast_unknown_key() {
+        let network = Loopback::default();
+        let mut sender = new_sess_mgr(new_group_keys(), &network);
+        let other_keys = new_group_keys();
+        other_keys.set_key_set(0, key_set(0x43)).unwrap();
+        let mut receiver = new_sess_mgr(other_keys, &network);
+
+        let tx = sender.new_tx().unwrap();
+        sender.send_groupcast(0, GROUP_ID, tx).unwrap();
+        assert_eq!(recv(&mut receiver).err(), Some(Error::NotFound));
+        assert_eq!(
+            sender
+                .send_groupcast(0, GROUP_ID + 2, sender.new_tx().unwrap())
+                .err(),
+            Some(Error::NotFound)
+        );
+    }
+}
@@ -0,0 +1,441 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    net::{Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    error::*,
+    utils::clock::{system_clock, SharedClock, Sleep},
+};
+
+use super::{
+    network::{Address, NetworkInterface},
+    udp::MATTER_PORT,
+};
+
+/// The conditions on the links between the nodes of a [LoopbackHub]
+///
+/// The probabilities are between 0.0 and 1.0
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LinkConditions {
+    /// The probability that a message is lost
+    pub loss: f64,
+    /// The probability that a message is delivered twice
+    pub duplication: f64,
+    /// The probability that a message overtakes some of the messages still in flight
+    pub reordering: f64,
+    /// How long a message is in flight
+    pub delay: Duration,
+}
+
+impl LinkConditions {
+    fn is_valid(&self) -> bool {
+        [self.loss, self.duplication, self.reordering]
+            .iter()
+            .all(|p| (0.0..=1.0).contains(p))
+    }
+}
+
+struct InFlight {
+    data: Vec<u8>,
+    src: Address,
+    deliver_at: SystemTime,
+}
+
+struct Node {
+    addr: SocketAddr,
+    in_flight: VecDeque<InFlight>,
+    groups: Vec<Ipv6Addr>,
+    waker: Option<Waker>,
+}
+
+struct HubInner {
+    nodes: Vec<Node>,
+    conditions: LinkConditions,
+    rng: StdRng,
+    clock: SharedClock,
+}
+
+impl HubInner {
+    fn route(&mut self, from: usize, data: &[u8], dst: SocketAddr) {
+        let src = Address::Udp(self.nodes[from].addr);
+        let targets: Vec<usize> = match dst.ip() {
+            std::net::IpAddr::V6(ip) if ip.is_multicast() => (0..self.nodes.len())
+                .filter(|i| *i != from && self.nodes[*i].groups.contains(&ip))
+                .collect(),
+            _ => (0..self.nodes.len())
+                .filter(|i| self.nodes[*i].addr == dst)
+                .collect(),
+        };
+
+        let conditions = self.conditions;
+        let deliver_at = self.clock.now() + conditions.delay;
+        for target in targets {
+            if self.rng.gen_bool(conditions.loss) {
+                continue;
+            }
+            let copies = if self.rng.gen_bool(conditions.duplication) {
+                2
+            } else {
+                1
+            };
+            for _ in 0..copies {
+                let msg = InFlight {
+                    data: data.to_vec(),
+                    src,
+                    deliver_at,
+                };
+                let len = self.nodes[target].in_flight.len();
+                let index = if len > 0 && self.rng.gen_bool(conditions.reordering) {
+                    self.rng.gen_range(0..len)
+                } else {
+                    len
+                };
+                self.nodes[target].in_flight.insert(index, msg);
+            }
+            if let Some(waker) = self.nodes[target].waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// An in-memory network that connects multiple nodes in the same process
+///
+/// Every node gets a [LoopbackNetwork] with its own address, which it uses like any other
+/// network interface. The hub routes the messages between them, to a node's address or to
+/// the nodes that joined a multicast address. The messages that go to an unknown address
+/// are dropped, like they would be with UDP.
+///
+/// The hub can also lose, duplicate, reorder and delay the messages, as set in its
+/// [LinkConditions]. The random choices come from a generator that is seeded at creation,
+/// so a test goes through the same sequence of events on every run. The delays go by the
+/// hub's clock.
+#[derive(Clone)]
+pub struct LoopbackHub(Arc<Mutex<HubInner>>);
+
+impl LoopbackHub {
+    pub fn new(seed: u64) -> Self {
+        Self::new_with(seed, system_clock())
+    }
+
+    /// Create a hub whose delays go by the given clock
+    pub fn new_with(seed: u64, clock: SharedClock) -> Self {
+        Self(Arc::new(Mutex::new(HubInner {
+            nodes: Vec::new(),
+            conditions: LinkConditions::default(),
+            rng: StdRng::seed_from_u64(seed),
+            clock,
+        })))
+    }
+
+    /// Set the conditions for the messages sent from now on
+    pub fn set_conditions(&self, conditions: LinkConditions) -> Result<(), Error> {
+        if !conditions.is_valid() {
+            return Err(Error::Invalid);
+        }
+        self.0.lock()?.conditions = conditions;
+        Ok(())
+    }
+
+    /// Add a node to the hub, and return its network interface
+    pub fn add_node(&self) -> LoopbackNetwork {
+        let mut inner = self.0.lock().unwrap();
+        let index = inner.nodes.len();
+        let ip = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, index as u16 + 1);
+        let addr = SocketAddr::from((ip, MATTER_PORT));
+        inner.nodes.push(Node {
+            addr,
+            in_flight: VecDeque::new(),
+            groups: Vec::new(),
+            waker: None,
+        });
+        LoopbackNetwork {
+            hub: self.clone(),
+            index,
+            addr,
+            timer: RefCell::new(None),
+        }
+    }
+}
+
+/// The network interface of a node on a [LoopbackHub]
+pub struct LoopbackNetwork {
+    hub: LoopbackHub,
+    index: usize,
+    addr: SocketAddr,
+    // Wakes the node up when a delayed message becomes due, by the hub's clock
+    timer: RefCell<Option<(SystemTime, Sleep)>>,
+}
+
+impl LoopbackNetwork {
+    /// The address that the other nodes reach this node at
+    pub fn addr(&self) -> Address {
+        Address::Udp(self.addr)
+    }
+}
+
+impl NetworkInterface for LoopbackNetwork {
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        in_buf: &mut [u8],
+    ) -> Poll<Result<(usize, Address), Error>> {
+        let mut inner = self.hub.0.lock()?;
+        let now = inner.clock.now();
+        let node = &mut inner.nodes[self.index];
+        let deliver_at = match node.in_flight.front() {
+            Some(msg) if msg.deliver_at <= now => None,
+            Some(msg) => Some(msg.deliver_at),
+            None => {
+                node.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        };
+
+        if let Some(deliver_at) = deliver_at {
+            node.waker = Some(cx.waker().clone());
+            let mut timer = self.timer.borrow_mut();
+            if !matches!(&*timer, Some((at, _)) if *at == deliver_at) {
+                *timer = Some((deliver_at, inner.clock.sleep_until(deliver_at)));
+            }
+            drop(inner);
+            if let Some((_, sleep)) = timer.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            *timer = None;
+            inner = self.hub.0.lock()?;
+        }
+
+        let msg = inner.nodes[self.index]
+            .in_flight
+            .pop_front()
+            .ok_or(Error::Network)?;
+        let dst = in_buf
+            .get_mut(..msg.data.len())
+            .ok_or(Error::BufferTooSmall)?;
+        dst.copy_from_slice(&msg.data);
+        Poll::Ready(Ok((msg.data.len(), msg.src)))
+    }
+
+    fn poll_send(
+        &self,
+        _cx: &mut Context<'_>,
+        out_buf: &[u8],
+        addr: Address,
+    ) -> Poll<Result<usize, Error>> {
+        match addr {
+            Address::Udp(dst) => {
+                self.hub.0.lock()?.route(self.index, out_buf, dst);
+                Poll::Ready(Ok(out_buf.len()))
+            }
+            _ => Poll::Ready(Err(Error::Invalid)),
+        }
+    }
+
+    fn join_multicast(&self, addr: &Ipv6Addr) -> Result<(), Error> {
+        let mut inner = self.hub.0.lock()?;
+        let groups = &mut inner.nodes[self.index].groups;
+        if !groups.contains(addr) {
+            groups.push(*addr);
+        }
+        Ok(())
+    }
+
+    fn leave_multicast(&self, addr: &Ipv6Addr) -> Result<(), Error> {
+        let mut inner = self.hub.0.lock()?;
+        inner.nodes[self.index].groups.retain(|a| a != addr);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv6Addr, SocketAddr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use smol::future;
+
+    use crate::{
+        error::Error,
+        transport::network::{self, Address, NetworkInterface},
+        utils::clock::MockClock,
+    };
+
+    use super::{LinkConditions, LoopbackHub, LoopbackNetwork};
+
+    fn send(network: &LoopbackNetwork, buf: &[u8], addr: Address) -> Result<usize, Error> {
+        smol::block_on(network::send(network, buf, addr))
+    }
+
+    fn recv(network: &LoopbackNetwork, buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        smol::block_on(network::recv(network, buf))
+    }
+
+    // Receive a message, if one can be received right away
+    fn try_recv(network: &LoopbackNetwork, buf: &mut [u8]) -> Option<usize> {
+        let recv = future::poll_once(network::recv(network, buf));
+        smol::block_on(recv).map(|r| r.unwrap().0)
+    }
+
+    fn recv_all(network: &LoopbackNetwork) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 10];
+        while !network.hub.0.lock().unwrap().nodes[network.index]
+            .in_flight
+            .is_empty()
+        {
+            let (len, _) = recv(network, &mut buf).unwrap();
+            received.extend_from_slice(&buf[..len]);
+        }
+        received
+    }
+
+    #[test]
+    fn test_unicast() {
+        let hub = LoopbackHub::new(0);
+        let a = hub.add_node();
+        let b = hub.add_node();
+        let c = hub.add_node();
+
+        send(&a, &[1, 2], b.addr()).unwrap();
+        let mut buf = [0u8; 10];
+        let (len, src) = recv(&b, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 2]);
+        assert!(src == a.addr());
+        assert!(recv_all(&c).is_empty());
+
+        // Nobody is at this address
+        let unknown = SocketAddr::from((Ipv6Addr::LOCALHOST, 5540));
+        assert_eq!(send(&a, &[1], Address::Udp(unknown)), Ok(1));
+    }
+
+    #[test]
+    fn test_multicast() {
+        let hub = LoopbackHub::new(0);
+        let a = hub.add_node();
+        let b = hub.add_node();
+        let c = hub.add_node();
+        let group: Ipv6Addr = "ff35:40:fd00::100:1".parse().unwrap();
+        let group_addr = Address::Udp(SocketAddr::from((group, 5540)));
+
+        a.join_multicast(&group).unwrap();
+        b.join_multicast(&group).unwrap();
+        send(&a, &[7], group_addr).unwrap();
+        // The sender doesn't get its own message
+        assert!(recv_all(&a).is_empty());
+        assert_eq!(recv_all(&b), vec![7]);
+        assert!(recv_all(&c).is_empty());
+
+        b.leave_multicast(&group).unwrap();
+        send(&c, &[8], group_addr).unwrap();
+        assert_eq!(recv_all(&a), vec![8]);
+        assert!(recv_all(&b).is_empty());
+    }
+
+    #[test]
+    fn test_loss_and_duplication() {
+        let hub = LoopbackHub::new(0);
+        let a = hub.add_node();
+        let b = hub.add_node();
+
+        hub.set_conditions(LinkConditions {
+            loss: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        send(&a, &[1], b.addr()).unwrap();
+        assert!(recv_all(&b).is_empty());
+
+        hub.set_conditions(LinkConditions {
+            duplication: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        send(&a, &[1], b.addr()).unwrap();
+        send(&a, &[2], b.addr()).unwrap();
+        assert_eq!(recv_all(&b), vec![1, 1, 2, 2]);
+
+        let invalid = LinkConditions {
+            loss: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(hub.set_conditions(invalid), Err(Error::Invalid));
+    }
+
+    fn reordered_run(seed: u64) -> Vec<u8> {
+        let hub = LoopbackHub::new(seed);
+        let a = hub.add_node();
+        let b = hub.add_node();
+        hub.set_conditions(LinkConditions {
+            reordering: 0.5,
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..10 {
+            send(&a, &[i], b.addr()).unwrap();
+        }
+        recv_all(&b)
+    }
+
+    #[test]
+    fn test_reordering_is_deterministic() {
+        let received = reordered_run(42);
+        assert_ne!(received, (0..10).collect::<Vec<u8>>());
+        let mut sorted = received.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..10).collect::<Vec<u8>>());
+        // The same seed gives the same order
+        assert_eq!(reordered_run(42), received);
+    }
+
+    #[test]
+    fn test_delay() {
+        let clock = Arc::new(MockClock::default());
+        let hub = LoopbackHub::new_with(0, clock.clone());
+        let a = hub.add_node();
+        let b = hub.add_node();
+        let delay = Duration::from_millis(50);
+        hub.set_conditions(LinkConditions {
+            delay,
+            ..Default::default()
+        })
+        .unwrap();
+
+        send(&a, &[1], b.addr()).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(try_recv(&b, &mut buf), None);
+        clock.advance(delay / 2);
+        assert_eq!(try_recv(&b, &mut buf), None);
+        clock.advance(delay / 2);
+        assert_eq!(try_recv(&b, &mut buf), Some(1));
+    }
+}
@@ -16,37 +16,102 @@
  */
 
 use async_channel::Receiver;
-use boxslab::{BoxSlab, Slab};
+use boxslab::BoxSlab;
 use heapless::LinearMap;
-use log::{debug, error, info};
+use log::{debug, error, info, trace};
+use smol::future;
+use std::time::{Duration, SystemTime};
 
 use crate::error::*;
+use crate::utils::clock::{system_clock, SharedClock, Sleep};
 
 use crate::transport::mrp::ReliableMessage;
 use crate::transport::packet::PacketPool;
-use crate::transport::{exchange, packet::Packet, proto_demux, queue, session, udp};
+use crate::transport::{exchange, proto_demux, session, udp};
 
+use super::group::GroupCtx;
+use super::network::{Address, NetworkInterface};
 use super::proto_demux::ProtoCtx;
-use super::queue::Msg;
+use super::queue::{Groupcast, Msg, Unicast};
+use super::session::SessionMode;
+use super::timer::Timers;
+
+/// The events that wake up the transport loop
+enum Event {
+    /// A message was read from a network interface
+    Rx(BoxSlab<PacketPool>),
+    /// Reading from the network interfaces failed, which is not fatal
+    RxError(Error),
+    /// The messages that waited for their network interface were sent
+    Sent,
+    /// The connection to the peer at the address went away
+    Closed(Address),
+    /// A message was posted on the work queue
+    Queue(Msg),
+    /// An MRP timer or one of the [Timers] has expired
+    Timeout,
+    /// A timer was added, which may expire before the one being waited for
+    TimersChanged,
+}
+
+/// How long to wait before reading from the network interfaces again, after a failed read
+const RX_ERROR_BACKOFF: Duration = Duration::from_millis(100);
 
 pub struct Mgr {
     exch_mgr: exchange::ExchangeMgr,
     proto_demux: proto_demux::ProtoDemux,
     rx_q: Receiver<Msg>,
+    timers: Timers,
+    // The reads are held off until then, after a failed read
+    rx_backoff: Option<SystemTime>,
 }
 
 impl Mgr {
-    pub fn new() -> Result<Mgr, Error> {
-        let mut sess_mgr = session::SessionMgr::new();
-        let udp_transport = Box::new(udp::UdpListener::new()?);
-        sess_mgr.add_network_interface(udp_transport)?;
+    /// Create a transport that listens on the given UDP port
+    ///
+    /// rx_q is the receiving end of the work queue whose sending end is shared with the
+    /// protocol handlers
+    pub fn new(rx_q: Receiver<Msg>, port: u16) -> Result<Mgr, Error> {
+        let udp_transport = Box::new(udp::UdpListener::new(port)?);
+        Mgr::new_with_network(rx_q, udp_transport, system_clock())
+    }
+
+    /// Create a transport over the given network interface, going by the given clock
+    ///
+    /// This is how a node is attached to a [LoopbackHub](super::loopback::LoopbackHub)
+    /// in the tests, for instance.
+    pub fn new_with_network(
+        rx_q: Receiver<Msg>,
+        network: Box<dyn NetworkInterface>,
+        clock: SharedClock,
+    ) -> Result<Mgr, Error> {
+        let timers = Timers::new(clock.clone());
+        let mut sess_mgr = session::SessionMgr::new_with(clock);
+        sess_mgr.add_network_interface(network)?;
         Ok(Mgr {
             proto_demux: proto_demux::ProtoDemux::new(),
             exch_mgr: exchange::ExchangeMgr::new(sess_mgr),
-            rx_q: queue::WorkQ::init()?,
+            rx_q,
+            timers,
+            rx_backoff: None,
         })
     }
 
+    /// Returns the timers that are driven by this transport
+    pub fn get_timers(&self) -> Timers {
+        self.timers.clone()
+    }
+
+    /// Add another network interface, like a [TcpTransport](super::tcp::TcpTransport), next to the UDP one
+    pub fn add_network_interface(
+        &mut self,
+        interface: Box<dyn NetworkInterface>,
+    ) -> Result<(), Error> {
+        self.exch_mgr
+            .get_sess_mgr()
+            .add_network_interface(interface)
+    }
+
     // Allows registration of different protocols with the Transport/Protocol Demux
     pub fn register_protocol(
         &mut self,
@@ -55,6 +120,11 @@ impl Mgr {
         self.proto_demux.register(proto_id_handle)
     }
 
+    /// Enable the reception and the transmission of group messages
+    pub fn set_group_ctx(&mut self, group: GroupCtx) {
+        self.exch_mgr.get_sess_mgr().set_group_ctx(group);
+    }
+
     fn send_to_exchange(
         &mut self,
         exch_id: u16,
@@ -63,8 +133,8 @@ impl Mgr {
         self.exch_mgr.send(exch_id, proto_tx)
     }
 
-    fn handle_rxtx(&mut self) -> Result<(), Error> {
-        let result = self.exch_mgr.recv().map_err(|e| {
+    fn handle_rxtx(&mut self, rx: BoxSlab<PacketPool>) -> Result<(), Error> {
+        let result = self.exch_mgr.recv(rx).map_err(|e| {
             error!("Error in recv: {:?}", e);
             e
         })?;
@@ -78,11 +148,17 @@ impl Mgr {
         let (rx, exch_ctx) = result.unwrap();
 
         debug!("Exchange is {:?}", exch_ctx.exch);
-        let tx = Self::new_tx()?;
+        let tx = exch_ctx.sess.new_tx()?;
 
         let mut proto_ctx = ProtoCtx::new(exch_ctx, rx, tx);
         // Proto Dispatch
-        match self.proto_demux.handle(&mut proto_ctx) {
+        let result = self.proto_demux.handle(&mut proto_ctx);
+        if proto_ctx.exch_ctx.sess.is_group() {
+            // Nothing is ever sent back over a group session
+            proto_ctx.exch_ctx.exch.close();
+            return result.map(|_| ());
+        }
+        match result {
             Ok(r) => {
                 if let proto_demux::ResponseRequired::No = r {
                     // We need to send the Ack if reliability is enabled, in this case
@@ -111,65 +187,319 @@ impl Mgr {
         Ok(())
     }
 
-    fn handle_queue_msgs(&mut self) -> Result<(), Error> {
-        if let Ok(msg) = self.rx_q.try_recv() {
-            match msg {
-                Msg::NewSession(clone_data) => {
-                    // If a new session was created, add it
-                    let _ = self
-                        .exch_mgr
-                        .add_session(&clone_data)
-                        .map_err(|e| error!("Error adding new session {:?}", e));
-                }
-                _ => {
-                    error!("Queue Message Type not yet handled {:?}", msg);
-                }
+    fn handle_queue_msg(&mut self, msg: Msg) -> Result<(), Error> {
+        match msg {
+            Msg::NewSession(clone_data) => {
+                // If a new session was created, add it
+                let _ = self
+                    .exch_mgr
+                    .add_session(&clone_data)
+                    .map_err(|e| error!("Error adding new session {:?}", e));
+            }
+            Msg::Groupcast(groupcast) => {
+                let _ = self
+                    .send_groupcast(groupcast)
+                    .map_err(|e| error!("Error sending groupcast {:?}", e));
+            }
+            Msg::Unicast(unicast) => {
+                let _ = self
+                    .send_unicast(unicast)
+                    .map_err(|e| error!("Error sending unicast {:?}", e));
+            }
+            Msg::RemoveSessions {
+                pase,
+                fab_idx,
+                keep_sess_id,
+            } => {
+                info!("Removing sessions, PASE: {} fabric: {:?}", pase, fab_idx);
+                self.exch_mgr.remove_sessions(|s| {
+                    if Some(s.get_local_sess_id()) == keep_sess_id {
+                        return false;
+                    }
+                    match s.get_session_mode() {
+                        SessionMode::Pase => pase,
+                        SessionMode::Case(c) => Some(c.fab_idx) == fab_idx,
+                        _ => false,
+                    }
+                });
+            }
+            _ => {
+                error!("Queue Message Type not yet handled {:?}", msg);
             }
         }
         Ok(())
     }
 
-    pub fn start(&mut self) -> Result<(), Error> {
+    fn send_groupcast(&mut self, groupcast: Groupcast) -> Result<(), Error> {
+        let mut proto_tx = self.new_tx()?;
+        proto_tx.set_proto_id(groupcast.proto_id);
+        proto_tx.set_proto_opcode(groupcast.proto_opcode);
+        proto_tx.proto.exch_id = rand::random();
+        proto_tx.proto.set_initiator();
+        proto_tx.get_writebuf()?.append(&groupcast.payload)?;
+        self.exch_mgr
+            .get_sess_mgr()
+            .send_groupcast(groupcast.fab_idx, groupcast.group_id, proto_tx)
+    }
+
+    fn send_unicast(&mut self, unicast: Unicast) -> Result<(), Error> {
+        let mut proto_tx = self.new_tx()?;
+        proto_tx.set_proto_id(unicast.proto_id);
+        proto_tx.set_proto_opcode(unicast.proto_opcode);
+        proto_tx.get_writebuf()?.append(&unicast.payload)?;
+        let result = self
+            .exch_mgr
+            .initiate(unicast.sess_id, unicast.peer_node_id, proto_tx);
+        if let (Err(Error::NotFound), Some(timer)) = (result, unicast.timer) {
+            // The session is gone, and so is whatever the timer was for
+            info!("Session {} is gone, cancelling its timer", unicast.sess_id);
+            self.timers.cancel(timer);
+        }
+        result.map(|_| ())
+    }
+
+    /// Wait until there is something for the transport loop to do
+    ///
+    /// This waits on the network interfaces, the work queue and the earliest pending MRP
+    /// timer or [Timers] deadline, all at the same time, and returns whichever is ready first.
+    /// The messages that wait for their network interface are sent meanwhile.
+    async fn wait_for_event(&self) -> Result<Event, Error> {
+        let deadline = match (self.exch_mgr.next_timeout(), self.timers.next_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        // The deadlines are in the time of the clock, so it is the clock that waits for them
+        let timer: Sleep = match deadline {
+            Some(t) => self.exch_mgr.get_clock().sleep_until(t),
+            None => Box::pin(future::pending()),
+        };
+
+        // A failed read, like when the packet pool runs out, is likely to fail again right
+        // away, so give it some time
+        let backoff = self
+            .rx_backoff
+            .map(|t| self.exch_mgr.get_clock().sleep_until(t));
+        let rx = async {
+            if let Some(backoff) = backoff {
+                backoff.await;
+            }
+            Ok(match self.exch_mgr.read().await {
+                Ok(rx) => Event::Rx(rx),
+                Err(e) => Event::RxError(e),
+            })
+        };
+        let sent = async {
+            if self.exch_mgr.has_pending_tx() {
+                self.exch_mgr.flush().await;
+                Ok(Event::Sent)
+            } else {
+                future::pending().await
+            }
+        };
+        let closed = async { Ok(Event::Closed(self.exch_mgr.closed().await)) };
+        let queue = async { Ok(Event::Queue(self.rx_q.recv().await?)) };
+        let timeout = async {
+            timer.await;
+            Ok(Event::Timeout)
+        };
+        let timers_changed = async {
+            self.timers.changed().await?;
+            Ok(Event::TimersChanged)
+        };
+
+        future::or(
+            rx,
+            future::or(
+                sent,
+                future::or(
+                    closed,
+                    future::or(queue, future::or(timeout, timers_changed)),
+                ),
+            ),
+        )
+        .await
+    }
+
+    /// Run the transport
+    ///
+    /// The returned future completes only when the work queue or the [Timers] are closed. The
+    /// errors of the network interfaces are logged, and the transport carries on. All the
+    /// processing of an event happens synchronously between two waits, so the future can be
+    /// dropped at any point without leaving an exchange or a session half updated.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        self.sync_group_memberships();
         loop {
+            let event = self.wait_for_event().await.map_err(|e| {
+                error!("Error waiting for events {:?}", e);
+                e
+            })?;
+            self.handle_event(event);
+        }
+    }
+
+    pub fn start(&mut self) -> Result<(), Error> {
+        smol::block_on(self.run())
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        self.timers.fire_expired();
+
+        match event {
             // Handle network operations
-            if self.handle_rxtx().is_err() {
-                error!("Error in handle_rxtx");
-                continue;
+            Event::Rx(rx) => {
+                self.rx_backoff = None;
+                if self.handle_rxtx(rx).is_err() {
+                    error!("Error in handle_rxtx");
+                }
             }
-
-            if self.handle_queue_msgs().is_err() {
-                error!("Error in handle_queue_msg");
-                continue;
+            Event::RxError(e) => {
+                error!("Error reading from the network {:?}", e);
+                self.rx_backoff = Some(self.exch_mgr.get_clock().now() + RX_ERROR_BACKOFF);
             }
+            Event::Queue(msg) => {
+                if self.handle_queue_msg(msg).is_err() {
+                    error!("Error in handle_queue_msg");
+                }
+            }
+            Event::Closed(addr) => {
+                info!("Removing the sessions with {}", addr);
+                self.exch_mgr.remove_sessions(|s| s.get_peer_addr() == addr);
+            }
+            Event::Sent | Event::Timeout | Event::TimersChanged => (),
+        }
 
-            // Handle any pending acknowledgement send
-            let mut acks_to_send: LinearMap<u16, (), { exchange::MAX_MRP_ENTRIES }> =
-                LinearMap::new();
-            self.exch_mgr.pending_acks(&mut acks_to_send);
-            for exch_id in acks_to_send.keys() {
-                info!("Sending MRP Standalone ACK for  exch {}", exch_id);
-                let mut proto_tx = match Self::new_tx() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        error!("Error creating proto_tx {:?}", e);
-                        break;
-                    }
-                };
-                ReliableMessage::prepare_ack(*exch_id, &mut proto_tx);
-                if let Err(e) = self.send_to_exchange(*exch_id, proto_tx) {
-                    error!("Error in sending Ack {:?}", e);
+        // Handle any pending acknowledgement send
+        let mut acks_to_send: LinearMap<u16, (), { exchange::MAX_MRP_ENTRIES }> = LinearMap::new();
+        self.exch_mgr.pending_acks(&mut acks_to_send);
+        for exch_id in acks_to_send.keys() {
+            info!("Sending MRP Standalone ACK for  exch {}", exch_id);
+            let mut proto_tx = match self.new_tx() {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Error creating proto_tx {:?}", e);
+                    break;
                 }
+            };
+            ReliableMessage::prepare_ack(*exch_id, &mut proto_tx);
+            if let Err(e) = self.send_to_exchange(*exch_id, proto_tx) {
+                error!("Error in sending Ack {:?}", e);
             }
+        }
+
+        // Handle any pending retransmissions
+        let mut retrans_to_send: LinearMap<u16, (), { exchange::MAX_MRP_ENTRIES }> =
+            LinearMap::new();
+        self.exch_mgr.pending_retrans(&mut retrans_to_send);
+        for exch_id in retrans_to_send.keys() {
+            if let Err(e) = self.exch_mgr.retrans(*exch_id) {
+                error!("Error in retransmission for exch {}: {:?}", exch_id, e);
+            }
+        }
+
+        self.sync_group_memberships();
 
-            // Handle exchange purging
-            //    This need not be done in each turn of the loop, maybe once in 5 times or so?
-            self.exch_mgr.purge();
+        // Handle exchange purging
+        //    This need not be done in each turn of the loop, maybe once in 5 times or so?
+        self.exch_mgr.purge();
 
-            info!("Exchange Mgr: {}", self.exch_mgr);
+        trace!("Exchange Mgr: {}", self.exch_mgr);
+    }
+
+    fn sync_group_memberships(&mut self) {
+        if let Err(e) = self.exch_mgr.get_sess_mgr().sync_group_memberships() {
+            error!("Error updating the group memberships {:?}", e);
         }
     }
 
-    fn new_tx() -> Result<BoxSlab<PacketPool>, Error> {
-        Slab::<PacketPool>::try_new(Packet::new_tx()?).ok_or(Error::PacketPoolExhaust)
+    /// Drop all the sessions, along with the exchanges and subscriptions on them
+    pub fn remove_all_sessions(&mut self) {
+        self.exch_mgr.remove_all_sessions();
+    }
+
+    fn new_tx(&mut self) -> Result<BoxSlab<PacketPool>, Error> {
+        self.exch_mgr.get_sess_mgr().new_tx()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    use smol::future;
+
+    use crate::{
+        error::Error,
+        transport::{
+            network::{Address, NetworkInterface},
+            queue::WorkQ,
+        },
+        utils::clock::MockClock,
+    };
+
+    use super::{Mgr, RX_ERROR_BACKOFF};
+
+    // Fails every read
+    struct FailingNetwork {
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl NetworkInterface for FailingNetwork {
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            _in_buf: &mut [u8],
+        ) -> Poll<Result<(usize, Address), Error>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            Poll::Ready(Err(Error::Network))
+        }
+
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            out_buf: &[u8],
+            _addr: Address,
+        ) -> Poll<Result<usize, Error>> {
+            Poll::Ready(Ok(out_buf.len()))
+        }
+    }
+
+    async fn yield_many() {
+        for _ in 0..10 {
+            future::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn test_rx_error_is_not_fatal() {
+        let clock = MockClock::default();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let (_work_q, rx_q) = WorkQ::new();
+        let network = Box::new(FailingNetwork {
+            reads: reads.clone(),
+        });
+        let mut mgr = Mgr::new_with_network(rx_q, network, Arc::new(clock.clone())).unwrap();
+
+        let result = smol::block_on(future::or(
+            async {
+                mgr.run().await?;
+                Ok(false)
+            },
+            async {
+                yield_many().await;
+                // The transport keeps running, but holds off the reads after a failure
+                assert_eq!(reads.load(Ordering::SeqCst), 1);
+                clock.advance(RX_ERROR_BACKOFF);
+                yield_many().await;
+                assert_eq!(reads.load(Ordering::SeqCst), 2);
+                Ok::<_, Error>(true)
+            },
+        ));
+        assert_eq!(result, Ok(true));
     }
 }
@@ -15,8 +15,11 @@
  *    limitations under the License.
  */
 
-mod dedup;
+pub mod counters;
+pub mod dedup;
 pub mod exchange;
+pub mod group;
+pub mod loopback;
 pub mod mgr;
 pub mod mrp;
 pub mod network;
@@ -26,4 +29,6 @@ pub mod proto_demux;
 pub mod proto_hdr;
 pub mod queue;
 pub mod session;
+pub mod tcp;
+pub mod timer;
 pub mod udp;
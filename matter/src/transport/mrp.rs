@@ -18,27 +18,150 @@
 use std::time::Duration;
 use std::time::SystemTime;
 
-use crate::{error::*, secure_channel, transport::packet::Packet};
+use crate::{
+    error::*,
+    secure_channel,
+    tlv::{FromTLV, TLVElement},
+    transport::packet::Packet,
+};
 use log::error;
+use rand::Rng;
 
 // 200 ms
 const MRP_STANDALONE_ACK_TIMEOUT: u64 = 200;
 
+/// The maximum number of transmissions (including the first one) of a reliable message
+pub const MRP_MAX_TRANSMISSIONS: u8 = 5;
+/// The base of the exponential backoff between retransmissions
+const MRP_BACKOFF_BASE: f64 = 1.6;
+/// The maximum random jitter, as a fraction of the retransmission interval
+const MRP_BACKOFF_JITTER: f64 = 0.25;
+/// A safety margin applied over the peer's advertised interval
+const MRP_BACKOFF_MARGIN: f64 = 1.1;
+/// The number of retransmissions before the exponential backoff kicks in
+const MRP_BACKOFF_THRESHOLD: u8 = 1;
+
+// 500 ms
+const MRP_DEFAULT_IDLE_INTERVAL: u64 = 500;
+// 300 ms
+const MRP_DEFAULT_ACTIVE_INTERVAL: u64 = 300;
+
+/// The MRP retransmission intervals of a peer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MrpParams {
+    /// The retransmission interval to use when the peer is idle
+    pub idle_interval: Duration,
+    /// The retransmission interval to use when the peer is active
+    pub active_interval: Duration,
+}
+
+impl Default for MrpParams {
+    fn default() -> Self {
+        Self {
+            idle_interval: Duration::from_millis(MRP_DEFAULT_IDLE_INTERVAL),
+            active_interval: Duration::from_millis(MRP_DEFAULT_ACTIVE_INTERVAL),
+        }
+    }
+}
+
+// The session parameters that an initiator may include in its PASE/CASE request. The
+// intervals are in milliseconds; the default is used for the ones that are absent
+//
+// Note: the tlvargs have to be the first attribute, so this can't be a doc comment
+#[derive(FromTLV, Debug, Default)]
+#[tlvargs(start = 1)]
+pub struct SessionParams {
+    pub idle_interval: Option<u32>,
+    pub active_interval: Option<u32>,
+}
+
+impl From<Option<SessionParams>> for MrpParams {
+    fn from(params: Option<SessionParams>) -> Self {
+        let mut mrp_params = MrpParams::default();
+        if let Some(params) = params {
+            if let Some(idle) = params.idle_interval {
+                mrp_params.idle_interval = Duration::from_millis(idle as u64);
+            }
+            if let Some(active) = params.active_interval {
+                mrp_params.active_interval = Duration::from_millis(active as u64);
+            }
+        }
+        mrp_params
+    }
+}
+
+/// Returns the time to wait before the next retransmission, given the base interval
+/// and the number of retransmissions that have already been made
+fn backoff(base_interval: Duration, retrans_count: u8) -> Duration {
+    let exponent = retrans_count.saturating_sub(MRP_BACKOFF_THRESHOLD) as i32;
+    let jitter = 1.0 + rand::thread_rng().gen_range(0.0..MRP_BACKOFF_JITTER);
+    base_interval.mul_f64(MRP_BACKOFF_MARGIN * MRP_BACKOFF_BASE.powi(exponent) * jitter)
+}
+
 #[derive(Debug)]
 pub struct RetransEntry {
     // The msg counter that we are waiting to be acknowledged
     msg_ctr: u32,
-    // This will additionally have retransmission count and periods once we implement it
+    // The encoded message, as it went out on the wire
+    payload: Vec<u8>,
+    // The number of times this message has been sent so far
+    send_count: u8,
+    // The base retransmission interval for the peer
+    base_interval: Duration,
+    // The time after which the message must be retransmitted
+    retrans_timeout: SystemTime,
 }
 
 impl RetransEntry {
-    pub fn new(msg_ctr: u32) -> Self {
-        Self { msg_ctr }
+    pub fn new(
+        msg_ctr: u32,
+        payload: &[u8],
+        base_interval: Duration,
+        now: SystemTime,
+    ) -> Result<Self, Error> {
+        let retrans_timeout = now
+            .checked_add(backoff(base_interval, 0))
+            .ok_or(Error::Invalid)?;
+        Ok(Self {
+            msg_ctr,
+            payload: payload.to_vec(),
+            send_count: 1,
+            base_interval,
+            retrans_timeout,
+        })
     }
 
     pub fn get_msg_ctr(&self) -> u32 {
         self.msg_ctr
     }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn get_send_count(&self) -> u8 {
+        self.send_count
+    }
+
+    pub fn get_timeout(&self) -> SystemTime {
+        self.retrans_timeout
+    }
+
+    pub fn has_timed_out(&self, now: SystemTime) -> bool {
+        self.retrans_timeout <= now
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.send_count >= MRP_MAX_TRANSMISSIONS
+    }
+
+    /// Record a retransmission of this entry and schedule the next one
+    pub fn record_retrans(&mut self, now: SystemTime) -> Result<(), Error> {
+        let delay = backoff(self.base_interval, self.send_count);
+        self.retrans_timeout = now.checked_add(delay).ok_or(Error::Invalid)?;
+        self.send_count += 1;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -50,9 +173,9 @@ pub struct AckEntry {
 }
 
 impl AckEntry {
-    pub fn new(msg_ctr: u32) -> Result<Self, Error> {
+    pub fn new(msg_ctr: u32, now: SystemTime) -> Result<Self, Error> {
         if let Some(ack_timeout) =
-            SystemTime::now().checked_add(Duration::from_millis(MRP_STANDALONE_ACK_TIMEOUT))
+            now.checked_add(Duration::from_millis(MRP_STANDALONE_ACK_TIMEOUT))
         {
             Ok(Self {
                 msg_ctr,
@@ -67,8 +190,12 @@ impl AckEntry {
         self.msg_ctr
     }
 
-    pub fn has_timed_out(&self) -> bool {
-        self.ack_timeout > SystemTime::now()
+    pub fn get_timeout(&self) -> SystemTime {
+        self.ack_timeout
+    }
+
+    pub fn has_timed_out(&self, now: SystemTime) -> bool {
+        self.ack_timeout <= now
     }
 }
 
@@ -90,15 +217,41 @@ impl ReliableMessage {
     }
 
     // Check any pending acknowledgements / retransmissions and take action
-    pub fn is_ack_ready(&self) -> bool {
+    pub fn is_ack_ready(&self, now: SystemTime) -> bool {
         // Acknowledgements
         if let Some(ack_entry) = self.ack {
-            ack_entry.has_timed_out()
+            ack_entry.has_timed_out(now)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_retrans_ready(&self, now: SystemTime) -> bool {
+        if let Some(entry) = &self.retrans {
+            entry.has_timed_out(now)
         } else {
             false
         }
     }
 
+    /// Returns the earliest time at which an acknowledgement or a retransmission is due
+    pub fn next_timeout(&self) -> Option<SystemTime> {
+        let ack = self.ack.map(|a| a.get_timeout());
+        let retrans = self.retrans.as_ref().map(|r| r.get_timeout());
+        match (ack, retrans) {
+            (Some(a), Some(r)) => Some(a.min(r)),
+            (a, r) => a.or(r),
+        }
+    }
+
+    pub fn get_retrans_entry(&mut self) -> Option<&mut RetransEntry> {
+        self.retrans.as_mut()
+    }
+
+    pub fn clear_retrans(&mut self) {
+        self.retrans = None;
+    }
+
     pub fn prepare_ack(_exch_id: u16, proto_tx: &mut Packet) {
         secure_channel::common::create_mrp_standalone_ack(proto_tx);
     }
@@ -122,8 +275,28 @@ impl ReliableMessage {
             error!("Previous retrans entry for this exchange already exists");
             return Err(Error::Invalid);
         }
+        Ok(())
+    }
 
-        self.retrans = Some(RetransEntry::new(proto_tx.plain.ctr));
+    /// Remember the encoded message, so that it can be retransmitted until acknowledged
+    ///
+    /// This must be called once the message is fully encoded, just before it goes out
+    pub fn post_encode(
+        &mut self,
+        proto_tx: &mut Packet,
+        base_interval: Duration,
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        if !proto_tx.is_reliable() {
+            return Ok(());
+        }
+
+        self.retrans = Some(RetransEntry::new(
+            proto_tx.plain.ctr,
+            proto_tx.as_borrow_slice(),
+            base_interval,
+            now,
+        )?);
         Ok(())
     }
 
@@ -132,16 +305,22 @@ impl ReliableMessage {
      * -  there can be only one pending retransmission per exchange (so this is per-exchange)
      * -  duplicate detection should happen per session (obviously), so that part is per-session
      */
-    pub fn recv(&mut self, proto_rx: &Packet) -> Result<(), Error> {
+    pub fn recv(&mut self, proto_rx: &Packet, now: SystemTime) -> Result<(), Error> {
         if proto_rx.proto.is_ack() {
             // Handle received Acks
             let ack_msg_ctr = proto_rx.proto.get_ack_msg_ctr().ok_or(Error::Invalid)?;
             if let Some(entry) = &self.retrans {
-                if entry.get_msg_ctr() != ack_msg_ctr {
-                    // TODO: XXX Fix this
-                    error!("Mismatch in retrans-table's msg counter and received msg counter: received {}, expected {}. This is expected for the timebeing", ack_msg_ctr, entry.get_msg_ctr());
+                if entry.get_msg_ctr() == ack_msg_ctr {
+                    self.retrans = None;
+                } else {
+                    // An ACK for some other message doesn't acknowledge the pending one,
+                    // which keeps being retransmitted
+                    error!(
+                        "Ignoring ACK for msg counter {}, the pending message has {}",
+                        ack_msg_ctr,
+                        entry.get_msg_ctr()
+                    );
                 }
-                self.retrans = None;
             }
         }
 
@@ -153,8 +332,133 @@ impl ReliableMessage {
                 return Err(Error::Invalid);
             }
 
-            self.ack = Some(AckEntry::new(proto_rx.plain.ctr)?);
+            self.ack = Some(AckEntry::new(proto_rx.plain.ctr, now)?);
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use std::time::Duration;
+
+    use boxslab::Slab;
+
+    use crate::{
+        tlv::{self, FromTLV},
+        transport::packet::{BufferPool, Packet},
+        utils::clock::{Clock, MockClock},
+    };
+
+    use super::{
+        backoff, AckEntry, MrpParams, ReliableMessage, RetransEntry, SessionParams,
+        MRP_MAX_TRANSMISSIONS, MRP_STANDALONE_ACK_TIMEOUT,
+    };
+
+    #[test]
+    fn test_session_params() {
+        // Only the idle interval: 5000 ms
+        let b = [0x15, 0x25, 0x01, 0x88, 0x13, 0x18];
+        let root = tlv::get_root_node_struct(&b).unwrap();
+        let params = SessionParams::from_tlv(&root).unwrap();
+        let mrp_params = MrpParams::from(Some(params));
+        assert_eq!(mrp_params.idle_interval, Duration::from_millis(5000));
+        assert_eq!(
+            mrp_params.active_interval,
+            MrpParams::default().active_interval
+        );
+
+        assert_eq!(MrpParams::from(None), MrpParams::default());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(300);
+        // The first two transmissions don't use the exponential backoff
+        for retrans_count in 0..2 {
+            let t = backoff(base, retrans_count);
+            assert!(t >= Duration::from_millis(329));
+            assert!(t <= Duration::from_micros(412_500));
+        }
+        // 300 * 1.1 * 1.6 ^ 2 = 844.8 ms, and up to 25% of jitter on top
+        let t = backoff(base, 3);
+        assert!(t >= Duration::from_millis(844));
+        assert!(t <= Duration::from_micros(1_056_000));
+    }
+
+    #[test]
+    fn test_retrans_exhausted() {
+        let clock = MockClock::default();
+        let mut entry =
+            RetransEntry::new(10, &[1, 2, 3], Duration::from_millis(0), clock.now()).unwrap();
+        assert_eq!(entry.get_payload(), &[1, 2, 3]);
+        assert_eq!(entry.has_timed_out(clock.now()), true);
+        for _ in 1..MRP_MAX_TRANSMISSIONS {
+            assert_eq!(entry.is_exhausted(), false);
+            entry.record_retrans(clock.now()).unwrap();
+        }
+        assert_eq!(entry.get_send_count(), MRP_MAX_TRANSMISSIONS);
+        assert_eq!(entry.is_exhausted(), true);
+    }
+
+    #[test]
+    fn test_retrans_timeout() {
+        let clock = MockClock::default();
+        let mut entry =
+            RetransEntry::new(10, &[1, 2, 3], Duration::from_secs(10), clock.now()).unwrap();
+        assert_eq!(entry.has_timed_out(clock.now()), false);
+        // 10 s with the margin, but not yet the jitter
+        clock.advance(Duration::from_millis(10_999));
+        assert_eq!(entry.has_timed_out(clock.now()), false);
+        // All of the jitter
+        clock.advance(Duration::from_millis(2_751));
+        assert_eq!(entry.has_timed_out(clock.now()), true);
+
+        // The next timeout is counted from the retransmission
+        entry.record_retrans(clock.now()).unwrap();
+        assert_eq!(entry.has_timed_out(clock.now()), false);
+    }
+
+    #[test]
+    fn test_ack_timeout() {
+        let clock = MockClock::default();
+        let ack = AckEntry::new(10, clock.now()).unwrap();
+        // The standalone ACK must only go out after the ACK timeout
+        assert_eq!(ack.has_timed_out(clock.now()), false);
+        clock.advance(Duration::from_millis(MRP_STANDALONE_ACK_TIMEOUT));
+        assert_eq!(ack.has_timed_out(clock.now()), true);
+
+        let mut mrp = ReliableMessage::new();
+        assert_eq!(mrp.next_timeout(), None);
+        mrp.ack = Some(ack);
+        assert_eq!(mrp.next_timeout(), Some(ack.get_timeout()));
+        assert_eq!(mrp.is_ack_ready(clock.now()), true);
+
+        let retrans =
+            RetransEntry::new(11, &[1, 2, 3], Duration::from_millis(0), clock.now()).unwrap();
+        let retrans_timeout = retrans.get_timeout();
+        mrp.retrans = Some(retrans);
+        assert_eq!(mrp.next_timeout(), Some(retrans_timeout));
+    }
+
+    #[test]
+    fn test_ack_mismatch() {
+        let clock = MockClock::default();
+        let pool = Slab::<BufferPool>::new();
+        let mut mrp = ReliableMessage::new();
+        mrp.retrans =
+            Some(RetransEntry::new(10, &[1, 2, 3], Duration::from_secs(1), clock.now()).unwrap());
+
+        // An ACK for another message leaves the pending one to be retransmitted
+        let mut rx = Packet::new_rx(&pool).unwrap();
+        rx.proto.set_ack(9);
+        mrp.recv(&rx, clock.now()).unwrap();
+        assert_eq!(mrp.retrans.as_ref().map(|r| r.get_msg_ctr()), Some(10));
+
+        let mut rx = Packet::new_rx(&pool).unwrap();
+        rx.proto.set_ack(10);
+        mrp.recv(&rx, clock.now()).unwrap();
+        assert!(mrp.retrans.is_none());
+    }
+}
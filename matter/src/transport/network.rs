@@ -17,14 +17,42 @@
 
 use std::{
     fmt::{Debug, Display},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::{Context, Poll},
 };
 
+use smol::future;
+
 use crate::error::Error;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum Address {
     Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+/// The transport protocol that an address, or a network interface, is for
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl Transport {
+    /// Whether the transport itself guarantees the delivery of the messages, in which case
+    /// MRP isn't used
+    pub fn is_reliable(&self) -> bool {
+        *self == Transport::Tcp
+    }
+}
+
+impl Address {
+    pub fn transport(&self) -> Transport {
+        match self {
+            Address::Udp(_) => Transport::Udp,
+            Address::Tcp(_) => Transport::Tcp,
+        }
+    }
 }
 
 impl Default for Address {
@@ -37,6 +65,7 @@ impl Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Address::Udp(addr) => writeln!(f, "{}", addr),
+            Address::Tcp(addr) => writeln!(f, "tcp:{}", addr),
         }
     }
 }
@@ -45,11 +74,156 @@ impl Debug for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Address::Udp(addr) => writeln!(f, "{}", addr),
+            Address::Tcp(addr) => writeln!(f, "tcp:{}", addr),
         }
     }
 }
 
+/// A network interface that messages are received from and sent over
+///
+/// The interface is asynchronous, it is driven by the transport loop, which awaits it with
+/// [recv()] and [send()]. The interface must register the task for wakeup whenever it
+/// returns Pending, like any future.
 pub trait NetworkInterface {
-    fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error>;
-    fn send(&self, out_buf: &[u8], addr: Address) -> Result<usize, Error>;
+    /// Attempt to receive a message, registering the current task for wakeup if none is
+    /// available yet
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        in_buf: &mut [u8],
+    ) -> Poll<Result<(usize, Address), Error>>;
+
+    /// Attempt to send a message, registering the current task for wakeup if the interface
+    /// isn't ready to accept it yet
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        out_buf: &[u8],
+        addr: Address,
+    ) -> Poll<Result<usize, Error>>;
+
+    /// Start receiving the messages sent to an IPv6 multicast address
+    ///
+    /// The default implementation doesn't support multicast, so no group messages are
+    /// received over such an interface.
+    fn join_multicast(&self, _addr: &Ipv6Addr) -> Result<(), Error> {
+        Err(Error::Invalid)
+    }
+
+    /// Stop receiving the messages sent to an IPv6 multicast address
+    fn leave_multicast(&self, _addr: &Ipv6Addr) -> Result<(), Error> {
+        Err(Error::Invalid)
+    }
+
+    /// Wait for a connection to a peer to go away, registering the current task for wakeup
+    /// until one does
+    ///
+    /// The sessions with the peer at the returned address can't be reached anymore, so
+    /// they are removed. The default implementation is for the interfaces without
+    /// connections, which never close.
+    fn poll_closed(&self, _cx: &mut Context<'_>) -> Poll<Address> {
+        Poll::Pending
+    }
+
+    /// The transport protocol of the interface
+    ///
+    /// Messages to an address are sent over an interface of the address's transport.
+    fn transport(&self) -> Transport {
+        Transport::Udp
+    }
+}
+
+/// Receive a message from the network interface
+pub async fn recv(
+    network: &dyn NetworkInterface,
+    in_buf: &mut [u8],
+) -> Result<(usize, Address), Error> {
+    future::poll_fn(|cx| network.poll_recv(cx, in_buf)).await
+}
+
+/// Send a message over the network interface
+pub async fn send(
+    network: &dyn NetworkInterface,
+    out_buf: &[u8],
+    addr: Address,
+) -> Result<usize, Error> {
+    future::poll_fn(|cx| network.poll_send(cx, out_buf, addr)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        task::{Context, Poll},
+    };
+
+    use smol::future;
+
+    use crate::error::Error;
+
+    use super::{Address, NetworkInterface};
+
+    #[derive(Default)]
+    struct QueueNetwork {
+        rx: RefCell<VecDeque<Vec<u8>>>,
+        tx: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl NetworkInterface for QueueNetwork {
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            in_buf: &mut [u8],
+        ) -> Poll<Result<(usize, Address), Error>> {
+            match self.rx.borrow_mut().pop_front() {
+                Some(data) => {
+                    in_buf[..data.len()].copy_from_slice(&data);
+                    Poll::Ready(Ok((data.len(), Address::default())))
+                }
+                None => Poll::Pending,
+            }
+        }
+
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            out_buf: &[u8],
+            _addr: Address,
+        ) -> Poll<Result<usize, Error>> {
+            self.tx.borrow_mut().push(out_buf.to_vec());
+            Poll::Ready(Ok(out_buf.len()))
+        }
+    }
+
+    #[test]
+    fn test_recv() {
+        let network = QueueNetwork::default();
+        let mut buf = [0u8; 10];
+        // Nothing to receive yet
+        assert!(smol::block_on(future::poll_once(super::recv(&network, &mut buf))).is_none());
+
+        network.rx.borrow_mut().push_back(vec![1, 2, 3]);
+        network.rx.borrow_mut().push_back(vec![4, 5]);
+        assert_eq!(
+            smol::block_on(super::recv(&network, &mut buf)).unwrap().0,
+            3
+        );
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(
+            smol::block_on(super::recv(&network, &mut buf)).unwrap().0,
+            2
+        );
+        assert_eq!(&buf[..2], &[4, 5]);
+    }
+
+    #[test]
+    fn test_send() {
+        let network = QueueNetwork::default();
+        assert_eq!(
+            smol::block_on(super::send(&network, &[1, 2, 3], Address::default())),
+            Ok(3)
+        );
+        assert_eq!(*network.tx.borrow(), vec![vec![1, 2, 3]]);
+    }
 }
@@ -15,9 +15,11 @@
  *    limitations under the License.
  */
 
+use std::{ops::DerefMut, sync::Arc};
+
 use log::{error, trace};
-use std::sync::Mutex;
 
+// The macro brings BoxSlab, Slab and SlabPool into scope
 use boxslab::box_slab;
 
 use crate::{
@@ -35,51 +37,15 @@ use super::{
 pub const MAX_RX_BUF_SIZE: usize = 1583;
 type Buffer = [u8; MAX_RX_BUF_SIZE];
 
-// TODO: I am not very happy with this construction, need to find another way to do this
-pub struct BufferPool {
-    buffers: [Option<Buffer>; MAX_PACKET_POOL_SIZE],
-}
-
-impl BufferPool {
-    const INIT: Option<Buffer> = None;
-    fn get() -> &'static Mutex<BufferPool> {
-        static mut BUFFER_HOLDER: Option<Mutex<BufferPool>> = None;
-        static ONCE: Once = Once::new();
-        unsafe {
-            ONCE.call_once(|| {
-                BUFFER_HOLDER = Some(Mutex::new(BufferPool {
-                    buffers: [BufferPool::INIT; MAX_PACKET_POOL_SIZE],
-                }));
-            });
-            BUFFER_HOLDER.as_ref().unwrap()
-        }
-    }
-
-    pub fn alloc() -> Option<(usize, &'static mut Buffer)> {
-        trace!("Buffer Alloc called\n");
+/// The pool that packet buffers are allocated from
+///
+/// Every node creates its own pool, with space for MAX_PACKET_POOL_SIZE buffers. Once
+/// these are all in use, allocating a packet fails with Error::NoSpace
+pub struct BufferPool;
 
-        let mut pool = BufferPool::get().lock().unwrap();
-        for i in 0..MAX_PACKET_POOL_SIZE {
-            if pool.buffers[i].is_none() {
-                pool.buffers[i] = Some([0; MAX_RX_BUF_SIZE]);
-                // Sigh! to by-pass the borrow-checker telling us we are stealing a mutable reference
-                // from under the lock
-                // In this case the lock only protects against the setting of Some/None,
-                // the objects then are independently accessed in a unique way
-                let buffer = unsafe { &mut *(pool.buffers[i].as_mut().unwrap() as *mut Buffer) };
-                return Some((i, buffer));
-            }
-        }
-        None
-    }
-
-    pub fn free(index: usize) {
-        trace!("Buffer Free called\n");
-        let mut pool = BufferPool::get().lock().unwrap();
-        if pool.buffers[index].is_some() {
-            pool.buffers[index] = None;
-        }
-    }
+impl SlabPool for BufferPool {
+    type SlabType = Buffer;
+    const SIZE: usize = MAX_PACKET_POOL_SIZE;
 }
 
 #[derive(PartialEq)]
@@ -98,27 +64,40 @@ pub struct Packet<'a> {
     pub plain: PlainHdr,
     pub proto: ProtoHdr,
     pub peer: Address,
+    // This borrows from the buffer below, and must be declared before it, so that it
+    // is dropped first
     data: Direction<'a>,
-    buffer_index: usize,
+    _buffer: BoxSlab<BufferPool>,
 }
 
 impl<'a> Packet<'a> {
     const HDR_RESERVE: usize = plain_hdr::max_plain_hdr_len() + proto_hdr::max_proto_hdr_len();
 
-    pub fn new_rx() -> Result<Self, Error> {
-        let (buffer_index, buffer) = BufferPool::alloc().ok_or(Error::NoSpace)?;
+    fn alloc_buffer(
+        pool: &Arc<Slab<BufferPool>>,
+    ) -> Result<(BoxSlab<BufferPool>, &'a mut Buffer), Error> {
+        trace!("Buffer Alloc called\n");
+        let mut slab = pool.try_new([0; MAX_RX_BUF_SIZE]).ok_or(Error::NoSpace)?;
+        // The slab storage doesn't move with the BoxSlab, and the slot is only freed
+        // when the BoxSlab is dropped, which happens after the borrow is dropped
+        let buffer = unsafe { &mut *(slab.deref_mut() as *mut Buffer) };
+        Ok((slab, buffer))
+    }
+
+    pub fn new_rx(pool: &Arc<Slab<BufferPool>>) -> Result<Self, Error> {
+        let (slab, buffer) = Self::alloc_buffer(pool)?;
         let buf_len = buffer.len();
         Ok(Self {
             plain: Default::default(),
             proto: Default::default(),
-            buffer_index,
             peer: Address::default(),
             data: Direction::Rx(ParseBuf::new(buffer, buf_len), RxState::Uninit),
+            _buffer: slab,
         })
     }
 
-    pub fn new_tx() -> Result<Self, Error> {
-        let (buffer_index, buffer) = BufferPool::alloc().ok_or(Error::NoSpace)?;
+    pub fn new_tx(pool: &Arc<Slab<BufferPool>>) -> Result<Self, Error> {
+        let (slab, buffer) = Self::alloc_buffer(pool)?;
         let buf_len = buffer.len();
 
         let mut wb = WriteBuf::new(buffer, buf_len);
@@ -127,9 +106,9 @@ impl<'a> Packet<'a> {
         let mut p = Self {
             plain: Default::default(),
             proto: Default::default(),
-            buffer_index,
             peer: Address::default(),
             data: Direction::Tx(wb),
+            _buffer: slab,
         };
         // Reliability on by default
         p.proto.set_reliable();
@@ -229,11 +208,32 @@ impl<'a> Packet<'a> {
     }
 }
 
-impl<'a> Drop for Packet<'a> {
-    fn drop(&mut self) {
-        BufferPool::free(self.buffer_index);
-        trace!("Dropping Packet......");
+box_slab!(PacketPool, Packet<'static>, MAX_PACKET_POOL_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use boxslab::Slab;
+
+    use crate::{error::Error, sys::MAX_PACKET_POOL_SIZE};
+
+    use super::{BufferPool, Packet};
+
+    #[test]
+    fn test_buffer_pool_exhaust() {
+        let pool = Slab::<BufferPool>::new();
+        let mut packets: Vec<Packet> = (0..MAX_PACKET_POOL_SIZE)
+            .map(|_| Packet::new_rx(&pool).unwrap())
+            .collect();
+        assert_eq!(Packet::new_tx(&pool).err(), Some(Error::NoSpace));
+
+        // Another pool has its own buffers
+        let other = Slab::<BufferPool>::new();
+        assert!(Packet::new_tx(&other).is_ok());
+
+        // Dropping a packet returns its buffer to the pool
+        packets.pop();
+        let mut tx = Packet::new_tx(&pool).unwrap();
+        tx.get_writebuf().unwrap().le_u8(5).unwrap();
+        assert_eq!(tx.get_writebuf().unwrap().as_mut_slice(), [5]);
     }
 }
-
-box_slab!(PacketPool, Packet<'static>, MAX_PACKET_POOL_SIZE);
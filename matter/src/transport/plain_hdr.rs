@@ -25,6 +25,7 @@ use log::info;
 pub enum SessionType {
     None,
     Encrypted,
+    Group,
 }
 
 impl Default for SessionType {
@@ -42,6 +43,12 @@ bitflags! {
     }
 }
 
+// The session type carried in the low bits of the security flags
+const SEC_FLAGS_SESS_TYPE_MASK: u8 = 0x03;
+const SEC_FLAGS_SESS_TYPE_GROUP: u8 = 0x01;
+// Set on the control messages, which use their own message counter
+const SEC_FLAGS_CONTROL: u8 = 0x40;
+
 // This is the unencrypted message
 #[derive(Debug, Default)]
 pub struct PlainHdr {
@@ -49,22 +56,59 @@ pub struct PlainHdr {
     pub sess_type: SessionType,
     pub sess_id: u16,
     pub ctr: u32,
-    peer_nodeid: Option<u64>,
+    pub is_control: bool,
+    src_nodeid: Option<u64>,
+    dest_nodeid: Option<u64>,
+    dest_group: Option<u16>,
 }
 
 impl PlainHdr {
     pub fn set_dest_u64(&mut self, id: u64) {
+        self.flags.remove(MsgFlags::DSIZ_GROUPCAST_NODEID);
         self.flags |= MsgFlags::DSIZ_UNICAST_NODEID;
-        self.peer_nodeid = Some(id);
+        self.dest_nodeid = Some(id);
+        self.dest_group = None;
+    }
+
+    pub fn set_dest_group(&mut self, group_id: u16) {
+        self.flags.remove(MsgFlags::DSIZ_UNICAST_NODEID);
+        self.flags |= MsgFlags::DSIZ_GROUPCAST_NODEID;
+        self.dest_group = Some(group_id);
+        self.dest_nodeid = None;
+    }
+
+    pub fn set_src_u64(&mut self, id: u64) {
+        self.flags |= MsgFlags::SRC_ADDR_PRESENT;
+        self.src_nodeid = Some(id);
     }
 
     pub fn get_src_u64(&self) -> Option<u64> {
         if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
-            self.peer_nodeid
+            self.src_nodeid
         } else {
             None
         }
     }
+
+    pub fn get_dest_u64(&self) -> Option<u64> {
+        self.dest_nodeid
+    }
+
+    pub fn get_dest_group(&self) -> Option<u16> {
+        self.dest_group
+    }
+
+    pub fn get_sec_flags(&self) -> u8 {
+        let mut sec_flags = if self.sess_type == SessionType::Group {
+            SEC_FLAGS_SESS_TYPE_GROUP
+        } else {
+            0
+        };
+        if self.is_control {
+            sec_flags |= SEC_FLAGS_CONTROL;
+        }
+        sec_flags
+    }
 }
 
 impl PlainHdr {
@@ -72,16 +116,35 @@ impl PlainHdr {
     pub fn decode(&mut self, msg: &mut ParseBuf) -> Result<(), Error> {
         self.flags = MsgFlags::from_bits(msg.le_u8()?).ok_or(Error::Invalid)?;
         self.sess_id = msg.le_u16()?;
-        let _sec_flags = msg.le_u8()?;
-        self.sess_type = if self.sess_id != 0 {
-            SessionType::Encrypted
-        } else {
-            SessionType::None
+        let sec_flags = msg.le_u8()?;
+        self.sess_type = match sec_flags & SEC_FLAGS_SESS_TYPE_MASK {
+            0 if self.sess_id != 0 => SessionType::Encrypted,
+            0 => SessionType::None,
+            SEC_FLAGS_SESS_TYPE_GROUP => SessionType::Group,
+            _ => return Err(Error::Invalid),
         };
+        self.is_control = sec_flags & SEC_FLAGS_CONTROL != 0;
         self.ctr = msg.le_u32()?;
 
         if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
-            self.peer_nodeid = Some(msg.le_u64()?);
+            self.src_nodeid = Some(msg.le_u64()?);
+        }
+
+        let dsiz = MsgFlags::DSIZ_UNICAST_NODEID | MsgFlags::DSIZ_GROUPCAST_NODEID;
+        match self.flags & dsiz {
+            MsgFlags::DSIZ_UNICAST_NODEID => self.dest_nodeid = Some(msg.le_u64()?),
+            MsgFlags::DSIZ_GROUPCAST_NODEID => self.dest_group = Some(msg.le_u16()?),
+            f if f.is_empty() => (),
+            _ => return Err(Error::Invalid),
+        }
+
+        // A group message must always identify its sender, and either its group or, for the
+        // group unicast control messages, its destination node
+        if self.sess_type == SessionType::Group
+            && (self.src_nodeid.is_none()
+                || (self.dest_group.is_none() && self.dest_nodeid.is_none()))
+        {
+            return Err(Error::Invalid);
         }
 
         info!(
@@ -94,10 +157,15 @@ impl PlainHdr {
     pub fn encode(&mut self, resp_buf: &mut WriteBuf) -> Result<(), Error> {
         resp_buf.le_u8(self.flags.bits())?;
         resp_buf.le_u16(self.sess_id)?;
-        resp_buf.le_u8(0)?;
+        resp_buf.le_u8(self.get_sec_flags())?;
         resp_buf.le_u32(self.ctr)?;
-        if let Some(d) = self.peer_nodeid {
+        if let Some(s) = self.get_src_u64() {
+            resp_buf.le_u64(s)?;
+        }
+        if let Some(d) = self.dest_nodeid {
             resp_buf.le_u64(d)?;
+        } else if let Some(g) = self.dest_group {
+            resp_buf.le_u16(g)?;
         }
         Ok(())
     }
@@ -105,6 +173,10 @@ impl PlainHdr {
     pub fn is_encrypted(&self) -> bool {
         self.sess_type == SessionType::Encrypted
     }
+
+    pub fn is_group(&self) -> bool {
+        self.sess_type == SessionType::Group
+    }
 }
 
 pub const fn max_plain_hdr_len() -> usize {
@@ -123,3 +195,76 @@ pub const fn max_plain_hdr_len() -> usize {
     // [optional] destination node ID
         8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_hdr_roundtrip() {
+        let mut buf = [0u8; max_plain_hdr_len()];
+        let buf_len = buf.len();
+        let mut wb = WriteBuf::new(&mut buf, buf_len);
+
+        let mut hdr = PlainHdr {
+            sess_type: SessionType::Group,
+            sess_id: 0xb9f7,
+            ctr: 0x12345678,
+            ..Default::default()
+        };
+        hdr.set_src_u64(0x1122334455667788);
+        hdr.set_dest_group(0x0101);
+        hdr.encode(&mut wb).unwrap();
+        let len = wb.as_slice().len();
+        // flags, session id, security flags, counter, source node, group id
+        assert_eq!(len, 1 + 2 + 1 + 4 + 8 + 2);
+        assert_eq!(buf[3], 0x01);
+
+        let mut pb = ParseBuf::new(&mut buf, len);
+        let mut decoded = PlainHdr::default();
+        decoded.decode(&mut pb).unwrap();
+        assert!(decoded.is_group());
+        assert!(!decoded.is_encrypted());
+        assert_eq!(decoded.sess_id, 0xb9f7);
+        assert_eq!(decoded.ctr, 0x12345678);
+        assert_eq!(decoded.get_src_u64(), Some(0x1122334455667788));
+        assert_eq!(decoded.get_dest_group(), Some(0x0101));
+        assert_eq!(decoded.get_dest_u64(), None);
+        assert!(!decoded.is_control);
+    }
+
+    #[test]
+    fn test_control_hdr_roundtrip() {
+        let mut buf = [0u8; max_plain_hdr_len()];
+        let buf_len = buf.len();
+        let mut wb = WriteBuf::new(&mut buf, buf_len);
+
+        let mut hdr = PlainHdr {
+            sess_type: SessionType::Group,
+            sess_id: 0xb9f7,
+            is_control: true,
+            ..Default::default()
+        };
+        hdr.set_src_u64(1);
+        hdr.set_dest_group(0x0101);
+        hdr.encode(&mut wb).unwrap();
+        let len = wb.as_slice().len();
+        assert_eq!(buf[3], 0x41);
+
+        let mut pb = ParseBuf::new(&mut buf, len);
+        let mut decoded = PlainHdr::default();
+        decoded.decode(&mut pb).unwrap();
+        assert!(decoded.is_group());
+        assert!(decoded.is_control);
+    }
+
+    #[test]
+    fn test_group_hdr_without_group_id() {
+        // Group security flags, but no destination group
+        let mut buf = [0x04, 0x01, 0x00, 0x01, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let len = buf.len();
+        let mut pb = ParseBuf::new(&mut buf, len);
+        let mut hdr = PlainHdr::default();
+        assert_eq!(hdr.decode(&mut pb), Err(Error::Invalid));
+    }
+}
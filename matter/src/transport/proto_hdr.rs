@@ -102,7 +102,13 @@ impl ProtoHdr {
     ) -> Result<(), Error> {
         if let Some(d) = dec_key {
             // We decrypt only if the decryption key is valid
-            decrypt_in_place(plain_hdr.ctr, peer_nodeid, parsebuf, d)?;
+            decrypt_in_place(
+                plain_hdr.ctr,
+                plain_hdr.get_sec_flags(),
+                peer_nodeid,
+                parsebuf,
+                d,
+            )?;
         }
 
         self.exch_flags = ExchFlags::from_bits(parsebuf.le_u8()?).ok_or(Error::Invalid)?;
@@ -163,11 +169,11 @@ impl fmt::Display for ProtoHdr {
     }
 }
 
-fn get_iv(recvd_ctr: u32, peer_nodeid: u64, iv: &mut [u8]) -> Result<(), Error> {
-    // The IV is the source address (64-bit) followed by the message counter (32-bit)
+fn get_iv(recvd_ctr: u32, sec_flags: u8, peer_nodeid: u64, iv: &mut [u8]) -> Result<(), Error> {
+    // The IV is the security flags, followed by the message counter (32-bit) and
+    // the source address (64-bit)
     let mut write_buf = WriteBuf::new(iv, iv.len());
-    // For some reason, this is 0 in the 'bypass' mode
-    write_buf.le_u8(0)?;
+    write_buf.le_u8(sec_flags)?;
     write_buf.le_u32(recvd_ctr)?;
     write_buf.le_u64(peer_nodeid)?;
     Ok(())
@@ -175,6 +181,7 @@ fn get_iv(recvd_ctr: u32, peer_nodeid: u64, iv: &mut [u8]) -> Result<(), Error>
 
 pub fn encrypt_in_place(
     send_ctr: u32,
+    sec_flags: u8,
     peer_nodeid: u64,
     plain_hdr: &[u8],
     writebuf: &mut WriteBuf,
@@ -182,7 +189,7 @@ pub fn encrypt_in_place(
 ) -> Result<(), Error> {
     // IV
     let mut iv = [0_u8; crypto::AEAD_NONCE_LEN_BYTES];
-    get_iv(send_ctr, peer_nodeid, &mut iv)?;
+    get_iv(send_ctr, sec_flags, peer_nodeid, &mut iv)?;
 
     // Cipher Text
     let tag_space = [0u8; crypto::AEAD_MIC_LEN_BYTES];
@@ -203,36 +210,51 @@ pub fn encrypt_in_place(
 
 fn decrypt_in_place(
     recvd_ctr: u32,
+    sec_flags: u8,
     peer_nodeid: u64,
     parsebuf: &mut ParseBuf,
     key: &[u8],
 ) -> Result<(), Error> {
     // AAD:
-    //    the unencrypted header of this packet
-    let mut aad = [0_u8; crypto::AEAD_AAD_LEN_BYTES];
+    //    the unencrypted header of this packet, which is variable sized
+    let mut aad = [0_u8; plain_hdr::max_plain_hdr_len()];
     let parsed_slice = parsebuf.parsed_as_slice();
-    if parsed_slice.len() == aad.len() {
-        // The plain_header is variable sized in length, I wonder if the AAD is fixed at 8, or the variable size.
-        // If so, we need to handle it cleanly here.
-        aad.copy_from_slice(parsed_slice);
-    } else {
+    if parsed_slice.len() > aad.len() {
         return Err(Error::InvalidAAD);
     }
+    let aad = &mut aad[..parsed_slice.len()];
+    aad.copy_from_slice(parsed_slice);
+
+    decrypt_payload(
+        recvd_ctr,
+        sec_flags,
+        peer_nodeid,
+        aad,
+        parsebuf.as_borrow_slice(),
+        key,
+    )?;
+    parsebuf.tail(crypto::AEAD_MIC_LEN_BYTES)?;
+    Ok(())
+}
 
+/// Decrypt and authenticate a message payload (including its MIC) in place
+///
+/// This is used directly when several keys have to be tried against the
+/// same message, as is the case with group messages.
+pub fn decrypt_payload(
+    recvd_ctr: u32,
+    sec_flags: u8,
+    peer_nodeid: u64,
+    aad: &[u8],
+    cipher_text: &mut [u8],
+    key: &[u8],
+) -> Result<(), Error> {
     // IV:
     //   the specific way for creating IV is in get_iv
     let mut iv = [0_u8; crypto::AEAD_NONCE_LEN_BYTES];
-    get_iv(recvd_ctr, peer_nodeid, &mut iv)?;
+    get_iv(recvd_ctr, sec_flags, peer_nodeid, &mut iv)?;
 
-    let cipher_text = parsebuf.as_borrow_slice();
-    //println!("AAD: {:x?}", aad);
-    //println!("Cipher Text: {:x?}", cipher_text);
-    //println!("IV: {:x?}", iv);
-    //println!("Key: {:x?}", key);
-
-    crypto::decrypt_in_place(key, &iv, &aad, cipher_text)?;
-    // println!("Plain Text: {:x?}", cipher_text);
-    parsebuf.tail(crypto::AEAD_MIC_LEN_BYTES)?;
+    crypto::decrypt_in_place(key, &iv, aad, cipher_text)?;
     Ok(())
 }
 
@@ -277,7 +299,7 @@ mod tests {
         parsebuf.le_u32().unwrap();
         parsebuf.le_u32().unwrap();
 
-        decrypt_in_place(recvd_ctr, 0, &mut parsebuf, &key).unwrap();
+        decrypt_in_place(recvd_ctr, 0, 0, &mut parsebuf, &key).unwrap();
         assert_eq!(
             parsebuf.as_slice(),
             [
@@ -311,7 +333,7 @@ mod tests {
             0x1b, 0x33,
         ];
 
-        encrypt_in_place(send_ctr, 0, &plain_hdr, &mut writebuf, &key).unwrap();
+        encrypt_in_place(send_ctr, 0, 0, &plain_hdr, &mut writebuf, &key).unwrap();
         assert_eq!(
             writebuf.as_slice(),
             [
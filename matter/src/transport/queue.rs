@@ -15,19 +15,51 @@
  *    limitations under the License.
  */
 
-use std::sync::Once;
-
 use async_channel::{bounded, Receiver, Sender};
 
 use crate::error::Error;
 
-use super::session::CloneData;
+use super::{session::CloneData, timer::TimerId};
+
+/// A message to be sent to all the members of a group
+#[derive(Debug)]
+pub struct Groupcast {
+    pub fab_idx: u8,
+    pub group_id: u16,
+    pub proto_id: u16,
+    pub proto_opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// A message to be sent to the peer of a session, on a new exchange
+#[derive(Debug)]
+pub struct Unicast {
+    /// The local id of the session
+    pub sess_id: u16,
+    /// The id of the peer, which tells the session apart from a later one with the same id
+    pub peer_node_id: Option<u64>,
+    pub proto_id: u16,
+    pub proto_opcode: u8,
+    pub payload: Vec<u8>,
+    /// The timer that posted this message, it is cancelled if the session is gone
+    pub timer: Option<TimerId>,
+}
 
 #[derive(Debug)]
 pub enum Msg {
     Tx(),
     Rx(),
     NewSession(CloneData),
+    Groupcast(Groupcast),
+    Unicast(Unicast),
+    /// Remove the PASE sessions, if pase is set, and the CASE sessions of the fabric, if
+    /// one is given, except for the session with the local id keep_sess_id. The peers are
+    /// not told about it.
+    RemoveSessions {
+        pase: bool,
+        fab_idx: Option<u8>,
+        keep_sess_id: Option<u16>,
+    },
 }
 
 #[derive(Clone)]
@@ -35,26 +67,14 @@ pub struct WorkQ {
     tx: Sender<Msg>,
 }
 
-static mut G_WQ: Option<WorkQ> = None;
-static INIT: Once = Once::new();
-
 impl WorkQ {
-    pub fn init() -> Result<Receiver<Msg>, Error> {
+    /// Create a work queue, returning the sending end and the receiving end
+    ///
+    /// The sending end can be cloned and handed out to anything that needs to post work
+    /// for the transport loop that owns the receiving end
+    pub fn new() -> (WorkQ, Receiver<Msg>) {
         let (tx, rx) = bounded::<Msg>(3);
-        WorkQ::configure(tx);
-        Ok(rx)
-    }
-
-    fn configure(tx: Sender<Msg>) {
-        unsafe {
-            INIT.call_once(|| {
-                G_WQ = Some(WorkQ { tx });
-            });
-        }
-    }
-
-    pub fn get() -> Result<WorkQ, Error> {
-        unsafe { G_WQ.as_ref().cloned().ok_or(Error::Invalid) }
+        (WorkQ { tx }, rx)
     }
 
     pub fn sync_send(&self, msg: Msg) -> Result<(), Error> {
@@ -64,4 +84,12 @@ impl WorkQ {
     pub async fn send(&self, msg: Msg) -> Result<(), Error> {
         self.tx.send(msg).await.map_err(|e| e.into())
     }
+
+    /// Post a message without waiting, failing if the queue is full
+    ///
+    /// This is for the code that runs within the transport loop, which would never get
+    /// to drain the queue if it waited on it
+    pub fn try_send(&self, msg: Msg) -> Result<(), Error> {
+        self.tx.try_send(msg).map_err(|e| e.into())
+    }
 }
@@ -18,24 +18,36 @@
 use core::fmt;
 use std::{
     any::Any,
-    ops::{Deref, DerefMut},
-    time::SystemTime,
+    cell::RefCell,
+    collections::VecDeque,
+    ops::{Deref, DerefMut, Range},
+    sync::Arc,
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime},
 };
 
 use crate::{
     error::*,
+    group_keys::GroupKey,
     transport::{plain_hdr, proto_hdr},
-    utils::writebuf::WriteBuf,
+    utils::{
+        clock::{system_clock, SharedClock},
+        writebuf::WriteBuf,
+    },
 };
 use boxslab::{BoxSlab, Slab};
 use colored::*;
-use log::{info, trace};
+use log::{error, info, trace};
 use rand::Rng;
+use smol::future;
 
 use super::{
+    counters::MSG_CTR_INIT_RANGE,
     dedup::RxCtrState,
-    network::{Address, NetworkInterface},
-    packet::{Packet, PacketPool},
+    group::GroupCtx,
+    mrp::MrpParams,
+    network::{Address, NetworkInterface, Transport},
+    packet::{BufferPool, Packet, PacketPool},
 };
 
 pub const MAX_CAT_IDS_PER_NOC: usize = 3;
@@ -43,6 +55,9 @@ pub type NocCatIds = [u32; MAX_CAT_IDS_PER_NOC];
 
 const MATTER_AES128_KEY_SIZE: usize = 16;
 
+// The peer is considered active if we heard from it within this period (4 s)
+const MRP_ACTIVE_THRESHOLD: Duration = Duration::from_millis(4000);
+
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct CaseDetails {
     pub fab_idx: u8,
@@ -58,12 +73,20 @@ impl CaseDetails {
     }
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct GroupDetails {
+    pub fab_idx: u8,
+    pub group_id: u16,
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum SessionMode {
     // The Case session will capture the local fabric index
     Case(CaseDetails),
     Pase,
     PlainText,
+    // A group session is a receive-only session per group and source node
+    Group(GroupDetails),
 }
 
 impl Default for SessionMode {
@@ -89,6 +112,9 @@ pub struct Session {
     mode: SessionMode,
     data: Option<Box<dyn Any>>,
     last_use: SystemTime,
+    last_rx: SystemTime,
+    mrp_params: MrpParams,
+    clock: SharedClock,
 }
 
 #[derive(Debug)]
@@ -102,6 +128,7 @@ pub struct CloneData {
     peer_nodeid: u64,
     peer_addr: Address,
     mode: SessionMode,
+    pub mrp_params: MrpParams,
 }
 impl CloneData {
     pub fn new(
@@ -122,14 +149,13 @@ impl CloneData {
             peer_sess_id,
             local_sess_id,
             mode,
+            mrp_params: Default::default(),
         }
     }
 }
 
-const MATTER_MSG_CTR_RANGE: u32 = 0x0fffffff;
-
 impl Session {
-    pub fn new(peer_addr: Address, peer_nodeid: Option<u64>) -> Session {
+    pub fn new(peer_addr: Address, peer_nodeid: Option<u64>, clock: SharedClock) -> Session {
         Session {
             peer_addr,
             local_nodeid: 0,
@@ -139,16 +165,45 @@ impl Session {
             att_challenge: [0; MATTER_AES128_KEY_SIZE],
             peer_sess_id: 0,
             local_sess_id: 0,
-            msg_ctr: rand::thread_rng().gen_range(0..MATTER_MSG_CTR_RANGE),
+            msg_ctr: rand::thread_rng().gen_range(0..MSG_CTR_INIT_RANGE),
             rx_ctr_state: RxCtrState::new(0),
             mode: SessionMode::PlainText,
             data: None,
-            last_use: SystemTime::now(),
+            last_use: clock.now(),
+            last_rx: SystemTime::UNIX_EPOCH,
+            mrp_params: Default::default(),
+            clock,
         }
     }
 
+    /// A session for the messages from (or to) a group, encrypted with the group's key
+    pub fn new_group(
+        peer_addr: Address,
+        local_nodeid: u64,
+        peer_nodeid: Option<u64>,
+        key: &GroupKey,
+        group_id: u16,
+        clock: SharedClock,
+    ) -> Session {
+        let mut session = Session::new(peer_addr, peer_nodeid, clock);
+        session.local_nodeid = local_nodeid;
+        session.set_group_key(key);
+        session.mode = SessionMode::Group(GroupDetails {
+            fab_idx: key.fab_idx,
+            group_id,
+        });
+        session
+    }
+
+    fn set_group_key(&mut self, key: &GroupKey) {
+        self.dec_key.copy_from_slice(key.op_key());
+        self.enc_key.copy_from_slice(key.op_key());
+        self.local_sess_id = key.session_id;
+        self.peer_sess_id = key.session_id;
+    }
+
     // A new encrypted session always clones from a previous 'new' session
-    pub fn clone(clone_from: &CloneData) -> Session {
+    pub fn clone(clone_from: &CloneData, clock: SharedClock) -> Session {
         Session {
             peer_addr: clone_from.peer_addr,
             local_nodeid: clone_from.local_nodeid,
@@ -158,11 +213,14 @@ impl Session {
             att_challenge: clone_from.att_challenge,
             local_sess_id: clone_from.local_sess_id,
             peer_sess_id: clone_from.peer_sess_id,
-            msg_ctr: rand::thread_rng().gen_range(0..MATTER_MSG_CTR_RANGE),
+            msg_ctr: rand::thread_rng().gen_range(0..MSG_CTR_INIT_RANGE),
             rx_ctr_state: RxCtrState::new(0),
             mode: clone_from.mode,
             data: None,
-            last_use: SystemTime::now(),
+            last_use: clock.now(),
+            last_rx: SystemTime::UNIX_EPOCH,
+            mrp_params: clone_from.mrp_params,
+            clock,
         }
     }
 
@@ -201,11 +259,20 @@ impl Session {
 
     pub fn is_encrypted(&self) -> bool {
         match self.mode {
-            SessionMode::Case(_) | SessionMode::Pase => true,
+            SessionMode::Case(_) | SessionMode::Pase | SessionMode::Group(_) => true,
             SessionMode::PlainText => false,
         }
     }
 
+    pub fn is_group(&self) -> bool {
+        matches!(self.mode, SessionMode::Group(_))
+    }
+
+    /// Whether the session runs over a reliable transport like TCP, where MRP isn't used
+    pub fn is_reliable_transport(&self) -> bool {
+        self.peer_addr.transport().is_reliable()
+    }
+
     pub fn get_peer_node_id(&self) -> Option<u64> {
         self.peer_nodeid
     }
@@ -220,6 +287,7 @@ impl Session {
     pub fn get_local_fabric_idx(&self) -> Option<u8> {
         match self.mode {
             SessionMode::Case(a) => Some(a.fab_idx),
+            SessionMode::Group(g) => Some(g.fab_idx),
             _ => None,
         }
     }
@@ -236,14 +304,14 @@ impl Session {
 
     pub fn get_dec_key(&self) -> Option<&[u8]> {
         match self.mode {
-            SessionMode::Case(_) | SessionMode::Pase => Some(&self.dec_key),
+            SessionMode::Case(_) | SessionMode::Pase | SessionMode::Group(_) => Some(&self.dec_key),
             SessionMode::PlainText => None,
         }
     }
 
     pub fn get_enc_key(&self) -> Option<&[u8]> {
         match self.mode {
-            SessionMode::Case(_) | SessionMode::Pase => Some(&self.enc_key),
+            SessionMode::Case(_) | SessionMode::Pase | SessionMode::Group(_) => Some(&self.enc_key),
             SessionMode::PlainText => None,
         }
     }
@@ -252,14 +320,56 @@ impl Session {
         &self.att_challenge
     }
 
+    /// The clock that the session, and everything running on it, goes by
+    pub fn get_clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub fn get_mrp_params(&self) -> MrpParams {
+        self.mrp_params
+    }
+
+    /// Returns the base retransmission interval for this peer
+    ///
+    /// The active interval is used if we have heard from the peer recently, the idle
+    /// interval otherwise
+    pub fn get_mrp_interval(&self) -> Duration {
+        let is_active = self
+            .clock
+            .now()
+            .duration_since(self.last_rx)
+            .map(|d| d < MRP_ACTIVE_THRESHOLD)
+            .unwrap_or(true);
+        if is_active {
+            self.mrp_params.active_interval
+        } else {
+            self.mrp_params.idle_interval
+        }
+    }
+
     pub fn recv(&mut self, proto_rx: &mut Packet) -> Result<(), Error> {
-        self.last_use = SystemTime::now();
+        self.last_use = self.clock.now();
+        self.last_rx = self.last_use;
         proto_rx.proto_decode(self.peer_nodeid.unwrap_or_default(), self.get_dec_key())
     }
 
-    pub fn pre_send(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
+    /// Prepare the plain-text header of a packet for this session
+    ///
+    /// Encrypted sessions have their own message counter, the unsecured sessions share the
+    /// node's global unencrypted message counter
+    pub fn pre_send(
+        &mut self,
+        proto_tx: &mut Packet,
+        unencrypted_ctr: &mut u32,
+    ) -> Result<(), Error> {
         proto_tx.plain.sess_id = self.get_peer_sess_id();
-        proto_tx.plain.ctr = self.get_msg_ctr();
+        proto_tx.plain.ctr = if self.is_encrypted() {
+            self.get_msg_ctr()
+        } else {
+            let ctr = *unencrypted_ctr;
+            *unencrypted_ctr = ctr.wrapping_add(1);
+            ctr
+        };
         if self.is_encrypted() {
             proto_tx.plain.sess_type = plain_hdr::SessionType::Encrypted;
         }
@@ -268,7 +378,7 @@ impl Session {
 
     // TODO: Most of this can now be moved into the 'Packet' module
     fn do_send(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
-        self.last_use = SystemTime::now();
+        self.last_use = self.clock.now();
         proto_tx.peer = self.peer_addr;
 
         // Generate encrypted header
@@ -294,6 +404,7 @@ impl Session {
         if let Some(e) = enc_key {
             proto_hdr::encrypt_in_place(
                 ctr,
+                proto_tx.plain.get_sec_flags(),
                 self.local_nodeid,
                 plain_hdr_bytes,
                 proto_tx.get_writebuf()?,
@@ -324,10 +435,30 @@ impl fmt::Display for Session {
 }
 
 pub const MAX_SESSIONS: usize = 16;
+/// The group peers that are tracked at most, apart from the unicast sessions
+pub const MAX_GROUP_PEERS: usize = 8;
+
+const UNICAST_SLOTS: Range<usize> = 0..MAX_SESSIONS;
+const GROUP_SLOTS: Range<usize> = MAX_SESSIONS..MAX_SESSIONS + MAX_GROUP_PEERS;
+/// A received message, along with the index of its session, if there is room for it
+pub type RxMsg = (BoxSlab<PacketPool>, Option<usize>);
+
+/// The maximum number of messages that wait for a network interface to be ready
+pub const MAX_TX_QUEUE: usize = 8;
+
 pub struct SessionMgr {
     next_sess_id: u16,
-    sessions: [Option<Session>; MAX_SESSIONS],
-    network: Option<Box<dyn NetworkInterface>>,
+    // The unicast sessions, followed by the group peers
+    sessions: [Option<Session>; MAX_SESSIONS + MAX_GROUP_PEERS],
+    networks: Vec<Box<dyn NetworkInterface>>,
+    // The encoded messages that wait for their network interface to be ready
+    tx_queue: RefCell<VecDeque<(Vec<u8>, Address)>>,
+    packet_pool: Arc<Slab<PacketPool>>,
+    buffer_pool: Arc<Slab<BufferPool>>,
+    // The Global Unencrypted Message Counter, this starts from a random value at boot
+    unencrypted_ctr: u32,
+    group: Option<GroupCtx>,
+    clock: SharedClock,
 }
 
 impl Default for SessionMgr {
@@ -338,22 +469,77 @@ impl Default for SessionMgr {
 
 impl SessionMgr {
     pub fn new() -> SessionMgr {
+        SessionMgr::new_with(system_clock())
+    }
+
+    /// Create a SessionMgr whose sessions go by the given clock
+    pub fn new_with(clock: SharedClock) -> SessionMgr {
         SessionMgr {
             sessions: Default::default(),
             next_sess_id: 1,
-            network: None,
+            networks: Vec::new(),
+            tx_queue: RefCell::new(VecDeque::new()),
+            packet_pool: Slab::new(),
+            buffer_pool: Slab::new(),
+            unencrypted_ctr: rand::thread_rng().gen_range(0..MSG_CTR_INIT_RANGE),
+            group: None,
+            clock,
         }
     }
 
+    pub fn get_clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    /// Allocate a packet for transmission from this SessionMgr's packet pool
+    pub fn new_tx(&self) -> Result<BoxSlab<PacketPool>, Error> {
+        self.packet_pool
+            .try_new(Packet::new_tx(&self.buffer_pool)?)
+            .ok_or(Error::PacketPoolExhaust)
+    }
+
+    /// Allocate a packet for reception from this SessionMgr's packet pool
+    pub fn new_rx(&self) -> Result<BoxSlab<PacketPool>, Error> {
+        self.packet_pool
+            .try_new(Packet::new_rx(&self.buffer_pool)?)
+            .ok_or(Error::PacketPoolExhaust)
+    }
+
+    /// Add a network interface that messages are received from and sent over
+    ///
+    /// Multiple interfaces can be added, like a UDP and a TCP one. The messages to a peer
+    /// go out over the first interface of the peer's transport.
     pub fn add_network_interface(
         &mut self,
         interface: Box<dyn NetworkInterface>,
     ) -> Result<(), Error> {
-        if self.network.is_none() {
-            self.network = Some(interface);
-            Ok(())
-        } else {
-            Err(Error::Invalid)
+        self.networks.push(interface);
+        Ok(())
+    }
+
+    fn get_network(&self, transport: Transport) -> Result<&dyn NetworkInterface, Error> {
+        self.networks
+            .iter()
+            .find(|n| n.transport() == transport)
+            .map(|n| n.as_ref())
+            .ok_or(Error::NoNetworkInterface)
+    }
+
+    /// Enable the reception and the transmission of group messages
+    pub fn set_group_ctx(&mut self, group: GroupCtx) {
+        self.group = Some(group);
+    }
+
+    /// Join or leave multicast addresses on the network interface, as the groups change
+    pub fn sync_group_memberships(&mut self) -> Result<(), Error> {
+        // Group messages are only ever sent over UDP multicast
+        let network = self
+            .networks
+            .iter()
+            .find(|n| n.transport() == Transport::Udp);
+        match (self.group.as_mut(), network) {
+            (Some(group), Some(network)) => group.sync_memberships(network.as_ref()),
+            _ => Ok(()),
         }
     }
 
@@ -380,14 +566,14 @@ impl SessionMgr {
         next_sess_id
     }
 
-    fn get_empty_slot(&self) -> Option<usize> {
-        self.sessions.iter().position(|x| x.is_none())
+    fn get_empty_slot(&self, slots: Range<usize>) -> Option<usize> {
+        slots.into_iter().find(|i| self.sessions[*i].is_none())
     }
 
-    pub fn get_lru(&mut self) -> usize {
-        let mut lru_index = 0;
-        let mut lru_ts = SystemTime::now();
-        for i in 0..MAX_SESSIONS {
+    fn get_lru_in(&self, slots: Range<usize>) -> usize {
+        let mut lru_index = slots.start;
+        let mut lru_ts = self.clock.now();
+        for i in slots {
             if let Some(s) = &self.sessions[i] {
                 if s.last_use < lru_ts {
                     lru_ts = s.last_use;
@@ -398,8 +584,21 @@ impl SessionMgr {
         lru_index
     }
 
+    /// The least recently used unicast session
+    pub fn get_lru(&mut self) -> usize {
+        self.get_lru_in(UNICAST_SLOTS)
+    }
+
+    /// The least recently used group peer
+    ///
+    /// The group peers are kept apart from the unicast sessions, so the group messages
+    /// never evict a unicast session.
+    pub fn get_group_lru(&mut self) -> usize {
+        self.get_lru_in(GROUP_SLOTS)
+    }
+
     pub fn add(&mut self, peer_addr: Address, peer_nodeid: Option<u64>) -> Result<usize, Error> {
-        let session = Session::new(peer_addr, peer_nodeid);
+        let session = Session::new(peer_addr, peer_nodeid, self.clock.clone());
         self.add_session(session)
     }
 
@@ -409,11 +608,33 @@ impl SessionMgr {
         self.sessions[idx] = None;
     }
 
+    /// Remove all the sessions, with the same caveat as [SessionMgr::remove]
+    pub fn remove_all(&mut self) {
+        self.sessions = Default::default();
+    }
+
+    /// The indexes of the sessions that match the filter
+    pub fn find_all<F>(&self, f: F) -> Vec<usize>
+    where
+        F: Fn(&Session) -> bool,
+    {
+        self.sessions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().filter(|s| f(s)).map(|_| i))
+            .collect()
+    }
+
     /// We could have returned a SessionHandle here. But the borrow checker doesn't support
     /// non-lexical lifetimes. This makes it harder for the caller of this function to take
     /// action in the error return path
     pub fn add_session(&mut self, session: Session) -> Result<usize, Error> {
-        if let Some(index) = self.get_empty_slot() {
+        let slots = if session.is_group() {
+            GROUP_SLOTS
+        } else {
+            UNICAST_SLOTS
+        };
+        if let Some(index) = self.get_empty_slot(slots) {
             self.sessions[index] = Some(session);
             Ok(index)
         } else {
@@ -422,7 +643,7 @@ impl SessionMgr {
     }
 
     pub fn clone_session(&mut self, clone_data: &CloneData) -> Result<usize, Error> {
-        let session = Session::clone(clone_data);
+        let session = Session::clone(clone_data, self.clock.clone());
         self.add_session(session)
     }
 
@@ -441,6 +662,7 @@ impl SessionMgr {
                     nodeid_matches = false;
                 }
                 x.local_sess_id == sess_id
+                    && !x.is_group()
                     && x.peer_addr == peer_addr
                     && x.is_encrypted() == is_encrypted
                     && nodeid_matches
@@ -451,10 +673,12 @@ impl SessionMgr {
     }
 
     pub fn get_with_id(&mut self, sess_id: u16) -> Option<SessionHandle> {
-        let index = self
-            .sessions
-            .iter_mut()
-            .position(|x| x.as_ref().map(|s| s.local_sess_id) == Some(sess_id))?;
+        let index = self.sessions.iter_mut().position(|x| {
+            x.as_ref()
+                .filter(|s| !s.is_group())
+                .map(|s| s.local_sess_id)
+                == Some(sess_id)
+        })?;
         Some(self.get_session_handle(index))
     }
 
@@ -478,7 +702,15 @@ impl SessionMgr {
 
     // We will try to get a session for this Packet. If no session exists, we will try to add one
     // If the session list is full we will return a None
-    pub fn post_recv(&mut self, rx: &Packet) -> Result<Option<usize>, Error> {
+    pub fn post_recv(&mut self, rx: &mut Packet) -> Result<Option<usize>, Error> {
+        if rx.plain.is_group() {
+            let key = self
+                .group
+                .as_ref()
+                .ok_or(Error::NoSession)?
+                .find_rx_key(rx)?;
+            return self.post_recv_group(rx, key);
+        }
         let sess_index = match self.get_or_add(
             rx.plain.sess_id,
             rx.peer,
@@ -504,26 +736,216 @@ impl SessionMgr {
         Ok(sess_index)
     }
 
-    pub fn recv(&mut self) -> Result<(BoxSlab<PacketPool>, Option<usize>), Error> {
-        let mut rx =
-            Slab::<PacketPool>::try_new(Packet::new_rx()?).ok_or(Error::PacketPoolExhaust)?;
+    fn post_recv_group(&mut self, rx: &mut Packet, key: GroupKey) -> Result<Option<usize>, Error> {
+        // The header was validated by find_rx_key()
+        let src = rx.plain.get_src_u64().ok_or(Error::Invalid)?;
+        let group_id = rx.plain.get_dest_group().ok_or(Error::Invalid)?;
 
-        let network = self.network.as_ref().ok_or(Error::NoNetworkInterface)?;
+        let mode = SessionMode::Group(GroupDetails {
+            fab_idx: key.fab_idx,
+            group_id,
+        });
+        let index = self
+            .sessions
+            .iter()
+            .position(|x| matches!(x, Some(x) if x.mode == mode && x.peer_nodeid == Some(src)));
+        let index = match index {
+            Some(index) => {
+                // The key may have been rotated since the last message
+                let session = self.sessions[index].as_mut().unwrap();
+                session.set_group_key(&key);
+                session.peer_addr = rx.peer;
+                index
+            }
+            None => {
+                let session =
+                    Session::new_group(rx.peer, 0, Some(src), &key, group_id, self.clock.clone());
+                match self.add_session(session) {
+                    Ok(index) => index,
+                    Err(Error::NoSpace) => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+            }
+        };
 
-        let (len, src) = network.recv(rx.as_borrow_slice())?;
-        rx.get_parsebuf()?.set_len(len);
-        rx.peer = src;
+        // The counters are per source node, and only checked once the message is known
+        // to be authentic
+        let group = self.group.as_ref().ok_or(Error::NoSession)?;
+        if group.is_duplicate(key.fab_idx, src, rx.plain.ctr, rx.plain.is_control) {
+            info!("Dropping duplicate group message");
+            return Err(Error::Duplicate);
+        }
+        Ok(Some(index))
+    }
+
+    // Attempt to receive a message into rx, the cached group messages that can now be
+    // received come first
+    fn poll_recv(&self, cx: &mut Context<'_>, rx: &mut Packet) -> Poll<Result<(), Error>> {
+        if let Some((msg, src)) = self.group.as_ref().and_then(|g| g.take_released()) {
+            rx.as_borrow_slice()
+                .get_mut(..msg.len())
+                .ok_or(Error::NoSpace)?
+                .copy_from_slice(&msg);
+            rx.get_parsebuf()?.set_len(msg.len());
+            rx.peer = src;
+            return Poll::Ready(Ok(()));
+        }
 
-        info!("{} from src: {}", "Received".blue(), src);
+        if self.networks.is_empty() {
+            return Poll::Ready(Err(Error::NoNetworkInterface));
+        }
+        for network in self.networks.iter() {
+            match network.poll_recv(cx, rx.as_borrow_slice()) {
+                Poll::Ready(Ok((len, src))) => {
+                    rx.get_parsebuf()?.set_len(len);
+                    rx.peer = src;
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => (),
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Wait for the next message from the network interfaces
+    ///
+    /// The message is only read here, it is processed by [SessionMgr::recv]. Dropping the
+    /// future doesn't lose any message.
+    pub async fn read(&self) -> Result<BoxSlab<PacketPool>, Error> {
+        let mut rx = self.new_rx()?;
+        future::poll_fn(|cx| self.poll_recv(cx, &mut rx)).await?;
+        Ok(rx)
+    }
+
+    /// Wait for a connection of the network interfaces to go away, returning the address of
+    /// the peer
+    pub async fn closed(&self) -> Address {
+        future::poll_fn(|cx| {
+            for network in self.networks.iter() {
+                if let Poll::Ready(addr) = network.poll_closed(cx) {
+                    return Poll::Ready(addr);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Process a message that was read, returning it along with the index of its session
+    ///
+    /// Returns None if the message was consumed by the transport, like a group message
+    /// that is cached until its sender is synchronized.
+    pub fn recv(&mut self, mut rx: BoxSlab<PacketPool>) -> Result<Option<RxMsg>, Error> {
+        info!("{} from src: {}", "Received".blue(), rx.peer);
         trace!("payload: {:x?}", rx.as_borrow_slice());
 
         // Read unencrypted packet header
         rx.plain_hdr_decode()?;
 
         // Get session
-        let sess_handle = self.post_recv(&rx)?;
+        let sess_handle = if rx.plain.is_group() {
+            if rx.plain.get_dest_u64().is_some() {
+                self.recv_group_ctrl(&mut rx)?;
+                return Ok(None);
+            }
+            let group = self.group.as_ref().ok_or(Error::NoSession)?;
+            let key = group.find_rx_key(&mut rx)?;
+            if group.cache_unsynced(&key, &mut rx)? {
+                self.send_sync_req(&key, &rx)?;
+                return Ok(None);
+            }
+            self.post_recv_group(&mut rx, key)?
+        } else {
+            self.post_recv(&mut rx)?
+        };
+
+        Ok(Some((rx, sess_handle)))
+    }
+
+    // Ask the sender of a cached group message for its counter
+    fn send_sync_req(&mut self, key: &GroupKey, rx: &Packet) -> Result<(), Error> {
+        let src = rx.plain.get_src_u64().ok_or(Error::Invalid)?;
+        let group = self.group.as_ref().ok_or(Error::NoSession)?;
+        let mut tx = self.new_tx()?;
+        let mut session = group.prepare_sync_req(key, src, rx.peer, &mut tx, &self.clock)?;
+        session.do_send(&mut tx)?;
+        info!("Sending MsgCounterSyncReq to {:x}", src);
+        let peer = tx.peer;
+        self.transmit(tx.as_borrow_slice(), peer)
+    }
+
+    // The group unicast messages are consumed here, and responded to if required
+    fn recv_group_ctrl(&mut self, rx: &mut Packet) -> Result<(), Error> {
+        let group = self.group.as_ref().ok_or(Error::NoSession)?;
+        let mut tx = self.new_tx()?;
+        if let Some(mut session) = group.recv_ctrl(rx, &mut tx, &self.clock)? {
+            session.do_send(&mut tx)?;
+            let peer = tx.peer;
+            self.transmit(tx.as_borrow_slice(), peer)?;
+        }
+        Ok(())
+    }
 
-        Ok((rx, sess_handle))
+    /// Encode (and encrypt, if required) the packet, so it is ready to go out on the wire
+    pub fn encode(&mut self, sess_idx: usize, proto_tx: &mut Packet) -> Result<(), Error> {
+        self.sessions[sess_idx]
+            .as_mut()
+            .ok_or(Error::NoSession)?
+            .do_send(proto_tx)
+    }
+
+    /// Transmit an already encoded message
+    ///
+    /// The message goes out right away if its network interface is ready for it. Otherwise
+    /// it is queued, until the transport loop gets to [flush](SessionMgr::flush) it.
+    pub fn transmit(&self, buf: &[u8], peer: Address) -> Result<(), Error> {
+        let network = self.get_network(peer.transport())?;
+        let mut tx_queue = self.tx_queue.borrow_mut();
+        if tx_queue.is_empty() {
+            // Nothing to wake up, the queue is flushed by the transport loop
+            let mut cx = Context::from_waker(Waker::noop());
+            if let Poll::Ready(result) = network.poll_send(&mut cx, buf, peer) {
+                result?;
+                trace!("Message Sent to {}", peer);
+                return Ok(());
+            }
+        }
+        if tx_queue.len() >= MAX_TX_QUEUE {
+            return Err(Error::NoSpace);
+        }
+        tx_queue.push_back((buf.to_vec(), peer));
+        Ok(())
+    }
+
+    /// Whether there are messages that wait for their network interface
+    pub fn has_pending_tx(&self) -> bool {
+        !self.tx_queue.borrow().is_empty()
+    }
+
+    fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut tx_queue = self.tx_queue.borrow_mut();
+        while let Some((buf, peer)) = tx_queue.front() {
+            let result = match self.get_network(peer.transport()) {
+                Ok(network) => match network.poll_send(cx, buf, *peer) {
+                    Poll::Ready(result) => result.map(|_| ()),
+                    Poll::Pending => return Poll::Pending,
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => trace!("Message Sent to {}", peer),
+                // Much like a message that is lost on the way
+                Err(e) => error!("Error sending the message to {}: {:?}", peer, e),
+            }
+            tx_queue.pop_front();
+        }
+        Poll::Ready(())
+    }
+
+    /// Send the queued messages, as their network interfaces get ready for them
+    pub async fn flush(&self) {
+        future::poll_fn(|cx| self.poll_flush(cx)).await
     }
 
     pub fn send(
@@ -531,16 +953,26 @@ impl SessionMgr {
         sess_idx: usize,
         mut proto_tx: BoxSlab<PacketPool>,
     ) -> Result<(), Error> {
-        self.sessions[sess_idx]
-            .as_mut()
-            .ok_or(Error::NoSession)?
-            .do_send(&mut proto_tx)?;
+        self.encode(sess_idx, &mut proto_tx)?;
+        let peer = proto_tx.peer;
+        self.transmit(proto_tx.as_borrow_slice(), peer)
+    }
 
-        let network = self.network.as_ref().ok_or(Error::NoNetworkInterface)?;
+    /// Send a message to a group of a fabric
+    ///
+    /// The message goes out to the group's multicast address, encrypted with the group's
+    /// current key and without MRP.
+    pub fn send_groupcast(
+        &mut self,
+        fab_idx: u8,
+        group_id: u16,
+        mut proto_tx: BoxSlab<PacketPool>,
+    ) -> Result<(), Error> {
+        let group = self.group.as_ref().ok_or(Error::Invalid)?;
+        let mut session = group.prepare_tx(fab_idx, group_id, &mut proto_tx, &self.clock)?;
+        session.do_send(&mut proto_tx)?;
         let peer = proto_tx.peer;
-        network.send(proto_tx.as_borrow_slice(), peer)?;
-        println!("Message Sent to {}", peer);
-        Ok(())
+        self.transmit(proto_tx.as_borrow_slice(), peer)
     }
 
     pub fn get_session_handle(&mut self, sess_idx: usize) -> SessionHandle {
@@ -575,6 +1007,27 @@ impl<'a> SessionHandle<'a> {
     pub fn send(&mut self, proto_tx: BoxSlab<PacketPool>) -> Result<(), Error> {
         self.sess_mgr.send(self.sess_idx, proto_tx)
     }
+
+    pub fn pre_send(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
+        let sess_mgr = &mut *self.sess_mgr;
+        sess_mgr.sessions[self.sess_idx]
+            .as_mut()
+            .ok_or(Error::NoSession)?
+            .pre_send(proto_tx, &mut sess_mgr.unencrypted_ctr)
+    }
+
+    pub fn new_tx(&self) -> Result<BoxSlab<PacketPool>, Error> {
+        self.sess_mgr.new_tx()
+    }
+
+    pub fn encode(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
+        self.sess_mgr.encode(self.sess_idx, proto_tx)
+    }
+
+    pub fn transmit(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let peer = self.get_peer_addr();
+        self.sess_mgr.transmit(buf, peer)
+    }
 }
 
 impl<'a> Deref for SessionHandle<'a> {
@@ -594,10 +1047,53 @@ impl<'a> DerefMut for SessionHandle<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        net::{Ipv6Addr, SocketAddr},
+        rc::Rc,
+        task::{Context, Poll},
+    };
+
+    use smol::future;
+
+    use crate::{
+        error::Error,
+        group_keys::GroupKey,
+        transport::network::{Address, NetworkInterface, Transport},
+        utils::clock::system_clock,
+    };
+
+    use super::{Session, SessionMgr, MAX_GROUP_PEERS, MAX_SESSIONS, MAX_TX_QUEUE};
+
+    // Records the messages sent over it
+    struct RecordingNetwork {
+        transport: Transport,
+        sent: Rc<RefCell<Vec<Address>>>,
+    }
 
-    use crate::transport::network::Address;
+    impl NetworkInterface for RecordingNetwork {
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            _in_buf: &mut [u8],
+        ) -> Poll<Result<(usize, Address), Error>> {
+            Poll::Pending
+        }
+
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            out_buf: &[u8],
+            addr: Address,
+        ) -> Poll<Result<usize, Error>> {
+            self.sent.borrow_mut().push(addr);
+            Poll::Ready(Ok(out_buf.len()))
+        }
 
-    use super::SessionMgr;
+        fn transport(&self) -> Transport {
+            self.transport
+        }
+    }
 
     #[test]
     fn test_next_sess_id_doesnt_reuse() {
@@ -625,4 +1121,127 @@ mod tests {
         assert_eq!(sm.get_next_sess_id(), 65535);
         assert_eq!(sm.get_next_sess_id(), 2);
     }
+
+    #[test]
+    fn test_unencrypted_ctr_is_global() {
+        let mut sm = SessionMgr::new();
+        let sess1 = sm.add(Address::default(), Some(1)).unwrap();
+        let sess2 = sm.add(Address::default(), Some(2)).unwrap();
+        let mut tx = sm.new_tx().unwrap();
+        sm.get_session_handle(sess1).pre_send(&mut tx).unwrap();
+        let ctr = tx.plain.ctr;
+        sm.get_session_handle(sess2).pre_send(&mut tx).unwrap();
+        assert_eq!(tx.plain.ctr, ctr.wrapping_add(1));
+        sm.get_session_handle(sess1).pre_send(&mut tx).unwrap();
+        assert_eq!(tx.plain.ctr, ctr.wrapping_add(2));
+    }
+
+    #[test]
+    fn test_group_peers_apart() {
+        let mut sm = SessionMgr::new();
+        let key = GroupKey::new(1, 1, &[0x42; 16], &[0; 8]).unwrap();
+        let new_group =
+            |peer| Session::new_group(Address::default(), 0, Some(peer), &key, 1, system_clock());
+
+        for i in 0..MAX_SESSIONS {
+            sm.add(Address::default(), Some(i as u64)).unwrap();
+        }
+        assert_eq!(sm.add(Address::default(), None).err(), Some(Error::NoSpace));
+
+        // The group peers have their own slots, and only ever evict each other
+        for i in 0..MAX_GROUP_PEERS {
+            let index = sm.add_session(new_group(i as u64)).unwrap();
+            assert!(index >= MAX_SESSIONS);
+        }
+        assert_eq!(sm.add_session(new_group(0)).err(), Some(Error::NoSpace));
+        assert!(sm.get_lru() < MAX_SESSIONS);
+        assert!(sm.get_group_lru() >= MAX_SESSIONS);
+    }
+
+    #[test]
+    fn test_transmit_picks_interface() {
+        let mut sm = SessionMgr::new();
+        let udp_sent = Rc::new(RefCell::new(Vec::new()));
+        let tcp_sent = Rc::new(RefCell::new(Vec::new()));
+        let peer = SocketAddr::from((Ipv6Addr::LOCALHOST, 5540));
+
+        sm.add_network_interface(Box::new(RecordingNetwork {
+            transport: Transport::Udp,
+            sent: udp_sent.clone(),
+        }))
+        .unwrap();
+        assert_eq!(
+            sm.transmit(&[1], Address::Tcp(peer)),
+            Err(Error::NoNetworkInterface)
+        );
+
+        sm.add_network_interface(Box::new(RecordingNetwork {
+            transport: Transport::Tcp,
+            sent: tcp_sent.clone(),
+        }))
+        .unwrap();
+        sm.transmit(&[1], Address::Udp(peer)).unwrap();
+        sm.transmit(&[1], Address::Tcp(peer)).unwrap();
+        assert!(*udp_sent.borrow() == vec![Address::Udp(peer)]);
+        assert!(*tcp_sent.borrow() == vec![Address::Tcp(peer)]);
+    }
+
+    // Takes the messages only when it is open
+    #[derive(Default)]
+    struct GatedNetwork {
+        open: Rc<Cell<bool>>,
+        sent: Rc<RefCell<Vec<Vec<u8>>>>,
+    }
+
+    impl NetworkInterface for GatedNetwork {
+        fn poll_recv(
+            &self,
+            _cx: &mut Context<'_>,
+            _in_buf: &mut [u8],
+        ) -> Poll<Result<(usize, Address), Error>> {
+            Poll::Pending
+        }
+
+        fn poll_send(
+            &self,
+            _cx: &mut Context<'_>,
+            out_buf: &[u8],
+            _addr: Address,
+        ) -> Poll<Result<usize, Error>> {
+            if !self.open.get() {
+                return Poll::Pending;
+            }
+            self.sent.borrow_mut().push(out_buf.to_vec());
+            Poll::Ready(Ok(out_buf.len()))
+        }
+    }
+
+    #[test]
+    fn test_transmit_queue() {
+        let mut sm = SessionMgr::new();
+        let network = GatedNetwork::default();
+        let (open, sent) = (network.open.clone(), network.sent.clone());
+        sm.add_network_interface(Box::new(network)).unwrap();
+        let peer = Address::default();
+
+        // The messages wait for the network interface, in order
+        for i in 0..MAX_TX_QUEUE {
+            sm.transmit(&[i as u8], peer).unwrap();
+        }
+        assert_eq!(sm.transmit(&[0xff], peer), Err(Error::NoSpace));
+        assert!(sm.has_pending_tx());
+        assert!(smol::block_on(future::poll_once(sm.flush())).is_none());
+        assert!(sent.borrow().is_empty());
+
+        open.set(true);
+        smol::block_on(sm.flush());
+        assert!(!sm.has_pending_tx());
+        let expected: Vec<Vec<u8>> = (0..MAX_TX_QUEUE).map(|i| vec![i as u8]).collect();
+        assert_eq!(*sent.borrow(), expected);
+
+        // And go out right away when it is ready
+        sm.transmit(&[0xff], peer).unwrap();
+        assert!(!sm.has_pending_tx());
+        assert_eq!(sent.borrow().last(), Some(&vec![0xff]));
+    }
 }
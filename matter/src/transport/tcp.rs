@@ -0,0 +1,576 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    convert::TryFrom,
+    future::Future,
+    io::{self, ErrorKind},
+    net::{Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::SystemTime,
+};
+
+use crate::{
+    error::*,
+    utils::clock::{system_clock, SharedClock},
+};
+use log::{error, info, warn};
+use smol::{
+    io::{AsyncRead, AsyncWrite},
+    Async,
+};
+
+use super::{
+    network::{Address, NetworkInterface, Transport},
+    packet::MAX_RX_BUF_SIZE,
+};
+
+/// The maximum number of connections that are kept open at a time
+pub const MAX_TCP_CONNECTIONS: usize = 8;
+
+/// The largest message that can be sent or received over a connection
+///
+/// Every message has to fit in a packet buffer, so this is capped at the size of the
+/// packet buffers, which is the same as for UDP. TCP doesn't lift the size limit on the
+/// messages: payloads larger than this still have to be chunked, as they are over UDP.
+///
+/// The receive buffer of a connection is sized to the length in the prefix of the message
+/// that is being received, so this also caps the memory that a peer can make a connection
+/// hold. A peer that announces a larger message has its connection closed.
+pub const MAX_TCP_MSG_SIZE: usize = MAX_RX_BUF_SIZE;
+
+// Every message on the stream is preceded by its length, as a 32-bit little endian value
+const LEN_PREFIX_SIZE: usize = 4;
+
+// Returns the length of the message that the prefix announces
+fn framed_len(prefix: &[u8; LEN_PREFIX_SIZE]) -> Result<usize, Error> {
+    let len = u32::from_le_bytes(*prefix) as usize;
+    if len > MAX_TCP_MSG_SIZE {
+        Err(Error::Invalid)
+    } else {
+        Ok(len)
+    }
+}
+
+type Connect = Pin<Box<dyn Future<Output = io::Result<Async<TcpStream>>>>>;
+
+struct Connection {
+    peer: SocketAddr,
+    stream: Async<TcpStream>,
+    // The length prefix of the message that is being received
+    rx_prefix: [u8; LEN_PREFIX_SIZE],
+    rx_prefix_len: usize,
+    // The message that is being received, sized to the length in its prefix, and the
+    // number of bytes of it received so far
+    rx: Vec<u8>,
+    rx_len: usize,
+    // The framed message that is still to be written, from its first unwritten byte on
+    tx: Vec<u8>,
+    last_use: SystemTime,
+}
+
+impl Connection {
+    fn new(peer: SocketAddr, stream: Async<TcpStream>, now: SystemTime) -> Self {
+        Self {
+            peer,
+            stream,
+            rx_prefix: [0; LEN_PREFIX_SIZE],
+            rx_prefix_len: 0,
+            rx: Vec::new(),
+            rx_len: 0,
+            tx: Vec::new(),
+            last_use: now,
+        }
+    }
+
+    fn has_msg(&self) -> bool {
+        self.rx_prefix_len == LEN_PREFIX_SIZE && self.rx_len == self.rx.len()
+    }
+
+    // Read from the stream until there is a complete message. Nothing beyond the message
+    // is read, the next message stays in the stream until this one is taken. An error
+    // means the connection can't be used anymore.
+    fn poll_msg(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            if self.has_msg() {
+                return Poll::Ready(Ok(()));
+            }
+            let mut stream = &self.stream;
+            let dst = if self.rx_prefix_len < LEN_PREFIX_SIZE {
+                &mut self.rx_prefix[self.rx_prefix_len..]
+            } else {
+                &mut self.rx[self.rx_len..]
+            };
+            let len = match Pin::new(&mut stream).poll_read(cx, dst) {
+                // The peer closed the connection
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(Error::Network)),
+                Poll::Ready(Ok(len)) => len,
+                Poll::Ready(Err(_)) => return Poll::Ready(Err(Error::Network)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if self.rx_prefix_len < LEN_PREFIX_SIZE {
+                self.rx_prefix_len += len;
+                if self.rx_prefix_len == LEN_PREFIX_SIZE {
+                    self.rx = vec![0; framed_len(&self.rx_prefix)?];
+                }
+            } else {
+                self.rx_len += len;
+            }
+        }
+    }
+
+    // Move the message, which must be complete, over to in_buf. A message that doesn't
+    // fit is taken all the same, and dropped: the stream continues with the next one.
+    fn take_msg(&mut self, in_buf: &mut [u8], now: SystemTime) -> Result<usize, Error> {
+        if !self.has_msg() {
+            return Err(Error::Invalid);
+        }
+        let msg = std::mem::take(&mut self.rx);
+        self.rx_prefix_len = 0;
+        self.rx_len = 0;
+        self.last_use = now;
+        let dst = in_buf.get_mut(..msg.len()).ok_or(Error::BufferTooSmall)?;
+        dst.copy_from_slice(&msg);
+        Ok(msg.len())
+    }
+
+    // Write out the pending bytes. An error means the connection can't be used anymore.
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        while !self.tx.is_empty() {
+            let mut stream = &self.stream;
+            match Pin::new(&mut stream).poll_write(cx, &self.tx) {
+                Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) => {
+                    return Poll::Ready(Err(Error::Network))
+                }
+                Poll::Ready(Ok(len)) => {
+                    self.tx.drain(..len);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // Take the message once the previous ones are written out. The message is written out
+    // as far as the stream allows, the rest goes out with the next flush.
+    fn poll_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        out_buf: &[u8],
+        now: SystemTime,
+    ) -> Poll<Result<(), Error>> {
+        match self.poll_flush(cx) {
+            Poll::Ready(Ok(())) => (),
+            other => return other,
+        }
+        let len = u32::try_from(out_buf.len()).map_err(|_| Error::Invalid)?;
+        self.tx = Vec::with_capacity(LEN_PREFIX_SIZE + out_buf.len());
+        self.tx.extend_from_slice(&len.to_le_bytes());
+        self.tx.extend_from_slice(out_buf);
+        self.last_use = now;
+        match self.poll_flush(cx) {
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// A network interface that carries the messages over TCP
+///
+/// The interface accepts connections on its port, and connects to a peer the first time a
+/// message is sent to it. The connections are pooled per peer, so all the messages of a
+/// session, which is bound to the peer's address, go over the same connection. When the
+/// pool is full, the least recently used connection is closed.
+///
+/// A session is bound to its connection: the address of a peer that connected to us has
+/// its ephemeral port, which is useless once the connection is gone. So every connection
+/// that closes, for whatever reason, is reported through
+/// [NetworkInterface::poll_closed], and the sessions with that peer are removed.
+///
+/// TCP is a reliable transport, so MRP isn't used on the sessions over this interface.
+pub struct TcpTransport {
+    listener: Async<TcpListener>,
+    connections: RefCell<Vec<Connection>>,
+    // The connections that are being established
+    connecting: RefCell<Vec<(SocketAddr, Connect)>>,
+    // The peers whose connection closed, which are yet to be reported
+    closed: RefCell<VecDeque<SocketAddr>>,
+    closed_waker: RefCell<Option<Waker>>,
+    clock: SharedClock,
+}
+
+impl TcpTransport {
+    pub fn new(port: u16) -> Result<TcpTransport, Error> {
+        TcpTransport::new_with(port, system_clock())
+    }
+
+    /// Create a TcpTransport whose connections go by the given clock
+    pub fn new_with(port: u16, clock: SharedClock) -> Result<TcpTransport, Error> {
+        let listener = Async::<TcpListener>::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))?;
+        Ok(TcpTransport {
+            listener,
+            connections: RefCell::new(Vec::new()),
+            connecting: RefCell::new(Vec::new()),
+            closed: RefCell::new(VecDeque::new()),
+            closed_waker: RefCell::new(None),
+            clock,
+        })
+    }
+
+    /// The address that the interface accepts connections on
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.get_ref().local_addr()?)
+    }
+
+    // Report that the connection to the peer is gone
+    fn closed(&self, peer: SocketAddr, reason: Error) {
+        info!("Connection to {} closed: {:?}", peer, reason);
+        self.closed.borrow_mut().push_back(peer);
+        if let Some(waker) = self.closed_waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+
+    fn add_connection(&self, peer: SocketAddr, stream: Async<TcpStream>) {
+        let mut connections = self.connections.borrow_mut();
+        if connections.len() >= MAX_TCP_CONNECTIONS {
+            if let Some(lru) = connections
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| c.last_use)
+                .map(|(i, _)| i)
+            {
+                let connection = connections.remove(lru);
+                self.closed(connection.peer, Error::NoSpace);
+            }
+        }
+        connections.retain(|c| c.peer != peer);
+        connections.push(Connection::new(peer, stream, self.clock.now()));
+    }
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Result<(), Error> {
+        while self.listener.poll_readable(cx).is_ready() {
+            loop {
+                match self.listener.get_ref().accept() {
+                    Ok((stream, peer)) => {
+                        info!("Accepted a connection from {}", peer);
+                        self.add_connection(peer, Async::new(stream)?);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Error accepting a connection: {:?}", e);
+                        return Err(Error::Network);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the index of a connection that has a complete message
+    fn poll_msg(&self, cx: &mut Context<'_>) -> Poll<Result<usize, Error>> {
+        if let Err(e) = self.poll_accept(cx) {
+            return Poll::Ready(Err(e));
+        }
+        let mut connections = self.connections.borrow_mut();
+        let mut ready = None;
+        let mut index = 0;
+        while index < connections.len() {
+            // The rest of the messages that were sent goes out meanwhile
+            if let Poll::Ready(Err(e)) = connections[index].poll_flush(cx) {
+                let connection = connections.remove(index);
+                self.closed(connection.peer, e);
+                continue;
+            }
+            match connections[index].poll_msg(cx) {
+                Poll::Ready(Ok(())) => {
+                    ready = Some(index);
+                    break;
+                }
+                Poll::Ready(Err(e)) => {
+                    let connection = connections.remove(index);
+                    self.closed(connection.peer, e);
+                }
+                Poll::Pending => index += 1,
+            }
+        }
+        match ready {
+            Some(index) => Poll::Ready(Ok(index)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl TcpTransport {
+    fn poll_connect(&self, cx: &mut Context<'_>, peer: SocketAddr) -> Poll<Result<(), Error>> {
+        if self.connections.borrow().iter().any(|c| c.peer == peer) {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut connecting = self.connecting.borrow_mut();
+        let index = match connecting.iter().position(|(p, _)| *p == peer) {
+            Some(index) => index,
+            None => {
+                info!("Connecting to {}", peer);
+                connecting.push((peer, Box::pin(Async::<TcpStream>::connect(peer))));
+                connecting.len() - 1
+            }
+        };
+        let result = match connecting[index].1.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        let _ = connecting.remove(index);
+        drop(connecting);
+        match result {
+            Ok(stream) => {
+                self.add_connection(peer, stream);
+                Poll::Ready(Ok(()))
+            }
+            Err(e) => {
+                error!("Error connecting to {}: {:?}", peer, e);
+                Poll::Ready(Err(Error::Network))
+            }
+        }
+    }
+}
+
+impl NetworkInterface for TcpTransport {
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        in_buf: &mut [u8],
+    ) -> Poll<Result<(usize, Address), Error>> {
+        loop {
+            let index = match self.poll_msg(cx) {
+                Poll::Ready(Ok(index)) => index,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            let mut connections = self.connections.borrow_mut();
+            let connection = &mut connections[index];
+            match connection.take_msg(in_buf, self.clock.now()) {
+                Ok(len) => return Poll::Ready(Ok((len, Address::Tcp(connection.peer)))),
+                // The message is dropped, and the connection stays open for the next ones
+                Err(e) => warn!("Dropping a message from {}: {:?}", connection.peer, e),
+            }
+        }
+    }
+
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        out_buf: &[u8],
+        addr: Address,
+    ) -> Poll<Result<usize, Error>> {
+        let peer = match addr {
+            Address::Tcp(peer) => peer,
+            _ => return Poll::Ready(Err(Error::Invalid)),
+        };
+        if out_buf.len() > MAX_TCP_MSG_SIZE {
+            return Poll::Ready(Err(Error::Invalid));
+        }
+        match self.poll_connect(cx, peer) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let mut connections = self.connections.borrow_mut();
+        let index = connections
+            .iter()
+            .position(|c| c.peer == peer)
+            .ok_or(Error::Network)?;
+        match connections[index].poll_send(cx, out_buf, self.clock.now()) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(out_buf.len())),
+            Poll::Ready(Err(e)) => {
+                let connection = connections.remove(index);
+                self.closed(connection.peer, e);
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_closed(&self, cx: &mut Context<'_>) -> Poll<Address> {
+        match self.closed.borrow_mut().pop_front() {
+            Some(peer) => Poll::Ready(Address::Tcp(peer)),
+            None => {
+                *self.closed_waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn transport(&self) -> Transport {
+        Transport::Tcp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{Ipv6Addr, SocketAddr},
+    };
+
+    use smol::future;
+
+    use crate::{
+        error::Error,
+        transport::network::{self, Address},
+    };
+
+    use super::{framed_len, NetworkInterface, TcpTransport, MAX_TCP_MSG_SIZE};
+
+    fn send(network: &TcpTransport, buf: &[u8], addr: Address) -> Result<usize, Error> {
+        smol::block_on(network::send(network, buf, addr))
+    }
+
+    fn recv(network: &TcpTransport, buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        smol::block_on(network::recv(network, buf))
+    }
+
+    async fn closed(network: &TcpTransport) -> Address {
+        future::poll_fn(|cx| network.poll_closed(cx)).await
+    }
+
+    #[test]
+    fn test_framed_len() {
+        assert_eq!(framed_len(&[3, 0, 0, 0]), Ok(3));
+        assert_eq!(framed_len(&[0, 1, 0, 0]), Ok(256));
+
+        let max = (MAX_TCP_MSG_SIZE as u32).to_le_bytes();
+        assert_eq!(framed_len(&max), Ok(MAX_TCP_MSG_SIZE));
+        let too_large = (MAX_TCP_MSG_SIZE as u32 + 1).to_le_bytes();
+        assert_eq!(framed_len(&too_large), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn test_tcp_exchange() {
+        let server = TcpTransport::new(0).unwrap();
+        let client = TcpTransport::new(0).unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv6Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        send(&client, &[1, 2, 3], Address::Tcp(server_addr)).unwrap();
+        send(&client, &[4, 5], Address::Tcp(server_addr)).unwrap();
+
+        let mut buf = [0u8; 10];
+        let (len, peer) = recv(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 2, 3]);
+        let (len, peer2) = recv(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[4, 5]);
+        assert!(peer == peer2);
+
+        // The reply goes back over the connection that the client opened
+        send(&server, &[6], peer).unwrap();
+        let (len, from) = recv(&client, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[6]);
+        assert!(from == Address::Tcp(server_addr));
+        assert_eq!(server.connections.borrow().len(), 1);
+        assert_eq!(client.connections.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_tcp_msg_too_large() {
+        let server = TcpTransport::new(0).unwrap();
+        let client = TcpTransport::new(0).unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv6Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        send(&client, &[0; 20], Address::Tcp(server_addr)).unwrap();
+        send(&client, &[1, 2], Address::Tcp(server_addr)).unwrap();
+
+        // The message that doesn't fit is dropped, and the next one still comes through
+        let mut buf = [0u8; 10];
+        let (len, _) = recv(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 2]);
+        assert_eq!(server.connections.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_tcp_msg_too_large_announced() {
+        let server = TcpTransport::new(0).unwrap();
+        let client = TcpTransport::new(0).unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv6Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        // A peer that announces a message larger than a packet has its connection closed
+        let mut stream = std::net::TcpStream::connect(server_addr).unwrap();
+        let too_large = (MAX_TCP_MSG_SIZE as u32 + 1).to_le_bytes();
+        stream.write_all(&too_large).unwrap();
+        let mut buf = [0u8; MAX_TCP_MSG_SIZE];
+        let addr = smol::block_on(future::or(
+            async {
+                let _ = network::recv(&server, &mut buf).await;
+                future::pending().await
+            },
+            closed(&server),
+        ));
+        assert!(addr == Address::Tcp(stream.local_addr().unwrap()));
+
+        // The other connections carry on
+        send(&client, &[1, 2], Address::Tcp(server_addr)).unwrap();
+        let (len, _) = recv(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 2]);
+    }
+
+    #[test]
+    fn test_tcp_msg_too_large_to_send() {
+        let server = TcpTransport::new(0).unwrap();
+        let client = TcpTransport::new(0).unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv6Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        let msg = vec![0; MAX_TCP_MSG_SIZE + 1];
+        assert_eq!(
+            send(&client, &msg, Address::Tcp(server_addr)),
+            Err(Error::Invalid)
+        );
+        // Nothing was sent, nor was a connection opened for it
+        assert!(client.connections.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_tcp_closed() {
+        let server = TcpTransport::new(0).unwrap();
+        let client = TcpTransport::new(0).unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv6Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        send(&client, &[1], Address::Tcp(server_addr)).unwrap();
+        let mut buf = [0u8; 10];
+        let (_, peer) = recv(&server, &mut buf).unwrap();
+        assert!(smol::block_on(future::poll_once(closed(&server))).is_none());
+
+        // The server finds out about the closed connection while waiting for messages
+        drop(client);
+        let addr = smol::block_on(future::or(
+            async {
+                let _ = network::recv(&server, &mut buf).await;
+                future::pending().await
+            },
+            closed(&server),
+        ));
+        assert!(addr == peer);
+        assert!(server.connections.borrow().is_empty());
+    }
+}
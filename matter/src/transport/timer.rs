@@ -0,0 +1,350 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use async_channel::{Receiver, Sender};
+use log::error;
+
+use crate::{error::Error, utils::clock::SharedClock};
+
+use super::queue::{Msg, WorkQ};
+
+/// Identifies a timer, so that it can be cancelled
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TimerId(u32);
+
+/// What to do when a timer fires
+pub enum TimerAction {
+    /// Call the closure, with the id of the timer that fired
+    Callback(Box<dyn FnMut(TimerId)>),
+    /// Post the message returned by the closure on the work queue
+    Post(WorkQ, Box<dyn FnMut() -> Msg>),
+}
+
+impl TimerAction {
+    fn fire(&mut self, id: TimerId) {
+        match self {
+            TimerAction::Callback(cb) => cb(id),
+            TimerAction::Post(work_q, msg) => {
+                if let Err(e) = work_q.try_send(msg()) {
+                    error!("Error posting the message of timer {:?}: {:?}", id, e);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TimerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimerAction::Callback(_) => write!(f, "Callback"),
+            TimerAction::Post(_, _) => write!(f, "Post"),
+        }
+    }
+}
+
+struct TimerEntry {
+    id: TimerId,
+    deadline: SystemTime,
+    period: Option<Duration>,
+    // This is taken out while the action runs
+    action: Option<TimerAction>,
+}
+
+struct TimersInner {
+    next_id: u32,
+    entries: Vec<TimerEntry>,
+}
+
+/// The timers of the stack
+///
+/// Anything with a timeout, like the fail-safe or the commissioning window, registers a
+/// one-shot or a periodic timer here instead of checking the time by itself. The timers are
+/// driven by the transport loop, which wakes up for the earliest one and runs the actions
+/// of those that have expired. So the actions run on the transport loop, between the
+/// processing of two messages, and must not block.
+///
+/// This is a handle, the clones share the same timers.
+#[derive(Clone)]
+pub struct Timers {
+    inner: Arc<Mutex<TimersInner>>,
+    clock: SharedClock,
+    changed_tx: Sender<()>,
+    changed_rx: Receiver<()>,
+}
+
+impl Timers {
+    pub fn new(clock: SharedClock) -> Self {
+        let (changed_tx, changed_rx) = async_channel::bounded(1);
+        Self {
+            inner: Arc::new(Mutex::new(TimersInner {
+                next_id: 0,
+                entries: Vec::new(),
+            })),
+            clock,
+            changed_tx,
+            changed_rx,
+        }
+    }
+
+    /// Run the action once, after the given duration
+    pub fn add_oneshot(&self, after: Duration, action: TimerAction) -> TimerId {
+        self.add(after, None, action)
+    }
+
+    /// Run the action every period, until the timer is cancelled
+    pub fn add_periodic(&self, period: Duration, action: TimerAction) -> Result<TimerId, Error> {
+        if period.is_zero() {
+            return Err(Error::Invalid);
+        }
+        Ok(self.add(period, Some(period), action))
+    }
+
+    fn add(&self, after: Duration, period: Option<Duration>, action: TimerAction) -> TimerId {
+        let deadline = self.clock.now() + after;
+        let id = {
+            let mut inner = self.inner.lock().unwrap();
+            let id = TimerId(inner.next_id);
+            inner.next_id = inner.next_id.wrapping_add(1);
+            inner.entries.push(TimerEntry {
+                id,
+                deadline,
+                period,
+                action: Some(action),
+            });
+            id
+        };
+        // The transport loop may have to wake up earlier now. A notification that is
+        // already queued covers this timer too
+        let _ = self.changed_tx.try_send(());
+        id
+    }
+
+    /// Cancel a timer, returning false if it doesn't exist anymore
+    ///
+    /// A one-shot timer that has fired doesn't exist anymore.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.entries.len();
+        inner.entries.retain(|e| e.id != id);
+        inner.entries.len() != len
+    }
+
+    pub fn is_pending(&self, id: TimerId) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .any(|e| e.id == id)
+    }
+
+    /// The time at which the earliest timer expires
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|e| e.deadline)
+            .min()
+    }
+
+    /// Wait until a timer is added
+    pub async fn changed(&self) -> Result<(), Error> {
+        self.changed_rx.recv().await.map_err(|e| e.into())
+    }
+
+    /// Run the actions of the timers that have expired, returning how many ran
+    ///
+    /// The actions run without the timers being locked, so they can add and cancel timers,
+    /// including their own.
+    pub fn fire_expired(&self) -> usize {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            for e in inner.entries.iter_mut().filter(|e| e.deadline <= now) {
+                if let Some(action) = e.action.take() {
+                    expired.push((e.id, action));
+                }
+                if let Some(period) = e.period {
+                    // Skip the periods that were missed, rather than firing in a burst
+                    e.deadline = (e.deadline + period).max(now + period);
+                }
+            }
+            // The one-shot timers that fired are done
+            inner
+                .entries
+                .retain(|e| e.period.is_some() || e.action.is_some());
+        }
+
+        let count = expired.len();
+        for (id, action) in expired.iter_mut() {
+            action.fire(*id);
+        }
+
+        // Periodic timers get their action back, unless they were cancelled in the meantime
+        let mut inner = self.inner.lock().unwrap();
+        for (id, action) in expired {
+            if let Some(e) = inner.entries.iter_mut().find(|e| e.id == id) {
+                e.action = Some(action);
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        transport::queue::{Msg, WorkQ},
+        utils::clock::{Clock, MockClock},
+    };
+
+    use super::{TimerAction, TimerId, Timers};
+
+    fn counter() -> (Arc<AtomicUsize>, TimerAction) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        let action = TimerAction::Callback(Box::new(move |_| {
+            c.fetch_add(1, Ordering::SeqCst);
+        }));
+        (count, action)
+    }
+
+    #[test]
+    fn test_oneshot() {
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let (count, action) = counter();
+        let id = timers.add_oneshot(Duration::from_secs(10), action);
+        assert_eq!(
+            timers.next_deadline(),
+            Some(clock.now() + Duration::from_secs(10))
+        );
+
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(timers.fire_expired(), 0);
+        assert!(timers.is_pending(id));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(timers.fire_expired(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(!timers.is_pending(id));
+        assert_eq!(timers.next_deadline(), None);
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(timers.fire_expired(), 0);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_periodic() {
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let (count, action) = counter();
+        assert!(timers.add_periodic(Duration::ZERO, action).is_err());
+
+        let (count_p, action) = counter();
+        let id = timers.add_periodic(Duration::from_secs(5), action).unwrap();
+        for i in 1..4 {
+            clock.advance(Duration::from_secs(5));
+            assert_eq!(timers.fire_expired(), 1);
+            assert_eq!(count_p.load(Ordering::SeqCst), i);
+        }
+
+        // Missed periods are skipped
+        clock.advance(Duration::from_secs(22));
+        assert_eq!(timers.fire_expired(), 1);
+        assert_eq!(count_p.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            timers.next_deadline(),
+            Some(clock.now() + Duration::from_secs(5))
+        );
+
+        assert!(timers.cancel(id));
+        assert!(!timers.cancel(id));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(timers.fire_expired(), 0);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_cancel_from_action() {
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let fired: Arc<Mutex<Vec<TimerId>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let t = timers.clone();
+        let f = fired.clone();
+        let id = timers
+            .add_periodic(
+                Duration::from_secs(1),
+                TimerAction::Callback(Box::new(move |id| {
+                    f.lock().unwrap().push(id);
+                    // A periodic timer that cancels itself
+                    t.cancel(id);
+                })),
+            )
+            .unwrap();
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(timers.fire_expired(), 1);
+        assert_eq!(*fired.lock().unwrap(), vec![id]);
+        assert!(!timers.is_pending(id));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(timers.fire_expired(), 0);
+    }
+
+    #[test]
+    fn test_post() {
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let (work_q, rx_q) = WorkQ::new();
+        timers.add_oneshot(
+            Duration::from_millis(100),
+            TimerAction::Post(work_q, Box::new(Msg::Tx)),
+        );
+
+        assert!(rx_q.try_recv().is_err());
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(timers.fire_expired(), 1);
+        assert!(matches!(rx_q.try_recv(), Ok(Msg::Tx())));
+    }
+
+    #[test]
+    fn test_changed() {
+        let timers = Timers::new(Arc::new(MockClock::default()));
+        let (_, action) = counter();
+        timers.add_oneshot(Duration::from_secs(1), action);
+        smol::block_on(timers.changed()).unwrap();
+    }
+}
@@ -15,15 +15,22 @@
  *    limitations under the License.
  */
 
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv6Addr, SocketAddr, UdpSocket},
+    task::{Context, Poll},
+};
+
 use crate::error::*;
-use smol::net::{Ipv6Addr, UdpSocket};
+use log::error;
+use smol::Async;
 
 use super::network::{Address, NetworkInterface};
 
 // We could get rid of the smol here, but keeping it around in case we have to process
 // any other events in this thread's context
 pub struct UdpListener {
-    socket: UdpSocket,
+    socket: Async<UdpSocket>,
 }
 
 // Currently matches with the one in connectedhomeip repo
@@ -33,25 +40,71 @@ pub const MAX_RX_BUF_SIZE: usize = 1583;
 pub const MATTER_PORT: u16 = 5540;
 
 impl UdpListener {
-    pub fn new() -> Result<UdpListener, Error> {
-        Ok(UdpListener {
-            socket: smol::block_on(UdpSocket::bind((Ipv6Addr::UNSPECIFIED, MATTER_PORT)))?,
-        })
+    pub fn new(port: u16) -> Result<UdpListener, Error> {
+        let socket = Async::<UdpSocket>::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))?;
+        // We are not interested in our own group messages
+        socket.get_ref().set_multicast_loop_v6(false)?;
+        Ok(UdpListener { socket })
+    }
+}
+
+impl UdpListener {
+    // Run a socket operation once the socket is ready for it, as per poll_ready
+    fn poll_io<T>(
+        &self,
+        cx: &mut Context<'_>,
+        poll_ready: impl Fn(&Async<UdpSocket>, &mut Context<'_>) -> Poll<io::Result<()>>,
+        mut op: impl FnMut(&UdpSocket) -> io::Result<T>,
+    ) -> Poll<Result<T, Error>> {
+        loop {
+            match poll_ready(&self.socket, cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+            match op(self.socket.get_ref()) {
+                Ok(result) => return Poll::Ready(Ok(result)),
+                // Someone else got there first, wait for the next readiness
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => {
+                    error!("Error on the network: {:?}", e);
+                    return Poll::Ready(Err(Error::Network));
+                }
+            }
+        }
     }
 }
 
 impl NetworkInterface for UdpListener {
-    fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
-        let (size, addr) = smol::block_on(self.socket.recv_from(in_buf)).map_err(|e| {
-            println!("Error on the network: {:?}", e);
-            Error::Network
-        })?;
-        Ok((size, Address::Udp(addr)))
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        in_buf: &mut [u8],
+    ) -> Poll<Result<(usize, Address), Error>> {
+        self.poll_io(cx, Async::poll_readable, |s| s.recv_from(in_buf))
+            .map_ok(|(size, addr)| (size, Address::Udp(addr)))
     }
 
-    fn send(&self, out_buf: &[u8], addr: Address) -> Result<usize, Error> {
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        out_buf: &[u8],
+        addr: Address,
+    ) -> Poll<Result<usize, Error>> {
         match addr {
-            Address::Udp(addr) => Ok(smol::block_on(self.socket.send_to(out_buf, addr))?),
+            Address::Udp(addr) => {
+                self.poll_io(cx, Async::poll_writable, |s| s.send_to(out_buf, addr))
+            }
+            _ => Poll::Ready(Err(Error::Invalid)),
         }
     }
+
+    fn join_multicast(&self, addr: &Ipv6Addr) -> Result<(), Error> {
+        // Let the OS pick the interface
+        Ok(self.socket.get_ref().join_multicast_v6(addr, 0)?)
+    }
+
+    fn leave_multicast(&self, addr: &Ipv6Addr) -> Result<(), Error> {
+        Ok(self.socket.get_ref().leave_multicast_v6(addr, 0)?)
+    }
 }
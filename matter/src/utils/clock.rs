@@ -0,0 +1,217 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use smol::Timer;
+
+/// The start of the Matter epoch, 2000-01-01 00:00:00 UTC, in seconds since the UNIX epoch
+pub const MATTER_EPOCH_SECS: u64 = 946_684_800;
+
+/// The source of the current time
+///
+/// Everything that deals with timeouts, like MRP, the sessions, the timed interactions and
+/// the PASE discard timeout, reads the time through a clock. The stack uses a
+/// [SystemClock], while the tests can use a [MockClock] to step the time precisely.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// A future that completes once the clock reaches the deadline
+    ///
+    /// The default waits for the remaining time on a real timer.
+    fn sleep_until(&self, deadline: SystemTime) -> Sleep {
+        let remaining = deadline.duration_since(self.now()).unwrap_or_default();
+        Box::pin(async move {
+            Timer::after(remaining).await;
+        })
+    }
+
+    /// The time in seconds since the UNIX epoch
+    fn epoch_secs(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// The time in microseconds since the Matter epoch
+    fn matter_epoch_us(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| (d.as_micros() as u64).saturating_sub(MATTER_EPOCH_SECS * 1_000_000))
+            .unwrap_or(0)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The wall clock
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Returns the wall clock, for sharing
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when it is told to
+///
+/// The futures from [Clock::sleep_until] complete when the clock is moved past their
+/// deadline, so the tests run in virtual time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    now: SystemTime,
+    sleepers: Vec<(SystemTime, Waker)>,
+}
+
+impl MockClock {
+    /// Create a clock that is stopped at the given time
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                now,
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, duration: Duration) {
+        let now = self.now() + duration;
+        self.set(now);
+    }
+
+    /// Move the clock to the given time
+    pub fn set(&self, now: SystemTime) {
+        let due = {
+            let mut state = self.state.lock().unwrap();
+            state.now = now;
+            let (due, pending) = state
+                .sleepers
+                .drain(..)
+                .partition(|(deadline, _)| *deadline <= now);
+            state.sleepers = pending;
+            due
+        };
+        // Wake outside the lock, the sleepers lock it again when they are polled
+        for (_, waker) in due {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        // The start of the Matter epoch is as good a start as any
+        Self::new(UNIX_EPOCH + Duration::from_secs(MATTER_EPOCH_SECS))
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.state.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: SystemTime) -> Sleep {
+        Box::pin(MockSleep {
+            state: self.state.clone(),
+            deadline,
+        })
+    }
+}
+
+struct MockSleep {
+    state: Arc<Mutex<MockState>>,
+    deadline: SystemTime,
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.now >= self.deadline {
+            return Poll::Ready(());
+        }
+        let deadline = self.deadline;
+        let registered = state
+            .sleepers
+            .iter()
+            .any(|(d, w)| *d == deadline && w.will_wake(cx.waker()));
+        if !registered {
+            state.sleepers.push((deadline, cx.waker().clone()));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use smol::future;
+
+    use super::{Clock, MockClock, MATTER_EPOCH_SECS};
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::default();
+        assert_eq!(clock.matter_epoch_us(), 0);
+        assert_eq!(clock.epoch_secs(), MATTER_EPOCH_SECS);
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.matter_epoch_us(), 1_500_000);
+        assert_eq!(clock.epoch_secs(), MATTER_EPOCH_SECS + 1);
+
+        clock.set(UNIX_EPOCH);
+        // Times before the Matter epoch are clamped
+        assert_eq!(clock.matter_epoch_us(), 0);
+    }
+
+    #[test]
+    fn test_mock_sleep() {
+        let clock = MockClock::default();
+        let mut sleep = clock.sleep_until(clock.now() + Duration::from_secs(1));
+        smol::block_on(async {
+            assert!(future::poll_once(&mut sleep).await.is_none());
+            clock.advance(Duration::from_millis(999));
+            assert!(future::poll_once(&mut sleep).await.is_none());
+            clock.advance(Duration::from_millis(1));
+            assert!(future::poll_once(&mut sleep).await.is_some());
+        });
+    }
+}
@@ -15,5 +15,6 @@
  *    limitations under the License.
  */
 
+pub mod clock;
 pub mod parsebuf;
 pub mod writebuf;
@@ -16,37 +16,43 @@
  */
 
 use crate::common::echo_cluster;
-use boxslab::Slab;
 use matter::{
     acl::{AclEntry, AclMgr, AuthMode},
     data_model::{
         cluster_basic_information::BasicInfoConfig,
         core::DataModel,
         device_types::device_type_add_on_off_light,
-        objects::Privilege,
+        objects::{AttrStore, Privilege},
         sdm::dev_att::{DataType, DevAttDataFetcher},
     },
     error::Error,
     fabric::FabricMgr,
+    group_keys::GroupKeys,
     interaction_model::{core::OpCode, InteractionModel},
+    mdns::Mdns,
+    persist::MemKvStore,
     secure_channel::pake::PaseMgr,
     tlv::{TLVWriter, TagType, ToTLV},
-    transport::packet::Packet,
     transport::{
         exchange::{self, Exchange, ExchangeCtx},
         network::Address,
-        packet::PacketPool,
         proto_demux::ProtoCtx,
+        queue::WorkQ,
         session::{CloneData, NocCatIds, SessionMgr, SessionMode},
+        timer::Timers,
+        udp::MATTER_PORT,
     },
-    transport::{proto_demux::HandleProto, session::CaseDetails},
-    utils::writebuf::WriteBuf,
+    transport::{proto_demux::HandleProto, queue::Msg, session::CaseDetails},
+    utils::{clock::MockClock, writebuf::WriteBuf},
 };
 use std::{
     net::{Ipv4Addr, SocketAddr},
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
+use async_channel::Receiver;
+
 pub struct DummyDevAtt {}
 impl DevAttDataFetcher for DummyDevAtt {
     fn get_devatt_data(&self, _data_type: DataType, _data: &mut [u8]) -> Result<usize, Error> {
@@ -58,7 +64,13 @@ impl DevAttDataFetcher for DummyDevAtt {
 pub struct ImEngine {
     pub dm: DataModel,
     pub acl_mgr: Arc<AclMgr>,
+    pub group_keys: Arc<GroupKeys>,
     pub im: Box<InteractionModel>,
+    // The timers only move with this clock, see ImEngine::advance()
+    pub clock: Arc<MockClock>,
+    pub timers: Timers,
+    // The messages that the data model posts for the transport
+    pub work_rx: Receiver<Msg>,
     // By default, a new exchange is created for every run, if you wish to instead using a specific
     // exchange, set this variable. This is helpful in situations where you have to run multiple
     // actions in the same transaction (exchange)
@@ -70,6 +82,7 @@ pub struct ImInput<'a> {
     data: &'a dyn ToTLV,
     peer_id: u64,
     cat_ids: NocCatIds,
+    session_mode: Option<SessionMode>,
 }
 
 pub const IM_ENGINE_PEER_ID: u64 = 445566;
@@ -80,6 +93,7 @@ impl<'a> ImInput<'a> {
             data,
             peer_id: IM_ENGINE_PEER_ID,
             cat_ids: Default::default(),
+            session_mode: None,
         }
     }
 
@@ -90,6 +104,11 @@ impl<'a> ImInput<'a> {
     pub fn set_cat_ids(&mut self, cat_ids: &NocCatIds) {
         self.cat_ids = *cat_ids;
     }
+
+    /// Use a session other than the default CASE session of fabric 1
+    pub fn set_session_mode(&mut self, mode: SessionMode) {
+        self.session_mode = Some(mode);
+    }
 }
 
 impl ImEngine {
@@ -106,35 +125,63 @@ impl ImEngine {
         };
 
         let dev_att = Box::new(DummyDevAtt {});
-        let fabric_mgr = Arc::new(FabricMgr::new().unwrap());
-        let acl_mgr = Arc::new(AclMgr::new_with(false).unwrap());
-        let pase_mgr = PaseMgr::new();
+        let storage = Arc::new(Mutex::new(MemKvStore::new()));
+        let mdns = Arc::new(Mdns::new(MATTER_PORT));
+        let fabric_mgr = Arc::new(FabricMgr::new(storage.clone(), mdns.clone(), None).unwrap());
+        let acl_mgr = Arc::new(AclMgr::new_with(None).unwrap());
+        let clock = Arc::new(MockClock::default());
+        let timers = Timers::new(clock.clone());
+        let (work_q, work_rx) = WorkQ::new();
+        let pase_mgr = PaseMgr::new(mdns, work_q.clone(), timers.clone());
         acl_mgr.erase_all();
         let mut default_acl = AclEntry::new(1, Privilege::ADMIN, AuthMode::Case);
         // Only allow the standard peer node id of the IM Engine
         default_acl.add_subject(IM_ENGINE_PEER_ID).unwrap();
         acl_mgr.add(default_acl).unwrap();
-        let dm = DataModel::new(dev_det, dev_att, fabric_mgr, acl_mgr.clone(), pase_mgr).unwrap();
+        let group_keys = Arc::new(GroupKeys::new(fabric_mgr.clone(), storage.clone()).unwrap());
+        let attr_store = Arc::new(AttrStore::new_with(storage, clock.clone()));
+        let dm = DataModel::new(
+            dev_det,
+            dev_att,
+            fabric_mgr,
+            acl_mgr.clone(),
+            pase_mgr,
+            attr_store,
+            group_keys.clone(),
+            timers.clone(),
+            work_q,
+        )
+        .unwrap();
 
         {
             let mut d = dm.node.write().unwrap();
-            let light_endpoint = device_type_add_on_off_light(&mut d).unwrap();
+            let light_endpoint = device_type_add_on_off_light(&mut d, group_keys.clone()).unwrap();
             d.add_cluster(0, echo_cluster::EchoCluster::new(2).unwrap())
                 .unwrap();
             d.add_cluster(light_endpoint, echo_cluster::EchoCluster::new(3).unwrap())
                 .unwrap();
         }
 
-        let im = Box::new(InteractionModel::new(Box::new(dm.clone())));
+        let im = Box::new(InteractionModel::new(Box::new(dm.clone()), timers.clone()));
 
         Self {
             dm,
             acl_mgr,
+            group_keys,
             im,
+            clock,
+            timers,
+            work_rx,
             exch: None,
         }
     }
 
+    /// Move the clock forward, running the timers that expire
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+        self.timers.fire_expired();
+    }
+
     /// Run a transaction through the interaction model engine
     pub fn process<'a>(&mut self, input: &ImInput, data_out: &'a mut [u8]) -> (u8, &'a mut [u8]) {
         let mut new_exch = Exchange::new(1, 0, exchange::Role::Responder);
@@ -152,13 +199,15 @@ impl ImEngine {
                 std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
                 5542,
             )),
-            SessionMode::Case(CaseDetails::new(1, &input.cat_ids)),
+            input
+                .session_mode
+                .unwrap_or_else(|| SessionMode::Case(CaseDetails::new(1, &input.cat_ids))),
         );
         let sess_idx = sess_mgr.clone_session(&clone_data).unwrap();
+        let mut rx = sess_mgr.new_rx().unwrap();
+        let tx = sess_mgr.new_tx().unwrap();
         let sess = sess_mgr.get_session_handle(sess_idx);
         let exch_ctx = ExchangeCtx { exch, sess };
-        let mut rx = Slab::<PacketPool>::try_new(Packet::new_rx().unwrap()).unwrap();
-        let tx = Slab::<PacketPool>::try_new(Packet::new_tx().unwrap()).unwrap();
         // Create fake rx packet
         rx.set_proto_id(0x01);
         rx.set_proto_opcode(input.action as u8);
@@ -0,0 +1,234 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+use matter::{
+    data_model::{
+        cluster_groups as groups, cluster_on_off as onoff,
+        objects::{AttrValue, EncodeValue},
+    },
+    group_keys::GroupKeyMapEntry,
+    interaction_model::{
+        core::{IMStatusCode, OpCode},
+        messages::{
+            ib::{CmdData, CmdPath, CmdStatus},
+            msg::{self, InvReq},
+        },
+    },
+    tlv::{self, FromTLV, TLVArray, TLVWriter, TagType},
+    transport::session::{GroupDetails, SessionMode},
+};
+
+use crate::common::{
+    commands::*,
+    im_engine::{ImEngine, ImInput},
+};
+
+const GROUP_ID: u16 = 0x101;
+
+fn groups_cmd(cmd: groups::Commands) -> CmdPath {
+    CmdPath::new(Some(1), Some(groups::ID), Some(cmd as u16))
+}
+
+// The responses only carry the status in tag 0
+fn groups_resp(cmd: groups::RespCommands, status: IMStatusCode) -> ExpectedInvResp {
+    ExpectedInvResp::Cmd(
+        CmdPath::new(Some(1), Some(groups::ID), Some(cmd as u16)),
+        status as u8,
+    )
+}
+
+fn handle_commands(im: &mut ImEngine, input: &[CmdData], expected: &[ExpectedInvResp]) {
+    let mut out_buf = [0u8; 400];
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let (_, out_buf) = im.process(&ImInput::new(OpCode::InvokeRequest, &req), &mut out_buf);
+    tlv::print_tlv_list(out_buf);
+    let root = tlv::get_root_node_struct(out_buf).unwrap();
+    let resp = msg::InvResp::from_tlv(&root).unwrap();
+    assert_inv_response(&resp, expected)
+}
+
+fn groupcast_on(im: &mut ImEngine, group_id: u16) {
+    let mut out_buf = [0u8; 400];
+    // Group commands never carry an endpoint
+    let path = CmdPath::new(None, Some(onoff::ID), Some(onoff::Commands::On as u16));
+    let input = [CmdData::new(path, EncodeValue::Value(&0_u32))];
+    let req = InvReq {
+        suppress_response: Some(true),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(&input)),
+    };
+    let mut input = ImInput::new(OpCode::InvokeRequest, &req);
+    input.set_session_mode(SessionMode::Group(GroupDetails {
+        fab_idx: 1,
+        group_id,
+    }));
+    im.process(&input, &mut out_buf);
+}
+
+fn is_on(im: &ImEngine) -> bool {
+    let node = im.dm.node.read().unwrap();
+    let onoff = node.get_cluster(1, onoff::ID).unwrap();
+    onoff
+        .base()
+        .read_attribute_raw(onoff::Attributes::OnOff as u16)
+        .unwrap()
+        == &AttrValue::Bool(true)
+}
+
+#[test]
+fn test_add_view_remove_group() {
+    let _ = env_logger::try_init();
+    let mut im = ImEngine::new();
+    let add = |tag: TagType, tw: &mut TLVWriter| {
+        let _ = tw.start_struct(tag);
+        let _ = tw.u16(TagType::Context(0), GROUP_ID);
+        let _ = tw.utf8(TagType::Context(1), b"Kitchen");
+        let _ = tw.end_container();
+    };
+    let add_cmd = [CmdData::new(
+        groups_cmd(groups::Commands::AddGroup),
+        EncodeValue::Closure(&add),
+    )];
+
+    // The group has to be mapped to a key set first
+    handle_commands(
+        &mut im,
+        &add_cmd,
+        &[groups_resp(
+            groups::RespCommands::AddGroupResp,
+            IMStatusCode::UnsupportedAccess,
+        )],
+    );
+    im.group_keys
+        .set_key_map(
+            1,
+            vec![GroupKeyMapEntry {
+                group_id: GROUP_ID,
+                key_set_id: 1,
+            }],
+        )
+        .unwrap();
+    handle_commands(
+        &mut im,
+        &add_cmd,
+        &[groups_resp(
+            groups::RespCommands::AddGroupResp,
+            IMStatusCode::Success,
+        )],
+    );
+    assert!(im.group_keys.is_group_member(1, GROUP_ID, 1));
+
+    let group = |tag: TagType, tw: &mut TLVWriter| {
+        let _ = tw.start_struct(tag);
+        let _ = tw.u16(TagType::Context(0), GROUP_ID);
+        let _ = tw.end_container();
+    };
+    let view_cmd = [CmdData::new(
+        groups_cmd(groups::Commands::ViewGroup),
+        EncodeValue::Closure(&group),
+    )];
+    handle_commands(
+        &mut im,
+        &view_cmd,
+        &[groups_resp(
+            groups::RespCommands::ViewGroupResp,
+            IMStatusCode::Success,
+        )],
+    );
+
+    let remove_cmd = [CmdData::new(
+        groups_cmd(groups::Commands::RemoveGroup),
+        EncodeValue::Closure(&group),
+    )];
+    handle_commands(
+        &mut im,
+        &remove_cmd,
+        &[groups_resp(
+            groups::RespCommands::RemoveGroupResp,
+            IMStatusCode::Success,
+        )],
+    );
+    handle_commands(
+        &mut im,
+        &remove_cmd,
+        &[groups_resp(
+            groups::RespCommands::RemoveGroupResp,
+            IMStatusCode::NotFound,
+        )],
+    );
+    handle_commands(
+        &mut im,
+        &view_cmd,
+        &[groups_resp(
+            groups::RespCommands::ViewGroupResp,
+            IMStatusCode::NotFound,
+        )],
+    );
+}
+
+#[test]
+fn test_remove_all_groups() {
+    let _ = env_logger::try_init();
+    let mut im = ImEngine::new();
+    im.group_keys
+        .add_group_endpoint(1, GROUP_ID, "", 1)
+        .unwrap();
+    im.group_keys
+        .add_group_endpoint(1, GROUP_ID + 1, "", 1)
+        .unwrap();
+    // Another fabric's groups stay
+    im.group_keys
+        .add_group_endpoint(2, GROUP_ID, "", 1)
+        .unwrap();
+
+    let path = groups_cmd(groups::Commands::RemoveAllGroups);
+    let no_fields = |tag: TagType, tw: &mut TLVWriter| {
+        let _ = tw.start_struct(tag);
+        let _ = tw.end_container();
+    };
+    let input = [CmdData::new(path, EncodeValue::Closure(&no_fields))];
+    handle_commands(
+        &mut im,
+        &input,
+        &[ExpectedInvResp::Status(CmdStatus::new(
+            path,
+            IMStatusCode::Success,
+            0,
+        ))],
+    );
+    assert!(im.group_keys.groups(1).unwrap().is_empty());
+    assert!(im.group_keys.is_group_member(2, GROUP_ID, 1));
+}
+
+#[test]
+fn test_groupcast_invoke() {
+    let _ = env_logger::try_init();
+    let mut im = ImEngine::new();
+    im.group_keys
+        .add_group_endpoint(1, GROUP_ID, "", 1)
+        .unwrap();
+
+    // Only the member endpoints handle the command
+    groupcast_on(&mut im, GROUP_ID + 1);
+    assert!(!is_on(&im));
+    groupcast_on(&mut im, GROUP_ID);
+    assert!(is_on(&im));
+}
@@ -17,10 +17,10 @@
 
 use matter::{
     data_model::{
-        cluster_basic_information as basic_info, cluster_on_off as onoff,
+        cluster_basic_information as basic_info, cluster_groups, cluster_on_off as onoff,
         objects::{EncodeValue, GlobalElements},
         sdm::{admin_commissioning as adm_comm, general_commissioning as gen_comm, noc},
-        system_model::{access_control as acl, descriptor},
+        system_model::{access_control as acl, descriptor, group_key_management as gkm},
     },
     interaction_model::{
         core::{IMStatusCode, OpCode},
@@ -33,9 +33,11 @@ use matter::{
     tlv::{self, ElementType, FromTLV, TLVElement, TagType, ToTLV},
     transport::{
         exchange::{self, Exchange},
+        queue::Msg,
         udp::MAX_RX_BUF_SIZE,
     },
 };
+use std::time::Duration;
 
 use crate::{
     attr_data,
@@ -109,6 +111,8 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(0, 60, adm_comm::Attributes::AdminVendorId, dont_care),
         attr_data!(0, 62, GlobalElements::FeatureMap, dont_care),
         attr_data!(0, 62, GlobalElements::AttributeList, dont_care),
+        attr_data!(0, 62, noc::Attributes::NOCs, dont_care),
+        attr_data!(0, 62, noc::Attributes::TrustedRootCerts, dont_care),
         attr_data!(0, 62, noc::Attributes::CurrentFabricIndex, dont_care),
         attr_data!(0, 62, noc::Attributes::Fabrics, dont_care),
         attr_data!(0, 62, noc::Attributes::SupportedFabrics, dont_care),
@@ -120,7 +124,16 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(0, 31, acl::Attributes::SubjectsPerEntry, dont_care),
         attr_data!(0, 31, acl::Attributes::TargetsPerEntry, dont_care),
         attr_data!(0, 31, acl::Attributes::EntriesPerFabric, dont_care),
+        attr_data!(0, 63, GlobalElements::FeatureMap, dont_care),
+        attr_data!(0, 63, GlobalElements::AttributeList, dont_care),
+        attr_data!(0, 63, gkm::Attributes::GroupKeyMap, dont_care),
+        attr_data!(0, 63, gkm::Attributes::GroupTable, dont_care),
+        attr_data!(0, 63, gkm::Attributes::MaxGroupsPerFabric, dont_care),
+        attr_data!(0, 63, gkm::Attributes::MaxGroupKeysPerFabric, dont_care),
         attr_data!(0, echo::ID, GlobalElements::FeatureMap, dont_care),
+    ];
+
+    let part2 = vec![
         attr_data!(0, echo::ID, GlobalElements::AttributeList, dont_care),
         attr_data!(0, echo::ID, echo::Attributes::Att1, dont_care),
         attr_data!(0, echo::ID, echo::Attributes::Att2, dont_care),
@@ -128,15 +141,15 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(1, 29, GlobalElements::FeatureMap, dont_care),
         attr_data!(1, 29, GlobalElements::AttributeList, dont_care),
         attr_data!(1, 29, descriptor::Attributes::DeviceTypeList, dont_care),
-    ];
-
-    let part2 = vec![
         attr_data!(1, 29, descriptor::Attributes::ServerList, dont_care),
         attr_data!(1, 29, descriptor::Attributes::PartsList, dont_care),
         attr_data!(1, 29, descriptor::Attributes::ClientList, dont_care),
         attr_data!(1, 6, GlobalElements::FeatureMap, dont_care),
         attr_data!(1, 6, GlobalElements::AttributeList, dont_care),
         attr_data!(1, 6, onoff::Attributes::OnOff, dont_care),
+        attr_data!(1, 4, GlobalElements::FeatureMap, dont_care),
+        attr_data!(1, 4, GlobalElements::AttributeList, dont_care),
+        attr_data!(1, 4, cluster_groups::Attributes::NameSupport, dont_care),
         attr_data!(1, echo::ID, GlobalElements::FeatureMap, dont_care),
         attr_data!(1, echo::ID, GlobalElements::AttributeList, dont_care),
         attr_data!(1, echo::ID, echo::Attributes::Att1, dont_care),
@@ -221,4 +234,22 @@ fn test_long_read_subscription_success() {
     let subs_resp = SubscribeResp::from_tlv(&root).unwrap();
     assert_eq!(out_code, OpCode::SubscriptResponse as u8);
     assert_eq!(subs_resp.subs_id, 1);
+    assert_eq!(subs_resp.max_int, 20);
+
+    // The subscriber hears from us at the max interval
+    let im_engine = &lr.im_engine;
+    assert_eq!(im_engine.dm.subs.ids(), [1]);
+    im_engine.advance(Duration::from_secs(19));
+    assert!(im_engine.work_rx.try_recv().is_err());
+    im_engine.advance(Duration::from_secs(1));
+    match im_engine.work_rx.try_recv() {
+        Ok(Msg::Unicast(unicast)) => {
+            assert_eq!(unicast.proto_opcode, OpCode::ReportData as u8);
+            let root = tlv::get_root_node_struct(&unicast.payload).unwrap();
+            let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+            assert_eq!(report_data.subscription_id, Some(1));
+            assert!(unicast.timer.is_some());
+        }
+        _ => panic!("No report for the subscription"),
+    }
 }
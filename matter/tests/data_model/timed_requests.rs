@@ -15,8 +15,7 @@
  *    limitations under the License.
  */
 
-use core::time;
-use std::thread;
+use std::time::Duration;
 
 use matter::{
     data_model::{
@@ -67,8 +66,7 @@ fn handle_timed_reqs<'a>(
     }
 
     // Process any delays
-    let delay = time::Duration::from_millis(delay.into());
-    thread::sleep(delay);
+    im_engine.advance(Duration::from_millis(delay.into()));
 
     // Send Write Req
     let input = ImInput::new(opcode, request);
@@ -15,7 +15,6 @@
  *    limitations under the License.
  */
 
-use boxslab::Slab;
 use matter::error::Error;
 use matter::interaction_model::core::OpCode;
 use matter::interaction_model::messages::msg::InvReq;
@@ -27,12 +26,12 @@ use matter::tlv::TLVWriter;
 use matter::transport::exchange::Exchange;
 use matter::transport::exchange::ExchangeCtx;
 use matter::transport::network::Address;
-use matter::transport::packet::Packet;
-use matter::transport::packet::PacketPool;
 use matter::transport::proto_demux::HandleProto;
 use matter::transport::proto_demux::ProtoCtx;
 use matter::transport::proto_demux::ResponseRequired;
 use matter::transport::session::SessionMgr;
+use matter::transport::timer::Timers;
+use matter::utils::clock::system_clock;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -135,7 +134,8 @@ fn handle_data(action: OpCode, data_in: &[u8], data_out: &mut [u8]) -> (DataMode
         command: 0,
         variable: 0,
     });
-    let mut interaction_model = InteractionModel::new(Box::new(data_model.clone()));
+    let timers = Timers::new(system_clock());
+    let mut interaction_model = InteractionModel::new(Box::new(data_model.clone()), timers);
     let mut exch: Exchange = Default::default();
     let mut sess_mgr: SessionMgr = Default::default();
     let sess_idx = sess_mgr
@@ -149,13 +149,13 @@ fn handle_data(action: OpCode, data_in: &[u8], data_out: &mut [u8]) -> (DataMode
             false,
         )
         .unwrap();
+    let mut rx = sess_mgr.new_rx().unwrap();
+    let tx = sess_mgr.new_tx().unwrap();
     let sess = sess_mgr.get_session_handle(sess_idx);
     let exch_ctx = ExchangeCtx {
         exch: &mut exch,
         sess,
     };
-    let mut rx = Slab::<PacketPool>::try_new(Packet::new_rx().unwrap()).unwrap();
-    let tx = Slab::<PacketPool>::try_new(Packet::new_tx().unwrap()).unwrap();
     // Create fake rx packet
     rx.set_proto_id(0x01);
     rx.set_proto_opcode(action as u8);
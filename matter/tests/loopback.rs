@@ -0,0 +1,880 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use heapless::LinearMap;
+use matter::cert::{Cert, CertBuilder, CertProfile, MatterDn};
+use matter::core::{CommissioningData, Matter};
+use matter::crypto::{self, CryptoKeyPair, HmacSha256, KeyPair, Sha256};
+use matter::data_model::cluster_basic_information::{self, BasicInfoConfig};
+use matter::data_model::objects::{EncodeValue, EncodeValueGen};
+use matter::data_model::sdm::dev_att::{DataType, DevAttDataFetcher};
+use matter::data_model::sdm::{general_commissioning, noc};
+use matter::error::Error;
+use matter::fabric::Fabric;
+use matter::interaction_model::core::{IMStatusCode, OpCode as IMOpCode};
+use matter::interaction_model::messages::ib::{self, AttrPath, CmdData, CmdPath, CmdStatus};
+use matter::interaction_model::messages::{msg, GenericPath};
+use matter::persist::MemKvStore;
+use matter::secure_channel::common::{
+    send_mrp_standalone_ack, OpCode as SCOpCode, PROTO_ID_SECURE_CHANNEL,
+};
+use matter::secure_channel::spake2p::{Spake2P, VerifierData};
+use matter::tlv::{get_root_node_struct, FromTLV, TLVArray, TLVElement, TLVWriter, TagType, ToTLV};
+use matter::transport::exchange::{Exchange, ExchangeMgr, Role, MAX_MRP_ENTRIES};
+use matter::transport::loopback::{LinkConditions, LoopbackHub};
+use matter::transport::mgr::Mgr;
+use matter::transport::mrp::ReliableMessage;
+use matter::transport::network::Address;
+use matter::transport::proto_demux::{HandleProto, ProtoCtx, ResponseRequired};
+use matter::transport::queue::WorkQ;
+use matter::transport::session::{CaseDetails, CloneData, SessionMgr, SessionMode};
+use matter::transport::udp::MATTER_PORT;
+use matter::utils::clock::{MockClock, SharedClock, Sleep};
+use matter::utils::writebuf::WriteBuf;
+use rand::RngCore;
+use smol::future;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// The step by which the virtual time moves
+const TICK: Duration = Duration::from_millis(10);
+
+// A protocol id that isn't used by the stack
+const TEST_PROTO_ID: u16 = 3;
+const OPCODE_REQ: u8 = 1;
+const OPCODE_RESP: u8 = 2;
+const RETRANS_INTERVAL: Duration = Duration::from_millis(500);
+
+const PROTO_ID_INTERACTION_MODEL: u16 = 1;
+
+const PASSCODE: u32 = 20202021;
+const DISCRIMINATOR: u16 = 3840;
+const VENDOR_ID: u16 = 0xFFF1;
+const FABRIC_ID: u64 = 0xFAB1;
+const DEVICE_NODE_ID: u64 = 0x1234;
+const CONTROLLER_NODE_ID: u64 = 0x1_0001;
+const IPK: [u8; 16] = [
+    0x4a, 0x71, 0xcd, 0xd7, 0xb2, 0xa3, 0xca, 0x90, 0x24, 0xf9, 0x6f, 0x3c, 0x96, 0xa1, 0x9d, 0xee,
+];
+// The ids of the controller's end of the sessions
+const PASE_SESS_ID: u16 = 1;
+const CASE_SESS_ID: u16 = 2;
+
+// Run the future in virtual time, the clock moves forward by a tick every time the
+// executor comes around to poll the future
+fn run_virtual<T>(clock: &MockClock, f: impl Future<Output = T>) -> T {
+    smol::block_on(future::or(f, async {
+        loop {
+            future::yield_now().await;
+            clock.advance(TICK);
+        }
+    }))
+}
+
+// Replies with the payload of the request, reversed
+struct ReverseProto {
+    handled: Arc<AtomicUsize>,
+}
+
+impl HandleProto for ReverseProto {
+    fn handle_proto_id(&mut self, proto_ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
+        self.handled.fetch_add(1, Ordering::SeqCst);
+        let mut payload = proto_ctx.rx.as_borrow_slice().to_vec();
+        payload.reverse();
+        proto_ctx.tx.set_proto_id(TEST_PROTO_ID);
+        proto_ctx.tx.set_proto_opcode(OPCODE_RESP);
+        proto_ctx.tx.get_writebuf()?.append(&payload)?;
+        Ok(ResponseRequired::Yes)
+    }
+
+    fn get_proto_id(&self) -> usize {
+        TEST_PROTO_ID as usize
+    }
+}
+
+// Send a request from a node without a transport loop, retransmitting it until the
+// response comes in
+async fn request(
+    client: &mut SessionMgr,
+    server: Address,
+    payload: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let sess_idx = client.add(server, None)?;
+    let mut exch = Exchange::new(1, sess_idx, Role::Initiator);
+    let mut tx = client.new_tx()?;
+    tx.set_proto_id(TEST_PROTO_ID);
+    tx.set_proto_opcode(OPCODE_REQ);
+    tx.get_writebuf()?.append(payload)?;
+    exch.send(tx, &mut client.get_session_handle(sess_idx))?;
+
+    loop {
+        let clock = client.get_clock();
+        let timer = clock.sleep_until(clock.now() + RETRANS_INTERVAL);
+        let rx = async { client.read().await.map(Some) };
+        let timeout = async {
+            timer.await;
+            Ok(None)
+        };
+        let rx = match future::or(rx, timeout).await? {
+            Some(rx) => rx,
+            None => {
+                exch.retrans(&mut client.get_session_handle(sess_idx))?;
+                continue;
+            }
+        };
+
+        let (mut rx, index) = match client.recv(rx) {
+            Ok(Some(r)) => r,
+            Ok(None) => continue,
+            // The server retransmits its response, as we never acknowledge it
+            Err(Error::Duplicate) => continue,
+            Err(e) => return Err(e),
+        };
+        let index = index.ok_or(Error::NoSession)?;
+        client.get_session_handle(index).recv(&mut rx)?;
+        if rx.get_proto_id() == TEST_PROTO_ID && rx.get_proto_opcode() == OPCODE_RESP {
+            return Ok(rx.as_borrow_slice().to_vec());
+        }
+    }
+}
+
+fn run_request(hub: &LoopbackHub, clock: &MockClock) -> (Result<Vec<u8>, Error>, usize) {
+    let server_network = hub.add_node();
+    let server_addr = server_network.addr();
+    let (_work_q, rx_q) = WorkQ::new();
+    let mut server =
+        Mgr::new_with_network(rx_q, Box::new(server_network), Arc::new(clock.clone())).unwrap();
+    let handled = Arc::new(AtomicUsize::new(0));
+    server
+        .register_protocol(Box::new(ReverseProto {
+            handled: handled.clone(),
+        }))
+        .unwrap();
+
+    let mut client = SessionMgr::new_with(Arc::new(clock.clone()));
+    client
+        .add_network_interface(Box::new(hub.add_node()))
+        .unwrap();
+
+    let result = run_virtual(
+        clock,
+        future::or(
+            async {
+                server.run().await?;
+                Err::<Vec<u8>, _>(Error::Invalid)
+            },
+            request(&mut client, server_addr, &[1, 2, 3]),
+        ),
+    );
+    (result, handled.load(Ordering::SeqCst))
+}
+
+#[test]
+fn test_request_response() {
+    let clock = MockClock::default();
+    let hub = LoopbackHub::new_with(0, Arc::new(clock.clone()));
+    let (result, handled) = run_request(&hub, &clock);
+    assert_eq!(result, Ok(vec![3, 2, 1]));
+    assert_eq!(handled, 1);
+}
+
+#[test]
+fn test_duplicates_are_dropped() {
+    let clock = MockClock::default();
+    let hub = LoopbackHub::new_with(1, Arc::new(clock.clone()));
+    hub.set_conditions(LinkConditions {
+        duplication: 1.0,
+        delay: Duration::from_millis(10),
+        ..Default::default()
+    })
+    .unwrap();
+    let (result, handled) = run_request(&hub, &clock);
+    assert_eq!(result, Ok(vec![3, 2, 1]));
+    // The copy of the request is caught by the duplicate detection
+    assert_eq!(handled, 1);
+}
+
+#[test]
+fn test_lossy_link() {
+    let clock = MockClock::default();
+    let hub = LoopbackHub::new_with(7, Arc::new(clock.clone()));
+    hub.set_conditions(LinkConditions {
+        loss: 0.5,
+        reordering: 0.5,
+        ..Default::default()
+    })
+    .unwrap();
+    let (result, handled) = run_request(&hub, &clock);
+    assert_eq!(result, Ok(vec![3, 2, 1]));
+    assert_eq!(handled, 1);
+}
+
+// Hands out a freshly generated DAC key pair, which is all that the CSR needs
+struct TestDevAtt {
+    pubkey: [u8; crypto::EC_POINT_LEN_BYTES],
+    privkey: [u8; crypto::BIGNUM_LEN_BYTES],
+}
+
+impl TestDevAtt {
+    fn new(dac: &KeyPair) -> Self {
+        let mut dev_att = Self {
+            pubkey: [0; crypto::EC_POINT_LEN_BYTES],
+            privkey: [0; crypto::BIGNUM_LEN_BYTES],
+        };
+        dac.get_public_key(&mut dev_att.pubkey).unwrap();
+        dac.get_private_key(&mut dev_att.privkey).unwrap();
+        dev_att
+    }
+}
+
+impl DevAttDataFetcher for TestDevAtt {
+    fn get_devatt_data(&self, data_type: DataType, data: &mut [u8]) -> Result<usize, Error> {
+        let src: &[u8] = match data_type {
+            DataType::DACPubKey => &self.pubkey,
+            DataType::DACPrivKey => &self.privkey,
+            _ => return Err(Error::NotFound),
+        };
+        data[..src.len()].copy_from_slice(src);
+        Ok(src.len())
+    }
+}
+
+fn tlv<F>(f: F) -> Result<Vec<u8>, Error>
+where
+    F: FnOnce(&mut TLVWriter) -> Result<(), Error>,
+{
+    let mut buf = [0u8; 1024];
+    let len = buf.len();
+    let mut wb = WriteBuf::new(&mut buf, len);
+    f(&mut TLVWriter::new(&mut wb))?;
+    Ok(wb.as_slice().to_vec())
+}
+
+fn cert_tlv(cert: &Cert) -> Result<Vec<u8>, Error> {
+    let mut buf = [0u8; 800];
+    let len = cert.as_tlv(&mut buf)?;
+    Ok(buf[..len].to_vec())
+}
+
+fn pubkey(key_pair: &KeyPair) -> Result<[u8; crypto::EC_POINT_LEN_BYTES], Error> {
+    let mut pubkey = [0; crypto::EC_POINT_LEN_BYTES];
+    key_pair.get_public_key(&mut pubkey)?;
+    Ok(pubkey)
+}
+
+fn hash(tt: &Sha256) -> Result<[u8; crypto::SHA256_HASH_LEN_BYTES], Error> {
+    let mut hash = [0; crypto::SHA256_HASH_LEN_BYTES];
+    tt.clone().finish(&mut hash)?;
+    Ok(hash)
+}
+
+fn derive_key(salt: &[&[u8]], secret: &[u8], info: &[u8], key: &mut [u8]) -> Result<(), Error> {
+    crypto::hkdf_sha256(&salt.concat(), secret, info, key).map_err(|_| Error::NoSpace)
+}
+
+fn assert_sc_success((opcode, payload): (u8, Vec<u8>)) {
+    assert_eq!(opcode, SCOpCode::StatusReport as u8);
+    // General code, protocol id and protocol code are all 0 for a success
+    assert_eq!(payload, [0; 8]);
+}
+
+// The data of the command response, fails on a status
+fn cmd_resp_data(payload: &[u8], path: CmdPath) -> Result<TLVElement<'_>, Error> {
+    let root = get_root_node_struct(payload)?;
+    let resp = msg::InvResp::from_tlv(&root)?;
+    match resp.inv_responses.ok_or(Error::Invalid)?.iter().next() {
+        Some(ib::InvResp::Cmd(CmdData {
+            path: p,
+            data: EncodeValue::Tlv(t),
+        })) if p == path => Ok(t),
+        r => panic!("Unexpected invoke response {:?}", r),
+    }
+}
+
+fn assert_cmd_success(payload: &[u8], path: CmdPath) -> Result<(), Error> {
+    let root = get_root_node_struct(payload)?;
+    let resp = msg::InvResp::from_tlv(&root)?;
+    match resp.inv_responses.ok_or(Error::Invalid)?.iter().next() {
+        Some(ib::InvResp::Status(s)) => {
+            assert_eq!(s, CmdStatus::new(path, IMStatusCode::Success, 0));
+            Ok(())
+        }
+        r => panic!("Unexpected invoke response {:?}", r),
+    }
+}
+
+// The root CA of the controller's fabric
+struct TestCa {
+    key_pair: KeyPair,
+    rcac: Cert,
+}
+
+impl TestCa {
+    fn new() -> Result<Self, Error> {
+        let key_pair = KeyPair::new()?;
+        let rcac = CertBuilder::new_with(CertProfile::Rcac)
+            .add_subject(MatterDn::RootCaId(1))
+            .set_pubkey(&pubkey(&key_pair)?)?
+            .sign(&key_pair)?;
+        Ok(Self { key_pair, rcac })
+    }
+
+    fn issue_noc(&self, node_id: u64, pubkey: &[u8]) -> Result<Cert, Error> {
+        CertBuilder::new_with(CertProfile::Noc)
+            .add_subject(MatterDn::NodeId(node_id))
+            .add_subject(MatterDn::FabricId(FABRIC_ID))
+            .set_pubkey(pubkey)?
+            .set_issuer(&self.rcac)?
+            .sign(&self.key_pair)
+    }
+}
+
+// A commissioner built from the exchange and session layers, as a controller would
+struct Controller {
+    exch_mgr: ExchangeMgr,
+    device: Address,
+    // The attestation challenge of the PASE session
+    att_challenge: [u8; crypto::SYMM_KEY_LEN_BYTES],
+}
+
+impl Controller {
+    fn new(hub: &LoopbackHub, device: Address, clock: SharedClock) -> Result<Self, Error> {
+        let mut sess_mgr = SessionMgr::new_with(clock);
+        sess_mgr.add_network_interface(Box::new(hub.add_node()))?;
+        Ok(Self {
+            exch_mgr: ExchangeMgr::new(sess_mgr),
+            device,
+            att_challenge: [0; crypto::SYMM_KEY_LEN_BYTES],
+        })
+    }
+
+    fn initiate(
+        &mut self,
+        sess_id: u16,
+        peer_node_id: Option<u64>,
+        proto_id: u16,
+        opcode: u8,
+        payload: &[u8],
+    ) -> Result<u16, Error> {
+        let mut tx = self.exch_mgr.get_sess_mgr().new_tx()?;
+        tx.set_proto_id(proto_id);
+        tx.set_proto_opcode(opcode);
+        tx.get_writebuf()?.append(payload)?;
+        self.exch_mgr.initiate(sess_id, peer_node_id, tx)
+    }
+
+    fn send(
+        &mut self,
+        exch_id: u16,
+        proto_id: u16,
+        opcode: u8,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let mut tx = self.exch_mgr.get_sess_mgr().new_tx()?;
+        tx.set_proto_id(proto_id);
+        tx.set_proto_opcode(opcode);
+        tx.get_writebuf()?.append(payload)?;
+        self.exch_mgr.send(exch_id, tx)
+    }
+
+    // Wait for the next message on the exchange, and return its opcode and payload
+    //
+    // If it is the last message of the exchange, the exchange is acknowledged and closed.
+    // The acknowledgements and the retransmissions of MRP are taken care of meanwhile.
+    async fn wait_for(&mut self, exch_id: u16, last: bool) -> Result<(u8, Vec<u8>), Error> {
+        loop {
+            if self.exch_mgr.has_pending_tx() {
+                self.exch_mgr.flush().await;
+            }
+            let timer: Sleep = match self.exch_mgr.next_timeout() {
+                Some(t) => self.exch_mgr.get_clock().sleep_until(t),
+                None => Box::pin(future::pending()),
+            };
+            let rx = async { self.exch_mgr.read().await.map(Some) };
+            let timeout = async {
+                timer.await;
+                Ok(None)
+            };
+
+            let mut msg = None;
+            if let Some(rx) = future::or(rx, timeout).await? {
+                match self.exch_mgr.recv(rx) {
+                    Ok(Some((mut rx, ctx))) => {
+                        let standalone_ack = rx.get_proto_id() == PROTO_ID_SECURE_CHANNEL as u16
+                            && rx.get_proto_opcode() == SCOpCode::MRPStandAloneAck as u8;
+                        if ctx.exch.get_id() == exch_id && !standalone_ack {
+                            if last {
+                                let mut sess = ctx.sess;
+                                send_mrp_standalone_ack(ctx.exch, &mut sess)?;
+                                ctx.exch.close();
+                            }
+                            msg = Some((rx.get_proto_opcode(), rx.as_borrow_slice().to_vec()));
+                        }
+                    }
+                    Ok(None) | Err(Error::Duplicate) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            self.handle_mrp()?;
+            if let Some(msg) = msg {
+                return Ok(msg);
+            }
+        }
+    }
+
+    fn handle_mrp(&mut self) -> Result<(), Error> {
+        let mut acks: LinearMap<u16, (), MAX_MRP_ENTRIES> = LinearMap::new();
+        self.exch_mgr.pending_acks(&mut acks);
+        for exch_id in acks.keys() {
+            let mut tx = self.exch_mgr.get_sess_mgr().new_tx()?;
+            ReliableMessage::prepare_ack(*exch_id, &mut tx);
+            self.exch_mgr.send(*exch_id, tx)?;
+        }
+
+        let mut retrans: LinearMap<u16, (), MAX_MRP_ENTRIES> = LinearMap::new();
+        self.exch_mgr.pending_retrans(&mut retrans);
+        for exch_id in retrans.keys() {
+            self.exch_mgr.retrans(*exch_id)?;
+        }
+        self.exch_mgr.purge();
+        Ok(())
+    }
+
+    // Establish a PASE session with the passcode
+    #[allow(non_snake_case)]
+    async fn pase(&mut self) -> Result<(), Error> {
+        self.exch_mgr.get_sess_mgr().add(self.device, None)?;
+
+        let mut random = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut random);
+        let req = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str8(TagType::Context(1), &random)?;
+            tw.u16(TagType::Context(2), PASE_SESS_ID)?;
+            tw.u16(TagType::Context(3), 0)?;
+            tw.bool(TagType::Context(4), false)?;
+            tw.end_container()
+        })?;
+        let exch_id = self.initiate(
+            0,
+            None,
+            PROTO_ID_SECURE_CHANNEL as u16,
+            SCOpCode::PBKDFParamRequest as u8,
+            &req,
+        )?;
+        let (opcode, resp) = self.wait_for(exch_id, false).await?;
+        assert_eq!(opcode, SCOpCode::PBKDFParamResponse as u8);
+        let root = get_root_node_struct(&resp)?;
+        assert_eq!(root.find_tag(1)?.slice()?, random);
+        let peer_sess_id = root.find_tag(3)?.u16()?;
+        let params = root.find_tag(4)?;
+
+        let mut spake2p = Spake2P::new();
+        spake2p.set_context(&req, &resp)?;
+        spake2p.start_prover(
+            PASSCODE,
+            params.find_tag(1)?.u32()?,
+            params.find_tag(2)?.slice()?,
+        )?;
+        let mut pA = [0u8; crypto::EC_POINT_LEN_BYTES];
+        spake2p.get_pA(&mut pA)?;
+        let pake1 = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str8(TagType::Context(1), &pA)?;
+            tw.end_container()
+        })?;
+        self.send(
+            exch_id,
+            PROTO_ID_SECURE_CHANNEL as u16,
+            SCOpCode::PASEPake1 as u8,
+            &pake1,
+        )?;
+
+        let (opcode, resp) = self.wait_for(exch_id, false).await?;
+        assert_eq!(opcode, SCOpCode::PASEPake2 as u8);
+        let root = get_root_node_struct(&resp)?;
+        let mut cA = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        let Ke = spake2p
+            .handle_pB(
+                &pA,
+                root.find_tag(1)?.slice()?,
+                root.find_tag(2)?.slice()?,
+                &mut cA,
+            )?
+            .to_vec();
+        let pake3 = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str8(TagType::Context(1), &cA)?;
+            tw.end_container()
+        })?;
+        self.send(
+            exch_id,
+            PROTO_ID_SECURE_CHANNEL as u16,
+            SCOpCode::PASEPake3 as u8,
+            &pake3,
+        )?;
+        assert_sc_success(self.wait_for(exch_id, true).await?);
+
+        let mut keys = [0u8; 3 * crypto::SYMM_KEY_LEN_BYTES];
+        derive_key(&[], &Ke, b"SessionKeys", &mut keys)?;
+        let mut clone_data = CloneData::new(
+            0,
+            0,
+            peer_sess_id,
+            PASE_SESS_ID,
+            self.device,
+            SessionMode::Pase,
+        );
+        // The keys of the initiator are the other way round
+        clone_data.enc_key.copy_from_slice(&keys[0..16]);
+        clone_data.dec_key.copy_from_slice(&keys[16..32]);
+        clone_data.att_challenge.copy_from_slice(&keys[32..48]);
+        self.att_challenge.copy_from_slice(&keys[32..48]);
+        self.exch_mgr.add_session(&clone_data)?;
+        Ok(())
+    }
+
+    async fn invoke(
+        &mut self,
+        sess_id: u16,
+        peer_node_id: Option<u64>,
+        path: CmdPath,
+        args: EncodeValueGen<'_>,
+    ) -> Result<Vec<u8>, Error> {
+        let cmds = [CmdData::new(path, EncodeValue::Closure(args))];
+        let req = msg::InvReq {
+            suppress_response: Some(false),
+            timed_request: Some(false),
+            inv_requests: Some(TLVArray::new(&cmds)),
+        };
+        let req = tlv(|tw| req.to_tlv(tw, TagType::Anonymous))?;
+        let exch_id = self.initiate(
+            sess_id,
+            peer_node_id,
+            PROTO_ID_INTERACTION_MODEL,
+            IMOpCode::InvokeRequest as u8,
+            &req,
+        )?;
+        let (opcode, resp) = self.wait_for(exch_id, true).await?;
+        assert_eq!(opcode, IMOpCode::InvokeResponse as u8);
+        Ok(resp)
+    }
+
+    // Commission the device into the fabric of the CA, over the PASE session
+    //
+    // Returns the public key of the NOC that the device was given.
+    async fn commission(&mut self, ca: &TestCa, dac: &KeyPair) -> Result<Vec<u8>, Error> {
+        let pase = Some(0);
+        let gen_comm = |cmd: general_commissioning::Commands| {
+            CmdPath::new(Some(0), Some(general_commissioning::ID), Some(cmd as u16))
+        };
+        let noc_cmd = |cmd: noc::Commands| CmdPath::new(Some(0), Some(noc::ID), Some(cmd as u16));
+
+        let args = |tag, tw: &mut TLVWriter| {
+            let _ = tw.start_struct(tag);
+            let _ = tw.u16(TagType::Context(0), 60);
+            let _ = tw.u64(TagType::Context(1), 0);
+            let _ = tw.end_container();
+        };
+        let path = gen_comm(general_commissioning::Commands::ArmFailsafe);
+        let resp = self.invoke(PASE_SESS_ID, pase, path, &args).await?;
+        let path = gen_comm(general_commissioning::Commands::ArmFailsafeResp);
+        assert_eq!(cmd_resp_data(&resp, path)?.find_tag(0)?.u8()?, 0);
+
+        let mut nonce = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let args = |tag, tw: &mut TLVWriter| {
+            let _ = tw.start_struct(tag);
+            let _ = tw.str8(TagType::Context(0), &nonce);
+            let _ = tw.end_container();
+        };
+        let path = noc_cmd(noc::Commands::CSRReq);
+        let resp = self.invoke(PASE_SESS_ID, pase, path, &args).await?;
+        let data = cmd_resp_data(&resp, noc_cmd(noc::Commands::CSRResp))?;
+        let elements = data.find_tag(0)?.slice()?;
+        // The elements are signed with the DAC, along with the challenge of the session
+        let signed = [elements, &self.att_challenge].concat();
+        KeyPair::new_from_public(&pubkey(dac)?)?.verify_msg(&signed, data.find_tag(1)?.slice()?)?;
+        let elements = get_root_node_struct(elements)?;
+        assert_eq!(elements.find_tag(2)?.slice()?, nonce);
+        // The public key is the only uncompressed point in the CSR, right after the
+        // header of its BIT STRING
+        let csr = elements.find_tag(1)?.slice()?;
+        let start = csr
+            .windows(4)
+            .position(|w| w == [0x03, 0x42, 0x00, 0x04])
+            .ok_or(Error::Invalid)?
+            + 3;
+        let noc_pubkey = csr[start..start + crypto::EC_POINT_LEN_BYTES].to_vec();
+
+        let rcac = cert_tlv(&ca.rcac)?;
+        let args = |tag, tw: &mut TLVWriter| {
+            let _ = tw.start_struct(tag);
+            let _ = tw.str16(TagType::Context(0), &rcac);
+            let _ = tw.end_container();
+        };
+        let path = noc_cmd(noc::Commands::AddTrustedRootCert);
+        let resp = self.invoke(PASE_SESS_ID, pase, path, &args).await?;
+        assert_cmd_success(&resp, path)?;
+
+        let noc = cert_tlv(&ca.issue_noc(DEVICE_NODE_ID, &noc_pubkey)?)?;
+        let args = |tag, tw: &mut TLVWriter| {
+            let _ = tw.start_struct(tag);
+            let _ = tw.str16(TagType::Context(0), &noc);
+            let _ = tw.str8(TagType::Context(1), &[]);
+            let _ = tw.str8(TagType::Context(2), &IPK);
+            let _ = tw.u64(TagType::Context(3), CONTROLLER_NODE_ID);
+            let _ = tw.u16(TagType::Context(4), VENDOR_ID);
+            let _ = tw.end_container();
+        };
+        let path = noc_cmd(noc::Commands::AddNOC);
+        let resp = self.invoke(PASE_SESS_ID, pase, path, &args).await?;
+        let data = cmd_resp_data(&resp, noc_cmd(noc::Commands::NOCResp))?;
+        assert_eq!(data.find_tag(0)?.u8()?, 0);
+
+        Ok(noc_pubkey)
+    }
+
+    // Establish a CASE session with the device, as a node of the fabric
+    async fn case(&mut self, fabric: &Fabric, device_pubkey: &[u8]) -> Result<(), Error> {
+        let eph_key = KeyPair::new()?;
+        let eph_pubkey = pubkey(&eph_key)?;
+        let mut random = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut random);
+        let mut mac = HmacSha256::new(fabric.ipk.op_key())?;
+        mac.update(&random)?;
+        mac.update(fabric.root_ca.get_pubkey())?;
+        mac.update(&FABRIC_ID.to_le_bytes())?;
+        mac.update(&DEVICE_NODE_ID.to_le_bytes())?;
+        let mut dest_id = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        mac.finish(&mut dest_id)?;
+
+        let sigma1 = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str8(TagType::Context(1), &random)?;
+            tw.u16(TagType::Context(2), CASE_SESS_ID)?;
+            tw.str8(TagType::Context(3), &dest_id)?;
+            tw.str8(TagType::Context(4), &eph_pubkey)?;
+            tw.end_container()
+        })?;
+        let exch_id = self.initiate(
+            0,
+            None,
+            PROTO_ID_SECURE_CHANNEL as u16,
+            SCOpCode::CASESigma1 as u8,
+            &sigma1,
+        )?;
+        let mut tt = Sha256::new()?;
+        tt.update(&sigma1)?;
+
+        let (opcode, sigma2) = self.wait_for(exch_id, false).await?;
+        assert_eq!(opcode, SCOpCode::CASESigma2 as u8);
+        let root = get_root_node_struct(&sigma2)?;
+        let peer_random = root.find_tag(1)?.slice()?;
+        let peer_sess_id = root.find_tag(2)?.u16()?;
+        let peer_pubkey = root.find_tag(3)?.slice()?;
+        let mut secret = [0u8; crypto::ECDH_SHARED_SECRET_LEN_BYTES];
+        eph_key.derive_secret(peer_pubkey, &mut secret)?;
+        let ipk = fabric.ipk.op_key();
+
+        let mut s2k = [0u8; crypto::SYMM_KEY_LEN_BYTES];
+        derive_key(
+            &[ipk, peer_random, peer_pubkey, &hash(&tt)?],
+            &secret,
+            b"Sigma2",
+            &mut s2k,
+        )?;
+        let mut decrypted = root.find_tag(4)?.slice()?.to_vec();
+        let len = crypto::decrypt_in_place(&s2k, b"NCASE_Sigma2N", &[], &mut decrypted)?;
+        let decrypted = get_root_node_struct(&decrypted[..len])?;
+        let device_noc = decrypted.find_tag(1)?.slice()?;
+        assert_eq!(Cert::new(device_noc)?.get_node_id()?, DEVICE_NODE_ID);
+        let tbs = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str16(TagType::Context(1), device_noc)?;
+            tw.str8(TagType::Context(3), peer_pubkey)?;
+            tw.str8(TagType::Context(4), &eph_pubkey)?;
+            tw.end_container()
+        })?;
+        KeyPair::new_from_public(device_pubkey)?
+            .verify_msg(&tbs, decrypted.find_tag(3)?.slice()?)?;
+        tt.update(&sigma2)?;
+
+        let noc = cert_tlv(&fabric.noc)?;
+        let tbs = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str16(TagType::Context(1), &noc)?;
+            tw.str8(TagType::Context(3), &eph_pubkey)?;
+            tw.str8(TagType::Context(4), peer_pubkey)?;
+            tw.end_container()
+        })?;
+        let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+        fabric.sign_msg(&tbs, &mut signature)?;
+        let mut encrypted = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str16(TagType::Context(1), &noc)?;
+            tw.str8(TagType::Context(3), &signature)?;
+            tw.end_container()
+        })?;
+        let mut s3k = [0u8; crypto::SYMM_KEY_LEN_BYTES];
+        derive_key(&[ipk, &hash(&tt)?], &secret, b"Sigma3", &mut s3k)?;
+        let len = encrypted.len();
+        encrypted.extend_from_slice(&[0; crypto::AEAD_MIC_LEN_BYTES]);
+        crypto::encrypt_in_place(&s3k, b"NCASE_Sigma3N", &[], &mut encrypted, len)?;
+        let sigma3 = tlv(|tw| {
+            tw.start_struct(TagType::Anonymous)?;
+            tw.str16(TagType::Context(1), &encrypted)?;
+            tw.end_container()
+        })?;
+        self.send(
+            exch_id,
+            PROTO_ID_SECURE_CHANNEL as u16,
+            SCOpCode::CASESigma3 as u8,
+            &sigma3,
+        )?;
+        assert_sc_success(self.wait_for(exch_id, true).await?);
+        tt.update(&sigma3)?;
+
+        let mut keys = [0u8; 3 * crypto::SYMM_KEY_LEN_BYTES];
+        derive_key(&[ipk, &hash(&tt)?], &secret, b"SessionKeys", &mut keys)?;
+        let mut clone_data = CloneData::new(
+            CONTROLLER_NODE_ID,
+            DEVICE_NODE_ID,
+            peer_sess_id,
+            CASE_SESS_ID,
+            self.device,
+            SessionMode::Case(CaseDetails::new(1, &Default::default())),
+        );
+        clone_data.enc_key.copy_from_slice(&keys[0..16]);
+        clone_data.dec_key.copy_from_slice(&keys[16..32]);
+        clone_data.att_challenge.copy_from_slice(&keys[32..48]);
+        self.exch_mgr.add_session(&clone_data)?;
+        Ok(())
+    }
+
+    async fn read_vendor_id(&mut self) -> Result<u16, Error> {
+        let paths = [AttrPath::new(&GenericPath::new(
+            Some(0),
+            Some(cluster_basic_information::ID),
+            Some(cluster_basic_information::Attributes::VendorId as u32),
+        ))];
+        let req = msg::ReadReq::new(true).set_attr_requests(&paths);
+        let req = tlv(|tw| req.to_tlv(tw, TagType::Anonymous))?;
+        let exch_id = self.initiate(
+            CASE_SESS_ID,
+            Some(DEVICE_NODE_ID),
+            PROTO_ID_INTERACTION_MODEL,
+            IMOpCode::ReadRequest as u8,
+            &req,
+        )?;
+        let (opcode, resp) = self.wait_for(exch_id, true).await?;
+        assert_eq!(opcode, IMOpCode::ReportData as u8);
+        let root = get_root_node_struct(&resp)?;
+        let report = msg::ReportDataMsg::from_tlv(&root)?;
+        match report.attr_reports.ok_or(Error::Invalid)?.iter().next() {
+            Some(ib::AttrResp::Data(ib::AttrData {
+                data: EncodeValue::Tlv(t),
+                ..
+            })) => t.u16(),
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    async fn commission_and_read(&mut self, dac: &KeyPair) -> Result<u16, Error> {
+        let ca = TestCa::new()?;
+        self.pase().await?;
+        let device_pubkey = self.commission(&ca, dac).await?;
+
+        let key_pair = KeyPair::new()?;
+        let noc = ca.issue_noc(CONTROLLER_NODE_ID, &pubkey(&key_pair)?)?;
+        let rcac = Cert::new(&cert_tlv(&ca.rcac)?)?;
+        let fabric = Fabric::new(key_pair, rcac, None, noc, &IPK, VENDOR_ID)?;
+        self.case(&fabric, &device_pubkey).await?;
+
+        // Commissioning completes over the operational session
+        let path = CmdPath::new(
+            Some(0),
+            Some(general_commissioning::ID),
+            Some(general_commissioning::Commands::CommissioningComplete as u16),
+        );
+        let args = |tag, tw: &mut TLVWriter| {
+            let _ = tw.start_struct(tag);
+            let _ = tw.end_container();
+        };
+        let resp = self
+            .invoke(CASE_SESS_ID, Some(DEVICE_NODE_ID), path, &args)
+            .await?;
+        let path = CmdPath::new(
+            Some(0),
+            Some(general_commissioning::ID),
+            Some(general_commissioning::Commands::CommissioningCompleteResp as u16),
+        );
+        assert_eq!(cmd_resp_data(&resp, path)?.find_tag(0)?.u8()?, 0);
+
+        self.read_vendor_id().await
+    }
+}
+
+#[test]
+fn test_commission_and_read() {
+    let clock = MockClock::default();
+    let shared_clock: SharedClock = Arc::new(clock.clone());
+    let hub = LoopbackHub::new_with(0, shared_clock.clone());
+
+    let dev_det = BasicInfoConfig {
+        vid: VENDOR_ID,
+        pid: 0x8000,
+        hw_ver: 1,
+        sw_ver: 1,
+        sw_ver_str: "1".to_string(),
+        serial_no: "aabbccdd".to_string(),
+        device_name: "Loopback Device".to_string(),
+    };
+    let dac = KeyPair::new().unwrap();
+    let dev_comm = CommissioningData {
+        verifier: VerifierData::new_with_pw(PASSCODE),
+        discriminator: DISCRIMINATOR,
+    };
+    let network = hub.add_node();
+    let device_addr = network.addr();
+    let mut device = Matter::new_with(
+        dev_det,
+        Box::new(TestDevAtt::new(&dac)),
+        dev_comm,
+        Arc::new(Mutex::new(MemKvStore::new())),
+        None,
+        MATTER_PORT,
+        Box::new(network),
+        shared_clock.clone(),
+    )
+    .unwrap();
+    let mut controller = Controller::new(&hub, device_addr, shared_clock).unwrap();
+
+    let result = run_virtual(
+        &clock,
+        future::or(
+            async {
+                device.run().await?;
+                Err(Error::Invalid)
+            },
+            controller.commission_and_read(&dac),
+        ),
+    );
+    assert_eq!(result, Ok(VENDOR_ID));
+}
@@ -0,0 +1,185 @@
+/*
+ *
+ *    Copyright (c) 2023 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+extern crate clap;
+use clap::{App, Arg, ArgMatches};
+use matter::attestation::{AttCert, CertDeclaration, MAX_CERT_DECLARATION_LEN};
+use matter::cert::pem;
+use matter::crypto::{self, KeyPair};
+use std::convert::TryFrom;
+use std::fs;
+use std::process;
+
+fn main() {
+    let m = App::new("cd_tool")
+        .about("Generate a Certification Declaration for test and development devices")
+        .arg(num_arg("vid", "Vendor ID").required(true))
+        .arg(
+            num_arg("pid", "Product ID, one for each product")
+                .required(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(num_arg("device-type", "Device Type ID").required(true))
+        .arg(
+            Arg::with_name("certificate-id")
+                .long("certificate-id")
+                .takes_value(true)
+                .required(true)
+                .help("Certificate ID"),
+        )
+        .arg(num_arg("security-level", "Security Level").default_value("0"))
+        .arg(num_arg("security-info", "Security Information").default_value("0"))
+        .arg(num_arg("version-number", "Version Number").default_value("0"))
+        .arg(
+            num_arg(
+                "certification-type",
+                "Certification Type: 0 for development and test, 1 provisional, 2 official",
+            )
+            .default_value("0"),
+        )
+        .arg(num_arg("dac-origin-vid", "Vendor ID in the DAC").requires("dac-origin-pid"))
+        .arg(num_arg("dac-origin-pid", "Product ID in the DAC").requires("dac-origin-vid"))
+        .arg(
+            Arg::with_name("authorized-paa")
+                .long("authorized-paa")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Certificate of a PAA that the DAC may chain to, in DER or PEM"),
+        )
+        .arg(
+            Arg::with_name("cert")
+                .long("cert")
+                .takes_value(true)
+                .required(true)
+                .help("Certificate of the signer, in DER or PEM"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .takes_value(true)
+                .required(true)
+                .help("Private key of the signer, in DER or PEM"),
+        )
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .takes_value(true)
+                .required(true)
+                .help("Output file for the signed Certification Declaration in DER"),
+        )
+        .get_matches();
+
+    let cert_dec = CertDeclaration {
+        format_version: 1,
+        vendor_id: num(&m, "vid"),
+        product_ids: m
+            .values_of("pid")
+            .unwrap()
+            .map(|pid| parse_num("pid", pid))
+            .collect::<Vec<_>>()
+            .into(),
+        device_type_id: num(&m, "device-type"),
+        certificate_id: m.value_of("certificate-id").unwrap().to_string(),
+        security_level: num(&m, "security-level"),
+        security_info: num(&m, "security-info"),
+        version_number: num(&m, "version-number"),
+        certification_type: num(&m, "certification-type"),
+        dac_origin_vid: m
+            .value_of("dac-origin-vid")
+            .map(|v| parse_num("dac-origin-vid", v)),
+        dac_origin_pid: m
+            .value_of("dac-origin-pid")
+            .map(|v| parse_num("dac-origin-pid", v)),
+        authorized_paa_list: m.values_of("authorized-paa").map(|paas| {
+            paas.map(|path| {
+                let der = read_der(path, &[pem::LABEL_CERTIFICATE]);
+                subject_key_id(path, &der)
+            })
+            .collect::<Vec<_>>()
+            .into()
+        }),
+    };
+
+    let cert_path = m.value_of("cert").unwrap();
+    let cert = read_der(cert_path, &[pem::LABEL_CERTIFICATE]);
+    let signer_key_id = subject_key_id(cert_path, &cert);
+    let pub_key = AttCert::new(&cert)
+        .unwrap_or_else(|e| fail(&format!("Invalid certificate {}: {}", cert_path, e)))
+        .get_pubkey();
+
+    let key_path = m.value_of("key").unwrap();
+    let key = read_der(
+        key_path,
+        &[pem::LABEL_EC_PRIVATE_KEY, pem::LABEL_PRIVATE_KEY],
+    );
+    let signer = crypto::decode_ec_private_key(&key)
+        .and_then(|priv_key| KeyPair::new_from_components(pub_key, priv_key))
+        .unwrap_or_else(|e| fail(&format!("Invalid key {}: {}", key_path, e)));
+
+    let mut buf = [0u8; MAX_CERT_DECLARATION_LEN];
+    let len = cert_dec
+        .sign(&signer_key_id, &signer, &mut buf)
+        .unwrap_or_else(|e| {
+            fail(&format!(
+                "Error signing the Certification Declaration: {}",
+                e
+            ))
+        });
+
+    let out = m.value_of("out").unwrap();
+    if let Err(e) = fs::write(out, &buf[..len]) {
+        fail(&format!("Error writing {}: {}", out, e));
+    }
+}
+
+fn num_arg<'a>(name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name).long(name).takes_value(true).help(help)
+}
+
+fn num<T: TryFrom<u64>>(m: &ArgMatches, name: &str) -> T {
+    parse_num(name, m.value_of(name).unwrap())
+}
+
+// All numbers are in hexadecimal, with or without the 0x prefix
+fn parse_num<T: TryFrom<u64>>(name: &str, value: &str) -> T {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u64::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|v| T::try_from(v).ok())
+        .unwrap_or_else(|| fail(&format!("Invalid value for {}: {}", name, value)))
+}
+
+// The DER from a file in either DER or PEM, the latter with any of the given labels
+fn read_der(path: &str, labels: &[&str]) -> Vec<u8> {
+    let data = fs::read(path).unwrap_or_else(|e| fail(&format!("Error reading {}: {}", path, e)));
+    pem::decode_any(&data, labels).unwrap_or_else(|_| fail(&format!("Invalid PEM in {}", path)))
+}
+
+fn subject_key_id(path: &str, der: &[u8]) -> Vec<u8> {
+    AttCert::new(der)
+        .ok()
+        .and_then(|cert| cert.get_subject_key_id())
+        .map(|skid| skid.to_vec())
+        .unwrap_or_else(|| fail(&format!("No Subject Key Identifier in {}", path)))
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}